@@ -0,0 +1,474 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Accountable safety: finding the voters responsible for two conflicting
+//! finalizations.
+//!
+//! If blocks on two different forks are finalized by the same voter set, more
+//! than a third of the voters misbehaved. Following the procedure of the
+//! GRANDPA paper, the voters are queried about the rounds between the two
+//! finalizations, going back from the later one, until votes are found which
+//! no honest voter could have cast:
+//!
+//! - votes for chains without the block finalized earlier, which the
+//!   precommits of the previous round don't justify, since they don't rule out
+//!   that block having been finalized;
+//! - precommits in the earlier round conflicting with the ones of its commit.
+//!
+//! The answers of the voters, i.e. the votes cast in each round, are supplied
+//! by a [`VoteHistory`].
+
+use crate::{
+	std::{self, collections::BTreeMap, vec::Vec},
+	validate_commit,
+	voter_set::VoterSet,
+	weights::VoteWeight,
+	BlockNumberOps, Chain, Commit, Equivocation, HistoricalVotes, Message, Precommit,
+	SignedMessage, SignedPrecommit,
+};
+
+#[cfg(feature = "derive-codec")]
+use parity_scale_codec::{Decode, Encode};
+#[cfg(feature = "derive-codec")]
+use scale_info::TypeInfo;
+
+/// The votes cast in the past rounds of a voter set.
+pub trait VoteHistory<H, N, S, Id> {
+	/// The votes cast in the given round, or `None` if they aren't known.
+	fn historical_votes(&self, round: u64) -> Option<HistoricalVotes<H, N, S, Id>>;
+}
+
+/// Evidence of the misbehavior of a voter.
+#[derive(Clone, PartialEq)]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
+#[cfg_attr(feature = "derive-codec", derive(Encode, Decode, TypeInfo))]
+pub enum Evidence<H, N, S, Id> {
+	/// The voter cast two different precommits in the round the earlier block
+	/// was finalized in, one of them in its commit.
+	PrecommitEquivocation(Equivocation<Id, Precommit<H, N>, S>),
+	/// The voter cast a vote for a chain without the earlier finalized block,
+	/// in a later round, although the precommits of the previous round don't
+	/// rule out that block having been finalized.
+	UnjustifiedVote {
+		/// The round the vote was cast in.
+		round: u64,
+		/// The vote.
+		vote: SignedMessage<H, N, S, Id>,
+		/// The precommits cast in the previous round.
+		previous_precommits: Vec<SignedPrecommit<H, N, S, Id>>,
+	},
+}
+
+/// The voters found to have misbehaved, with evidence of it for each of them.
+pub type Culprits<H, N, S, Id> = BTreeMap<Id, Evidence<H, N, S, Id>>;
+
+/// Errors that can occur when looking for the culprits of two finalizations.
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
+pub enum AccountableSafetyError {
+	/// A commit doesn't finalize its target.
+	InvalidCommit,
+	/// The blocks finalized by the commits are on the same chain.
+	NotConflicting,
+	/// The votes of the given round aren't known.
+	MissingVotes(u64),
+}
+
+#[cfg(feature = "std")]
+impl std::fmt::Display for AccountableSafetyError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match *self {
+			AccountableSafetyError::InvalidCommit =>
+				write!(f, "Commit doesn't finalize its target"),
+			AccountableSafetyError::NotConflicting =>
+				write!(f, "Finalized blocks are on the same chain"),
+			AccountableSafetyError::MissingVotes(round) =>
+				write!(f, "Votes of round {} are unknown", round),
+		}
+	}
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for AccountableSafetyError {}
+
+/// Find the voters responsible for the finalization of two blocks on
+/// different forks, given as the commits finalizing them along with their
+/// round numbers.
+///
+/// Returns the culprits found, with evidence of their misbehavior. The votes
+/// of the rounds from the earlier finalization up to the one before the later
+/// finalization are looked up in `history`. Signatures are assumed to have
+/// been checked.
+pub fn find_culprits<H, N, S, Id, C, V>(
+	voters: &VoterSet<Id>,
+	chain: &C,
+	history: &V,
+	first: (u64, &Commit<H, N, S, Id>),
+	second: (u64, &Commit<H, N, S, Id>),
+) -> Result<Culprits<H, N, S, Id>, AccountableSafetyError>
+where
+	H: Clone + Eq + Ord + std::fmt::Debug,
+	N: Copy + BlockNumberOps + std::fmt::Debug,
+	S: Clone + Eq,
+	Id: Clone + Eq + Ord + std::fmt::Debug,
+	C: Chain<H, N>,
+	V: VoteHistory<H, N, S, Id>,
+{
+	for (_, commit) in [first, second].iter() {
+		match validate_commit(commit, voters, chain) {
+			Ok(result) if result.ghost().is_some() => {},
+			_ => return Err(AccountableSafetyError::InvalidCommit),
+		}
+	}
+
+	let (earlier, later) = if first.0 <= second.0 { (first, second) } else { (second, first) };
+	let finalized = (earlier.1.target_hash.clone(), earlier.1.target_number);
+	if chain.is_equal_or_descendent_of(finalized.0.clone(), later.1.target_hash.clone()) ||
+		chain.is_equal_or_descendent_of(later.1.target_hash.clone(), finalized.0.clone())
+	{
+		return Err(AccountableSafetyError::NotConflicting)
+	}
+
+	let mut culprits = BTreeMap::new();
+
+	// both blocks finalized in the same round: the voters in both commits
+	// precommitted twice.
+	if earlier.0 == later.0 {
+		for second in &later.1.precommits {
+			if let Some(first) = earlier.1.precommits.iter().find(|first| first.id == second.id) {
+				culprits
+					.entry(second.id.clone())
+					.or_insert_with(|| precommit_equivocation(earlier.0, first, second));
+			}
+		}
+
+		return Ok(culprits)
+	}
+
+	// the votes of the current round for chains without the finalized block,
+	// which must be justified by the precommits of the previous round.
+	let mut round = later.0;
+	let mut excluding: Vec<_> = later
+		.1
+		.precommits
+		.iter()
+		.map(|signed| SignedMessage {
+			message: Message::Precommit(signed.precommit.clone()),
+			signature: signed.signature.clone(),
+			id: signed.id.clone(),
+		})
+		.collect();
+
+	loop {
+		let previous = round - 1;
+		let previous_votes = history
+			.historical_votes(previous)
+			.ok_or(AccountableSafetyError::MissingVotes(previous))?;
+		let previous_precommits: Vec<_> = previous_votes
+			.seen()
+			.iter()
+			.filter_map(|signed| match signed.message {
+				Message::Precommit(ref precommit) => Some(SignedPrecommit {
+					precommit: precommit.clone(),
+					signature: signed.signature.clone(),
+					id: signed.id.clone(),
+				}),
+				_ => None,
+			})
+			.collect();
+
+		if !rules_out(voters, chain, &finalized, &previous_precommits) {
+			for vote in excluding {
+				culprits.entry(vote.id.clone()).or_insert_with(|| Evidence::UnjustifiedVote {
+					round,
+					vote,
+					previous_precommits: previous_precommits.clone(),
+				});
+			}
+
+			return Ok(culprits)
+		}
+
+		if previous == earlier.0 {
+			// the precommits ruling out the finalized block conflict with the
+			// ones of its commit.
+			for second in &previous_precommits {
+				if includes(chain, &finalized, &second.precommit.target_hash) {
+					continue
+				}
+
+				if let Some(first) = earlier.1.precommits.iter().find(|first| first.id == second.id)
+				{
+					culprits
+						.entry(second.id.clone())
+						.or_insert_with(|| precommit_equivocation(previous, first, second));
+				}
+			}
+
+			return Ok(culprits)
+		}
+
+		excluding = previous_votes
+			.seen()
+			.iter()
+			.filter(|signed| !includes(chain, &finalized, signed.message.target().0))
+			.cloned()
+			.collect();
+		round = previous;
+	}
+}
+
+// whether the target is the given block or one of its descendents.
+fn includes<H: Clone + Eq, N: Copy + BlockNumberOps, C: Chain<H, N>>(
+	chain: &C,
+	block: &(H, N),
+	target: &H,
+) -> bool {
+	chain.is_equal_or_descendent_of(block.0.clone(), target.clone())
+}
+
+// whether the precommits rule out the block having been finalized in their
+// round, i.e. voters other than those who precommitted for other chains only
+// don't reach the threshold. equivocators could have precommitted for it.
+fn rules_out<H, N, S, Id, C>(
+	voters: &VoterSet<Id>,
+	chain: &C,
+	block: &(H, N),
+	precommits: &[SignedPrecommit<H, N, S, Id>],
+) -> bool
+where
+	H: Clone + Eq,
+	N: Copy + BlockNumberOps,
+	Id: Eq + Ord,
+	C: Chain<H, N>,
+{
+	let mut targets: BTreeMap<&Id, Vec<&Precommit<H, N>>> = BTreeMap::new();
+	for signed in precommits {
+		let targets = targets.entry(&signed.id).or_default();
+		if !targets.contains(&&signed.precommit) {
+			targets.push(&signed.precommit);
+		}
+	}
+
+	let excluded = targets
+		.into_iter()
+		.filter(|(_, targets)| {
+			targets.len() == 1 && !includes(chain, block, &targets[0].target_hash)
+		})
+		.filter_map(|(id, _)| voters.get(id))
+		.fold(VoteWeight(0), |weight, info| weight + info.weight());
+
+	voters.total_weight() - excluded < voters.threshold()
+}
+
+fn precommit_equivocation<H: Clone, N: Clone, S: Clone, Id: Clone>(
+	round: u64,
+	first: &SignedPrecommit<H, N, S, Id>,
+	second: &SignedPrecommit<H, N, S, Id>,
+) -> Evidence<H, N, S, Id> {
+	Evidence::PrecommitEquivocation(Equivocation {
+		round_number: round,
+		identity: first.id.clone(),
+		first: (first.precommit.clone(), first.signature.clone()),
+		second: (second.precommit.clone(), second.signature.clone()),
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		std::collections::BTreeSet,
+		testing::chain::{DummyChain, GENESIS_HASH},
+		Prevote,
+	};
+
+	type Id = u32;
+	type Signature = u32;
+
+	// the signature on a vote is the id of its voter.
+	struct History(BTreeMap<u64, HistoricalVotes<&'static str, u32, Signature, Id>>);
+
+	impl VoteHistory<&'static str, u32, Signature, Id> for History {
+		fn historical_votes(
+			&self,
+			round: u64,
+		) -> Option<HistoricalVotes<&'static str, u32, Signature, Id>> {
+			self.0.get(&round).cloned()
+		}
+	}
+
+	fn voters() -> VoterSet<Id> {
+		VoterSet::new((0..4).map(|id| (id, 1))).expect("nonempty")
+	}
+
+	// two forks on top of "A": "B" - "C" and "D" - "E".
+	fn chain() -> DummyChain {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C"]);
+		chain.push_blocks("A", &["D", "E"]);
+		chain
+	}
+
+	fn commit(target: (&'static str, u32), ids: &[Id]) -> Commit<&'static str, u32, Signature, Id> {
+		Commit {
+			target_hash: target.0,
+			target_number: target.1,
+			precommits: ids
+				.iter()
+				.map(|&id| SignedPrecommit {
+					precommit: Precommit::new(target.0, target.1),
+					signature: id,
+					id,
+				})
+				.collect(),
+		}
+	}
+
+	fn votes(
+		votes: &[(Id, Message<&'static str, u32>)],
+	) -> HistoricalVotes<&'static str, u32, Signature, Id> {
+		HistoricalVotes::new_with(
+			votes
+				.iter()
+				.map(|(id, message)| SignedMessage {
+					message: message.clone(),
+					signature: *id,
+					id: *id,
+				})
+				.collect(),
+			None,
+			None,
+		)
+	}
+
+	fn culprits(culprits: &Culprits<&'static str, u32, Signature, Id>) -> BTreeSet<Id> {
+		culprits.keys().cloned().collect()
+	}
+
+	#[test]
+	fn finds_equivocators_of_the_same_round() {
+		let (chain, voters) = (chain(), voters());
+		let history = History(BTreeMap::new());
+
+		let first = commit(("C", 4), &[0, 1, 2]);
+		let second = commit(("E", 4), &[1, 2, 3]);
+		let found = find_culprits(&voters, &chain, &history, (1, &first), (1, &second)).unwrap();
+
+		assert_eq!(culprits(&found), [1, 2].iter().cloned().collect());
+		assert_eq!(
+			found[&1],
+			Evidence::PrecommitEquivocation(Equivocation {
+				round_number: 1,
+				identity: 1,
+				first: (Precommit::new("C", 4), 1),
+				second: (Precommit::new("E", 4), 1),
+			}),
+		);
+	}
+
+	#[test]
+	fn blames_unjustified_votes_of_later_rounds() {
+		let (chain, voters) = (chain(), voters());
+
+		// the precommits of round 1 don't rule out "C" having been finalized.
+		let mut history = BTreeMap::new();
+		history.insert(
+			1,
+			votes(&[
+				(0, Message::Precommit(Precommit::new("C", 4))),
+				(1, Message::Precommit(Precommit::new("C", 4))),
+				(2, Message::Precommit(Precommit::new("C", 4))),
+				(3, Message::Precommit(Precommit::new("A", 2))),
+			]),
+		);
+		let history = History(history);
+
+		let first = commit(("C", 4), &[0, 1, 2]);
+		let second = commit(("E", 4), &[1, 2, 3]);
+		let found = find_culprits(&voters, &chain, &history, (2, &second), (1, &first)).unwrap();
+
+		assert_eq!(culprits(&found), [1, 2, 3].iter().cloned().collect());
+		assert!(matches!(
+			found[&3],
+			Evidence::UnjustifiedVote { round: 2, ref previous_precommits, .. }
+				if previous_precommits.len() == 4
+		));
+	}
+
+	#[test]
+	fn follows_justified_votes_back_to_the_finalizing_round() {
+		let (chain, voters) = (chain(), voters());
+
+		// in round 1, voters 1 and 2 precommitted for "E" besides their
+		// precommits in the commit for "C", ruling it out. the votes for "E" in
+		// round 2 are justified, and so are the ones of round 3.
+		let mut history = BTreeMap::new();
+		history.insert(
+			1,
+			votes(&[
+				(1, Message::Precommit(Precommit::new("E", 4))),
+				(2, Message::Precommit(Precommit::new("E", 4))),
+				(3, Message::Precommit(Precommit::new("E", 4))),
+			]),
+		);
+		history.insert(
+			2,
+			votes(&[
+				(1, Message::Prevote(Prevote::new("E", 4))),
+				(2, Message::Prevote(Prevote::new("E", 4))),
+				(3, Message::Prevote(Prevote::new("E", 4))),
+				(1, Message::Precommit(Precommit::new("E", 4))),
+				(2, Message::Precommit(Precommit::new("E", 4))),
+				(3, Message::Precommit(Precommit::new("E", 4))),
+			]),
+		);
+		let history = History(history);
+
+		let first = commit(("C", 4), &[0, 1, 2]);
+		let second = commit(("E", 4), &[1, 2, 3]);
+		let found = find_culprits(&voters, &chain, &history, (1, &first), (3, &second)).unwrap();
+
+		assert_eq!(culprits(&found), [1, 2].iter().cloned().collect());
+		assert!(matches!(
+			found[&2],
+			Evidence::PrecommitEquivocation(Equivocation { round_number: 1, .. })
+		));
+	}
+
+	#[test]
+	fn rejects_commits_which_dont_conflict() {
+		let (chain, voters) = (chain(), voters());
+		let history = History(BTreeMap::new());
+
+		let first = commit(("B", 3), &[0, 1, 2]);
+		let second = commit(("C", 4), &[1, 2, 3]);
+		assert_eq!(
+			find_culprits(&voters, &chain, &history, (1, &first), (2, &second)),
+			Err(AccountableSafetyError::NotConflicting),
+		);
+
+		let invalid = commit(("E", 4), &[3]);
+		assert_eq!(
+			find_culprits(&voters, &chain, &history, (1, &first), (2, &invalid)),
+			Err(AccountableSafetyError::InvalidCommit),
+		);
+
+		let second = commit(("E", 4), &[1, 2, 3]);
+		assert_eq!(
+			find_culprits(&voters, &chain, &history, (1, &first), (3, &second)),
+			Err(AccountableSafetyError::MissingVotes(2)),
+		);
+	}
+}
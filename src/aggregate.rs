@@ -0,0 +1,358 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Commits authenticated by a single aggregate signature.
+//!
+//! Instead of carrying a signature and an id per precommit like
+//! [`CompactCommit`](crate::CompactCommit), an [`AggregateCommit`] carries one
+//! signature aggregated from all precommit signatures (e.g. with BLS) and a
+//! bitfield of the signers, indexed by their position in the voter set.
+//!
+//! The crate doesn't depend on any particular signature scheme, aggregation and
+//! verification are provided through the [`SignatureAggregation`] trait.
+
+use crate::{
+	bitfield::{Bit1, Bitfield},
+	std::vec::Vec,
+	voter_set::VoterSet,
+	Commit, Precommit, SignedPrecommit,
+};
+
+#[cfg(feature = "derive-codec")]
+use parity_scale_codec::{Decode, Encode};
+#[cfg(feature = "derive-codec")]
+use scale_info::TypeInfo;
+
+/// Aggregation and verification of precommit signatures.
+pub trait SignatureAggregation<H, N, S, Id> {
+	/// The aggregate signature.
+	type Aggregate;
+
+	/// Aggregate the given precommit signatures into one. Returns `None` if the
+	/// signatures can't be aggregated.
+	fn aggregate(&self, signatures: &[S]) -> Option<Self::Aggregate>;
+
+	/// Returns true if `aggregate` is a valid aggregate of the signatures of
+	/// the given voters on their precommits, cast in round `round` of voter set
+	/// `set_id`.
+	fn verify_aggregate(
+		&self,
+		set_id: u64,
+		round: u64,
+		aggregate: &Self::Aggregate,
+		precommits: &[(Precommit<H, N>, Id)],
+	) -> bool;
+}
+
+/// Authentication data for a set of precommits, made of a single aggregate
+/// signature and the positions of the signers in the voter set.
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
+#[cfg_attr(feature = "derive-codec", derive(Encode, Decode, TypeInfo))]
+pub struct AggregateAuthData<A> {
+	/// The aggregate signature.
+	pub signature: A,
+	/// The signers, by position in the voter set.
+	pub signers: Bitfield,
+}
+
+/// A commit message whose precommits are authenticated by a single aggregate
+/// signature.
+///
+/// There is at most one precommit per signer, ordered by the signers' position
+/// in the voter set. Hence equivocations can't be represented.
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
+#[cfg_attr(feature = "derive-codec", derive(Encode, Decode, TypeInfo))]
+pub struct AggregateCommit<H, N, A> {
+	/// The target block's hash.
+	pub target_hash: H,
+	/// The target block's number.
+	pub target_number: N,
+	/// Precommits for target block or any block after it that justify this commit.
+	pub precommits: Vec<Precommit<H, N>>,
+	/// Authentication data for the commit.
+	pub auth_data: AggregateAuthData<A>,
+}
+
+/// Errors that can occur when converting or verifying aggregate commits.
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
+pub enum AggregateError {
+	/// A precommit is from a voter which isn't part of the voter set, or the
+	/// signers bitfield references a position outside of the voter set.
+	UnknownVoter,
+	/// A voter cast more than one precommit.
+	DuplicateVoter,
+	/// The number of signers doesn't match the number of precommits.
+	SignersMismatch,
+	/// The signatures couldn't be aggregated.
+	AggregationFailed,
+	/// The aggregate signature is invalid.
+	InvalidSignature,
+}
+
+#[cfg(feature = "std")]
+impl std::fmt::Display for AggregateError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match *self {
+			AggregateError::UnknownVoter => write!(f, "Precommit from unknown voter"),
+			AggregateError::DuplicateVoter => write!(f, "Multiple precommits from the same voter"),
+			AggregateError::SignersMismatch =>
+				write!(f, "Number of signers doesn't match number of precommits"),
+			AggregateError::AggregationFailed => write!(f, "Failed to aggregate signatures"),
+			AggregateError::InvalidSignature => write!(f, "Invalid aggregate signature"),
+		}
+	}
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for AggregateError {}
+
+impl<H, N, A> AggregateCommit<H, N, A> {
+	/// Create an aggregate commit from a commit of the given voter set,
+	/// aggregating the signatures on its precommits.
+	///
+	/// Fails if any precommit is from a voter outside of the set or if a voter
+	/// cast more than one precommit.
+	pub fn from_commit<S, Id, G>(
+		commit: Commit<H, N, S, Id>,
+		voters: &VoterSet<Id>,
+		aggregation: &G,
+	) -> Result<Self, AggregateError>
+	where
+		Id: Eq + Ord,
+		G: SignatureAggregation<H, N, S, Id, Aggregate = A>,
+	{
+		let mut signed = Vec::with_capacity(commit.precommits.len());
+		for SignedPrecommit { precommit, signature, id } in commit.precommits {
+			let position = voters.get(&id).ok_or(AggregateError::UnknownVoter)?.position();
+			signed.push((position, precommit, signature));
+		}
+
+		signed.sort_by_key(|(position, _, _)| *position);
+		if signed.windows(2).any(|w| w[0].0 == w[1].0) {
+			return Err(AggregateError::DuplicateVoter)
+		}
+
+		let mut signers = Bitfield::new();
+		let mut precommits = Vec::with_capacity(signed.len());
+		let mut signatures = Vec::with_capacity(signed.len());
+		for (position, precommit, signature) in signed {
+			signers.set_bit(position);
+			precommits.push(precommit);
+			signatures.push(signature);
+		}
+
+		let signature =
+			aggregation.aggregate(&signatures).ok_or(AggregateError::AggregationFailed)?;
+
+		Ok(AggregateCommit {
+			target_hash: commit.target_hash,
+			target_number: commit.target_number,
+			precommits,
+			auth_data: AggregateAuthData { signature, signers },
+		})
+	}
+
+	/// Get the ids of the signers of the precommits, in order. Fails if the
+	/// signers bitfield references positions outside of the voter set or if
+	/// the number of signers doesn't match the number of precommits.
+	pub fn signers<Id: Clone + Eq + Ord>(
+		&self,
+		voters: &VoterSet<Id>,
+	) -> Result<Vec<Id>, AggregateError> {
+		let signers = self
+			.auth_data
+			.signers
+			.iter1s_all()
+			.map(|Bit1 { position }| {
+				voters
+					.nth(position)
+					.map(|(id, _)| id.clone())
+					.ok_or(AggregateError::UnknownVoter)
+			})
+			.collect::<Result<Vec<_>, _>>()?;
+
+		if signers.len() != self.precommits.len() {
+			return Err(AggregateError::SignersMismatch)
+		}
+
+		Ok(signers)
+	}
+
+	/// Verify the aggregate signature of a commit made in round `round` of
+	/// voter set `set_id`.
+	pub fn verify<S, Id, G>(
+		&self,
+		set_id: u64,
+		round: u64,
+		voters: &VoterSet<Id>,
+		aggregation: &G,
+	) -> Result<(), AggregateError>
+	where
+		H: Clone,
+		N: Clone,
+		Id: Clone + Eq + Ord,
+		G: SignatureAggregation<H, N, S, Id, Aggregate = A>,
+	{
+		let precommits =
+			self.precommits.iter().cloned().zip(self.signers(voters)?).collect::<Vec<_>>();
+
+		if aggregation.verify_aggregate(set_id, round, &self.auth_data.signature, &precommits) {
+			Ok(())
+		} else {
+			Err(AggregateError::InvalidSignature)
+		}
+	}
+
+	/// Convert into a commit of the given voter set whose precommits carry
+	/// no signature, e.g. to check it with [`validate_commit`](crate::validate_commit).
+	///
+	/// This is a one-way conversion: individual signatures can't be recovered
+	/// from the aggregate, so the resulting commit can't be aggregated again
+	/// and the aggregate signature should be checked with
+	/// [`AggregateCommit::verify`] beforehand.
+	pub fn into_unsigned_commit<Id: Clone + Eq + Ord>(
+		self,
+		voters: &VoterSet<Id>,
+	) -> Result<Commit<H, N, (), Id>, AggregateError> {
+		let signers = self.signers(voters)?;
+
+		Ok(Commit {
+			target_hash: self.target_hash,
+			target_number: self.target_number,
+			precommits: self
+				.precommits
+				.into_iter()
+				.zip(signers)
+				.map(|(precommit, id)| SignedPrecommit { precommit, signature: (), id })
+				.collect(),
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		testing::chain::{DummyChain, GENESIS_HASH},
+		validate_commit,
+	};
+
+	// signatures are the ids of the signers, aggregates the list of all of them.
+	struct Aggregation;
+
+	impl SignatureAggregation<&'static str, u32, u32, u32> for Aggregation {
+		type Aggregate = Vec<u32>;
+
+		fn aggregate(&self, signatures: &[u32]) -> Option<Vec<u32>> {
+			Some(signatures.to_vec())
+		}
+
+		fn verify_aggregate(
+			&self,
+			_set_id: u64,
+			_round: u64,
+			aggregate: &Vec<u32>,
+			precommits: &[(Precommit<&'static str, u32>, u32)],
+		) -> bool {
+			aggregate.iter().eq(precommits.iter().map(|(_, id)| id))
+		}
+	}
+
+	fn voters() -> VoterSet<u32> {
+		VoterSet::new([(10, 1), (20, 1), (30, 1), (40, 1)].iter().cloned()).expect("nonempty")
+	}
+
+	fn commit(signers: &[u32]) -> Commit<&'static str, u32, u32, u32> {
+		Commit {
+			target_hash: "C",
+			target_number: 4,
+			precommits: signers
+				.iter()
+				.map(|&id| SignedPrecommit { precommit: Precommit::new("C", 4), signature: id, id })
+				.collect(),
+		}
+	}
+
+	#[test]
+	fn aggregates_commit() {
+		let voters = voters();
+		let aggregate =
+			AggregateCommit::from_commit(commit(&[30, 10, 40]), &voters, &Aggregation).unwrap();
+
+		// precommits and signatures are ordered by the signers' position.
+		assert_eq!(aggregate.auth_data.signature, vec![10, 30, 40]);
+		assert_eq!(aggregate.signers(&voters), Ok(vec![10, 30, 40]));
+		assert_eq!(aggregate.verify(0, 1, &voters, &Aggregation), Ok(()));
+
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C"]);
+
+		let commit = aggregate.into_unsigned_commit(&voters).unwrap();
+		let result = validate_commit(&commit, &voters, &chain).unwrap();
+		assert_eq!(result.ghost(), Some(&("C", 4)));
+	}
+
+	#[test]
+	fn rejects_precommits_from_unknown_or_duplicate_voters() {
+		let voters = voters();
+
+		assert_eq!(
+			AggregateCommit::from_commit(commit(&[10, 50]), &voters, &Aggregation),
+			Err(AggregateError::UnknownVoter),
+		);
+		assert_eq!(
+			AggregateCommit::from_commit(commit(&[10, 20, 10]), &voters, &Aggregation),
+			Err(AggregateError::DuplicateVoter),
+		);
+	}
+
+	#[test]
+	fn rejects_signers_outside_of_voter_set() {
+		let voters = voters();
+		let mut aggregate =
+			AggregateCommit::from_commit(commit(&[10, 20, 30]), &voters, &Aggregation).unwrap();
+
+		// there's no fifth voter.
+		aggregate.auth_data.signers.set_bit(4);
+		aggregate.precommits.push(Precommit::new("C", 4));
+
+		assert_eq!(aggregate.signers(&voters), Err(AggregateError::UnknownVoter));
+		assert_eq!(
+			aggregate.verify(0, 1, &voters, &Aggregation),
+			Err(AggregateError::UnknownVoter)
+		);
+		assert_eq!(aggregate.into_unsigned_commit(&voters), Err(AggregateError::UnknownVoter));
+	}
+
+	#[test]
+	fn rejects_mismatching_signers_or_signature() {
+		let voters = voters();
+		let aggregate =
+			AggregateCommit::from_commit(commit(&[10, 20, 30]), &voters, &Aggregation).unwrap();
+
+		let mut missing_signer = aggregate.clone();
+		missing_signer.precommits.push(Precommit::new("C", 4));
+		assert_eq!(missing_signer.signers(&voters), Err(AggregateError::SignersMismatch));
+
+		let mut invalid_signature = aggregate;
+		invalid_signature.auth_data.signature = vec![10, 20, 40];
+		assert_eq!(
+			invalid_signature.verify(0, 1, &voters, &Aggregation),
+			Err(AggregateError::InvalidSignature),
+		);
+	}
+}
@@ -20,8 +20,14 @@
 use crate::std::{cmp::Ordering, iter, ops::BitOr, vec::Vec};
 use either::Either;
 
+#[cfg(feature = "derive-codec")]
+use parity_scale_codec::{Decode, Encode};
+#[cfg(feature = "derive-codec")]
+use scale_info::TypeInfo;
+
 /// A dynamically sized, write-once (per bit), lazily allocating bitfield.
 #[derive(Eq, PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "derive-codec", derive(Encode, Decode, TypeInfo))]
 pub struct Bitfield {
 	bits: Vec<u64>,
 }
@@ -88,6 +94,22 @@ impl Bitfield {
 		self
 	}
 
+	/// Whether the bitfield, as allocated, holds no more than `len` bits
+	/// (rounded up to whole words) and has no bit set at or beyond `len`.
+	#[cfg(feature = "derive-codec")]
+	pub(crate) fn fits(&self, len: usize) -> bool {
+		self.bits.len() * 64 < len + 64 && self.iter1s_all().all(|bit| bit.position < len)
+	}
+
+	/// Whether all the bits set in the other bitfield are set in this one.
+	#[cfg(feature = "derive-codec")]
+	pub(crate) fn contains(&self, other: &Self) -> bool {
+		other.bits.iter().enumerate().all(|(i, word)| {
+			let own = self.bits.get(i).cloned().unwrap_or(0);
+			own & word == *word
+		})
+	}
+
 	/// Test if the bit at the specified position is set.
 	#[cfg(test)]
 	pub fn test_bit(&self, position: usize) -> bool {
@@ -100,6 +122,11 @@ impl Bitfield {
 		test_bit(self.bits[word_off], position % 64)
 	}
 
+	/// Get an iterator over all bits that are set (i.e. 1).
+	pub fn iter1s_all(&self) -> impl Iterator<Item = Bit1> + '_ {
+		self.iter1s(0, 0)
+	}
+
 	/// Get an iterator over all bits that are set (i.e. 1) at even bit positions.
 	pub fn iter1s_even(&self) -> impl Iterator<Item = Bit1> + '_ {
 		self.iter1s(0, 1)
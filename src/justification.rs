@@ -0,0 +1,330 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Finality proofs, verifiable without access to the full chain.
+//!
+//! A justification bundles a commit together with the headers needed to prove
+//! that every precommit in it is for a descendent of the commit target. This
+//! allows light clients to check that a block was finalized by a given voter
+//! set without a `Chain` implementation backed by a database.
+
+use crate::{
+	std::{
+		self,
+		collections::{BTreeMap, BTreeSet},
+		vec::Vec,
+	},
+	validate_commit,
+	voter_set::VoterSet,
+	BlockNumberOps, Chain, Commit, Error,
+};
+
+#[cfg(feature = "derive-codec")]
+use parity_scale_codec::{Decode, Encode};
+#[cfg(feature = "derive-codec")]
+use scale_info::TypeInfo;
+
+/// A block header in the ancestry of the precommits of a justification.
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
+#[cfg_attr(feature = "derive-codec", derive(Encode, Decode, TypeInfo))]
+pub struct AncestryEntry<H, N> {
+	/// The block hash.
+	pub hash: H,
+	/// The block number.
+	pub number: N,
+	/// The hash of the parent block.
+	pub parent_hash: H,
+}
+
+/// A GRANDPA justification for block finality, it includes a commit message and
+/// an ancestry proof including all headers routing all precommit target blocks
+/// to the commit target block.
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
+#[cfg_attr(feature = "derive-codec", derive(Encode, Decode, TypeInfo))]
+pub struct GrandpaJustification<H, N, S, Id> {
+	/// The round in which the commit was made.
+	pub round: u64,
+	/// The commit message.
+	pub commit: Commit<H, N, S, Id>,
+	/// The headers between the commit target and the precommit targets,
+	/// excluding the commit target itself.
+	pub votes_ancestries: Vec<AncestryEntry<H, N>>,
+}
+
+/// Errors that can occur when verifying a justification.
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
+pub enum JustificationError {
+	/// A precommit target isn't proven to be the commit target or one of its
+	/// descendents by the ancestry.
+	PrecommitNotCovered,
+	/// The ancestry includes headers which aren't needed to prove the ancestry
+	/// of any precommit.
+	UnusedAncestry,
+	/// A header in the ancestry doesn't have a number one greater than its
+	/// parent's.
+	InvalidAncestryNumber,
+	/// The precommits don't have enough weight to finalize the commit target.
+	InvalidCommit,
+}
+
+#[cfg(feature = "std")]
+impl std::fmt::Display for JustificationError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match *self {
+			JustificationError::PrecommitNotCovered =>
+				write!(f, "Precommit target not covered by the justification ancestry"),
+			JustificationError::UnusedAncestry =>
+				write!(f, "Justification ancestry contains unused headers"),
+			JustificationError::InvalidAncestryNumber =>
+				write!(f, "Justification ancestry header number doesn't follow its parent"),
+			JustificationError::InvalidCommit =>
+				write!(f, "Justification commit doesn't finalize its target"),
+		}
+	}
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for JustificationError {}
+
+impl<H, N, S, Id> GrandpaJustification<H, N, S, Id>
+where
+	H: Clone + Eq + Ord + std::fmt::Debug,
+	N: Copy + BlockNumberOps + std::fmt::Debug,
+	S: Clone + Eq,
+	Id: Clone + Eq + Ord + std::fmt::Debug,
+{
+	/// Verify that the justification proves the finality of the commit target
+	/// by the given voter set.
+	///
+	/// Every precommit target must be routed to the commit target by the
+	/// embedded ancestry and every header in the ancestry must be used in doing
+	/// so. The commit is then validated with the same logic as
+	/// [`validate_commit`], using the ancestry as chain.
+	///
+	/// Signatures on precommits are assumed to have been checked.
+	pub fn verify(&self, voters: &VoterSet<Id>) -> Result<(), JustificationError> {
+		let chain = AncestryChain::new(&self.votes_ancestries);
+		let commit_target = (self.commit.target_hash.clone(), self.commit.target_number);
+
+		let mut visited = BTreeSet::new();
+		for signed in &self.commit.precommits {
+			let target = (signed.precommit.target_hash.clone(), signed.precommit.target_number);
+			if target == commit_target {
+				continue
+			}
+
+			// the ancestry must agree with the precommit on the block number.
+			match chain.ancestry.get(&target.0) {
+				Some(entry) if entry.number == target.1 => {},
+				_ => return Err(JustificationError::PrecommitNotCovered),
+			}
+
+			let route = chain
+				.ancestry(commit_target.0.clone(), target.0.clone())
+				.map_err(|_| JustificationError::PrecommitNotCovered)?;
+
+			// every header on the route must be numbered one above its parent,
+			// down to the commit target.
+			let mut child_number = target.1;
+			for hash in &route {
+				let parent =
+					chain.ancestry.get(hash).ok_or(JustificationError::PrecommitNotCovered)?;
+				if !is_child_number(child_number, parent.number) {
+					return Err(JustificationError::InvalidAncestryNumber)
+				}
+				child_number = parent.number;
+			}
+			if !is_child_number(child_number, commit_target.1) {
+				return Err(JustificationError::InvalidAncestryNumber)
+			}
+
+			visited.insert(target.0);
+			visited.extend(route);
+		}
+
+		if visited.len() != self.votes_ancestries.len() {
+			return Err(JustificationError::UnusedAncestry)
+		}
+
+		match validate_commit(&self.commit, voters, &chain) {
+			Ok(result) if result.ghost().is_some() => Ok(()),
+			_ => Err(JustificationError::InvalidCommit),
+		}
+	}
+}
+
+fn is_child_number<N: Copy + BlockNumberOps>(child: N, parent: N) -> bool {
+	child > parent && child - parent == N::one()
+}
+
+// A `Chain` backed by the ancestry headers of a justification.
+struct AncestryChain<'a, H, N> {
+	ancestry: BTreeMap<H, &'a AncestryEntry<H, N>>,
+}
+
+impl<'a, H: Clone + Ord, N> AncestryChain<'a, H, N> {
+	fn new(ancestry: &'a [AncestryEntry<H, N>]) -> Self {
+		AncestryChain {
+			ancestry: ancestry.iter().map(|entry| (entry.hash.clone(), entry)).collect(),
+		}
+	}
+}
+
+impl<'a, H, N> Chain<H, N> for AncestryChain<'a, H, N>
+where
+	H: Clone + Eq + Ord,
+	N: Copy + BlockNumberOps,
+{
+	fn ancestry(&self, base: H, block: H) -> Result<Vec<H>, Error> {
+		let mut route = Vec::new();
+		let mut current_hash = block;
+
+		while current_hash != base {
+			// guard against cycles in the ancestry.
+			if route.len() > self.ancestry.len() {
+				return Err(Error::NotDescendent)
+			}
+
+			match self.ancestry.get(&current_hash) {
+				Some(entry) => {
+					current_hash = entry.parent_hash.clone();
+					route.push(current_hash.clone());
+				},
+				None => return Err(Error::NotDescendent),
+			}
+		}
+
+		// remove the base.
+		route.pop();
+
+		Ok(route)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{Precommit, SignedPrecommit};
+
+	fn voters() -> VoterSet<&'static str> {
+		VoterSet::new([("Alice", 4), ("Bob", 7), ("Eve", 3)].iter().cloned()).expect("nonempty")
+	}
+
+	#[derive(PartialEq, Eq, Clone, Debug)]
+	struct Signature(&'static str);
+
+	fn entry(
+		hash: &'static str,
+		number: u32,
+		parent_hash: &'static str,
+	) -> AncestryEntry<&'static str, u32> {
+		AncestryEntry { hash, number, parent_hash }
+	}
+
+	fn precommit(
+		target_hash: &'static str,
+		target_number: u32,
+		id: &'static str,
+	) -> SignedPrecommit<&'static str, u32, Signature, &'static str> {
+		SignedPrecommit {
+			precommit: Precommit::new(target_hash, target_number),
+			signature: Signature(id),
+			id,
+		}
+	}
+
+	// a justification for "C" with precommits on two forks on top of it.
+	fn justification() -> GrandpaJustification<&'static str, u32, Signature, &'static str> {
+		GrandpaJustification {
+			round: 1,
+			commit: Commit {
+				target_hash: "C",
+				target_number: 3,
+				precommits: vec![
+					precommit("C", 3, "Alice"),
+					precommit("E", 5, "Bob"),
+					precommit("DA", 4, "Eve"),
+				],
+			},
+			votes_ancestries: vec![entry("D", 4, "C"), entry("E", 5, "D"), entry("DA", 4, "C")],
+		}
+	}
+
+	#[test]
+	fn valid_justification() {
+		assert_eq!(justification().verify(&voters()), Ok(()));
+	}
+
+	#[test]
+	fn rejects_precommit_without_ancestry() {
+		let mut justification = justification();
+		justification.votes_ancestries.retain(|entry| entry.hash != "D");
+
+		assert_eq!(justification.verify(&voters()), Err(JustificationError::PrecommitNotCovered));
+
+		// the ancestry must also agree on the number of the precommit target.
+		let mut justification = self::justification();
+		justification.commit.precommits[1].precommit.target_number = 6;
+
+		assert_eq!(justification.verify(&voters()), Err(JustificationError::PrecommitNotCovered));
+	}
+
+	#[test]
+	fn rejects_unused_ancestry() {
+		let mut justification = justification();
+		justification.votes_ancestries.push(entry("F", 6, "E"));
+
+		assert_eq!(justification.verify(&voters()), Err(JustificationError::UnusedAncestry));
+	}
+
+	#[test]
+	fn rejects_commit_without_enough_weight() {
+		let mut justification = justification();
+		justification.commit.precommits.retain(|signed| signed.id != "Bob");
+		justification.votes_ancestries.retain(|entry| entry.hash == "DA");
+
+		assert_eq!(justification.verify(&voters()), Err(JustificationError::InvalidCommit));
+	}
+
+	#[test]
+	fn rejects_ancestry_with_forged_numbers() {
+		let justification = GrandpaJustification {
+			round: 1,
+			commit: Commit {
+				target_hash: "C",
+				target_number: 3,
+				precommits: vec![
+					precommit("C", 3, "Alice"),
+					precommit("E", 9, "Bob"),
+					precommit("F", 4, "Eve"),
+				],
+			},
+			votes_ancestries: vec![entry("E", 9, "C"), entry("F", 4, "E")],
+		};
+
+		assert_eq!(justification.verify(&voters()), Err(JustificationError::InvalidAncestryNumber));
+	}
+
+	#[test]
+	fn rejects_cyclic_ancestry() {
+		let mut justification = justification();
+		justification.votes_ancestries =
+			vec![entry("D", 4, "E"), entry("E", 5, "D"), entry("DA", 4, "C")];
+
+		assert_eq!(justification.verify(&voters()), Err(JustificationError::PrecommitNotCovered));
+	}
+}
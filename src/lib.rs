@@ -34,15 +34,41 @@ extern crate alloc;
 #[cfg(feature = "std")]
 extern crate std;
 
+// logging is only available with `std`. the macros don't log anything
+// otherwise, but still use their arguments so that the bindings only used for
+// logging aren't reported as unused.
+#[cfg(not(feature = "std"))]
+macro_rules! trace {
+	(target: $target:expr, $($arg:expr),+ $(,)?) => {
+		if false {
+			$(let _ = &$arg;)+
+		}
+	};
+}
+#[cfg(not(feature = "std"))]
+macro_rules! debug {
+	($($arg:tt)*) => {
+		trace!($($arg)*)
+	};
+}
+#[cfg(not(feature = "std"))]
+macro_rules! warn {
+	($($arg:tt)*) => {
+		trace!($($arg)*)
+	};
+}
+
+pub mod accountable_safety;
+pub mod aggregate;
+mod bitfield;
+pub mod justification;
 pub mod round;
+#[cfg(feature = "test-helpers")]
+pub mod simulation;
 pub mod vote_graph;
-#[cfg(feature = "std")]
 pub mod voter;
 pub mod voter_set;
 
-mod bitfield;
-#[cfg(feature = "std")]
-mod bridge_state;
 #[cfg(any(test, feature = "fuzz-helpers"))]
 pub mod fuzz_helpers;
 #[cfg(any(test))]
@@ -50,7 +76,11 @@ mod testing;
 mod weights;
 #[cfg(not(feature = "std"))]
 mod std {
-	pub use core::{cmp, hash, iter, mem, num, ops};
+	pub use core::{cmp, hash, iter, marker, mem, num, ops, slice};
+
+	pub mod boxed {
+		pub use alloc::boxed::Box;
+	}
 
 	pub mod vec {
 		pub use alloc::vec::Vec;
@@ -60,6 +90,7 @@ mod std {
 		pub use alloc::collections::{
 			btree_map::{self, BTreeMap},
 			btree_set::{self, BTreeSet},
+			vec_deque::VecDeque,
 		};
 	}
 
@@ -71,6 +102,8 @@ mod std {
 	}
 }
 
+pub use bitfield::Bitfield;
+
 use crate::{std::vec::Vec, voter_set::VoterSet};
 #[cfg(feature = "derive-codec")]
 use parity_scale_codec::{Decode, Encode};
@@ -194,13 +227,53 @@ pub trait Chain<H: Eq, N: Copy + BlockNumberOps> {
 			return true
 		}
 
-		// TODO: currently this function always succeeds since the only error
-		// variant is `Error::NotDescendent`, this may change in the future as
+		// TODO: currently this function always succeeds since `ancestry` only
+		// fails with `Error::NotDescendent`, this may change in the future as
 		// other errors (e.g. IO) are not being exposed.
-		match self.ancestry(base, block) {
-			Ok(_) => true,
-			Err(Error::NotDescendent) => false,
-		}
+		self.ancestry(base, block).is_ok()
+	}
+
+	/// Get the hash of the ancestor of `block` with the given number, or of
+	/// `block` itself if that is its number.
+	///
+	/// Returns `None` if there is no such block, or if the chain can't look up
+	/// blocks by number. The latter is the default, and such chains can only
+	/// be used with vote-graphs keeping the full ancestry of their vote-nodes,
+	/// see [`vote_graph::VoteGraph::new_compressed`].
+	fn hash_at(&self, _block: H, _number: N) -> Option<H> {
+		None
+	}
+}
+
+/// Verification of the signatures on GRANDPA messages.
+///
+/// Signatures are made over a message localized to the round and voter set
+/// it was cast in, so implementations are given both alongside the message.
+pub trait SignatureVerifier<H, N, S, Id> {
+	/// Returns true if `signature` is a valid signature by `id` on the given
+	/// message, cast in round `round` of voter set `set_id`.
+	fn verify(
+		&self,
+		set_id: u64,
+		round: u64,
+		message: &Message<H, N>,
+		signature: &S,
+		id: &Id,
+	) -> bool;
+
+	/// Returns true if the signatures on all precommits of the commit are valid.
+	///
+	/// By default every signature is checked individually with [`Self::verify`],
+	/// implementations supporting batch verification should override this.
+	fn verify_commit(&self, set_id: u64, round: u64, commit: &Commit<H, N, S, Id>) -> bool
+	where
+		H: Clone,
+		N: Clone,
+	{
+		commit.precommits.iter().all(|signed| {
+			let message = Message::Precommit(signed.precommit.clone());
+			self.verify(set_id, round, &message, &signed.signature, &signed.id)
+		})
 	}
 }
 
@@ -218,6 +291,83 @@ pub struct Equivocation<Id, V, S> {
 	pub second: (V, S),
 }
 
+impl<Id: Ord, V, S> Equivocation<Id, V, S> {
+	/// Verify the equivocation proof, cast in voter set `set_id`: the votes
+	/// must be for different targets, by a voter in the set, and both
+	/// signatures must be valid.
+	pub fn verify<H, N>(
+		&self,
+		voters: &VoterSet<Id>,
+		set_id: u64,
+		verifier: &dyn SignatureVerifier<H, N, S, Id>,
+	) -> Result<(), EquivocationError>
+	where
+		V: Clone + Into<Message<H, N>>,
+		H: Eq,
+		N: Copy + Eq,
+	{
+		if !voters.contains(&self.identity) {
+			return Err(EquivocationError::UnknownVoter)
+		}
+
+		let first: Message<H, N> = self.first.0.clone().into();
+		let second: Message<H, N> = self.second.0.clone().into();
+		if first.target() == second.target() {
+			return Err(EquivocationError::SameTarget)
+		}
+
+		for (message, signature) in [(first, &self.first.1), (second, &self.second.1)].iter() {
+			if !verifier.verify(set_id, self.round_number, message, signature, &self.identity) {
+				return Err(EquivocationError::InvalidSignature)
+			}
+		}
+
+		Ok(())
+	}
+}
+
+/// Errors that can occur when verifying an equivocation proof.
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
+pub enum EquivocationError {
+	/// The equivocator isn't part of the voter set.
+	UnknownVoter,
+	/// Both votes are for the same target.
+	SameTarget,
+	/// The signature on one of the votes is invalid.
+	InvalidSignature,
+}
+
+#[cfg(feature = "std")]
+impl std::fmt::Display for EquivocationError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match *self {
+			EquivocationError::UnknownVoter => write!(f, "Equivocator not in the voter set"),
+			EquivocationError::SameTarget => write!(f, "Equivocated votes have the same target"),
+			EquivocationError::InvalidSignature =>
+				write!(f, "Invalid signature on an equivocated vote"),
+		}
+	}
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for EquivocationError {}
+
+/// Encode the payload signed by a voter casting the given message in round
+/// `round` of voter set `set_id`, i.e. the SCALE encoding of
+/// `(message, round, set_id)`.
+///
+/// Signers and [`SignatureVerifier`] implementations should use this encoding,
+/// so that votes and equivocation proofs can be checked by other parties.
+#[cfg(feature = "derive-codec")]
+pub fn localized_payload<H: Encode, N: Encode>(
+	round: u64,
+	set_id: u64,
+	message: &Message<H, N>,
+) -> Vec<u8> {
+	(message, round, set_id).encode()
+}
+
 /// A protocol message or vote.
 #[derive(Clone, PartialEq, Eq)]
 #[cfg_attr(any(feature = "std", test), derive(Debug))]
@@ -234,6 +384,18 @@ pub enum Message<H, N> {
 	PrimaryPropose(PrimaryPropose<H, N>),
 }
 
+impl<H, N> From<Prevote<H, N>> for Message<H, N> {
+	fn from(prevote: Prevote<H, N>) -> Self {
+		Message::Prevote(prevote)
+	}
+}
+
+impl<H, N> From<Precommit<H, N>> for Message<H, N> {
+	fn from(precommit: Precommit<H, N>) -> Self {
+		Message::Precommit(precommit)
+	}
+}
+
 impl<H, N: Copy> Message<H, N> {
 	/// Get the target block of the vote.
 	pub fn target(&self) -> (&H, N) {
@@ -330,6 +492,8 @@ pub struct CompactCommit<H, N, S, Id> {
 #[cfg_attr(any(feature = "std", test), derive(Debug))]
 #[cfg_attr(feature = "derive-codec", derive(Encode, Decode, TypeInfo))]
 pub struct CatchUp<H, N, S, Id> {
+	/// The voter set id the round belongs to.
+	pub set_id: u64,
 	/// Round number.
 	pub round_number: u64,
 	/// Prevotes for target block or any block after it that justify this catch-up.
@@ -342,8 +506,8 @@ pub struct CatchUp<H, N, S, Id> {
 	pub base_number: N,
 }
 
-/// Authentication data for a set of many messages, currently a set of precommit signatures but
-/// in the future could be optimized with BLS signature aggregation.
+/// Authentication data for a set of many messages, a set of precommit signatures. See
+/// [`aggregate::AggregateCommit`] for commits authenticated by an aggregate signature instead.
 pub type MultiAuthData<S, Id> = Vec<(S, Id)>;
 
 impl<H, N, S, Id> From<CompactCommit<H, N, S, Id>> for Commit<H, N, S, Id> {
@@ -431,7 +595,8 @@ impl<H, N> Default for CommitValidationResult<H, N> {
 /// the precommits in the commit message and using the commit target as a
 /// base.
 ///
-/// Signatures on precommits are assumed to have been checked.
+/// Signatures on precommits are assumed to have been checked, see
+/// [`validate_commit_with_verifier`] otherwise.
 ///
 /// Duplicate votes or votes from voters not in the voter-set will be ignored, but it is recommended
 /// for the caller of this function to remove those at signature-verification time.
@@ -499,6 +664,37 @@ where
 	Ok(validation_result)
 }
 
+/// Validates a GRANDPA commit message made in the given round of voter set
+/// `set_id`, like [`validate_commit`], but also checks the signatures on the
+/// precommits with the given verifier.
+///
+/// A commit with any invalid signature is rejected as a whole.
+pub fn validate_commit_with_verifier<H, N, S, I, C, V>(
+	commit: &Commit<H, N, S, I>,
+	set_id: u64,
+	round: u64,
+	voters: &VoterSet<I>,
+	chain: &C,
+	verifier: &V,
+) -> Result<CommitValidationResult<H, N>, crate::Error>
+where
+	H: Clone + Eq + Ord + std::fmt::Debug,
+	N: Copy + BlockNumberOps + std::fmt::Debug,
+	I: Clone + Ord + Eq + std::fmt::Debug,
+	S: Clone + Eq,
+	C: Chain<H, N>,
+	V: SignatureVerifier<H, N, S, I> + ?Sized,
+{
+	if !verifier.verify_commit(set_id, round, commit) {
+		return Ok(CommitValidationResult {
+			num_precommits: commit.precommits.len(),
+			..Default::default()
+		})
+	}
+
+	validate_commit(commit, voters, chain)
+}
+
 /// Runs the callback with the appropriate `CommitProcessingOutcome` based on
 /// the given `CommitValidationResult`. Outcome is bad if ghost is undefined,
 /// good otherwise.
@@ -575,6 +771,23 @@ impl<H, N, S, Id> HistoricalVotes<H, N, S, Id> {
 
 #[cfg(test)]
 mod tests {
+	use crate::{Message, SignatureVerifier};
+
+	// a signature is valid if it's the signer's id.
+	struct Verifier;
+
+	impl SignatureVerifier<&'static str, u32, u32, u32> for Verifier {
+		fn verify(
+			&self,
+			_set_id: u64,
+			_round: u64,
+			_message: &Message<&'static str, u32>,
+			signature: &u32,
+			id: &u32,
+		) -> bool {
+			signature == id
+		}
+	}
 
 	#[cfg(feature = "derive-codec")]
 	#[test]
@@ -594,4 +807,92 @@ mod tests {
 		let signed2 = crate::SignedMessage::decode(&mut &encoded[..]).unwrap();
 		assert_eq!(signed, signed2);
 	}
+
+	#[test]
+	fn validate_commit_with_verifier_rejects_invalid_signatures() {
+		use crate::{
+			testing::chain::{DummyChain, GENESIS_HASH},
+			voter_set::VoterSet,
+			Commit, Precommit, SignedPrecommit,
+		};
+
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C"]);
+
+		let voters = VoterSet::new((0..3).map(|i| (i, 1))).expect("nonempty");
+		let commit = |signatures: [u32; 3]| Commit {
+			target_hash: "C",
+			target_number: 4,
+			precommits: (0..3)
+				.map(|i| SignedPrecommit {
+					precommit: Precommit::new("C", 4),
+					signature: signatures[i as usize],
+					id: i,
+				})
+				.collect(),
+		};
+
+		let result = crate::validate_commit_with_verifier(
+			&commit([0, 1, 2]),
+			0,
+			1,
+			&voters,
+			&chain,
+			&Verifier,
+		)
+		.unwrap();
+		assert_eq!(result.ghost(), Some(&("C", 4)));
+
+		let result = crate::validate_commit_with_verifier(
+			&commit([0, 1, 0]),
+			0,
+			1,
+			&voters,
+			&chain,
+			&Verifier,
+		)
+		.unwrap();
+		assert_eq!(result.ghost(), None);
+		assert_eq!(result.num_precommits(), 3);
+	}
+
+	#[test]
+	fn verifies_equivocation_proofs() {
+		use crate::{voter_set::VoterSet, Equivocation, EquivocationError, Prevote};
+
+		let voters = VoterSet::new((0..3).map(|i| (i, 1))).expect("nonempty");
+		// an equivocation of `identity` between prevotes for "C" and `second`.
+		let equivocation =
+			|identity: u32, second: &'static str, second_signature: u32| Equivocation {
+				round_number: 1,
+				identity,
+				first: (Prevote::new("C", 4), identity),
+				second: (Prevote::new(second, 4), second_signature),
+			};
+
+		assert_eq!(equivocation(1, "D", 1).verify(&voters, 0, &Verifier), Ok(()));
+		assert_eq!(
+			equivocation(5, "D", 5).verify(&voters, 0, &Verifier),
+			Err(EquivocationError::UnknownVoter),
+		);
+		assert_eq!(
+			equivocation(1, "C", 1).verify(&voters, 0, &Verifier),
+			Err(EquivocationError::SameTarget),
+		);
+		assert_eq!(
+			equivocation(1, "D", 2).verify(&voters, 0, &Verifier),
+			Err(EquivocationError::InvalidSignature),
+		);
+	}
+
+	#[cfg(feature = "derive-codec")]
+	#[test]
+	fn localized_payload_encoding_is_stable() {
+		let message = Message::Precommit(crate::Precommit::new(1u32, 2u32));
+
+		assert_eq!(
+			crate::localized_payload(3, 4, &message),
+			vec![1, 1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0],
+		);
+	}
 }
@@ -21,14 +21,20 @@ use context::{Context, Vote, VoteNode};
 #[cfg(feature = "derive-codec")]
 use parity_scale_codec::{Decode, Encode};
 
+#[cfg(feature = "derive-codec")]
+use crate::bitfield::Bitfield;
+
 use crate::{
 	std::{
 		self,
 		collections::btree_map::{BTreeMap, Entry},
-		fmt,
+		fmt, slice,
 		vec::Vec,
 	},
-	vote_graph::VoteGraph,
+	vote_graph::{
+		without_chain, AncestryKind, CompressedAncestry, ExportedGraph, FullAncestry,
+		GhostCheckpoint, NoChain, VoteGraph,
+	},
 	voter_set::{VoterInfo, VoterSet},
 	weights::{VoteWeight, VoterWeight},
 };
@@ -49,6 +55,8 @@ pub enum Phase {
 }
 
 /// The observed vote from a single voter.
+#[derive(Clone)]
+#[cfg_attr(feature = "derive-codec", derive(Encode, Decode, scale_info::TypeInfo))]
 enum VoteMultiplicity<Vote, Signature> {
 	/// A single vote has been observed from the voter.
 	Single(Vote, Signature),
@@ -85,6 +93,21 @@ impl<Id: Ord + Eq + Clone, Vote: Clone + Eq, Signature: Clone + Eq>
 		VoteTracker { votes: BTreeMap::new(), current_weight: VoteWeight(0) }
 	}
 
+	// restore a tracker from the votes of a snapshot, returning `None` if any
+	// of them is cast by a voter outside the given set.
+	#[cfg(feature = "derive-codec")]
+	fn restore(
+		votes: BTreeMap<Id, VoteMultiplicity<Vote, Signature>>,
+		voters: &VoterSet<Id>,
+	) -> Option<Self> {
+		let mut current_weight = VoteWeight(0);
+		for id in votes.keys() {
+			current_weight = current_weight + voters.get(id)?.weight();
+		}
+
+		Some(VoteTracker { votes, current_weight })
+	}
+
 	// track a vote, returning a value containing the multiplicity of all votes from this ID
 	// and a bool indicating if the vote is duplicated.
 	// if the vote is the first equivocation, returns a value indicating
@@ -189,11 +212,13 @@ pub struct RoundParams<Id: Ord + Eq, H, N> {
 	pub base: (H, N),
 }
 
-/// Stores data for a round.
-pub struct Round<Id: Ord + Eq, H: Ord + Eq, N, Signature> {
+/// Stores data for a round. The vote-graph of the round keeps the blocks between
+/// the targets of the votes unless `A` is [`CompressedAncestry`], see
+/// [`Round::new_compressed`].
+pub struct Round<Id: Ord + Eq, H: Ord + Eq, N, Signature, A = FullAncestry> {
 	round_number: u64,
 	context: Context<Id>,
-	graph: VoteGraph<H, N, VoteNode>, // DAG of blocks which have been voted on.
+	graph: VoteGraph<H, N, VoteNode, A>, // DAG of blocks which have been voted on.
 	prevote: VoteTracker<Id, Prevote<H, N>, Signature>, // tracks prevotes that have been counted
 	precommit: VoteTracker<Id, Precommit<H, N>, Signature>, // tracks precommits
 	historical_votes: HistoricalVotes<H, N, Signature, Id>,
@@ -202,24 +227,330 @@ pub struct Round<Id: Ord + Eq, H: Ord + Eq, N, Signature> {
 	finalized: Option<(H, N)>,       // best finalized block in this round.
 	estimate: Option<(H, N)>,        // current memoized round-estimate
 	completable: bool,               // whether the round is completable
+	// where the last prevote-GHOST and precommit-GHOST searches started from.
+	prevote_ghost_checkpoint: Option<GhostCheckpoint<H>>,
+	precommit_ghost_checkpoint: Option<GhostCheckpoint<H>>,
 }
 
-/// Result of importing a Prevote or Precommit.
-pub(crate) struct ImportResult<Id, P, Signature> {
-	/// Indicates if the voter is part of the voter set.
+/// Result of importing a Prevote or Precommit, see [`Round::import_prevote`]
+/// and [`Round::import_precommit`].
+#[derive(Clone, PartialEq)]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
+pub struct ImportResult<Id, P, Signature> {
+	_priv: (), // lets us add stuff without breaking API.
 	pub(crate) valid_voter: bool,
-	/// Indicates if the vote is duplicated.
 	pub(crate) duplicated: bool,
-	/// An equivocation proof, if the vote is an equivocation.
 	pub(crate) equivocation: Option<Equivocation<Id, P, Signature>>,
+	pub(crate) prevote_ghost_changed: bool,
+	pub(crate) estimate_changed: bool,
+	pub(crate) finalized_changed: bool,
+	pub(crate) completable_changed: bool,
 }
 
 impl<Id, P, Signature> Default for ImportResult<Id, P, Signature> {
 	fn default() -> Self {
-		ImportResult { valid_voter: false, duplicated: false, equivocation: None }
+		ImportResult {
+			_priv: (),
+			valid_voter: false,
+			duplicated: false,
+			equivocation: None,
+			prevote_ghost_changed: false,
+			estimate_changed: false,
+			finalized_changed: false,
+			completable_changed: false,
+		}
+	}
+}
+
+impl<Id, P, Signature> ImportResult<Id, P, Signature> {
+	/// Whether the voter is part of the voter set.
+	pub fn valid_voter(&self) -> bool {
+		self.valid_voter
+	}
+
+	/// Whether the vote is duplicated.
+	pub fn duplicated(&self) -> bool {
+		self.duplicated
+	}
+
+	/// The equivocation proof, if the vote is an equivocation.
+	pub fn equivocation(&self) -> Option<&Equivocation<Id, P, Signature>> {
+		self.equivocation.as_ref()
+	}
+
+	/// Take the equivocation proof, if the vote is an equivocation.
+	pub fn into_equivocation(self) -> Option<Equivocation<Id, P, Signature>> {
+		self.equivocation
+	}
+
+	/// Whether the prevote-GHOST of the round changed.
+	pub fn prevote_ghost_changed(&self) -> bool {
+		self.prevote_ghost_changed
+	}
+
+	/// Whether the estimate of the round changed.
+	pub fn estimate_changed(&self) -> bool {
+		self.estimate_changed
+	}
+
+	/// Whether the block finalized in the round changed.
+	pub fn finalized_changed(&self) -> bool {
+		self.finalized_changed
+	}
+
+	/// Whether the round became completable.
+	pub fn completable_changed(&self) -> bool {
+		self.completable_changed
 	}
 }
 
+/// The prevote and precommit weight on a vote-node, accounting for
+/// equivocations.
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
+pub struct NodeWeight {
+	/// The prevote weight.
+	pub prevote: u64,
+	/// The precommit weight.
+	pub precommit: u64,
+}
+
+/// A block of interest in the vote-graph of a round.
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
+pub enum HighlightKind {
+	/// The prevote-GHOST of the round.
+	PrevoteGhost,
+	/// The estimate of the round.
+	Estimate,
+	/// The block finalized in the round.
+	Finalized,
+}
+
+/// A highlighted block in the vote-graph of a round. The block isn't
+/// necessarily a vote-node, it can be on the compressed edge of some.
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
+pub struct GraphHighlight<H, N> {
+	/// Why the block is highlighted.
+	pub kind: HighlightKind,
+	/// The block.
+	pub block: (H, N),
+	/// The vote-nodes which are the block or have it on their compressed edge.
+	pub nodes: Vec<H>,
+}
+
+/// The vote-graph of a round, see [`Round::export_graph`].
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
+pub struct RoundGraph<H, N> {
+	/// The vote-nodes with their prevote and precommit weight.
+	pub graph: ExportedGraph<H, N, NodeWeight>,
+	/// The prevote-GHOST, estimate and finalized blocks, if any.
+	pub highlights: Vec<GraphHighlight<H, N>>,
+}
+
+#[cfg(feature = "std")]
+impl<H: fmt::Debug + Eq, N: fmt::Display> RoundGraph<H, N> {
+	/// Render the vote-graph in the Graphviz DOT format. Vote-nodes are
+	/// labelled with their number and weights, edges with their length, and
+	/// highlighted blocks are filled, or drawn apart when they aren't
+	/// vote-nodes.
+	pub fn to_dot(&self) -> String {
+		use std::fmt::Write;
+
+		// the debug output of a hash, escaped to be quoted.
+		fn escaped<H: fmt::Debug>(hash: &H) -> String {
+			format!("{:?}", hash).replace('\\', "\\\\").replace('"', "\\\"")
+		}
+
+		fn id<H: fmt::Debug>(hash: &H) -> String {
+			format!("\"{}\"", escaped(hash))
+		}
+
+		fn style(kind: HighlightKind) -> (&'static str, &'static str) {
+			match kind {
+				HighlightKind::PrevoteGhost => ("prevote-GHOST", "lightblue"),
+				HighlightKind::Estimate => ("estimate", "orange"),
+				HighlightKind::Finalized => ("finalized", "palegreen"),
+			}
+		}
+
+		// the names of the highlights on a block, and the color of the last one.
+		let highlights = |hash: &H| {
+			let mut names = String::new();
+			let mut color = None;
+			for highlight in self.highlights.iter().filter(|h| h.block.0 == *hash) {
+				let (name, fill) = style(highlight.kind);
+				let _ = write!(names, "\\n{}", name);
+				color = Some(fill);
+			}
+
+			(names, color)
+		};
+
+		let mut dot = String::from("digraph {\n");
+
+		for node in &self.graph.nodes {
+			let (names, color) = highlights(&node.hash);
+			let _ = writeln!(
+				dot,
+				"\t{} [label=\"{}\\n#{}\\nprevotes: {}, precommits: {}{}\"{}];",
+				id(&node.hash),
+				escaped(&node.hash),
+				node.number,
+				node.weight.prevote,
+				node.weight.precommit,
+				names,
+				color.map(|c| format!(", style=filled, fillcolor={}", c)).unwrap_or_default(),
+			);
+
+			if let Some(ancestor) = &node.ancestor {
+				let _ = writeln!(
+					dot,
+					"\t{} -> {} [label=\"{}\"];",
+					id(ancestor),
+					id(&node.hash),
+					node.edge_length,
+				);
+			}
+		}
+
+		// highlighted blocks on compressed edges point to their vote-nodes.
+		for (i, highlight) in self.highlights.iter().enumerate() {
+			let hash = &highlight.block.0;
+			if self.graph.nodes.iter().any(|node| node.hash == *hash) ||
+				self.highlights[..i].iter().any(|h| h.block.0 == *hash)
+			{
+				continue
+			}
+
+			let (names, color) = highlights(hash);
+			let _ = writeln!(
+				dot,
+				"\t{} [label=\"{}\\n#{}{}\", shape=box, style=filled, fillcolor={}];",
+				id(hash),
+				escaped(hash),
+				highlight.block.1,
+				names,
+				color.unwrap_or("white"),
+			);
+			for node in &highlight.nodes {
+				let _ = writeln!(dot, "\t{} -> {} [style=dashed];", id(hash), id(node));
+			}
+		}
+
+		dot.push_str("}\n");
+		dot
+	}
+}
+
+/// An iterator replaying historical votes into a [`Round`], yielding the state
+/// of the round after each vote. See [`Round::replay`].
+pub struct Replay<'a, Id: Ord + Eq, H: Ord + Eq, N, Signature, C> {
+	round: Round<Id, H, N, Signature>,
+	votes: slice::Iter<'a, SignedMessage<H, N, Signature, Id>>,
+	chain: &'a C,
+}
+
+impl<'a, Id, H, N, Signature, C> Replay<'a, Id, H, N, Signature, C>
+where
+	Id: Ord + Eq,
+	H: Ord + Eq,
+{
+	/// The round with the votes replayed so far.
+	pub fn round(&self) -> &Round<Id, H, N, Signature> {
+		&self.round
+	}
+
+	/// Consume the replay, returning the round with the votes replayed so far.
+	pub fn into_round(self) -> Round<Id, H, N, Signature> {
+		self.round
+	}
+}
+
+impl<'a, Id, H, N, Signature, C> Iterator for Replay<'a, Id, H, N, Signature, C>
+where
+	Id: Ord + Clone + Eq + fmt::Debug,
+	H: Ord + Clone + Eq + Ord + fmt::Debug,
+	N: Copy + fmt::Debug + BlockNumberOps,
+	Signature: Eq + Clone,
+	C: Chain<H, N>,
+{
+	type Item = Result<State<H, N>, crate::Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let SignedMessage { message, signature, id } = self.votes.next()?.clone();
+		let imported = match message {
+			Message::Prevote(prevote) =>
+				self.round.import_prevote(self.chain, prevote, id, signature).map(|_| ()),
+			Message::Precommit(precommit) =>
+				self.round.import_precommit(self.chain, precommit, id, signature).map(|_| ()),
+			Message::PrimaryPropose(_) => Ok(()),
+		};
+
+		Some(imported.map(|()| self.round.state()))
+	}
+}
+
+/// A snapshot of the full state of a [`Round`], including its vote-graph, to
+/// restore it without replaying all of its votes. See [`Round::snapshot`] and
+/// [`Round::restore`].
+#[cfg(feature = "derive-codec")]
+#[derive(Encode, Decode, scale_info::TypeInfo)]
+#[scale_info(skip_type_params(A))]
+pub struct RoundSnapshot<Id: Ord + Eq, H: Ord + Eq, N, Signature, A = FullAncestry> {
+	round_number: u64,
+	voters: Vec<(Id, u64)>,
+	equivocations: Bitfield,
+	graph: VoteGraph<H, N, VoteNode, A>,
+	prevotes: BTreeMap<Id, VoteMultiplicity<Prevote<H, N>, Signature>>,
+	precommits: BTreeMap<Id, VoteMultiplicity<Precommit<H, N>, Signature>>,
+	historical_votes: HistoricalVotes<H, N, Signature, Id>,
+	prevote_ghost: Option<(H, N)>,
+	precommit_ghost: Option<(H, N)>,
+	finalized: Option<(H, N)>,
+	estimate: Option<(H, N)>,
+	completable: bool,
+}
+
+/// Errors that can occur when restoring a [`Round`] from a snapshot.
+#[cfg(feature = "derive-codec")]
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
+pub enum RestoreError {
+	/// The snapshot was taken with another voter set.
+	VoterSetMismatch,
+	/// The snapshot contains votes of voters outside of the voter set.
+	UnknownVoter,
+	/// The base of the snapshot's vote-graph isn't one of its nodes, or is
+	/// above a block of the round state.
+	GraphBaseMismatch,
+	/// A bitfield of the snapshot is larger than the voter set.
+	BitfieldLength,
+	/// The links between the nodes of the snapshot's vote-graph don't match,
+	/// or the votes on a node don't include the votes on its descendents.
+	MalformedGraph,
+}
+
+#[cfg(all(feature = "derive-codec", feature = "std"))]
+impl std::fmt::Display for RestoreError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match *self {
+			RestoreError::VoterSetMismatch => write!(f, "Round snapshot of another voter set"),
+			RestoreError::UnknownVoter => write!(f, "Round snapshot with votes of unknown voters"),
+			RestoreError::GraphBaseMismatch =>
+				write!(f, "Round snapshot with a vote-graph base not matching the round"),
+			RestoreError::BitfieldLength =>
+				write!(f, "Round snapshot with bitfields larger than the voter set"),
+			RestoreError::MalformedGraph => write!(f, "Round snapshot with a malformed vote-graph"),
+		}
+	}
+}
+
+#[cfg(all(feature = "derive-codec", feature = "std"))]
+impl std::error::Error for RestoreError {}
+
 impl<Id, H, N, Signature> Round<Id, H, N, Signature>
 where
 	Id: Ord + Clone + Eq + fmt::Debug,
@@ -229,12 +560,93 @@ where
 {
 	/// Create a new round accumulator for given round number and with given weight.
 	pub fn new(round_params: RoundParams<Id, H, N>) -> Self {
-		let (base_hash, base_number) = round_params.base;
+		let (base_hash, base_number) = round_params.base.clone();
+		Self::with_graph(round_params, VoteGraph::new(base_hash, base_number, VoteNode::default()))
+	}
+
+	/// Rebuild a round by importing the given historical votes in order. The
+	/// markers of the votes seen before prevoting and precommitting are kept.
+	pub fn from_historical_votes<C: Chain<H, N>>(
+		round_params: RoundParams<Id, H, N>,
+		votes: &HistoricalVotes<H, N, Signature, Id>,
+		chain: &C,
+	) -> Result<Self, crate::Error> {
+		let mut replay = Round::replay(round_params, votes, chain);
+		for state in &mut replay {
+			state?;
+		}
+
+		let mut round = replay.into_round();
+		round.historical_votes.prevote_idx = votes.prevote_idx();
+		round.historical_votes.precommit_idx = votes.precommit_idx();
+
+		Ok(round)
+	}
+
+	/// Replay the given historical votes into a new round, yielding the state
+	/// of the round after each vote.
+	///
+	/// The state at the moment of prevoting (or precommitting) is the one
+	/// yielded after `votes.prevote_idx()` (or `votes.precommit_idx()`) votes,
+	/// or the state of an empty round if that index is zero.
+	pub fn replay<'a, C: Chain<H, N>>(
+		round_params: RoundParams<Id, H, N>,
+		votes: &'a HistoricalVotes<H, N, Signature, Id>,
+		chain: &'a C,
+	) -> Replay<'a, Id, H, N, Signature, C> {
+		Replay { round: Round::new(round_params), votes: votes.seen().iter(), chain }
+	}
+
+	/// Export the vote-graph of the round, with the prevote and precommit
+	/// weight on each vote-node and the prevote-GHOST, estimate and finalized
+	/// blocks highlighted, e.g. to inspect a stuck round.
+	pub fn export_graph(&self) -> RoundGraph<H, N> {
+		without_chain(self.export_graph_with_chain(&NoChain))
+	}
+
+	/// Compute and cache the precommit-GHOST.
+	pub fn precommit_ghost(&mut self) -> Option<(H, N)> {
+		without_chain(self.precommit_ghost_with_chain(&NoChain))
+	}
+}
+
+impl<Id, H, N, Signature> Round<Id, H, N, Signature, CompressedAncestry>
+where
+	Id: Ord + Clone + Eq + fmt::Debug,
+	H: Ord + Clone + Eq + Ord + fmt::Debug,
+	N: Copy + fmt::Debug + BlockNumberOps,
+	Signature: Eq + Clone,
+{
+	/// Create a new round accumulator like [`Round::new`], whose vote-graph
+	/// doesn't keep the blocks between the targets of the votes, see
+	/// [`VoteGraph::new_compressed`]. The chain passed to the round must
+	/// support [`Chain::hash_at`], and its precommit-GHOST and vote-graph can
+	/// only be queried with [`Round::precommit_ghost_with_chain`] and
+	/// [`Round::export_graph_with_chain`].
+	pub fn new_compressed(round_params: RoundParams<Id, H, N>) -> Self {
+		let (base_hash, base_number) = round_params.base.clone();
+		Self::with_graph(
+			round_params,
+			VoteGraph::new_compressed(base_hash, base_number, VoteNode::default()),
+		)
+	}
+}
 
+impl<Id, H, N, Signature, A: AncestryKind> Round<Id, H, N, Signature, A>
+where
+	Id: Ord + Clone + Eq + fmt::Debug,
+	H: Ord + Clone + Eq + Ord + fmt::Debug,
+	N: Copy + fmt::Debug + BlockNumberOps,
+	Signature: Eq + Clone,
+{
+	fn with_graph(
+		round_params: RoundParams<Id, H, N>,
+		graph: VoteGraph<H, N, VoteNode, A>,
+	) -> Self {
 		Round {
 			round_number: round_params.round_number,
 			context: Context::new(round_params.voters),
-			graph: VoteGraph::new(base_hash, base_number, VoteNode::default()),
+			graph,
 			prevote: VoteTracker::new(),
 			precommit: VoteTracker::new(),
 			historical_votes: HistoricalVotes::new(),
@@ -243,20 +655,56 @@ where
 			finalized: None,
 			estimate: None,
 			completable: false,
+			prevote_ghost_checkpoint: None,
+			precommit_ghost_checkpoint: None,
 		}
 	}
 
+	/// Export the vote-graph of the round like [`Round::export_graph`],
+	/// looking up the highlighted blocks on the given chain if the vote-graph
+	/// is compressed.
+	pub fn export_graph_with_chain<C: Chain<H, N>>(
+		&self,
+		chain: &C,
+	) -> Result<RoundGraph<H, N>, crate::Error> {
+		let graph = self.graph.export(|node| NodeWeight {
+			prevote: self.context.weight(node, Phase::Prevote).0,
+			precommit: self.context.weight(node, Phase::Precommit).0,
+		});
+
+		let highlights = [
+			(HighlightKind::PrevoteGhost, &self.prevote_ghost),
+			(HighlightKind::Estimate, &self.estimate),
+			(HighlightKind::Finalized, &self.finalized),
+		]
+		.iter()
+		.filter_map(|(kind, block)| {
+			block.as_ref().map(|(hash, number)| {
+				Ok(GraphHighlight {
+					kind: *kind,
+					block: (hash.clone(), *number),
+					nodes: self.graph.nodes_containing(hash.clone(), *number, chain)?,
+				})
+			})
+		})
+		.collect::<Result<_, _>>()?;
+
+		Ok(RoundGraph { graph, highlights })
+	}
+
 	/// Return the round number.
 	pub fn number(&self) -> u64 {
 		self.round_number
 	}
 
-	/// Import a prevote. Returns an equivocation proof, if the vote is an equivocation,
-	/// and a bool indicating if the vote is duplicated (see `ImportResult`).
+	/// Import a prevote. Returns whether the voter is part of the voter set,
+	/// whether the vote is duplicated, an equivocation proof if the vote is an
+	/// equivocation, and which parts of the round state changed (see
+	/// `ImportResult`).
 	///
-	/// Ignores duplicate prevotes (not equivocations).
-	#[cfg_attr(not(feature = "std"), allow(unused))]
-	pub(crate) fn import_prevote<C: Chain<H, N>>(
+	/// Ignores duplicate prevotes (not equivocations). The target of the vote
+	/// must be a known block descending from the base of the round.
+	pub fn import_prevote<C: Chain<H, N>>(
 		&mut self,
 		chain: &C,
 		prevote: Prevote<H, N>,
@@ -325,24 +773,40 @@ where
 			}
 		};
 
-		// update prevote-GHOST
+		// the round state only changes from here on.
+		let before = self.state();
+
+		// update prevote-GHOST, searching again only if votes were inserted under
+		// it or the weights changed with an equivocation.
+		if equivocation.is_some() {
+			self.prevote_ghost_checkpoint = None;
+		}
+
 		let threshold = self.threshold();
 		if self.prevote.current_weight >= threshold {
-			self.prevote_ghost = self.graph.find_ghost(self.prevote_ghost.take(), |v| {
-				self.context.weight(v, Phase::Prevote) >= threshold
-			});
+			let context = &self.context;
+			self.prevote_ghost = self.graph.find_ghost_since_with_chain(
+				self.prevote_ghost.take(),
+				&mut self.prevote_ghost_checkpoint,
+				chain,
+				|v| context.weight(v, Phase::Prevote) >= threshold,
+			)?;
 		}
 
-		self.update();
+		self.update(chain)?;
 		import_result.equivocation = equivocation;
+		self.note_state_changes(before, &mut import_result);
 		Ok(import_result)
 	}
 
-	/// Import a precommit. Returns an equivocation proof, if the vote is an
-	/// equivocation, and a bool indicating if the vote is duplicated (see `ImportResult`).
+	/// Import a precommit. Returns whether the voter is part of the voter set,
+	/// whether the vote is duplicated, an equivocation proof if the vote is an
+	/// equivocation, and which parts of the round state changed (see
+	/// `ImportResult`).
 	///
-	/// Ignores duplicate precommits (not equivocations).
-	pub(crate) fn import_precommit<C: Chain<H, N>>(
+	/// Ignores duplicate precommits (not equivocations). The target of the vote
+	/// must be a known block descending from the base of the round.
+	pub fn import_precommit<C: Chain<H, N>>(
 		&mut self,
 		chain: &C,
 		precommit: Precommit<H, N>,
@@ -410,11 +874,32 @@ where
 			}
 		};
 
-		self.update();
+		// the round state only changes from here on.
+		let before = self.state();
+
+		// the precommit-GHOST must be searched again with the new weights.
+		if equivocation.is_some() {
+			self.precommit_ghost_checkpoint = None;
+		}
+
+		self.update(chain)?;
 		import_result.equivocation = equivocation;
+		self.note_state_changes(before, &mut import_result);
 		Ok(import_result)
 	}
 
+	// note the changes to the round state since `before` in the import result.
+	fn note_state_changes<P>(
+		&self,
+		before: State<H, N>,
+		import_result: &mut ImportResult<Id, P, Signature>,
+	) {
+		import_result.prevote_ghost_changed = before.prevote_ghost != self.prevote_ghost;
+		import_result.estimate_changed = before.estimate != self.estimate;
+		import_result.finalized_changed = before.finalized != self.finalized;
+		import_result.completable_changed = before.completable != self.completable;
+	}
+
 	/// Return the current state.
 	pub fn state(&self) -> State<H, N> {
 		State {
@@ -425,17 +910,25 @@ where
 		}
 	}
 
-	/// Compute and cache the precommit-GHOST.
-	pub fn precommit_ghost(&mut self) -> Option<(H, N)> {
+	/// Compute and cache the precommit-GHOST like [`Round::precommit_ghost`],
+	/// looking up blocks on the given chain if the vote-graph is compressed.
+	pub fn precommit_ghost_with_chain<C: Chain<H, N>>(
+		&mut self,
+		chain: &C,
+	) -> Result<Option<(H, N)>, crate::Error> {
 		// update precommit-GHOST
 		let threshold = self.threshold();
 		if self.precommit.current_weight >= threshold {
-			self.precommit_ghost = self.graph.find_ghost(self.precommit_ghost.take(), |v| {
-				self.context.weight(v, Phase::Precommit) >= threshold
-			});
+			let context = &self.context;
+			self.precommit_ghost = self.graph.find_ghost_since_with_chain(
+				self.precommit_ghost.take(),
+				&mut self.precommit_ghost_checkpoint,
+				chain,
+				|v| context.weight(v, Phase::Precommit) >= threshold,
+			)?;
 		}
 
-		self.precommit_ghost.clone()
+		Ok(self.precommit_ghost.clone())
 	}
 
 	/// Returns an iterator of all precommits targeting the finalized hash.
@@ -505,15 +998,15 @@ where
 	}
 
 	// update the round-estimate and whether the round is completable.
-	fn update(&mut self) {
+	fn update<C: Chain<H, N>>(&mut self, chain: &C) -> Result<(), crate::Error> {
 		let threshold = self.threshold();
 
 		if self.prevote.current_weight < threshold {
-			return
+			return Ok(())
 		}
 
 		let (g_hash, g_num) = match self.prevote_ghost.clone() {
-			None => return,
+			None => return Ok(()),
 			Some(x) => x,
 		};
 
@@ -523,9 +1016,9 @@ where
 		// 2/3+ prevote and precommit weight.
 		let current_precommits = self.precommit.current_weight;
 		if current_precommits >= self.threshold() {
-			self.finalized = self.graph.find_ancestor(g_hash.clone(), g_num, |v| {
+			self.finalized = self.graph.find_ancestor_with_chain(g_hash.clone(), g_num, chain, |v| {
 				ctx.weight(v, Phase::Precommit) >= threshold
-			});
+			})?;
 		};
 
 		// figuring out whether a block can still be committed for is
@@ -574,22 +1067,31 @@ where
 		// the round-estimate is the highest block in the chain with head
 		// `prevote_ghost` that could have supermajority-commits.
 		if self.precommit.current_weight >= threshold {
-			self.estimate = self.graph.find_ancestor(g_hash.clone(), g_num, possible_to_precommit);
+			self.estimate = self.graph.find_ancestor_with_chain(
+				g_hash.clone(),
+				g_num,
+				chain,
+				possible_to_precommit,
+			)?;
 		} else {
 			self.estimate = Some((g_hash, g_num));
-			return
+			return Ok(())
 		}
 
-		self.completable = self.estimate.clone().map_or(false, |(b_hash, b_num)| {
-			b_hash != g_hash || {
+		self.completable = match self.estimate.clone() {
+			None => false,
+			Some((b_hash, _)) if b_hash != g_hash => true,
+			Some((b_hash, b_num)) => {
 				// round-estimate is the same as the prevote-ghost.
 				// this round is still completable if no further blocks
 				// could have commit-supermajority.
 				self.graph
-					.find_ghost(Some((b_hash, b_num)), possible_to_precommit)
+					.find_ghost_with_chain(Some((b_hash, b_num)), chain, possible_to_precommit)?
 					.map_or(true, |x| x == (g_hash, g_num))
-			}
-		})
+			},
+		};
+
+		Ok(())
 	}
 
 	/// Fetch the "round-estimate": the best block which might have been finalized
@@ -625,6 +1127,45 @@ where
 		self.graph.base()
 	}
 
+	/// Move the base of the round forward to the given block, e.g. once it has
+	/// been finalized, dropping the votes on the forks which don't include it.
+	/// The block must be the finalized block of the round or one of its
+	/// ancestors, so that the state of the round is unaffected.
+	///
+	/// Only the vote-graph is pruned: the dropped votes are still tracked, so
+	/// that they count towards the participation of the round and further votes
+	/// of their voters are detected as equivocations, and they are kept in the
+	/// historical votes.
+	pub fn prune_to<C: Chain<H, N>>(
+		&mut self,
+		chain: &C,
+		hash: H,
+		number: N,
+	) -> Result<(), crate::Error> {
+		let below_finalized = match self.finalized {
+			Some((ref f_hash, f_num)) =>
+				*f_hash == hash ||
+					number < f_num && chain.ancestry(hash.clone(), f_hash.clone()).is_ok(),
+			None => false,
+		};
+		if !below_finalized {
+			return Err(crate::Error::NotDescendent)
+		}
+
+		if hash == self.graph.base().0 {
+			return Ok(())
+		}
+
+		self.graph.prune_to(hash.clone(), number, chain)?;
+
+		// the weights under the new base are unchanged, but the checkpoints may
+		// refer to nodes which were dropped.
+		self.prevote_ghost_checkpoint = None;
+		self.precommit_ghost_checkpoint = None;
+
+		Ok(())
+	}
+
 	/// Return the round voters and weights.
 	pub fn voters(&self) -> &VoterSet<Id> {
 		self.context.voters()
@@ -686,6 +1227,94 @@ where
 	pub fn precommitted_index(&self) -> Option<u64> {
 		self.historical_votes.precommit_idx
 	}
+
+	/// Take a snapshot of the full state of the round, which can be encoded and
+	/// later restored with [`Round::restore`].
+	#[cfg(feature = "derive-codec")]
+	pub fn snapshot(&self) -> RoundSnapshot<Id, H, N, Signature, A> {
+		RoundSnapshot {
+			round_number: self.round_number,
+			voters: self
+				.voters()
+				.iter()
+				.map(|(id, info)| (id.clone(), info.weight().get()))
+				.collect(),
+			equivocations: self.context.equivocations().clone(),
+			graph: self.graph.clone(),
+			prevotes: self.prevote.votes.clone(),
+			precommits: self.precommit.votes.clone(),
+			historical_votes: self.historical_votes.clone(),
+			prevote_ghost: self.prevote_ghost.clone(),
+			precommit_ghost: self.precommit_ghost.clone(),
+			finalized: self.finalized.clone(),
+			estimate: self.estimate.clone(),
+			completable: self.completable,
+		}
+	}
+
+	/// Restore a round from a snapshot taken with [`Round::snapshot`]. The
+	/// snapshot is rejected if it wasn't taken with the given voter set, or if
+	/// its vote-graph is malformed or doesn't fit the voter set and the state
+	/// of the round.
+	#[cfg(feature = "derive-codec")]
+	pub fn restore(
+		voters: VoterSet<Id>,
+		snapshot: RoundSnapshot<Id, H, N, Signature, A>,
+	) -> Result<Self, RestoreError> {
+		let same_voters = voters
+			.iter()
+			.map(|(id, info)| (id, info.weight().get()))
+			.eq(snapshot.voters.iter().map(|(id, weight)| (id, *weight)));
+		if !same_voters {
+			return Err(RestoreError::VoterSetMismatch)
+		}
+
+		// the state of the round must be on top of the base of the graph.
+		let base_number = snapshot.graph.base().1;
+		let above_base = [
+			&snapshot.prevote_ghost,
+			&snapshot.precommit_ghost,
+			&snapshot.finalized,
+			&snapshot.estimate,
+		]
+		.iter()
+		.all(|block| block.as_ref().map_or(true, |block| block.1 >= base_number));
+		if !snapshot.graph.has_base_node() || !above_base {
+			return Err(RestoreError::GraphBaseMismatch)
+		}
+
+		let len = voters.len().get();
+		if !snapshot.equivocations.fits(len * 2) ||
+			!snapshot.graph.votes().all(|node| node.fits(len))
+		{
+			return Err(RestoreError::BitfieldLength)
+		}
+
+		if !snapshot.graph.is_well_formed(|node, descendent| node.covers(descendent)) {
+			return Err(RestoreError::MalformedGraph)
+		}
+
+		let prevote =
+			VoteTracker::restore(snapshot.prevotes, &voters).ok_or(RestoreError::UnknownVoter)?;
+		let precommit =
+			VoteTracker::restore(snapshot.precommits, &voters).ok_or(RestoreError::UnknownVoter)?;
+
+		Ok(Round {
+			round_number: snapshot.round_number,
+			context: Context::with_equivocations(voters, snapshot.equivocations),
+			graph: snapshot.graph,
+			prevote,
+			precommit,
+			historical_votes: snapshot.historical_votes,
+			prevote_ghost: snapshot.prevote_ghost,
+			precommit_ghost: snapshot.precommit_ghost,
+			finalized: snapshot.finalized,
+			estimate: snapshot.estimate,
+			completable: snapshot.completable,
+			prevote_ghost_checkpoint: None,
+			precommit_ghost_checkpoint: None,
+		})
+	}
 }
 
 #[cfg(test)]
@@ -698,6 +1327,7 @@ mod tests {
 	}
 
 	#[derive(PartialEq, Eq, Clone, Debug)]
+	#[cfg_attr(feature = "derive-codec", derive(Encode))]
 	struct Signature(&'static str);
 
 	#[test]
@@ -903,4 +1533,426 @@ mod tests {
 			)
 		);
 	}
+
+	#[test]
+	fn prune_to_drops_votes_on_other_forks() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E", "F"]);
+		chain.push_blocks("E", &["EA", "EB", "EC", "ED"]);
+		chain.push_blocks("F", &["FA", "FB", "FC"]);
+
+		let mut round =
+			Round::new(RoundParams { round_number: 1, voters: voters(), base: ("C", 4) });
+
+		round
+			.import_prevote(&chain, Prevote::new("ED", 10), "Alice", Signature("Alice"))
+			.unwrap();
+		round.set_prevoted_index();
+		round
+			.import_prevote(&chain, Prevote::new("FC", 10), "Bob", Signature("Bob"))
+			.unwrap();
+		round
+			.import_prevote(&chain, Prevote::new("FC", 10), "Eve", Signature("Eve"))
+			.unwrap();
+		round
+			.import_precommit(&chain, Precommit::new("ED", 10), "Alice", Signature("Alice"))
+			.unwrap();
+		round.set_precommitted_index();
+		round
+			.import_precommit(&chain, Precommit::new("FC", 10), "Bob", Signature("Bob"))
+			.unwrap();
+		round
+			.import_precommit(&chain, Precommit::new("FC", 10), "Eve", Signature("Eve"))
+			.unwrap();
+
+		assert_eq!(round.finalized(), Some(&("FC", 10)));
+		let state = round.state();
+
+		// only the finalized block and its ancestors are valid new bases.
+		assert_eq!(round.prune_to(&chain, "EA", 7), Err(crate::Error::NotDescendent));
+		assert_eq!(round.prune_to(&chain, "FA", 8).map(|_| round.base()), Ok(("FA", 8)));
+
+		// the votes of Alice are gone from the graph while the state is unchanged.
+		assert_eq!(round.state(), state);
+		assert_eq!(round.prevote_participation(), (VoteWeight(14), 3));
+		assert_eq!(round.precommit_participation(), (VoteWeight(14), 3));
+		assert_eq!(round.graph.find_ancestor("ED", 10, |_| true), None);
+
+		// but they are still part of the historical votes.
+		assert_eq!(round.historical_votes().seen().len(), 6);
+		assert_eq!(round.historical_votes().prevote_idx(), Some(1));
+		assert_eq!(round.historical_votes().precommit_idx(), Some(4));
+
+		// and further votes of hers are still checked against them.
+		let result = round
+			.import_prevote(&chain, Prevote::new("ED", 10), "Alice", Signature("Alice"))
+			.unwrap();
+		assert!(result.duplicated());
+
+		let result = round
+			.import_prevote(&chain, Prevote::new("FC", 10), "Alice", Signature("Alice"))
+			.unwrap();
+		assert_eq!(
+			result.equivocation().map(|e| (&e.first.0, &e.second.0)),
+			Some((&Prevote::new("ED", 10), &Prevote::new("FC", 10))),
+		);
+		assert_eq!(round.prevote_participation(), (VoteWeight(14), 3));
+		assert_eq!(round.historical_votes().seen().len(), 7);
+	}
+
+	#[test]
+	fn import_result_reports_state_changes() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E", "F"]);
+		chain.push_blocks("E", &["EA", "EB", "EC", "ED"]);
+		chain.push_blocks("F", &["FA", "FB", "FC"]);
+
+		let mut round =
+			Round::new(RoundParams { round_number: 1, voters: voters(), base: ("C", 4) });
+
+		let result = round
+			.import_prevote(&chain, Prevote::new("FC", 10), "Alice", Signature("Alice"))
+			.unwrap();
+		assert_eq!(result, ImportResult { valid_voter: true, ..Default::default() });
+
+		let result = round
+			.import_prevote(&chain, Prevote::new("FC", 10), "Alice", Signature("Alice"))
+			.unwrap();
+		assert_eq!(
+			result,
+			ImportResult { valid_voter: true, duplicated: true, ..Default::default() }
+		);
+
+		let result = round
+			.import_prevote(&chain, Prevote::new("FC", 10), "Dave", Signature("Dave"))
+			.unwrap();
+		assert_eq!(result, ImportResult::default());
+
+		// the prevote-GHOST and estimate are found.
+		let result = round
+			.import_prevote(&chain, Prevote::new("ED", 10), "Bob", Signature("Bob"))
+			.unwrap();
+		assert!(result.valid_voter && result.prevote_ghost_changed && result.estimate_changed);
+		assert!(!result.finalized_changed);
+
+		// the round is finalized and completed.
+		let result = round
+			.import_precommit(&chain, Precommit::new("E", 6), "Alice", Signature("Alice"))
+			.unwrap();
+		assert_eq!(result, ImportResult { valid_voter: true, ..Default::default() });
+
+		let result = round
+			.import_precommit(&chain, Precommit::new("E", 6), "Bob", Signature("Bob"))
+			.unwrap();
+		assert!(result.finalized_changed && result.completable_changed);
+		assert!(!result.prevote_ghost_changed);
+		assert_eq!(round.finalized(), Some(&("E", 6)));
+		assert!(round.completable());
+
+		// equivocations are reported.
+		let result = round
+			.import_prevote(&chain, Prevote::new("EA", 7), "Bob", Signature("Bob"))
+			.unwrap();
+		assert!(result.valid_voter && !result.duplicated);
+		assert_eq!(result.equivocation.map(|e| e.identity), Some("Bob"));
+	}
+
+	#[cfg(feature = "derive-codec")]
+	#[test]
+	fn snapshot_and_restore() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E", "F"]);
+		chain.push_blocks("E", &["EA", "EB", "EC", "ED"]);
+		chain.push_blocks("F", &["FA", "FB", "FC"]);
+
+		let mut round =
+			Round::new(RoundParams { round_number: 1, voters: voters(), base: ("C", 4) });
+
+		let prevotes = [("Alice", "FC", 10), ("Bob", "ED", 10), ("Eve", "EA", 7), ("Eve", "FA", 8)];
+		for (id, hash, number) in prevotes.iter().cloned() {
+			round
+				.import_prevote(&chain, Prevote::new(hash, number), id, Signature(id))
+				.unwrap();
+		}
+		round
+			.import_precommit(&chain, Precommit::new("ED", 10), "Bob", Signature("Bob"))
+			.unwrap();
+
+		let mut restored = Round::restore(voters(), round.snapshot()).unwrap();
+		assert_eq!(restored.snapshot().encode(), round.snapshot().encode());
+
+		assert_eq!(restored.number(), 1);
+		assert_eq!(restored.base(), ("C", 4));
+		assert_eq!(restored.state(), round.state());
+		assert_eq!(restored.prevotes(), round.prevotes());
+		assert_eq!(restored.precommits(), round.precommits());
+		assert_eq!(restored.prevote_participation(), round.prevote_participation());
+		assert_eq!(restored.historical_votes(), round.historical_votes());
+
+		// the restored round keeps counting votes, including equivocations.
+		for round in [&mut round, &mut restored] {
+			round
+				.import_precommit(&chain, Precommit::new("EA", 7), "Eve", Signature("Eve"))
+				.unwrap();
+			round
+				.import_precommit(&chain, Precommit::new("FC", 10), "Alice", Signature("Alice"))
+				.unwrap();
+		}
+		assert_eq!(restored.state(), round.state());
+		assert_eq!(restored.precommit_ghost(), round.precommit_ghost());
+
+		// snapshots of another voter set are rejected.
+		let other_voters =
+			VoterSet::new([("Alice", 4), ("Bob", 7)].iter().cloned()).expect("nonempty");
+		assert_eq!(
+			Round::restore(other_voters, round.snapshot()).err(),
+			Some(RestoreError::VoterSetMismatch),
+		);
+
+		// as are snapshots with a vote-graph not matching the round.
+		let mut snapshot = round.snapshot();
+		snapshot.graph = VoteGraph::new("FC", 10, VoteNode::default());
+		assert_eq!(
+			Round::restore(voters(), snapshot).err(),
+			Some(RestoreError::GraphBaseMismatch),
+		);
+
+		// or with bitfields larger than the voter set.
+		let mut snapshot = round.snapshot();
+		snapshot.equivocations.set_bit(6);
+		assert_eq!(Round::restore(voters(), snapshot).err(), Some(RestoreError::BitfieldLength));
+
+		let mut snapshot = round.snapshot();
+		snapshot.equivocations = Bitfield::from(vec![0, 0]);
+		assert_eq!(Round::restore(voters(), snapshot).err(), Some(RestoreError::BitfieldLength));
+	}
+
+	#[cfg(feature = "derive-codec")]
+	#[test]
+	fn restore_rejects_malformed_snapshots() {
+		let voters = VoterSet::new([(1u8, 1), (2, 1), (3, 1)].iter().cloned()).expect("nonempty");
+		let params = || RoundParams { round_number: 1, voters: voters.clone(), base: (1u32, 1u32) };
+
+		let round = Round::<u8, u32, u32, u8>::new(params());
+		let encoded = round.snapshot().encode();
+		let snapshot = RoundSnapshot::decode(&mut &encoded[..]).unwrap();
+		assert!(Round::<u8, u32, u32, u8>::restore(voters.clone(), snapshot).is_ok());
+
+		// the vote-graph of a compressed round doesn't keep the blocks a full
+		// vote-graph must have.
+		let compressed = Round::<u8, u32, u32, u8, _>::new_compressed(params());
+		let encoded = compressed.snapshot().encode();
+		let snapshot = RoundSnapshot::decode(&mut &encoded[..]).unwrap();
+		assert_eq!(
+			Round::<u8, u32, u32, u8>::restore(voters, snapshot).err(),
+			Some(RestoreError::MalformedGraph),
+		);
+	}
+
+	#[test]
+	fn replays_historical_votes() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E", "F"]);
+		chain.push_blocks("E", &["EA", "EB", "EC", "ED"]);
+		chain.push_blocks("F", &["FA", "FB", "FC"]);
+
+		let params = || RoundParams { round_number: 1, voters: voters(), base: ("C", 4) };
+		let mut round = Round::new(params());
+
+		round
+			.import_prevote(&chain, Prevote::new("FC", 10), "Alice", Signature("Alice"))
+			.unwrap();
+		round
+			.import_prevote(&chain, Prevote::new("ED", 10), "Bob", Signature("Bob"))
+			.unwrap();
+		round.set_prevoted_index();
+		let prevoted_state = round.state();
+
+		round
+			.import_precommit(&chain, Precommit::new("E", 6), "Alice", Signature("Alice"))
+			.unwrap();
+		round
+			.import_prevote(&chain, Prevote::new("EA", 7), "Eve", Signature("Eve"))
+			.unwrap();
+		round
+			.import_prevote(&chain, Prevote::new("FA", 8), "Eve", Signature("Eve"))
+			.unwrap();
+		round.set_precommitted_index();
+		let precommitted_state = round.state();
+
+		round
+			.import_precommit(&chain, Precommit::new("ED", 10), "Bob", Signature("Bob"))
+			.unwrap();
+
+		let votes = round.historical_votes().clone();
+		let states =
+			Round::replay(params(), &votes, &chain).collect::<Result<Vec<_>, _>>().unwrap();
+		assert_eq!(states.len(), 6);
+		assert_eq!(states[votes.prevote_idx().unwrap() as usize - 1], prevoted_state);
+		assert_eq!(states[votes.precommit_idx().unwrap() as usize - 1], precommitted_state);
+		assert_eq!(states.last(), Some(&round.state()));
+
+		let replayed = Round::from_historical_votes(params(), &votes, &chain).unwrap();
+		assert_eq!(replayed.state(), round.state());
+		assert_eq!(replayed.prevotes(), round.prevotes());
+		assert_eq!(replayed.precommits(), round.precommits());
+		assert_eq!(replayed.historical_votes(), round.historical_votes());
+	}
+
+	#[test]
+	fn exports_graph() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E", "F"]);
+		chain.push_blocks("E", &["EA", "EB", "EC", "ED"]);
+		chain.push_blocks("F", &["FA", "FB", "FC"]);
+
+		let mut round =
+			Round::new(RoundParams { round_number: 1, voters: voters(), base: ("C", 4) });
+
+		round
+			.import_prevote(&chain, Prevote::new("FC", 10), "Alice", Signature("Alice"))
+			.unwrap();
+		round
+			.import_prevote(&chain, Prevote::new("ED", 10), "Bob", Signature("Bob"))
+			.unwrap();
+		round
+			.import_precommit(&chain, Precommit::new("C", 4), "Bob", Signature("Bob"))
+			.unwrap();
+
+		let exported = round.export_graph();
+		let nodes = exported
+			.graph
+			.nodes
+			.iter()
+			.map(|node| (node.hash, node.edge_length, node.weight.prevote, node.weight.precommit))
+			.collect::<Vec<_>>();
+		assert_eq!(nodes, vec![("C", 0, 11, 7), ("ED", 6, 7, 0), ("FC", 6, 4, 0)]);
+
+		// the prevote-GHOST and estimate are on the compressed edges.
+		assert_eq!(
+			exported.highlights,
+			vec![
+				GraphHighlight {
+					kind: HighlightKind::PrevoteGhost,
+					block: ("E", 6),
+					nodes: vec!["ED", "FC"],
+				},
+				GraphHighlight {
+					kind: HighlightKind::Estimate,
+					block: ("E", 6),
+					nodes: vec!["ED", "FC"],
+				},
+			]
+		);
+
+		#[cfg(feature = "std")]
+		{
+			let dot = exported.to_dot();
+			assert!(dot.contains(r#"	"\"C\"" -> "\"ED\"" [label="6"];"#));
+			assert!(dot.contains(r#"	"\"ED\"" [label="\"ED\"\n#10\nprevotes: 7, precommits: 0"];"#));
+			assert!(dot.contains(
+				r#"	"\"E\"" [label="\"E\"\n#6\nprevote-GHOST\nestimate", shape=box, style=filled, fillcolor=orange];"#
+			));
+			assert_eq!(dot.matches(r#""\"E\"" ["#).count(), 1);
+		}
+	}
+
+	fn forked_chain() -> DummyChain {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E", "F"]);
+		chain.push_blocks("E", &["EA", "EB", "EC", "ED"]);
+		chain.push_blocks("F", &["FA", "FB", "FC"]);
+		chain
+	}
+
+	// import a vote by one of the voters, on one of the blocks of `forked_chain`
+	// above ("C", 4), which is either a prevote or a precommit.
+	fn import_vote<A: AncestryKind>(
+		round: &mut Round<&'static str, &'static str, u32, Signature, A>,
+		chain: &DummyChain,
+		(voter, block, precommit): (u8, u8, bool),
+	) {
+		const BLOCKS: [(&str, u32); 8] =
+			[("D", 5), ("E", 6), ("F", 7), ("EA", 7), ("EC", 9), ("ED", 10), ("FA", 8), ("FC", 10)];
+
+		let id = ["Alice", "Bob", "Eve"][voter as usize % 3];
+		let (hash, number) = BLOCKS[block as usize % BLOCKS.len()];
+		if precommit {
+			round
+				.import_precommit(chain, Precommit::new(hash, number), id, Signature(id))
+				.unwrap();
+		} else {
+			round
+				.import_prevote(chain, Prevote::new(hash, number), id, Signature(id))
+				.unwrap();
+		}
+	}
+
+	#[test]
+	fn incremental_ghost_matches_full_search() {
+		use quickcheck::{QuickCheck, TestResult};
+
+		// voters may equivocate.
+		fn prop(votes: Vec<(u8, u8, bool)>) -> TestResult {
+			let chain = forked_chain();
+			let mut round =
+				Round::new(RoundParams { round_number: 1, voters: voters(), base: ("C", 4) });
+			let (mut prevote_ghost, mut precommit_ghost) = (None, None);
+
+			for vote in votes {
+				let precommit = vote.2;
+				let seen = round.historical_votes().seen().len();
+				import_vote(&mut round, &chain, vote);
+
+				// search the prevote-GHOST after every imported prevote, and the
+				// precommit-GHOST after every vote, from scratch.
+				let imported = round.historical_votes().seen().len() > seen;
+				let threshold = round.threshold();
+				let context = &round.context;
+				if imported && !precommit && round.prevote.current_weight >= threshold {
+					prevote_ghost = round.graph.find_ghost(prevote_ghost, |v| {
+						context.weight(v, Phase::Prevote) >= threshold
+					});
+				}
+				if round.precommit.current_weight >= threshold {
+					precommit_ghost = round.graph.find_ghost(precommit_ghost, |v| {
+						context.weight(v, Phase::Precommit) >= threshold
+					});
+				}
+
+				if prevote_ghost != round.prevote_ghost ||
+					precommit_ghost != round.precommit_ghost()
+				{
+					return TestResult::failed()
+				}
+			}
+
+			TestResult::passed()
+		}
+
+		QuickCheck::new().quickcheck(prop as fn(Vec<(u8, u8, bool)>) -> TestResult);
+	}
+
+	#[test]
+	fn compressed_round_matches_round() {
+		use quickcheck::QuickCheck;
+
+		fn prop(votes: Vec<(u8, u8, bool)>) -> bool {
+			let chain = forked_chain();
+			let params = || RoundParams { round_number: 1, voters: voters(), base: ("C", 4) };
+			let mut round = Round::new(params());
+			let mut compressed = Round::new_compressed(params());
+
+			votes.into_iter().all(|vote| {
+				import_vote(&mut round, &chain, vote);
+				import_vote(&mut compressed, &chain, vote);
+
+				round.state() == compressed.state() &&
+					Ok(round.precommit_ghost()) == compressed.precommit_ghost_with_chain(&chain) &&
+					Ok(round.export_graph()) == compressed.export_graph_with_chain(&chain)
+			})
+		}
+
+		QuickCheck::new().quickcheck(prop as fn(Vec<(u8, u8, bool)>) -> bool);
+	}
 }
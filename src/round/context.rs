@@ -24,6 +24,11 @@ use crate::{
 
 use super::Phase;
 
+#[cfg(feature = "derive-codec")]
+use parity_scale_codec::{Decode, Encode};
+#[cfg(feature = "derive-codec")]
+use scale_info::TypeInfo;
+
 /// The context of a `Round` in which vote weights are calculated.
 #[cfg_attr(any(feature = "std", test), derive(Debug))]
 #[cfg_attr(test, derive(Clone))]
@@ -38,6 +43,19 @@ impl<T: Ord + Eq> Context<T> {
 		Context { voters, equivocations: Bitfield::new() }
 	}
 
+	/// Create a context for a round with the given set of voters and the
+	/// equivocations already observed, as returned by [`Context::equivocations`].
+	#[cfg(feature = "derive-codec")]
+	pub fn with_equivocations(voters: VoterSet<T>, equivocations: Bitfield) -> Self {
+		Context { voters, equivocations }
+	}
+
+	/// Get the equivocations observed, as bits of the voters' positions.
+	#[cfg(feature = "derive-codec")]
+	pub fn equivocations(&self) -> &Bitfield {
+		&self.equivocations
+	}
+
 	/// Get the set of voters.
 	pub fn voters(&self) -> &VoterSet<T> {
 		&self.voters
@@ -112,6 +130,7 @@ impl Vote {
 /// The weight of any `VoteNode` is always computed in a `Context`,
 /// taking into account equivocations. See [`Context::weight`].
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "derive-codec", derive(Encode, Decode, TypeInfo))]
 pub struct VoteNode {
 	bits: Bitfield,
 }
@@ -122,6 +141,20 @@ impl Default for VoteNode {
 	}
 }
 
+impl VoteNode {
+	/// Whether the vote bits fit a voter set with the given number of voters.
+	#[cfg(feature = "derive-codec")]
+	pub(crate) fn fits(&self, voters: usize) -> bool {
+		self.bits.fits(voters * 2)
+	}
+
+	/// Whether all the votes on the other node are on this one as well.
+	#[cfg(feature = "derive-codec")]
+	pub(crate) fn covers(&self, other: &VoteNode) -> bool {
+		self.bits.contains(&other.bits)
+	}
+}
+
 impl AddAssign<&VoteNode> for VoteNode {
 	fn add_assign(&mut self, rhs: &VoteNode) {
 		self.bits.merge(&rhs.bits);
@@ -0,0 +1,939 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A deterministic, discrete-event network simulator driving a set of voters.
+//!
+//! All voters run on a single thread, on top of a virtual clock and a simulated
+//! network. Every source of randomness (message latency, message loss, commit
+//! delays) is drawn from a seeded RNG, hence a simulation run with a given
+//! seed always produces the same [`Trace`] of finalized blocks.
+//!
+//! ```
+//! use finality_grandpa::simulation::{Simulation, SimulationConfig};
+//!
+//! let mut simulation = Simulation::new(SimulationConfig { voters: 4, ..Default::default() });
+//! let best = *simulation.with_chain(|chain| chain.push_blocks(0, 10)).last().unwrap();
+//!
+//! let trace = simulation.run(30_000);
+//! assert!(simulation.is_safe(&trace));
+//! for node in 0..4 {
+//!     assert_eq!(trace.last_finalized(node).map(|f| f.hash), Some(best));
+//! }
+//! ```
+
+use futures::{
+	channel::mpsc::{self, UnboundedSender},
+	future,
+	prelude::*,
+};
+use parking_lot::Mutex;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use std::{
+	collections::BTreeMap,
+	pin::Pin,
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Arc,
+	},
+	task::{Context, Poll, Wake, Waker},
+};
+
+use crate::{
+	round::State as RoundState,
+	voter::{
+		Callback, CommunicationIn, CommunicationOut, RoundData, SelfEquivocationGuard, Voter,
+		VoterParams,
+	},
+	voter_set::VoterSet,
+	Chain, Commit, Equivocation, Error, HistoricalVotes, Message, Precommit, Prevote,
+	PrimaryPropose, SignedMessage,
+};
+
+/// The block hash type of the simulated chain.
+pub type Hash = u64;
+/// The block number type of the simulated chain.
+pub type BlockNumber = u64;
+/// The id of a simulated node, which is also its voter id.
+pub type Id = u32;
+/// The signature type of the simulated voters, a signature being the id of the
+/// signer.
+pub type Signature = u32;
+
+/// The hash of the genesis block.
+pub const GENESIS_HASH: Hash = 0;
+
+/// The block tree shared by all simulated nodes.
+///
+/// Blocks get consecutive hashes in the order they are added, starting with
+/// the genesis block at number 0.
+pub struct SimulationChain {
+	// block hash -> (number, parent hash)
+	blocks: BTreeMap<Hash, (BlockNumber, Hash)>,
+}
+
+impl SimulationChain {
+	fn new() -> Self {
+		let mut blocks = BTreeMap::new();
+		blocks.insert(GENESIS_HASH, (0, GENESIS_HASH));
+		SimulationChain { blocks }
+	}
+
+	/// Add a chain of `count` blocks on top of `parent`, returning their hashes.
+	pub fn push_blocks(&mut self, parent: Hash, count: usize) -> Vec<Hash> {
+		let mut parent = (parent, self.number(parent).expect("parent block must be known"));
+
+		(0..count)
+			.map(|_| {
+				let hash = self.blocks.len() as Hash;
+				self.blocks.insert(hash, (parent.1 + 1, parent.0));
+				parent = (hash, parent.1 + 1);
+				hash
+			})
+			.collect()
+	}
+
+	/// Get the number of a block, if known.
+	pub fn number(&self, hash: Hash) -> Option<BlockNumber> {
+		self.blocks.get(&hash).map(|(number, _)| *number)
+	}
+
+	/// Get the highest block containing `base` in its chain, the one with the
+	/// lowest hash among equally high blocks.
+	pub fn best_chain_containing(&self, base: Hash) -> Option<(Hash, BlockNumber)> {
+		let base_number = self.number(base)?;
+
+		self.blocks
+			.iter()
+			.filter(|(hash, (number, _))| {
+				*number >= base_number && self.is_equal_or_descendent_of(base, **hash)
+			})
+			.map(|(hash, (number, _))| (*hash, *number))
+			.fold(None, |best: Option<(Hash, BlockNumber)>, block| match best {
+				Some(best) if best.1 >= block.1 => Some(best),
+				_ => Some(block),
+			})
+	}
+}
+
+impl Chain<Hash, BlockNumber> for SimulationChain {
+	fn ancestry(&self, base: Hash, block: Hash) -> Result<Vec<Hash>, Error> {
+		let mut ancestry = Vec::new();
+		let mut current = block;
+
+		loop {
+			match self.blocks.get(&current) {
+				Some((0, _)) | None => return Err(Error::NotDescendent),
+				Some((_, parent)) => current = *parent,
+			}
+
+			if current == base {
+				return Ok(ancestry)
+			}
+
+			ancestry.push(current);
+		}
+	}
+}
+
+/// The properties of the network link from one node to another.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Link {
+	/// The minimum latency of a message, in milliseconds.
+	pub min_latency: u64,
+	/// The maximum latency of a message, in milliseconds.
+	pub max_latency: u64,
+	/// The probability of a message being lost, between 0 and 1. Lost
+	/// messages are never retransmitted.
+	pub drop_rate: f64,
+}
+
+impl Default for Link {
+	fn default() -> Self {
+		Link { min_latency: 10, max_latency: 100, drop_rate: 0.0 }
+	}
+}
+
+/// A network partition during a period of time.
+///
+/// While the partition is in place, messages between nodes in different groups
+/// are held back until it heals, as a gossip layer would propagate them once
+/// the nodes are connected again. Nodes which aren't part of any group are
+/// isolated from all others.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Partition {
+	/// The time the partition starts, in milliseconds.
+	pub start: u64,
+	/// The time the partition heals, in milliseconds.
+	pub end: u64,
+	/// The groups of nodes which can still communicate with each other.
+	pub groups: Vec<Vec<Id>>,
+}
+
+impl Partition {
+	fn separates(&self, time: u64, from: Id, to: Id) -> bool {
+		let group = |id| self.groups.iter().position(|group| group.contains(&id));
+
+		time >= self.start && time < self.end && (group(from).is_none() || group(from) != group(to))
+	}
+}
+
+/// The configuration of a simulation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulationConfig {
+	/// The seed all randomness of the simulation is derived from.
+	pub seed: u64,
+	/// The number of nodes, all of them voters with equal weight.
+	pub voters: usize,
+	/// The properties of links which haven't been configured otherwise.
+	pub default_link: Link,
+	/// The gossip time estimate, in milliseconds. Prevotes are cast after
+	/// `2 * gossip_duration` and precommits after `4 * gossip_duration`, commits
+	/// are broadcast after a random delay of up to `gossip_duration`.
+	pub gossip_duration: u64,
+}
+
+impl Default for SimulationConfig {
+	fn default() -> Self {
+		SimulationConfig { seed: 0, voters: 4, default_link: Link::default(), gossip_duration: 250 }
+	}
+}
+
+/// A block finalized by a node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finalized {
+	/// The virtual time the block was finalized at, in milliseconds.
+	pub time: u64,
+	/// The hash of the block.
+	pub hash: Hash,
+	/// The number of the block.
+	pub number: BlockNumber,
+	/// The round the block was finalized in.
+	pub round: u64,
+}
+
+/// The outcome of a simulation.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Trace {
+	/// The blocks finalized by each node, in order.
+	pub finalized: BTreeMap<Id, Vec<Finalized>>,
+	/// The nodes whose voter failed, with the error.
+	pub errors: BTreeMap<Id, Error>,
+}
+
+impl Trace {
+	/// Get the last block finalized by the given node.
+	pub fn last_finalized(&self, id: Id) -> Option<&Finalized> {
+		self.finalized.get(&id).and_then(|finalized| finalized.last())
+	}
+}
+
+/// A simulation of a network of voters.
+pub struct Simulation {
+	config: SimulationConfig,
+	chain: Arc<Mutex<SimulationChain>>,
+	clock: Arc<Mutex<Clock>>,
+	network: Arc<Mutex<Network>>,
+	trace: Arc<Mutex<Trace>>,
+	nodes: Vec<Node>,
+}
+
+impl Simulation {
+	/// Create a simulation with the given configuration. All voters start in the
+	/// first round of voter set 0, on top of the genesis block.
+	pub fn new(config: SimulationConfig) -> Self {
+		let voters =
+			VoterSet::new((0..config.voters as Id).map(|id| (id, 1))).expect("no voters given");
+
+		let chain = Arc::new(Mutex::new(SimulationChain::new()));
+		let clock = Arc::new(Mutex::new(Clock::default()));
+		let network = Arc::new(Mutex::new(Network::new(&config)));
+		let trace = Arc::new(Mutex::new(Trace::default()));
+
+		let nodes = (0..config.voters as Id)
+			.map(|id| {
+				let env = Arc::new(Environment {
+					id,
+					gossip_duration: config.gossip_duration,
+					chain: chain.clone(),
+					clock: clock.clone(),
+					network: network.clone(),
+					trace: trace.clone(),
+					equivocation_guard: SelfEquivocationGuard::new(),
+				});
+
+				let (global_in, global_in_rx) = mpsc::unbounded();
+				network.lock().global_inboxes.insert(id, global_in);
+
+				let global_out = GlobalOut { id, network: network.clone(), clock: clock.clone() };
+				let genesis = (GENESIS_HASH, 0);
+
+				let voter = Voter::new(
+					env,
+					(global_in_rx.map(Ok), global_out),
+					VoterParams::new(voters.clone(), 0, genesis),
+				);
+
+				Node { id, voter: Some(Box::pin(voter)), woken: Arc::new(NodeWaker::default()) }
+			})
+			.collect();
+
+		Simulation { config, chain, clock, network, trace, nodes }
+	}
+
+	/// Get the configuration of the simulation.
+	pub fn config(&self) -> &SimulationConfig {
+		&self.config
+	}
+
+	/// Get the current virtual time, in milliseconds.
+	pub fn now(&self) -> u64 {
+		self.clock.lock().now
+	}
+
+	/// Access the chain shared by all nodes, e.g. to add blocks.
+	pub fn with_chain<F, U>(&self, f: F) -> U
+	where
+		F: FnOnce(&mut SimulationChain) -> U,
+	{
+		f(&mut self.chain.lock())
+	}
+
+	/// Set the properties of the link from node `from` to node `to`.
+	pub fn set_link(&mut self, from: Id, to: Id, link: Link) {
+		self.network.lock().links.insert((from, to), link);
+	}
+
+	/// Add a network partition.
+	pub fn add_partition(&mut self, partition: Partition) {
+		self.network.lock().partitions.push(partition);
+	}
+
+	/// Run the simulation until the virtual clock reaches `until` (in
+	/// milliseconds) or until there is nothing left to do, returning the trace
+	/// of the simulation so far.
+	///
+	/// The simulation can be resumed by running it again with a later time.
+	pub fn run(&mut self, until: u64) -> Trace {
+		loop {
+			self.settle();
+
+			let next_delivery = self.network.lock().next_delivery();
+			let next_timer = self.clock.lock().next_deadline();
+			let next = match (next_delivery, next_timer) {
+				(Some(a), Some(b)) => a.min(b),
+				(a, b) => match a.or(b) {
+					Some(next) => next,
+					None => break,
+				},
+			};
+
+			if next > until {
+				break
+			}
+
+			self.clock.lock().advance_to(next);
+		}
+
+		self.clock.lock().advance_to(until);
+		self.trace.lock().clone()
+	}
+
+	/// Whether all blocks finalized in the given trace are on a single chain,
+	/// i.e. no two nodes finalized conflicting blocks.
+	pub fn is_safe(&self, trace: &Trace) -> bool {
+		let chain = self.chain.lock();
+		let mut finalized = trace
+			.finalized
+			.values()
+			.flatten()
+			.map(|f| (f.number, f.hash))
+			.collect::<Vec<_>>();
+
+		finalized.sort();
+		finalized.windows(2).all(|w| chain.is_equal_or_descendent_of(w[0].1, w[1].1))
+	}
+
+	// poll all woken voters and deliver all messages due, until nothing happens
+	// anymore at the current time.
+	fn settle(&mut self) {
+		loop {
+			let now = self.clock.lock().now;
+			let delivered = self.network.lock().deliver(now);
+
+			let mut polled = false;
+			for node in &mut self.nodes {
+				if !node.woken.0.swap(false, Ordering::SeqCst) {
+					continue
+				}
+
+				if let Some(ref mut voter) = node.voter {
+					polled = true;
+
+					let waker = Waker::from(node.woken.clone());
+					match voter.as_mut().poll(&mut Context::from_waker(&waker)) {
+						Poll::Ready(Ok(())) => node.voter = None,
+						Poll::Ready(Err(e)) => {
+							self.trace.lock().errors.insert(node.id, e);
+							node.voter = None;
+						},
+						Poll::Pending => {},
+					}
+				}
+			}
+
+			if !delivered && !polled {
+				break
+			}
+		}
+	}
+}
+
+type VoterFuture = Pin<Box<dyn Future<Output = Result<(), Error>>>>;
+
+// a simulated node.
+struct Node {
+	id: Id,
+	voter: Option<VoterFuture>,
+	woken: Arc<NodeWaker>,
+}
+
+// flags the node to be polled. nodes start out woken.
+struct NodeWaker(AtomicBool);
+
+impl Default for NodeWaker {
+	fn default() -> Self {
+		NodeWaker(AtomicBool::new(true))
+	}
+}
+
+impl Wake for NodeWaker {
+	fn wake(self: Arc<Self>) {
+		self.0.store(true, Ordering::SeqCst);
+	}
+}
+
+// the virtual clock, with the timers waiting on it.
+#[derive(Default)]
+struct Clock {
+	now: u64,
+	timers: Vec<(u64, Waker)>,
+}
+
+impl Clock {
+	fn next_deadline(&self) -> Option<u64> {
+		self.timers.iter().map(|(deadline, _)| *deadline).min()
+	}
+
+	fn advance_to(&mut self, time: u64) {
+		self.now = self.now.max(time);
+
+		let now = self.now;
+		let (expired, pending) = self.timers.drain(..).partition(|(deadline, _)| *deadline <= now);
+		self.timers = pending;
+
+		for (_, waker) in expired {
+			waker.wake();
+		}
+	}
+}
+
+// a timer on the virtual clock.
+struct Timer {
+	deadline: u64,
+	clock: Arc<Mutex<Clock>>,
+}
+
+impl Future for Timer {
+	type Output = Result<(), Error>;
+
+	fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+		let mut clock = self.clock.lock();
+		if clock.now >= self.deadline {
+			Poll::Ready(Ok(()))
+		} else {
+			clock.timers.push((self.deadline, cx.waker().clone()));
+			Poll::Pending
+		}
+	}
+}
+
+type SignedMessageOf = SignedMessage<Hash, BlockNumber, Signature, Id>;
+type CommunicationInOf = CommunicationIn<Hash, BlockNumber, Signature, Id>;
+
+// a message on its way to a node.
+enum Payload {
+	Round(u64, u64, SignedMessageOf),
+	Global(CommunicationInOf),
+}
+
+// the incoming round messages of a node.
+enum Inbox {
+	// the node hasn't entered the round yet.
+	Pending(Vec<SignedMessageOf>),
+	Open(UnboundedSender<SignedMessageOf>),
+}
+
+// the simulated network.
+struct Network {
+	rng: StdRng,
+	default_link: Link,
+	links: BTreeMap<(Id, Id), Link>,
+	partitions: Vec<Partition>,
+	nodes: Vec<Id>,
+	// messages in flight, by delivery time and sequence number.
+	in_flight: BTreeMap<(u64, u64), (Id, Payload)>,
+	sequence: u64,
+	round_inboxes: BTreeMap<(Id, u64, u64), Inbox>,
+	global_inboxes: BTreeMap<Id, UnboundedSender<CommunicationInOf>>,
+}
+
+impl Network {
+	fn new(config: &SimulationConfig) -> Self {
+		Network {
+			rng: StdRng::seed_from_u64(config.seed),
+			default_link: config.default_link.clone(),
+			links: BTreeMap::new(),
+			partitions: Vec::new(),
+			nodes: (0..config.voters as Id).collect(),
+			in_flight: BTreeMap::new(),
+			sequence: 0,
+			round_inboxes: BTreeMap::new(),
+			global_inboxes: BTreeMap::new(),
+		}
+	}
+
+	fn next_delivery(&self) -> Option<u64> {
+		self.in_flight.keys().next().map(|(time, _)| *time)
+	}
+
+	// send a message from one node to another at the given time.
+	fn send(&mut self, now: u64, from: Id, to: Id, payload: Payload) {
+		let delivery = if from == to {
+			now
+		} else {
+			let link = self.links.get(&(from, to)).unwrap_or(&self.default_link).clone();
+			if link.drop_rate > 0.0 && self.rng.gen_bool(link.drop_rate.min(1.0)) {
+				return
+			}
+
+			// messages are held back until all partitions separating the nodes heal.
+			let mut sent = now;
+			while let Some(end) = self
+				.partitions
+				.iter()
+				.filter(|partition| partition.separates(sent, from, to))
+				.map(|partition| partition.end)
+				.max()
+			{
+				sent = end;
+			}
+
+			sent + self.rng.gen_range(link.min_latency..=link.max_latency.max(link.min_latency))
+		};
+
+		self.sequence += 1;
+		self.in_flight.insert((delivery, self.sequence), (to, payload));
+	}
+
+	fn broadcast_round_message(
+		&mut self,
+		now: u64,
+		set_id: u64,
+		round: u64,
+		message: SignedMessageOf,
+	) {
+		for to in self.nodes.clone() {
+			self.send(now, message.id, to, Payload::Round(set_id, round, message.clone()));
+		}
+	}
+
+	// deliver all messages due at the given time. returns whether any was.
+	fn deliver(&mut self, now: u64) -> bool {
+		let mut delivered = false;
+
+		while let Some((&(time, sequence), _)) = self.in_flight.iter().next() {
+			if time > now {
+				break
+			}
+
+			let (to, payload) =
+				self.in_flight.remove(&(time, sequence)).expect("key was just read; qed");
+			delivered = true;
+
+			match payload {
+				Payload::Round(set_id, round, message) => match self
+					.round_inboxes
+					.entry((to, set_id, round))
+					.or_insert_with(|| Inbox::Pending(Vec::new()))
+				{
+					Inbox::Pending(messages) => messages.push(message),
+					Inbox::Open(sender) => {
+						let _ = sender.unbounded_send(message);
+					},
+				},
+				Payload::Global(message) =>
+					if let Some(sender) = self.global_inboxes.get(&to) {
+						let _ = sender.unbounded_send(message);
+					},
+			}
+		}
+
+		delivered
+	}
+
+	// open the inbox of round messages of a node, with all messages received so far.
+	fn open_round_inbox(
+		&mut self,
+		id: Id,
+		set_id: u64,
+		round: u64,
+	) -> mpsc::UnboundedReceiver<SignedMessageOf> {
+		let (sender, receiver) = mpsc::unbounded();
+
+		if let Some(Inbox::Pending(messages)) = self.round_inboxes.get_mut(&(id, set_id, round)) {
+			for message in messages.drain(..) {
+				let _ = sender.unbounded_send(message);
+			}
+		}
+
+		self.round_inboxes.insert((id, set_id, round), Inbox::Open(sender));
+		receiver
+	}
+}
+
+// the outgoing round messages of a node, signed and broadcast to all nodes.
+struct RoundOut {
+	id: Id,
+	set_id: u64,
+	round: u64,
+	network: Arc<Mutex<Network>>,
+	clock: Arc<Mutex<Clock>>,
+}
+
+impl Sink<Message<Hash, BlockNumber>> for RoundOut {
+	type Error = Error;
+
+	fn poll_ready(self: Pin<&mut Self>, _: &mut Context) -> Poll<Result<(), Error>> {
+		Poll::Ready(Ok(()))
+	}
+
+	fn start_send(self: Pin<&mut Self>, message: Message<Hash, BlockNumber>) -> Result<(), Error> {
+		let now = self.clock.lock().now;
+		let signed = SignedMessage { message, signature: self.id, id: self.id };
+		self.network
+			.lock()
+			.broadcast_round_message(now, self.set_id, self.round, signed);
+		Ok(())
+	}
+
+	fn poll_flush(self: Pin<&mut Self>, _: &mut Context) -> Poll<Result<(), Error>> {
+		Poll::Ready(Ok(()))
+	}
+
+	fn poll_close(self: Pin<&mut Self>, _: &mut Context) -> Poll<Result<(), Error>> {
+		Poll::Ready(Ok(()))
+	}
+}
+
+// the outgoing global messages of a node, broadcast to all other nodes.
+struct GlobalOut {
+	id: Id,
+	network: Arc<Mutex<Network>>,
+	clock: Arc<Mutex<Clock>>,
+}
+
+impl Sink<CommunicationOut<Hash, BlockNumber, Signature, Id>> for GlobalOut {
+	type Error = Error;
+
+	fn poll_ready(self: Pin<&mut Self>, _: &mut Context) -> Poll<Result<(), Error>> {
+		Poll::Ready(Ok(()))
+	}
+
+	fn start_send(
+		self: Pin<&mut Self>,
+		message: CommunicationOut<Hash, BlockNumber, Signature, Id>,
+	) -> Result<(), Error> {
+		let now = self.clock.lock().now;
+		let mut network = self.network.lock();
+
+		for to in network.nodes.clone() {
+			if to == self.id {
+				continue
+			}
+
+			let message = match message {
+				CommunicationOut::Commit(set_id, round, ref commit) =>
+					CommunicationIn::Commit(set_id, round, commit.clone().into(), Callback::Blank),
+				CommunicationOut::Neighbor(ref packet) => {
+					// lagging nodes are answered with a catch-up.
+					let (network, clock, from) = (self.network.clone(), self.clock.clone(), to);
+					let peer = self.id;
+					let callback = Callback::Work(Box::new(move |catch_up| {
+						let now = clock.lock().now;
+						let catch_up = CommunicationIn::CatchUp(catch_up, Callback::Blank);
+						network.lock().send(now, from, peer, Payload::Global(catch_up));
+					}));
+
+					CommunicationIn::Neighbor(packet.clone(), callback)
+				},
+			};
+
+			network.send(now, self.id, to, Payload::Global(message));
+		}
+
+		Ok(())
+	}
+
+	fn poll_flush(self: Pin<&mut Self>, _: &mut Context) -> Poll<Result<(), Error>> {
+		Poll::Ready(Ok(()))
+	}
+
+	fn poll_close(self: Pin<&mut Self>, _: &mut Context) -> Poll<Result<(), Error>> {
+		Poll::Ready(Ok(()))
+	}
+}
+
+// the environment of a simulated voter.
+struct Environment {
+	id: Id,
+	gossip_duration: u64,
+	chain: Arc<Mutex<SimulationChain>>,
+	clock: Arc<Mutex<Clock>>,
+	network: Arc<Mutex<Network>>,
+	trace: Arc<Mutex<Trace>>,
+	equivocation_guard: SelfEquivocationGuard<Hash, BlockNumber>,
+}
+
+impl Environment {
+	fn timer(&self, delay: u64) -> Timer {
+		Timer { deadline: self.clock.lock().now + delay, clock: self.clock.clone() }
+	}
+}
+
+impl Chain<Hash, BlockNumber> for Environment {
+	fn ancestry(&self, base: Hash, block: Hash) -> Result<Vec<Hash>, Error> {
+		self.chain.lock().ancestry(base, block)
+	}
+}
+
+impl crate::voter::Environment<Hash, BlockNumber> for Environment {
+	type Timer = Timer;
+	type BestChain = future::Ready<Result<Option<(Hash, BlockNumber)>, Error>>;
+	type Id = Id;
+	type Signature = Signature;
+	type In = Box<dyn Stream<Item = Result<SignedMessageOf, Error>> + Unpin + Send>;
+	type Out = Pin<Box<dyn Sink<Message<Hash, BlockNumber>, Error = Error> + Send>>;
+	type Error = Error;
+
+	fn best_chain_containing(&self, base: Hash) -> Self::BestChain {
+		future::ready(Ok(self.chain.lock().best_chain_containing(base)))
+	}
+
+	fn round_data(
+		&self,
+		set_id: u64,
+		round: u64,
+	) -> RoundData<Self::Id, Self::Timer, Self::In, Self::Out> {
+		let incoming = self.network.lock().open_round_inbox(self.id, set_id, round);
+		let outgoing = RoundOut {
+			id: self.id,
+			set_id,
+			round,
+			network: self.network.clone(),
+			clock: self.clock.clone(),
+		};
+
+		RoundData {
+			voter_id: Some(self.id),
+			prevote_timer: self.timer(2 * self.gossip_duration),
+			precommit_timer: self.timer(4 * self.gossip_duration),
+			incoming: Box::new(incoming.map(Ok)),
+			outgoing: Box::pin(self.equivocation_guard.guard(set_id, round, outgoing)),
+		}
+	}
+
+	fn round_commit_timer(&self) -> Self::Timer {
+		let delay = self.network.lock().rng.gen_range(0..=self.gossip_duration);
+		self.timer(delay)
+	}
+
+	fn proposed(
+		&self,
+		_set_id: u64,
+		_round: u64,
+		_propose: PrimaryPropose<Hash, BlockNumber>,
+	) -> Result<(), Error> {
+		Ok(())
+	}
+
+	fn prevoted(
+		&self,
+		_set_id: u64,
+		_round: u64,
+		_prevote: Prevote<Hash, BlockNumber>,
+	) -> Result<(), Error> {
+		Ok(())
+	}
+
+	fn precommitted(
+		&self,
+		_set_id: u64,
+		_round: u64,
+		_precommit: Precommit<Hash, BlockNumber>,
+	) -> Result<(), Error> {
+		Ok(())
+	}
+
+	fn completed(
+		&self,
+		_set_id: u64,
+		_round: u64,
+		_state: RoundState<Hash, BlockNumber>,
+		_base: (Hash, BlockNumber),
+		_votes: &HistoricalVotes<Hash, BlockNumber, Signature, Id>,
+	) -> Result<(), Error> {
+		Ok(())
+	}
+
+	fn concluded(
+		&self,
+		_set_id: u64,
+		_round: u64,
+		_state: RoundState<Hash, BlockNumber>,
+		_base: (Hash, BlockNumber),
+		_votes: &HistoricalVotes<Hash, BlockNumber, Signature, Id>,
+	) -> Result<(), Error> {
+		Ok(())
+	}
+
+	fn finalize_block(
+		&self,
+		hash: Hash,
+		number: BlockNumber,
+		_set_id: u64,
+		round: u64,
+		_commit: Commit<Hash, BlockNumber, Signature, Id>,
+	) -> Result<(), Error> {
+		let time = self.clock.lock().now;
+		self.trace.lock().finalized.entry(self.id).or_default().push(Finalized {
+			time,
+			hash,
+			number,
+			round,
+		});
+
+		Ok(())
+	}
+
+	fn prevote_equivocation(
+		&self,
+		_set_id: u64,
+		_round: u64,
+		_equivocation: Equivocation<Id, Prevote<Hash, BlockNumber>, Signature>,
+	) {
+	}
+
+	fn precommit_equivocation(
+		&self,
+		_set_id: u64,
+		_round: u64,
+		_equivocation: Equivocation<Id, Precommit<Hash, BlockNumber>, Signature>,
+	) {
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn simulation(config: SimulationConfig) -> (Simulation, Hash) {
+		let simulation = Simulation::new(config);
+		let best = *simulation
+			.with_chain(|chain| chain.push_blocks(GENESIS_HASH, 10))
+			.last()
+			.unwrap();
+		(simulation, best)
+	}
+
+	#[test]
+	fn all_nodes_finalize_best_block() {
+		let (mut simulation, best) = simulation(SimulationConfig::default());
+
+		let trace = simulation.run(30_000);
+		assert!(trace.errors.is_empty());
+		assert!(simulation.is_safe(&trace));
+
+		for id in 0..4 {
+			assert_eq!(trace.last_finalized(id).map(|f| (f.hash, f.number)), Some((best, 10)));
+		}
+	}
+
+	#[test]
+	fn runs_are_reproducible() {
+		let config = SimulationConfig {
+			seed: 42,
+			voters: 7,
+			default_link: Link { min_latency: 10, max_latency: 500, drop_rate: 0.05 },
+			..Default::default()
+		};
+
+		let run = |config| {
+			let (mut simulation, best) = simulation(config);
+			simulation.run(10_000);
+			simulation.with_chain(|chain| chain.push_blocks(best, 5));
+			simulation.run(20_000)
+		};
+
+		let trace = run(config.clone());
+		assert!(trace.finalized.values().any(|finalized| !finalized.is_empty()));
+		assert_eq!(trace, run(config.clone()));
+		assert_ne!(trace, run(SimulationConfig { seed: 43, ..config }));
+	}
+
+	#[test]
+	fn isolated_node_catches_up_after_partition_heals() {
+		let (mut simulation, best) = simulation(SimulationConfig::default());
+		simulation.add_partition(Partition { start: 0, end: 10_000, groups: vec![vec![0, 1, 2]] });
+
+		// the remaining nodes have enough weight to keep finalizing.
+		let trace = simulation.run(10_000);
+		for id in 0..3 {
+			assert_eq!(trace.last_finalized(id).map(|f| f.hash), Some(best));
+		}
+		assert_eq!(trace.last_finalized(3), None);
+
+		let trace = simulation.run(20_000);
+		assert!(simulation.is_safe(&trace));
+		assert_eq!(trace.last_finalized(3).map(|f| f.hash), Some(best));
+		assert!(trace.last_finalized(3).unwrap().time >= 10_000);
+	}
+
+	#[test]
+	fn no_finality_without_supermajority() {
+		let (mut simulation, _) = simulation(SimulationConfig::default());
+		simulation.add_partition(Partition {
+			start: 0,
+			end: 10_000,
+			groups: vec![vec![0, 1], vec![2, 3]],
+		});
+
+		let trace = simulation.run(10_000);
+		assert!(trace.finalized.is_empty());
+
+		let trace = simulation.run(20_000);
+		assert!(simulation.is_safe(&trace));
+		assert_eq!(trace.finalized.len(), 4);
+	}
+}
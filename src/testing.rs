@@ -136,6 +136,20 @@ pub mod chain {
 
 			Ok(ancestry)
 		}
+
+		fn hash_at(&self, mut block: &'static str, number: u32) -> Option<&'static str> {
+			loop {
+				let record = self.inner.get(block)?;
+				if record.number == number {
+					return Some(block)
+				}
+				if record.number < number {
+					return None
+				}
+
+				block = record.parent;
+			}
+		}
 	}
 }
 
@@ -144,9 +158,12 @@ pub mod environment {
 	use super::chain::*;
 	use crate::{
 		round::State as RoundState,
-		voter::{Callback, CommunicationIn, CommunicationOut, RoundData},
+		voter::{
+			Callback, CatchUpRejection, CommunicationIn, CommunicationOut, Metrics, RoundData,
+			SelfEquivocationGuard, VoterPersistentState,
+		},
 		Chain, Commit, Equivocation, Error, HistoricalVotes, Message, Precommit, Prevote,
-		PrimaryPropose, SignedMessage,
+		PrimaryPropose, SignatureVerifier, SignedMessage,
 	};
 	use futures::{
 		channel::mpsc::{self, UnboundedReceiver, UnboundedSender},
@@ -168,6 +185,20 @@ pub mod environment {
 	#[derive(Debug, Clone, PartialEq, Eq)]
 	pub struct Signature(pub u32);
 
+	/// The metrics recorded by an environment, see [`Environment::recording_metrics`].
+	#[derive(Debug, Default, Clone)]
+	pub struct RecordedMetrics {
+		pub prevote_ghost_times: usize,
+		pub completable_times: usize,
+		pub finalization_times: usize,
+		pub completed_round_weights: Vec<(u64, u64)>,
+		pub rounds_per_finalized_block: Vec<f64>,
+		pub commits_imported: usize,
+		pub commits_broadcast: usize,
+		pub catch_ups_accepted: usize,
+		pub catch_ups_rejected: Vec<CatchUpRejection>,
+	}
+
 	pub struct Environment {
 		chain: Mutex<DummyChain>,
 		local_id: Id,
@@ -176,6 +207,11 @@ pub mod environment {
 			Vec<UnboundedSender<(&'static str, u32, Commit<&'static str, u32, Signature, Id>)>>,
 		>,
 		last_completed_and_concluded: Mutex<(u64, u64)>,
+		verify_signatures: bool,
+		persisted_states: Mutex<Vec<VoterPersistentState<&'static str, u32, Signature, Id>>>,
+		equivocation_guard: SelfEquivocationGuard<&'static str, u32>,
+		record_metrics: bool,
+		metrics: Mutex<RecordedMetrics>,
 	}
 
 	impl Environment {
@@ -186,9 +222,32 @@ pub mod environment {
 				network,
 				listeners: Mutex::new(Vec::new()),
 				last_completed_and_concluded: Mutex::new((0, 0)),
+				verify_signatures: false,
+				persisted_states: Mutex::new(Vec::new()),
+				equivocation_guard: SelfEquivocationGuard::new(),
+				record_metrics: false,
+				metrics: Mutex::new(RecordedMetrics::default()),
 			}
 		}
 
+		/// Check the signatures on incoming messages, a signature being valid
+		/// if it matches the id of the signer.
+		pub fn verifying_signatures(mut self) -> Self {
+			self.verify_signatures = true;
+			self
+		}
+
+		/// Record the metrics updated by the voter.
+		pub fn recording_metrics(mut self) -> Self {
+			self.record_metrics = true;
+			self
+		}
+
+		/// Get the metrics recorded so far.
+		pub fn recorded_metrics(&self) -> RecordedMetrics {
+			self.metrics.lock().clone()
+		}
+
 		pub fn with_chain<F, U>(&self, f: F) -> U
 		where
 			F: FnOnce(&mut DummyChain) -> U,
@@ -206,6 +265,13 @@ pub mod environment {
 			rx
 		}
 
+		/// Get all voter states persisted so far, oldest first.
+		pub fn persisted_states(
+			&self,
+		) -> Vec<VoterPersistentState<&'static str, u32, Signature, Id>> {
+			self.persisted_states.lock().clone()
+		}
+
 		/// Get the last completed and concluded rounds.
 		pub fn last_completed_and_concluded(&self) -> (u64, u64) {
 			self.last_completed_and_concluded.lock().clone()
@@ -220,6 +286,64 @@ pub mod environment {
 		) -> Result<Vec<&'static str>, Error> {
 			self.chain.lock().ancestry(base, block)
 		}
+
+		fn hash_at(&self, block: &'static str, number: u32) -> Option<&'static str> {
+			self.chain.lock().hash_at(block, number)
+		}
+	}
+
+	impl SignatureVerifier<&'static str, u32, Signature, Id> for Environment {
+		fn verify(
+			&self,
+			_set_id: u64,
+			_round: u64,
+			_message: &Message<&'static str, u32>,
+			signature: &Signature,
+			id: &Id,
+		) -> bool {
+			signature.0 == id.0
+		}
+	}
+
+	impl Metrics for Environment {
+		fn observe_prevote_ghost_time(&self, _elapsed: Duration) {
+			self.metrics.lock().prevote_ghost_times += 1;
+		}
+
+		fn observe_completable_time(&self, _elapsed: Duration) {
+			self.metrics.lock().completable_times += 1;
+		}
+
+		fn observe_finalization_time(&self, _elapsed: Duration) {
+			self.metrics.lock().finalization_times += 1;
+		}
+
+		fn observe_completed_round_weight(&self, prevote_weight: u64, precommit_weight: u64) {
+			self.metrics
+				.lock()
+				.completed_round_weights
+				.push((prevote_weight, precommit_weight));
+		}
+
+		fn observe_rounds_per_finalized_block(&self, rounds: f64) {
+			self.metrics.lock().rounds_per_finalized_block.push(rounds);
+		}
+
+		fn inc_commits_imported(&self) {
+			self.metrics.lock().commits_imported += 1;
+		}
+
+		fn inc_commits_broadcast(&self) {
+			self.metrics.lock().commits_broadcast += 1;
+		}
+
+		fn inc_catch_ups_accepted(&self) {
+			self.metrics.lock().catch_ups_accepted += 1;
+		}
+
+		fn inc_catch_ups_rejected(&self, reason: CatchUpRejection) {
+			self.metrics.lock().catch_ups_rejected.push(reason);
+		}
 	}
 
 	impl crate::voter::Environment<&'static str, u32> for Environment {
@@ -240,16 +364,38 @@ pub mod environment {
 			Box::new(future::ok(self.chain.lock().best_chain_containing(base)))
 		}
 
-		fn round_data(&self, round: u64) -> RoundData<Self::Id, Self::Timer, Self::In, Self::Out> {
+		fn round_data(
+			&self,
+			set_id: u64,
+			round: u64,
+		) -> RoundData<Self::Id, Self::Timer, Self::In, Self::Out> {
 			const GOSSIP_DURATION: Duration = Duration::from_millis(500);
 
-			let (incoming, outgoing) = self.network.make_round_comms(round, self.local_id);
+			let (incoming, outgoing) = self.network.make_round_comms(set_id, round, self.local_id);
 			RoundData {
 				voter_id: Some(self.local_id),
 				prevote_timer: Box::new(Delay::new(GOSSIP_DURATION).map(Ok)),
 				precommit_timer: Box::new(Delay::new(GOSSIP_DURATION + GOSSIP_DURATION).map(Ok)),
 				incoming: Box::new(incoming),
-				outgoing: Box::pin(outgoing),
+				outgoing: Box::pin(self.equivocation_guard.guard(set_id, round, outgoing)),
+			}
+		}
+
+		fn signature_verifier(
+			&self,
+		) -> Option<&dyn SignatureVerifier<&'static str, u32, Signature, Id>> {
+			if self.verify_signatures {
+				Some(self)
+			} else {
+				None
+			}
+		}
+
+		fn metrics(&self) -> Option<&dyn Metrics> {
+			if self.record_metrics {
+				Some(self)
+			} else {
+				None
 			}
 		}
 
@@ -263,8 +409,17 @@ pub mod environment {
 			Box::new(Delay::new(delay).map(Ok))
 		}
 
+		fn persist_state(
+			&self,
+			state: &VoterPersistentState<&'static str, u32, Signature, Id>,
+		) -> Result<(), Error> {
+			self.persisted_states.lock().push(state.clone());
+			Ok(())
+		}
+
 		fn completed(
 			&self,
+			_set_id: u64,
 			round: u64,
 			_state: RoundState<&'static str, u32>,
 			_base: (&'static str, u32),
@@ -276,6 +431,7 @@ pub mod environment {
 
 		fn concluded(
 			&self,
+			_set_id: u64,
 			round: u64,
 			_state: RoundState<&'static str, u32>,
 			_base: (&'static str, u32),
@@ -289,6 +445,7 @@ pub mod environment {
 			&self,
 			hash: &'static str,
 			number: u32,
+			_set_id: u64,
 			_round: u64,
 			commit: Commit<&'static str, u32, Signature, Id>,
 		) -> Result<(), Error> {
@@ -314,6 +471,7 @@ pub mod environment {
 
 		fn proposed(
 			&self,
+			_set_id: u64,
 			_round: u64,
 			_propose: PrimaryPropose<&'static str, u32>,
 		) -> Result<(), Self::Error> {
@@ -322,6 +480,7 @@ pub mod environment {
 
 		fn prevoted(
 			&self,
+			_set_id: u64,
 			_round: u64,
 			_prevote: Prevote<&'static str, u32>,
 		) -> Result<(), Self::Error> {
@@ -330,6 +489,7 @@ pub mod environment {
 
 		fn precommitted(
 			&self,
+			_set_id: u64,
 			_round: u64,
 			_precommit: Precommit<&'static str, u32>,
 		) -> Result<(), Self::Error> {
@@ -338,6 +498,7 @@ pub mod environment {
 
 		fn prevote_equivocation(
 			&self,
+			_set_id: u64,
 			round: u64,
 			equivocation: Equivocation<Id, Prevote<&'static str, u32>, Signature>,
 		) {
@@ -346,6 +507,7 @@ pub mod environment {
 
 		fn precommit_equivocation(
 			&self,
+			_set_id: u64,
 			round: u64,
 			equivocation: Equivocation<Id, Precommit<&'static str, u32>, Signature>,
 		) {
@@ -353,6 +515,33 @@ pub mod environment {
 		}
 	}
 
+	impl crate::voter::ObserverEnvironment<&'static str, u32> for Environment {
+		type Id = Id;
+		type Signature = Signature;
+		type Error = Error;
+
+		fn best_chain_containing(&self, base: &'static str) -> Option<(&'static str, u32)> {
+			self.chain.lock().best_chain_containing(base)
+		}
+
+		fn finalize_block(
+			&self,
+			hash: &'static str,
+			number: u32,
+			set_id: u64,
+			round: u64,
+			commit: Commit<&'static str, u32, Signature, Id>,
+		) -> Result<(), Error> {
+			crate::voter::Environment::finalize_block(self, hash, number, set_id, round, commit)
+		}
+
+		fn signature_verifier(
+			&self,
+		) -> Option<&dyn SignatureVerifier<&'static str, u32, Signature, Id>> {
+			crate::voter::Environment::signature_verifier(self)
+		}
+	}
+
 	// p2p network data for a round.
 	struct BroadcastNetwork<M> {
 		receiver: UnboundedReceiver<M>,
@@ -433,13 +622,14 @@ pub mod environment {
 	/// A test network. Instantiate this with `make_network`,
 	#[derive(Clone)]
 	pub struct Network {
-		rounds: Arc<Mutex<HashMap<u64, RoundNetwork>>>,
+		rounds: Arc<Mutex<HashMap<(u64, u64), RoundNetwork>>>,
 		global_messages: Arc<Mutex<GlobalMessageNetwork>>,
 	}
 
 	impl Network {
 		pub fn make_round_comms(
 			&self,
+			set_id: u64,
 			round_number: u64,
 			node_id: Id,
 		) -> (
@@ -447,14 +637,13 @@ pub mod environment {
 			impl Sink<Message<&'static str, u32>, Error = Error>,
 		) {
 			let mut rounds = self.rounds.lock();
-			rounds
-				.entry(round_number)
-				.or_insert_with(RoundNetwork::new)
-				.add_node(move |message| SignedMessage {
+			rounds.entry((set_id, round_number)).or_insert_with(RoundNetwork::new).add_node(
+				move |message| SignedMessage {
 					message,
 					signature: Signature(node_id.0),
 					id: node_id,
-				})
+				},
+			)
 		}
 
 		pub fn make_global_comms(
@@ -465,8 +654,10 @@ pub mod environment {
 		) {
 			let mut global_messages = self.global_messages.lock();
 			global_messages.add_node(|message| match message {
-				CommunicationOut::Commit(r, commit) =>
-					CommunicationIn::Commit(r, commit.into(), Callback::Blank),
+				CommunicationOut::Commit(set_id, r, commit) =>
+					CommunicationIn::Commit(set_id, r, commit.into(), Callback::Blank),
+				CommunicationOut::Neighbor(packet) =>
+					CommunicationIn::Neighbor(packet, Callback::Blank),
 			})
 		}
 
@@ -478,7 +669,7 @@ pub mod environment {
 
 	/// the network routing task.
 	pub struct NetworkRouting {
-		rounds: Arc<Mutex<HashMap<u64, RoundNetwork>>>,
+		rounds: Arc<Mutex<HashMap<(u64, u64), RoundNetwork>>>,
 		global_messages: Arc<Mutex<GlobalMessageNetwork>>,
 	}
 
@@ -19,65 +19,265 @@
 use crate::std::{
 	collections::{BTreeMap, BTreeSet},
 	fmt::Debug,
+	iter,
+	marker::PhantomData,
 	ops::AddAssign,
 	vec::Vec,
 };
 
 use super::{BlockNumberOps, Chain, Error};
 
+#[cfg(feature = "derive-codec")]
+use parity_scale_codec::{Decode, Encode};
+#[cfg(feature = "derive-codec")]
+use scale_info::TypeInfo;
+
+// the blocks between a vote-node and its ancestor vote-node.
+#[derive(Clone)]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
+#[cfg_attr(feature = "derive-codec", derive(Encode, Decode, TypeInfo))]
+enum Ancestry<H, N> {
+	// ancestor hashes in reverse order, e.g. the first entry is the parent
+	// and the last entry is the hash of the ancestor vote-node.
+	Full(Vec<H>),
+	// only the ancestor vote-node and its number. the blocks in between are
+	// looked up with `Chain::hash_at`.
+	Compressed(Option<(H, N)>),
+}
+
+impl<H: Clone, N: BlockNumberOps> Ancestry<H, N> {
+	// the ancestry of a vote-node without ancestor, of the same kind.
+	fn empty(&self) -> Self {
+		match self {
+			Ancestry::Full(_) => Ancestry::Full(Vec::new()),
+			Ancestry::Compressed(_) => Ancestry::Compressed(None),
+		}
+	}
+
+	// the hash of the ancestor vote-node.
+	fn node(&self) -> Option<H> {
+		match self {
+			Ancestry::Full(ancestors) => ancestors.last().cloned(),
+			Ancestry::Compressed(ancestor) => ancestor.as_ref().map(|(hash, _)| hash.clone()),
+		}
+	}
+
+	fn is_empty(&self) -> bool {
+		match self {
+			Ancestry::Full(ancestors) => ancestors.is_empty(),
+			Ancestry::Compressed(ancestor) => ancestor.is_none(),
+		}
+	}
+
+	// split the ancestry of a vote-node with the given number at the block with
+	// the given hash and number, keeping the blocks above it, including the block.
+	// returns the ancestry of the block.
+	//
+	// example: splitting number 10 at ancestor 4
+	// before: [9 8 7 6 5 4 3 2 1]
+	// after: [9 8 7 6 5 4], [3 2 1]
+	fn split_at(&mut self, own_number: N, hash: H, number: N) -> Self {
+		match self {
+			Ancestry::Full(ancestors) => {
+				let offset: usize = (own_number - number).as_();
+				Ancestry::Full(ancestors.drain(offset..).collect())
+			},
+			Ancestry::Compressed(ancestor) =>
+				Ancestry::Compressed(ancestor.replace((hash, number))),
+		}
+	}
+}
+
+#[derive(Clone)]
 #[cfg_attr(any(feature = "std", test), derive(Debug))]
+#[cfg_attr(feature = "derive-codec", derive(Encode, Decode, TypeInfo))]
 struct Entry<H, N, V> {
 	number: N,
-	// ancestor hashes in reverse order, e.g. ancestors[0] is the parent
-	// and the last entry is the hash of the parent vote-node.
-	ancestors: Vec<H>,
+	ancestry: Ancestry<H, N>,
 	descendents: Vec<H>, // descendent vote-nodes
 	cumulative_vote: V,
+	// the generation of the graph when a vote was last inserted on this node
+	// or one of its descendents.
+	changed: u64,
 }
 
 impl<H: Ord + PartialEq + Clone, N: BlockNumberOps, V> Entry<H, N, V> {
-	// whether the given hash, number pair is a direct ancestor of this node.
-	// `None` signifies that the graph must be traversed further back.
-	fn in_direct_ancestry(&self, hash: &H, number: N) -> Option<bool> {
-		self.ancestor_block(number).map(|h| h == hash)
+	// whether the given hash, number pair is a direct ancestor of this node,
+	// whose hash is `own_hash`. `None` signifies that the graph must be traversed
+	// further back.
+	fn in_direct_ancestry<C: Chain<H, N>>(
+		&self,
+		own_hash: &H,
+		hash: &H,
+		number: N,
+		chain: &C,
+	) -> Result<Option<bool>, Error> {
+		Ok(self.ancestor_block(own_hash, number, chain)?.map(|h| &h == hash))
 	}
 
 	// Get ancestor block by number. Returns `None` if there is no block
-	// by that number in the direct ancestry.
-	fn ancestor_block(&self, number: N) -> Option<&H> {
+	// by that number in the direct ancestry, and an error if the block
+	// can't be looked up on the chain.
+	fn ancestor_block<C: Chain<H, N>>(
+		&self,
+		own_hash: &H,
+		number: N,
+		chain: &C,
+	) -> Result<Option<H>, Error> {
 		if number >= self.number {
-			return None
+			return Ok(None)
 		}
-		let offset = self.number - number - N::one();
 
-		self.ancestors.get(offset.as_())
+		match self.ancestry {
+			Ancestry::Full(ref ancestors) => {
+				let offset = self.number - number - N::one();
+				Ok(ancestors.get(offset.as_()).cloned())
+			},
+			Ancestry::Compressed(Some((ref ancestor, ancestor_number))) =>
+				if number < ancestor_number {
+					Ok(None)
+				} else if number == ancestor_number {
+					Ok(Some(ancestor.clone()))
+				} else {
+					// the block is between this node and its ancestor vote-node,
+					// so the chain must know it.
+					chain.hash_at(own_hash.clone(), number).map(Some).ok_or(Error::NotDescendent)
+				},
+			Ancestry::Compressed(None) => Ok(None),
+		}
+	}
+
+	// get the parent block, `None` for the base.
+	fn parent_block<C: Chain<H, N>>(&self, own_hash: &H, chain: &C) -> Result<Option<H>, Error> {
+		if self.ancestry.is_empty() {
+			return Ok(None)
+		}
+
+		self.ancestor_block(own_hash, self.number - N::one(), chain)
 	}
 
 	// get ancestor vote-node.
 	fn ancestor_node(&self) -> Option<H> {
-		self.ancestors.last().cloned()
+		self.ancestry.node()
+	}
+
+	// the number of blocks between this node and its ancestor vote-node, plus one.
+	fn edge_length(&self) -> usize {
+		match self.ancestry {
+			Ancestry::Full(ref ancestors) => ancestors.len(),
+			Ancestry::Compressed(Some((_, ancestor_number))) =>
+				(self.number - ancestor_number).as_(),
+			Ancestry::Compressed(None) => 0,
+		}
 	}
 }
 
-// a subchain of blocks by hash.
-struct Subchain<H, N> {
-	hashes: Vec<H>, // forward order.
-	best_number: N,
+// a chain without any blocks, used for graphs keeping the full ancestry of their
+// vote-nodes, which never look up blocks on the chain.
+pub(crate) struct NoChain;
+
+impl<H: Eq, N: Copy + BlockNumberOps> Chain<H, N> for NoChain {
+	fn ancestry(&self, _base: H, _block: H) -> Result<Vec<H>, Error> {
+		Err(Error::NotDescendent)
+	}
 }
 
-impl<H: Clone, N: Copy + BlockNumberOps> Subchain<H, N> {
-	fn best(&self) -> Option<(H, N)> {
-		self.hashes.last().map(|x| (x.clone(), self.best_number))
+// unwrap the result of a search done with `NoChain` on a graph keeping the full
+// ancestry of its vote-nodes.
+pub(crate) fn without_chain<T>(result: Result<T, Error>) -> T {
+	match result {
+		Ok(value) => value,
+		Err(_) => panic!("graphs keeping the full ancestry never look up blocks on the chain; qed"),
 	}
 }
 
+mod sealed {
+	pub trait Sealed {}
+
+	impl Sealed for super::FullAncestry {}
+	impl Sealed for super::CompressedAncestry {}
+}
+
+/// How a [`VoteGraph`] keeps the blocks between its vote-nodes, either
+/// [`FullAncestry`] or [`CompressedAncestry`].
+pub trait AncestryKind: sealed::Sealed + Clone {
+	/// Whether only the vote-nodes themselves are kept.
+	const COMPRESSED: bool;
+}
+
+/// A [`VoteGraph`] keeping all the blocks between its vote-nodes, which can be
+/// searched without a chain. See [`VoteGraph::new`].
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
+pub enum FullAncestry {}
+
+impl AncestryKind for FullAncestry {
+	const COMPRESSED: bool = false;
+}
+
+/// A [`VoteGraph`] keeping only its vote-nodes, which must be searched with a
+/// chain. See [`VoteGraph::new_compressed`].
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
+pub enum CompressedAncestry {}
+
+impl AncestryKind for CompressedAncestry {
+	const COMPRESSED: bool = true;
+}
+
+/// The vote-node a GHOST search started from, along with the generation of the
+/// graph at the time, see [`VoteGraph::find_ghost_since`].
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
+pub struct GhostCheckpoint<H> {
+	node: H,
+	generation: u64,
+}
+
+/// A vote-node of a [`VoteGraph`], see [`VoteGraph::export`].
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
+pub struct ExportedNode<H, N, W> {
+	/// The hash of the block.
+	pub hash: H,
+	/// The number of the block.
+	pub number: N,
+	/// The ancestor vote-node, `None` for the base of the graph.
+	pub ancestor: Option<H>,
+	/// The length of the compressed edge from the ancestor vote-node, i.e.
+	/// the number of blocks between them plus one.
+	pub edge_length: usize,
+	/// The cumulative vote on the block and its descendents.
+	pub weight: W,
+}
+
+/// The vote-nodes of a [`VoteGraph`], see [`VoteGraph::export`].
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
+pub struct ExportedGraph<H, N, W> {
+	/// The base block of the graph.
+	pub base: (H, N),
+	/// The hashes of the vote-nodes without descendents.
+	pub heads: Vec<H>,
+	/// The vote-nodes, ordered by number and hash.
+	pub nodes: Vec<ExportedNode<H, N, W>>,
+}
+
 /// Maintains a DAG of blocks in the chain which have votes attached to them,
-/// and vote data which is accumulated along edges.
-pub struct VoteGraph<H: Ord + Eq, N, V> {
+/// and vote data which is accumulated along edges. The blocks along the edges
+/// are either kept in the graph, or looked up on the chain when needed, see
+/// [`VoteGraph::new_compressed`].
+#[derive(Clone)]
+#[cfg_attr(feature = "derive-codec", derive(Encode, Decode, TypeInfo))]
+#[cfg_attr(feature = "derive-codec", scale_info(skip_type_params(A)))]
+pub struct VoteGraph<H: Ord + Eq, N, V, A = FullAncestry> {
 	entries: BTreeMap<H, Entry<H, N, V>>,
 	heads: BTreeSet<H>,
 	base: H,
 	base_number: N,
+	// incremented on every change to the graph.
+	generation: u64,
+	ancestry: PhantomData<A>,
 }
 
 impl<H, N, V> VoteGraph<H, N, V>
@@ -88,21 +288,125 @@ where
 {
 	/// Create a new `VoteGraph` with base node as given.
 	pub fn new(base_hash: H, base_number: N, base_node: V) -> Self {
+		Self::with_base(base_hash, base_number, base_node)
+	}
+
+	/// Find the block with the highest block number in the chain with the given head
+	/// which fulfills the given condition.
+	///
+	/// Returns `None` if the given head is not in the graph or no node fulfills the
+	/// given condition.
+	pub fn find_ancestor<F>(&self, hash: H, number: N, condition: F) -> Option<(H, N)>
+	where
+		F: Fn(&V) -> bool,
+	{
+		without_chain(self.find_ancestor_with_chain(hash, number, &NoChain, condition))
+	}
+
+	/// Find the total vote on a given block.
+	pub fn cumulative_vote(&self, hash: H, number: N) -> V {
+		without_chain(self.cumulative_vote_with_chain(hash, number, &NoChain))
+	}
+
+	/// Find the best GHOST descendent of the given block.
+	/// Pass a closure used to evaluate the cumulative vote value.
+	///
+	/// The GHOST (hash, number) returned will be the block with highest number for which the
+	/// cumulative votes of descendents and itself causes the closure to evaluate to true.
+	///
+	/// This assumes that the evaluation closure is one which returns true for at most a single
+	/// descendent of a block, in that only one fork of a block can be "heavy"
+	/// enough to trigger the threshold.
+	///
+	/// Returns `None` when the given `current_best` does not fulfill the condition.
+	pub fn find_ghost<F>(&self, current_best: Option<(H, N)>, condition: F) -> Option<(H, N)>
+	where
+		F: Fn(&V) -> bool,
+	{
+		without_chain(self.find_ghost_with_chain(current_best, &NoChain, condition))
+	}
+
+	/// Find the best GHOST descendent of `current_best` like
+	/// [`VoteGraph::find_ghost`], but only search again if votes were inserted
+	/// under the vote-node the previous search started from, as recorded in
+	/// `checkpoint`. Otherwise `current_best` is returned as is.
+	///
+	/// `current_best` must be the result of the previous search, and the
+	/// checkpoint must be reset to `None` whenever the condition changes for
+	/// the same votes, e.g. when the weight of an equivocator is added.
+	pub fn find_ghost_since<F>(
+		&self,
+		current_best: Option<(H, N)>,
+		checkpoint: &mut Option<GhostCheckpoint<H>>,
+		condition: F,
+	) -> Option<(H, N)>
+	where
+		F: Fn(&V) -> bool,
+	{
+		without_chain(self.find_ghost_since_with_chain(
+			current_best,
+			checkpoint,
+			&NoChain,
+			condition,
+		))
+	}
+}
+
+impl<H, N, V> VoteGraph<H, N, V, CompressedAncestry>
+where
+	H: Eq + Clone + Ord + Debug,
+	V: for<'a> AddAssign<&'a V> + Default + Clone + Debug,
+	N: Copy + Debug + BlockNumberOps,
+{
+	/// Create a new `VoteGraph` with base node as given, which only keeps the
+	/// vote-nodes themselves. The blocks between them are looked up with
+	/// [`Chain::hash_at`] when needed, so the memory taken by the graph doesn't
+	/// grow with the distance of the votes from the base, at the cost of more
+	/// lookups on the chain.
+	///
+	/// The graph can only be searched with the methods taking a chain, e.g.
+	/// [`VoteGraph::find_ghost_with_chain`], which fail if the chain doesn't
+	/// support [`Chain::hash_at`]. A GHOST search looks up a number of blocks
+	/// logarithmic in the length of the edges it walks, for each vote-node
+	/// at the end of them.
+	pub fn new_compressed(base_hash: H, base_number: N, base_node: V) -> Self {
+		Self::with_base(base_hash, base_number, base_node)
+	}
+}
+
+impl<H, N, V, A: AncestryKind> VoteGraph<H, N, V, A>
+where
+	H: Eq + Clone + Ord + Debug,
+	V: for<'a> AddAssign<&'a V> + Default + Clone + Debug,
+	N: Copy + Debug + BlockNumberOps,
+{
+	fn with_base(base_hash: H, base_number: N, base_node: V) -> Self {
+		let ancestry =
+			if A::COMPRESSED { Ancestry::Compressed(None) } else { Ancestry::Full(Vec::new()) };
+
 		let mut entries = BTreeMap::new();
 		entries.insert(
 			base_hash.clone(),
 			Entry {
 				number: base_number,
-				ancestors: Vec::new(),
+				ancestry,
 				descendents: Vec::new(),
 				cumulative_vote: base_node,
+				changed: 0,
 			},
 		);
 
 		let mut heads = BTreeSet::new();
 		heads.insert(base_hash.clone());
 
-		VoteGraph { entries, heads, base: base_hash, base_number }
+		VoteGraph {
+			entries,
+			heads,
+			base: base_hash,
+			base_number,
+			generation: 0,
+			ancestry: PhantomData,
+		}
 	}
 
 	/// Get the base block.
@@ -110,6 +414,56 @@ where
 		(self.base.clone(), self.base_number)
 	}
 
+	// whether the base is a node of the graph, with the right number. always
+	// holds unless the graph was decoded from untrusted data.
+	#[cfg(feature = "derive-codec")]
+	pub(crate) fn has_base_node(&self) -> bool {
+		self.entries.get(&self.base).map_or(false, |entry| entry.number == self.base_number)
+	}
+
+	// whether the ancestor and descendent links of the vote-nodes match each
+	// other, the heads are the vote-nodes without descendents, and the
+	// cumulative vote on each vote-node covers the ones on its descendents,
+	// according to `covers`. always holds unless the graph was decoded from
+	// untrusted data.
+	#[cfg(feature = "derive-codec")]
+	pub(crate) fn is_well_formed(&self, covers: impl Fn(&V, &V) -> bool) -> bool {
+		let heads_match = self.heads.iter().all(|head| self.entries.contains_key(head)) &&
+			self.entries
+				.iter()
+				.all(|(hash, entry)| entry.descendents.is_empty() == self.heads.contains(hash));
+
+		heads_match &&
+			self.entries.iter().all(|(hash, entry)| {
+				let same_kind = matches!(entry.ancestry, Ancestry::Compressed(_)) == A::COMPRESSED;
+
+				// only the base has no ancestor vote-node.
+				let ancestor_resolves = match entry.ancestor_node() {
+					None => *hash == self.base,
+					Some(ancestor) => self.entries.get(&ancestor).map_or(false, |ancestor| {
+						ancestor.number < entry.number &&
+							entry.edge_length() == (entry.number - ancestor.number).as_() &&
+							ancestor.descendents.contains(hash)
+					}),
+				};
+
+				let descendents_resolve = entry.descendents.iter().all(|descendent| {
+					self.entries.get(descendent).map_or(false, |descendent| {
+						descendent.ancestor_node().as_ref() == Some(hash) &&
+							covers(&entry.cumulative_vote, &descendent.cumulative_vote)
+					})
+				});
+
+				same_kind && ancestor_resolves && descendents_resolve
+			})
+	}
+
+	// the cumulative votes on the vote-nodes of the graph.
+	#[cfg(feature = "derive-codec")]
+	pub(crate) fn votes(&self) -> impl Iterator<Item = &V> + '_ {
+		self.entries.values().map(|entry| &entry.cumulative_vote)
+	}
+
 	/// Adjust the base of the graph. The new base must be an ancestor of the
 	/// old base.
 	///
@@ -138,17 +492,25 @@ where
 			new_number
 		};
 
+		self.generation += 1;
 		let entry = {
 			let old_entry =
 				self.entries.get_mut(&self.base).expect("base hash entry always exists; qed");
 
-			old_entry.ancestors.extend(ancestry_proof.iter().cloned());
+			let ancestry = old_entry.ancestry.empty();
+			match old_entry.ancestry {
+				Ancestry::Full(ref mut ancestors) =>
+					ancestors.extend(ancestry_proof.iter().cloned()),
+				Ancestry::Compressed(ref mut ancestor) =>
+					*ancestor = Some((new_hash.clone(), new_number)),
+			}
 
 			Entry {
 				number: new_number,
-				ancestors: Vec::new(),
+				ancestry,
 				descendents: vec![self.base.clone()],
 				cumulative_vote: old_entry.cumulative_vote.clone(),
+				changed: self.generation,
 			}
 		};
 
@@ -157,6 +519,52 @@ where
 		self.base_number = new_number;
 	}
 
+	/// Move the base of the graph forward to the given block, which must be in
+	/// the graph, e.g. once it has been finalized. The vote-nodes which don't
+	/// descend from the new base are discarded, along with their votes, and
+	/// the cumulative votes of the remaining vote-nodes are unaffected.
+	///
+	/// Returns an error if the block is neither a vote-node nor on the
+	/// ancestor-edge of one.
+	pub fn prune_to<C: Chain<H, N>>(&mut self, hash: H, number: N, chain: &C) -> Result<(), Error> {
+		if hash == self.base {
+			return Ok(())
+		}
+
+		match self.find_containing_nodes(hash.clone(), number, chain)? {
+			None if self.entries.get(&hash).map(|entry| entry.number) == Some(number) => {},
+			Some(ref containing) if !containing.is_empty() => {
+				self.generation += 1;
+				self.introduce_branch(containing.clone(), hash.clone(), number, chain);
+			},
+			_ => return Err(Error::NotDescendent),
+		}
+
+		// keep the new base and its descendents only.
+		let mut kept = BTreeSet::new();
+		let mut to_visit = vec![hash.clone()];
+		while let Some(node) = to_visit.pop() {
+			let entry = self
+				.entries
+				.get(&node)
+				.expect("descendents always present in node storage; qed");
+			to_visit.extend(entry.descendents.iter().cloned());
+			kept.insert(node);
+		}
+
+		self.entries.retain(|node, _| kept.contains(node));
+		self.heads.retain(|node| kept.contains(node));
+
+		self.generation += 1;
+		let entry = self.entries.get_mut(&hash).expect("new base is a vote-node; qed");
+		entry.ancestry = entry.ancestry.empty();
+
+		self.base = hash;
+		self.base_number = number;
+
+		Ok(())
+	}
+
 	/// Insert a vote with given value into the graph at given hash and number.
 	pub fn insert<C: Chain<H, N>, W>(
 		&mut self,
@@ -168,11 +576,14 @@ where
 	where
 		V: for<'a> AddAssign<&'a W>,
 	{
-		if let Some(containing) = self.find_containing_nodes(hash.clone(), number) {
+		self.generation += 1;
+		let generation = self.generation;
+
+		if let Some(containing) = self.find_containing_nodes(hash.clone(), number, chain)? {
 			if containing.is_empty() {
 				self.append(hash.clone(), number, chain)?;
 			} else {
-				self.introduce_branch(containing, hash.clone(), number);
+				self.introduce_branch(containing, hash.clone(), number, chain);
 			}
 		} else {
 			// this entry already exists
@@ -188,6 +599,7 @@ where
 				.expect("vote-node and its ancestry always exist after initial phase; qed");
 
 			active_entry.cumulative_vote += &vote;
+			active_entry.changed = generation;
 
 			match active_entry.ancestor_node() {
 				Some(parent) => inspecting_hash = parent,
@@ -199,34 +611,37 @@ where
 	}
 
 	/// Find the block with the highest block number in the chain with the given head
-	/// which fulfills the given condition.
+	/// which fulfills the given condition, like [`VoteGraph::find_ancestor`].
+	/// The blocks between the vote-nodes of a compressed graph are looked up
+	/// on the given chain.
 	///
-	/// Returns `None` if the given head is not in the graph or no node fulfills the
-	/// given condition.
-	pub fn find_ancestor<'a, F>(
-		&'a self,
+	/// Returns an error if the chain can't look up one of those blocks.
+	pub fn find_ancestor_with_chain<C, F>(
+		&self,
 		mut hash: H,
 		mut number: N,
+		chain: &C,
 		condition: F,
-	) -> Option<(H, N)>
+	) -> Result<Option<(H, N)>, Error>
 	where
+		C: Chain<H, N>,
 		F: Fn(&V) -> bool,
 	{
 		loop {
-			match self.find_containing_nodes(hash.clone(), number) {
+			match self.find_containing_nodes(hash.clone(), number, chain)? {
 				None => {
 					// The block has a vote-node in the graph.
 					let node =
 						self.entries.get(&hash).expect("by defn of find_containing_nodes; qed");
 					// If the weight is sufficient, we are done.
 					if condition(&node.cumulative_vote) {
-						return Some((hash, number))
+						return Ok(Some((hash, number)))
 					}
 					// Not enough weight, check the parent block.
-					match node.ancestors.iter().next() {
-						None => return None,
-						Some(a) => {
-							hash = a.clone();
+					match node.parent_block(&hash, chain)? {
+						None => return Ok(None),
+						Some(parent) => {
+							hash = parent;
 							number = node.number - N::one();
 						},
 					}
@@ -235,28 +650,27 @@ where
 					// If there are no vote-nodes below the block in the graph,
 					// the block is not in the graph at all.
 					if children.is_empty() {
-						return None
+						return Ok(None)
 					}
 					// The block is "contained" in the graph (i.e. in the ancestry-chain
 					// of at least one vote-node) but does not itself have a vote-node.
 					// Check if the accumulated weight on all child vote-nodes is sufficient.
 					let mut v = V::default();
 					for c in &children {
-						let e = self.entries.get(&c).expect("all children in graph; qed");
+						let e = self.entries.get(c).expect("all children in graph; qed");
 						v += &e.cumulative_vote;
 					}
 					if condition(&v) {
-						return Some((hash, number))
+						return Ok(Some((hash, number)))
 					}
 
 					// Not enough weight, check the parent block.
 					let child = children.last().expect("children not empty; qed");
 					let entry = self.entries.get(child).expect("all children in graph; qed");
-					let offset = (entry.number - number).as_();
-					match entry.ancestors.get(offset) {
-						None => return None, // Reached base without sufficient weight.
+					match entry.ancestor_block(child, number - N::one(), chain)? {
+						None => return Ok(None), // Reached base without sufficient weight.
 						Some(parent) => {
-							hash = parent.clone();
+							hash = parent;
 							number = number - N::one();
 						},
 					}
@@ -265,8 +679,17 @@ where
 		}
 	}
 
-	/// Find the total vote on a given block.
-	pub fn cumulative_vote<'a>(&'a self, hash: H, number: N) -> V {
+	/// Find the total vote on a given block, like [`VoteGraph::cumulative_vote`].
+	/// The blocks between the vote-nodes of a compressed graph are looked up
+	/// on the given chain.
+	///
+	/// Returns an error if the chain can't look up one of those blocks.
+	pub fn cumulative_vote_with_chain<'a, C: Chain<H, N>>(
+		&'a self,
+		hash: H,
+		number: N,
+		chain: &C,
+	) -> Result<V, Error> {
 		let entries = &self.entries;
 		let get_node = |hash: &_| -> &'a _ {
 			entries
@@ -274,7 +697,7 @@ where
 				.expect("node either base or referenced by other in graph; qed")
 		};
 
-		match self.find_containing_nodes(hash.clone(), number.clone()) {
+		Ok(match self.find_containing_nodes(hash.clone(), number, chain)? {
 			None => get_node(&hash).cumulative_vote.clone(),
 			Some(nodes) => {
 				let mut v = Default::default();
@@ -284,22 +707,87 @@ where
 
 				v
 			},
-		}
+		})
 	}
 
-	/// Find the best GHOST descendent of the given block.
-	/// Pass a closure used to evaluate the cumulative vote value.
-	///
-	/// The GHOST (hash, number) returned will be the block with highest number for which the
-	/// cumulative votes of descendents and itself causes the closure to evaluate to true.
+	/// Export the vote-nodes of the graph, e.g. to inspect it while debugging.
+	/// The cumulative vote on each node is mapped with `weight`.
+	pub fn export<W>(&self, weight: impl Fn(&V) -> W) -> ExportedGraph<H, N, W> {
+		let mut nodes = self
+			.entries
+			.iter()
+			.map(|(hash, entry)| ExportedNode {
+				hash: hash.clone(),
+				number: entry.number,
+				ancestor: entry.ancestor_node(),
+				edge_length: entry.edge_length(),
+				weight: weight(&entry.cumulative_vote),
+			})
+			.collect::<Vec<_>>();
+		nodes.sort_by(|a, b| (a.number, &a.hash).cmp(&(b.number, &b.hash)));
+
+		ExportedGraph { base: self.base(), heads: self.heads.iter().cloned().collect(), nodes }
+	}
+
+	/// Find the best GHOST descendent of the given block, like
+	/// [`VoteGraph::find_ghost`]. The blocks between the vote-nodes of a
+	/// compressed graph are looked up on the given chain.
 	///
-	/// This assumes that the evaluation closure is one which returns true for at most a single
-	/// descendent of a block, in that only one fork of a block can be "heavy"
-	/// enough to trigger the threshold.
+	/// Returns an error if the chain can't look up one of those blocks.
+	pub fn find_ghost_with_chain<C, F>(
+		&self,
+		current_best: Option<(H, N)>,
+		chain: &C,
+		condition: F,
+	) -> Result<Option<(H, N)>, Error>
+	where
+		C: Chain<H, N>,
+		F: Fn(&V) -> bool,
+	{
+		Ok(self.ghost_search(current_best, chain, condition)?.0)
+	}
+
+	/// Find the best GHOST descendent of `current_best` since the given
+	/// checkpoint, like [`VoteGraph::find_ghost_since`]. The blocks between the
+	/// vote-nodes of a compressed graph are looked up on the given chain.
 	///
-	/// Returns `None` when the given `current_best` does not fulfill the condition.
-	pub fn find_ghost<'a, F>(&'a self, current_best: Option<(H, N)>, condition: F) -> Option<(H, N)>
+	/// Returns an error if the chain can't look up one of those blocks.
+	pub fn find_ghost_since_with_chain<C, F>(
+		&self,
+		current_best: Option<(H, N)>,
+		checkpoint: &mut Option<GhostCheckpoint<H>>,
+		chain: &C,
+		condition: F,
+	) -> Result<Option<(H, N)>, Error>
 	where
+		C: Chain<H, N>,
+		F: Fn(&V) -> bool,
+	{
+		if let Some(GhostCheckpoint { node, generation }) = checkpoint {
+			if matches!(self.entries.get(node), Some(entry) if entry.changed <= *generation) {
+				return Ok(current_best)
+			}
+		}
+
+		let (ghost, start) = self.ghost_search(current_best, chain, condition)?;
+		*checkpoint = ghost
+			.as_ref()
+			.map(|_| GhostCheckpoint { node: start, generation: self.generation });
+
+		Ok(ghost)
+	}
+
+	// find the best GHOST descendent of the given block, along with the vote-node
+	// the search started from, whose descendents comprise all the vote-nodes the
+	// result depends on.
+	fn ghost_search<'a, C, F>(
+		&'a self,
+		current_best: Option<(H, N)>,
+		chain: &C,
+		condition: F,
+	) -> Result<(Option<(H, N)>, H), Error>
+	where
+		C: Chain<H, N>,
 		F: Fn(&V) -> bool,
 	{
 		let entries = &self.entries;
@@ -309,9 +797,8 @@ where
 				.expect("node either base or referenced by other in graph; qed")
 		};
 
-		let (mut node_key, mut force_constrain) = current_best
-			.clone()
-			.and_then(|(hash, number)| match self.find_containing_nodes(hash.clone(), number) {
+		let start = match current_best.clone() {
+			Some((hash, number)) => match self.find_containing_nodes(hash.clone(), number, chain)? {
 				None => Some((hash, false)),
 				Some(ref x) if !x.is_empty() => {
 					let ancestor = get_node(&x[0])
@@ -321,30 +808,37 @@ where
 					Some((ancestor, true))
 				},
 				Some(_) => None,
-			})
-			.unwrap_or_else(|| (self.base.clone(), false));
+			},
+			None => None,
+		};
+		let (mut node_key, mut force_constrain) =
+			start.unwrap_or_else(|| (self.base.clone(), false));
 
+		let start = node_key.clone();
 		let mut active_node = get_node(&node_key);
 
 		if !condition(&active_node.cumulative_vote) {
-			return None
+			return Ok((None, start))
 		}
 
 		// breadth-first search starting from this node.
 		loop {
-			let next_descendent = active_node
-				.descendents
-				.iter()
-				.map(|d| (d.clone(), get_node(d)))
-				.filter(|&(_, node)| {
-					// take only descendents with our block in the ancestry.
-					if let (true, Some(&(ref h, n))) = (force_constrain, current_best.as_ref()) {
-						node.in_direct_ancestry(h, n).unwrap_or(false)
-					} else {
-						true
+			let mut next_descendent = None;
+			for d in &active_node.descendents {
+				let node = get_node(d);
+
+				// take only descendents with our block in the ancestry.
+				if let (true, Some(&(ref h, n))) = (force_constrain, current_best.as_ref()) {
+					if node.in_direct_ancestry(d, h, n, chain)? != Some(true) {
+						continue
 					}
-				})
-				.find(|&(_, node)| condition(&node.cumulative_vote));
+				}
+
+				if condition(&node.cumulative_vote) {
+					next_descendent = Some((d.clone(), node));
+					break
+				}
+			}
 
 			match next_descendent {
 				Some((key, node)) => {
@@ -362,83 +856,117 @@ where
 		// its descendents comprise frontier of vote-nodes which individually don't have enough votes
 		// to pass the threshold but some subset of them join either at `active_node`'s block or at some
 		// descendent block of it, giving that block sufficient votes.
-		self.ghost_find_merge_point(
+		let ghost = self.ghost_find_merge_point(
 			node_key,
 			active_node,
 			if force_constrain { current_best } else { None },
+			chain,
 			condition,
-		)
-		.best()
+		)?;
+
+		Ok((Some(ghost), start))
 	}
 
 	// given a key, node pair (which must correspond), assuming this node fulfills the condition,
 	// this function will find the highest point at which its descendents merge, which may be the
 	// node itself.
-	fn ghost_find_merge_point<'a, F>(
+	//
+	// going up from the node, the cumulative vote of the descendents only ever gets split
+	// further between forks, so the highest merge point is found with a binary search. this
+	// takes a number of block lookups logarithmic in the distance to the descendents, rather
+	// than linear, which matters for compressed graphs with long edges.
+	fn ghost_find_merge_point<'a, C, F>(
 		&'a self,
 		node_key: H,
 		active_node: &'a Entry<H, N, V>,
 		force_constrain: Option<(H, N)>,
+		chain: &C,
 		condition: F,
-	) -> Subchain<H, N>
+	) -> Result<(H, N), Error>
 	where
+		C: Chain<H, N>,
 		F: Fn(&V) -> bool,
 	{
-		let mut descendent_nodes: Vec<_> = active_node
-			.descendents
-			.iter()
-			.map(|h| self.entries.get(h).expect("descendents always present in node storage; qed"))
-			.filter(|n| {
-				if let Some((ref h, num)) = force_constrain {
-					n.in_direct_ancestry(h, num).unwrap_or(false)
-				} else {
-					true
+		let mut descendent_nodes = Vec::with_capacity(active_node.descendents.len());
+		for d_hash in &active_node.descendents {
+			let d_node =
+				self.entries.get(d_hash).expect("descendents always present in node storage; qed");
+
+			if let Some((ref h, num)) = force_constrain {
+				if d_node.in_direct_ancestry(d_hash, h, num, chain)? != Some(true) {
+					continue
 				}
-			})
-			.collect();
-
-		let base_number = active_node.number;
-		let mut best_number = active_node.number;
-		let mut descendent_blocks = Vec::with_capacity(descendent_nodes.len());
-		let mut hashes = vec![node_key];
+			}
 
-		// TODO: for long ranges of blocks this could get inefficient
-		let mut offset = N::zero();
-		loop {
-			offset = offset + N::one();
+			descendent_nodes.push((d_hash, d_node));
+		}
 
-			let mut new_best = None;
-			for d_node in &descendent_nodes {
-				if let Some(d_block) = d_node.ancestor_block(base_number + offset) {
-					match descendent_blocks.binary_search_by_key(&d_block, |&(ref x, _)| x) {
+		// the block with the given number at which descendents with enough cumulative
+		// vote merge, if any.
+		let merge_at = |number: N| -> Result<Option<H>, Error> {
+			let mut descendent_blocks: Vec<(H, V)> = Vec::with_capacity(descendent_nodes.len());
+			for (d_hash, d_node) in &descendent_nodes {
+				if let Some(d_block) = d_node.ancestor_block(d_hash, number, chain)? {
+					match descendent_blocks.binary_search_by(|(x, _)| x.cmp(&d_block)) {
 						Ok(idx) => {
 							descendent_blocks[idx].1 += &d_node.cumulative_vote;
 							if condition(&descendent_blocks[idx].1) {
-								new_best = Some(d_block.clone());
-								break
+								return Ok(Some(d_block))
 							}
 						},
-						Err(idx) => descendent_blocks
-							.insert(idx, (d_block.clone(), d_node.cumulative_vote.clone())),
+						Err(idx) =>
+							descendent_blocks.insert(idx, (d_block, d_node.cumulative_vote.clone())),
 					}
 				}
 			}
 
-			match new_best {
-				Some(new_best) => {
-					best_number = best_number + N::one();
+			Ok(None)
+		};
 
-					descendent_blocks.clear();
-					descendent_nodes
-						.retain(|n| n.in_direct_ancestry(&new_best, best_number).unwrap_or(false));
+		let base_number = active_node.number;
+		let mut best = (node_key, base_number);
 
-					hashes.push(new_best);
-				},
-				None => break,
+		// descendents can only merge below the highest of them.
+		let range = match descendent_nodes.iter().map(|(_, d_node)| d_node.number).max() {
+			Some(highest) => highest - base_number - N::one(),
+			None => return Ok(best),
+		};
+
+		// the powers of two up to the range, to search by decreasing steps.
+		let mut steps = Vec::new();
+		let mut step = N::one();
+		while step <= range {
+			steps.push(step);
+			if step > range - step {
+				break
 			}
+			step = step + step;
 		}
 
-		Subchain { hashes, best_number }
+		for step in steps.into_iter().rev() {
+			if best.1 - base_number > range - step {
+				continue
+			}
+
+			let number = best.1 + step;
+			if let Some(block) = merge_at(number)? {
+				best = (block, number);
+			}
+		}
+
+		Ok(best)
+	}
+
+	// the vote-nodes which are the given block or have it in their ancestor-edge.
+	pub(crate) fn nodes_containing<C: Chain<H, N>>(
+		&self,
+		hash: H,
+		number: N,
+		chain: &C,
+	) -> Result<Vec<H>, Error> {
+		Ok(self
+			.find_containing_nodes(hash.clone(), number, chain)?
+			.unwrap_or_else(|| iter::once(hash).collect()))
 	}
 
 	// attempts to find the containing node keys for the given hash and number.
@@ -446,9 +974,14 @@ where
 	// returns `None` if there is a node by that key already, and a vector
 	// (potentially empty) of nodes with the given block in its ancestor-edge
 	// otherwise.
-	fn find_containing_nodes(&self, hash: H, number: N) -> Option<Vec<H>> {
+	fn find_containing_nodes<C: Chain<H, N>>(
+		&self,
+		hash: H,
+		number: N,
+		chain: &C,
+	) -> Result<Option<Vec<H>>, Error> {
 		if self.entries.contains_key(&hash) {
-			return None
+			return Ok(None)
 		}
 
 		let mut containing_keys = Vec::new();
@@ -470,7 +1003,7 @@ where
 					break
 				}
 
-				match active_entry.in_direct_ancestry(&hash, number) {
+				match active_entry.in_direct_ancestry(&head, &hash, number, chain)? {
 					Some(true) => {
 						// set containing node and continue search.
 						containing_keys.push(head.clone());
@@ -487,7 +1020,7 @@ where
 			}
 		}
 
-		Some(containing_keys)
+		Ok(Some(containing_keys))
 	}
 
 	// introduce a branch to given vote-nodes.
@@ -497,35 +1030,42 @@ where
 	// This function panics if any member of `descendents` is not a vote-node
 	// or does not have ancestor with given hash and number OR if `ancestor_hash`
 	// is already a known entry.
-	fn introduce_branch(&mut self, descendents: Vec<H>, ancestor_hash: H, ancestor_number: N) {
+	fn introduce_branch<C: Chain<H, N>>(
+		&mut self,
+		descendents: Vec<H>,
+		ancestor_hash: H,
+		ancestor_number: N,
+		chain: &C,
+	) {
+		let generation = self.generation;
 		let produced_entry = descendents.into_iter().fold(None, |mut maybe_entry, descendent| {
 			let entry = self
 				.entries
 				.get_mut(&descendent)
 				.expect("this function only invoked with keys of vote-nodes; qed");
 
-			debug_assert!(entry.in_direct_ancestry(&ancestor_hash, ancestor_number).unwrap());
+			debug_assert!(matches!(
+				entry.in_direct_ancestry(&descendent, &ancestor_hash, ancestor_number, chain),
+				Ok(Some(true)),
+			));
 
-			// example: splitting number 10 at ancestor 4
-			// before: [9 8 7 6 5 4 3 2 1]
-			// after: [9 8 7 6 5 4], [3 2 1]
-			// we ensure the `entry.ancestors` is drained regardless of whether
+			// we ensure the ancestry of `entry` is split regardless of whether
 			// the `new_entry` has already been constructed.
 			{
 				let prev_ancestor = entry.ancestor_node();
-				let offset_usize: usize = if ancestor_number > entry.number {
+				if ancestor_number > entry.number {
 					panic!("this function only invoked with direct ancestors; qed")
-				} else {
-					(entry.number - ancestor_number).as_()
-				};
-				let new_ancestors = entry.ancestors.drain(offset_usize..);
+				}
+				let new_ancestry =
+					entry.ancestry.split_at(entry.number, ancestor_hash.clone(), ancestor_number);
 
 				let &mut (ref mut new_entry, _) = maybe_entry.get_or_insert_with(move || {
 					let new_entry = Entry {
 						number: ancestor_number,
-						ancestors: new_ancestors.collect(),
+						ancestry: new_ancestry,
 						descendents: vec![],
 						cumulative_vote: V::default(),
+						changed: generation,
 					};
 
 					(new_entry, prev_ancestor)
@@ -559,33 +1099,40 @@ where
 	// append a vote-node onto the chain-tree. This should only be called if
 	// no node in the tree keeps the target anyway.
 	fn append<C: Chain<H, N>>(&mut self, hash: H, number: N, chain: &C) -> Result<(), Error> {
-		let mut ancestry = chain.ancestry(self.base.clone(), hash.clone())?;
-		ancestry.push(self.base.clone()); // ancestry doesn't include base.
-
-		let mut ancestor_index = None;
-		for (i, ancestor) in ancestry.iter().enumerate() {
-			if let Some(entry) = self.entries.get_mut(ancestor) {
-				entry.descendents.push(hash.clone());
-				ancestor_index = Some(i);
-				break
-			}
-		}
-
-		let ancestor_index = ancestor_index.expect(
-			"base is kept; \
-			chain returns ancestry only if the block is a descendent of base; qed",
-		);
+		let base = self.entries.get(&self.base).expect("base hash entry always exists; qed");
+		let ancestry = match base.ancestry {
+			Ancestry::Full(_) => {
+				let mut ancestry = chain.ancestry(self.base.clone(), hash.clone())?;
+				ancestry.push(self.base.clone()); // ancestry doesn't include base.
+
+				let ancestor_index = ancestry.iter().position(|a| self.entries.contains_key(a));
+				let ancestor_index = ancestor_index.expect(
+					"base is kept; \
+					chain returns ancestry only if the block is a descendent of base; qed",
+				);
+
+				ancestry.truncate(ancestor_index + 1);
+				Ancestry::Full(ancestry)
+			},
+			Ancestry::Compressed(_) =>
+				Ancestry::Compressed(Some(self.find_ancestor_node(&hash, number, chain)?)),
+		};
 
-		let ancestor_hash = ancestry[ancestor_index].clone();
-		ancestry.truncate(ancestor_index + 1);
+		let ancestor_hash = ancestry.node().expect("appended nodes always have an ancestor; qed");
+		self.entries
+			.get_mut(&ancestor_hash)
+			.expect("ancestor is a vote-node; qed")
+			.descendents
+			.push(hash.clone());
 
 		self.entries.insert(
 			hash.clone(),
 			Entry {
 				number,
-				ancestors: ancestry,
+				ancestry,
 				descendents: Vec::new(),
 				cumulative_vote: V::default(),
+				changed: self.generation,
 			},
 		);
 
@@ -594,6 +1141,44 @@ where
 
 		Ok(())
 	}
+
+	// find the closest ancestor vote-node of the given block, looking up the
+	// ancestors of the block by number.
+	fn find_ancestor_node<C: Chain<H, N>>(
+		&self,
+		hash: &H,
+		number: N,
+		chain: &C,
+	) -> Result<(H, N), Error> {
+		if number <= self.base_number ||
+			chain.hash_at(hash.clone(), self.base_number).as_ref() != Some(&self.base)
+		{
+			return Err(Error::NotDescendent)
+		}
+
+		let mut ancestor = (self.base.clone(), self.base_number);
+		loop {
+			let entry = self.entries.get(&ancestor.0).expect("vote-nodes are in the graph; qed");
+			let next = entry.descendents.iter().find_map(|d| {
+				let d_number = self
+					.entries
+					.get(d)
+					.expect("descendents always present in node storage; qed")
+					.number;
+
+				if d_number < number && chain.hash_at(hash.clone(), d_number).as_ref() == Some(d) {
+					Some((d.clone(), d_number))
+				} else {
+					None
+				}
+			});
+
+			match next {
+				Some(next) => ancestor = next,
+				None => return Ok(ancestor),
+			}
+		}
+	}
 }
 
 #[cfg(test)]
@@ -799,7 +1384,10 @@ mod tests {
 
 		for block in test_cases {
 			let number = chain.number(block);
-			assert_eq!(tracker.find_ancestor(block, number, |&x| x >= 20).unwrap(), ("C", 4));
+			assert_eq!(
+				tracker.find_ancestor(block, number, |&x| x >= 20).unwrap(),
+				("C", 4)
+			);
 		}
 	}
 
@@ -833,6 +1421,52 @@ mod tests {
 		assert_eq!(tracker.entries.get(GENESIS_HASH).unwrap().cumulative_vote, 15);
 	}
 
+	#[test]
+	fn prune_to() {
+		fn check<A: AncestryKind>(mut tracker: VoteGraph<&'static str, u32, u32, A>) {
+			let mut chain = DummyChain::new();
+
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E", "F"]);
+			chain.push_blocks("E", &["EA", "EB", "EC", "ED"]);
+			chain.push_blocks("F", &["FA", "FB", "FC"]);
+
+			tracker.insert("FC", 10, 5, &chain).unwrap();
+			tracker.insert("ED", 10, 7, &chain).unwrap();
+			tracker.insert("EA", 7, 3, &chain).unwrap();
+			tracker.insert("B", 3, 2, &chain).unwrap();
+
+			// "E" isn't a vote-node yet.
+			tracker.prune_to("E", 6, &chain).unwrap();
+			assert_eq!(tracker.base(), ("E", 6));
+			assert_eq!(tracker.cumulative_vote_with_chain("E", 6, &chain).unwrap(), 15);
+			assert_eq!(tracker.cumulative_vote_with_chain("EB", 8, &chain).unwrap(), 7);
+			assert!(!tracker.entries.contains_key("B"));
+			assert!(!tracker.entries.contains_key(GENESIS_HASH));
+
+			tracker.prune_to("FA", 8, &chain).unwrap();
+			assert_eq!(tracker.base(), ("FA", 8));
+			assert_eq!(tracker.cumulative_vote_with_chain("FA", 8, &chain).unwrap(), 5);
+			assert_eq!(tracker.heads.iter().collect::<Vec<_>>(), vec![&"FC"]);
+			assert_eq!(tracker.entries.keys().collect::<Vec<_>>(), vec![&"FA", &"FC"]);
+
+			// blocks which aren't in the graph.
+			assert_eq!(tracker.prune_to("EB", 8, &chain), Err(Error::NotDescendent));
+			assert_eq!(tracker.prune_to("F", 7, &chain), Err(Error::NotDescendent));
+			assert_eq!(tracker.prune_to("FB", 8, &chain), Err(Error::NotDescendent));
+
+			assert_eq!(tracker.insert("ED", 10, 1, &chain), Err(Error::NotDescendent));
+			tracker.insert("FB", 9, 1, &chain).unwrap();
+			assert_eq!(tracker.cumulative_vote_with_chain("FA", 8, &chain).unwrap(), 6);
+			assert_eq!(
+				tracker.find_ghost_with_chain(None, &chain, |&x| x >= 6).unwrap(),
+				Some(("FB", 9))
+			);
+		}
+
+		check(VoteGraph::new(GENESIS_HASH, 1, 0u32));
+		check(VoteGraph::new_compressed(GENESIS_HASH, 1, 0u32));
+	}
+
 	#[test]
 	fn find_ancestor_is_largest() {
 		let mut chain = DummyChain::new();
@@ -855,4 +1489,426 @@ mod tests {
 		// `actual` used to (incorrectly) be (genesis, 0)
 		assert_eq!(actual, ("A", 1));
 	}
+
+	#[test]
+	fn export_graph() {
+		let mut chain = DummyChain::new();
+		let mut tracker = VoteGraph::new(GENESIS_HASH, 1, 0u32);
+
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C"]);
+		chain.push_blocks("C", &["D1", "E1", "F1"]);
+		chain.push_blocks("C", &["D2", "E2", "F2"]);
+
+		tracker.insert("A", 2, 100, &chain).unwrap();
+		tracker.insert("E1", 6, 100, &chain).unwrap();
+		tracker.insert("F2", 7, 100, &chain).unwrap();
+
+		let exported = tracker.export(|vote| vote * 2);
+		assert_eq!(exported.base, (GENESIS_HASH, 1));
+		assert_eq!(exported.heads, vec!["E1", "F2"]);
+		assert_eq!(
+			exported.nodes,
+			vec![
+				ExportedNode {
+					hash: GENESIS_HASH,
+					number: 1,
+					ancestor: None,
+					edge_length: 0,
+					weight: 600
+				},
+				ExportedNode {
+					hash: "A",
+					number: 2,
+					ancestor: Some(GENESIS_HASH),
+					edge_length: 1,
+					weight: 600
+				},
+				ExportedNode {
+					hash: "E1",
+					number: 6,
+					ancestor: Some("A"),
+					edge_length: 4,
+					weight: 200
+				},
+				ExportedNode {
+					hash: "F2",
+					number: 7,
+					ancestor: Some("A"),
+					edge_length: 5,
+					weight: 200
+				},
+			]
+		);
+
+		assert_eq!(tracker.nodes_containing("D2", 5, &chain).unwrap(), vec!["F2"]);
+		assert_eq!(tracker.nodes_containing("C", 4, &chain).unwrap(), vec!["E1", "F2"]);
+		assert_eq!(tracker.nodes_containing("A", 2, &chain).unwrap(), vec!["A"]);
+	}
+
+	// a tree of blocks on top of the genesis block, where `parents` picks the
+	// parent of each block among the blocks before it.
+	fn tree_of_blocks(parents: &[u8]) -> (DummyChain, Vec<(&'static str, u32)>) {
+		const BLOCKS: [&str; 24] = [
+			"A", "B", "C", "D", "E", "F", "G", "H", "I", "J", "K", "L", "M", "N", "O", "P", "Q",
+			"R", "S", "T", "U", "V", "W", "X",
+		];
+
+		let mut chain = DummyChain::new();
+		let mut blocks = vec![(GENESIS_HASH, 1)];
+		for (i, parent) in parents.iter().take(BLOCKS.len()).enumerate() {
+			let (parent, number) = blocks[*parent as usize % blocks.len()];
+			chain.push_blocks(parent, &[BLOCKS[i]]);
+			blocks.push((BLOCKS[i], number + 1));
+		}
+
+		(chain, blocks)
+	}
+
+	#[test]
+	fn find_ghost_since_matches_find_ghost() {
+		use quickcheck::{QuickCheck, TestResult};
+
+		// `parents` shapes a tree of blocks, on which the `votes` are cast.
+		fn prop(parents: Vec<u8>, votes: Vec<(u8, u8)>) -> TestResult {
+			if parents.is_empty() || votes.is_empty() {
+				return TestResult::discard()
+			}
+
+			let (chain, blocks) = tree_of_blocks(&parents);
+			let votes = votes
+				.iter()
+				.map(|(block, weight)| {
+					(blocks[*block as usize % blocks.len()], *weight as u32 % 10 + 1)
+				})
+				.collect::<Vec<_>>();
+			let threshold = votes.iter().map(|(_, weight)| weight).sum::<u32>() * 2 / 3 + 1;
+			let condition = |vote: &u32| *vote >= threshold;
+
+			let mut tracker = VoteGraph::new(GENESIS_HASH, 1, 0u32);
+			let (mut ghost, mut incremental_ghost, mut checkpoint) = (None, None, None);
+			for ((hash, number), weight) in votes {
+				tracker.insert(hash, number, weight, &chain).unwrap();
+
+				ghost = tracker.find_ghost(ghost, condition);
+				incremental_ghost =
+					tracker.find_ghost_since(incremental_ghost, &mut checkpoint, condition);
+				if ghost != incremental_ghost {
+					return TestResult::failed()
+				}
+			}
+
+			TestResult::passed()
+		}
+
+		QuickCheck::new().quickcheck(prop as fn(Vec<u8>, Vec<(u8, u8)>) -> TestResult);
+	}
+
+	#[test]
+	fn compressed_graph_matches_full_graph() {
+		use quickcheck::{QuickCheck, TestResult};
+
+		// `parents` shapes a tree of blocks, on which the `votes` are cast.
+		fn prop(parents: Vec<u8>, votes: Vec<(u8, u8)>) -> TestResult {
+			if parents.is_empty() || votes.is_empty() {
+				return TestResult::discard()
+			}
+
+			let (chain, blocks) = tree_of_blocks(&parents);
+
+			let mut full = VoteGraph::new(GENESIS_HASH, 1, 0u32);
+			let mut compressed = VoteGraph::new_compressed(GENESIS_HASH, 1, 0u32);
+			let mut total = 0;
+			for (block, weight) in votes {
+				let (hash, number) = blocks[block as usize % blocks.len()];
+				let weight = weight as u32 % 10 + 1;
+				total += weight;
+
+				full.insert(hash, number, weight, &chain).unwrap();
+				compressed.insert(hash, number, weight, &chain).unwrap();
+				if full.export(|v| *v) != compressed.export(|v| *v) {
+					return TestResult::failed()
+				}
+
+				for threshold in &[total / 2 + 1, total * 2 / 3 + 1] {
+					let condition = |vote: &u32| vote >= threshold;
+					if full.find_ghost(None, condition) !=
+						compressed.find_ghost_with_chain(None, &chain, condition).unwrap()
+					{
+						return TestResult::failed()
+					}
+
+					for &(hash, number) in &blocks {
+						if Ok(full.find_ghost(Some((hash, number)), condition)) !=
+							compressed.find_ghost_with_chain(Some((hash, number)), &chain, condition) ||
+							Ok(full.find_ancestor(hash, number, condition)) !=
+								compressed.find_ancestor_with_chain(hash, number, &chain, condition)
+						{
+							return TestResult::failed()
+						}
+					}
+				}
+
+				for &(hash, number) in &blocks {
+					if full.cumulative_vote(hash, number) !=
+						compressed.cumulative_vote_with_chain(hash, number, &chain).unwrap()
+					{
+						return TestResult::failed()
+					}
+				}
+			}
+
+			TestResult::passed()
+		}
+
+		QuickCheck::new().quickcheck(prop as fn(Vec<u8>, Vec<(u8, u8)>) -> TestResult);
+	}
+
+	#[test]
+	fn compressed_graph_keeps_only_vote_nodes() {
+		let mut chain = DummyChain::new();
+		let blocks = (2..=1000)
+			.map(|number| &*crate::std::boxed::Box::leak(format!("{}", number).into_boxed_str()))
+			.collect::<Vec<_>>();
+		chain.push_blocks(GENESIS_HASH, &blocks);
+		chain.push_blocks("500", &["F1", "F2"]);
+
+		let mut tracker = VoteGraph::new_compressed(GENESIS_HASH, 1, 0u32);
+		tracker.insert("1000", 1000, 10, &chain).unwrap();
+		tracker.insert("F2", 502, 5, &chain).unwrap();
+
+		// the chains of both votes merge far from their vote-nodes.
+		assert_eq!(
+			tracker.find_ghost_with_chain(None, &chain, |&x| x >= 15).unwrap(),
+			Some(("500", 500))
+		);
+		assert_eq!(
+			tracker.find_ancestor_with_chain("1000", 1000, &chain, |&x| x >= 15).unwrap(),
+			Some(("500", 500))
+		);
+		assert_eq!(tracker.cumulative_vote_with_chain("700", 700, &chain).unwrap(), 10);
+		assert_eq!(tracker.cumulative_vote_with_chain("400", 400, &chain).unwrap(), 15);
+
+		// introduce a branch where they merge.
+		tracker.insert("500", 500, 1, &chain).unwrap();
+		assert_eq!(tracker.nodes_containing("400", 400, &chain).unwrap(), vec!["500"]);
+		assert_eq!(
+			tracker.find_ghost_with_chain(None, &chain, |&x| x >= 16).unwrap(),
+			Some(("500", 500))
+		);
+		assert_eq!(
+			tracker.find_ghost_with_chain(None, &chain, |&x| x >= 6).unwrap(),
+			Some(("1000", 1000))
+		);
+
+		let entry = tracker.entries.get("1000").unwrap();
+		assert_eq!(entry.ancestor_node(), Some("500"));
+		assert_eq!(entry.edge_length(), 500);
+
+		for (hash, entry) in &tracker.entries {
+			match entry.ancestry {
+				Ancestry::Compressed(Some((ancestor, number))) =>
+					assert_eq!(tracker.entries.get(ancestor).unwrap().number, number),
+				Ancestry::Compressed(None) => assert_eq!(*hash, GENESIS_HASH),
+				Ancestry::Full(_) => panic!("compressed graph only keeps vote-nodes"),
+			}
+		}
+	}
+
+	#[test]
+	fn compressed_graph_fails_without_hash_at() {
+		// a chain which can't look up blocks by number.
+		struct AncestryOnly<'a>(&'a DummyChain);
+
+		impl<'a> Chain<&'static str, u32> for AncestryOnly<'a> {
+			fn ancestry(
+				&self,
+				base: &'static str,
+				block: &'static str,
+			) -> Result<Vec<&'static str>, Error> {
+				self.0.ancestry(base, block)
+			}
+		}
+
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D"]);
+		chain.push_blocks("B", &["C1", "D1"]);
+
+		let mut tracker = VoteGraph::new_compressed(GENESIS_HASH, 1, 0u32);
+		assert_eq!(tracker.insert("D", 5, 1, &AncestryOnly(&chain)), Err(Error::NotDescendent));
+
+		tracker.insert("D", 5, 1, &chain).unwrap();
+		tracker.insert("D1", 5, 1, &chain).unwrap();
+
+		let chain = AncestryOnly(&chain);
+		assert_eq!(tracker.cumulative_vote_with_chain("B", 3, &chain), Err(Error::NotDescendent));
+		assert_eq!(
+			tracker.find_ghost_with_chain(None, &chain, |&x| x >= 2),
+			Err(Error::NotDescendent)
+		);
+		assert_eq!(
+			tracker.find_ancestor_with_chain("D", 5, &chain, |&x| x >= 2),
+			Err(Error::NotDescendent)
+		);
+	}
+
+	#[cfg(feature = "derive-codec")]
+	#[test]
+	fn detects_malformed_graphs() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C"]);
+		chain.push_blocks("C", &["D1", "E1", "F1"]);
+		chain.push_blocks("C", &["D2", "E2", "F2"]);
+
+		let mut tracker = VoteGraph::new(GENESIS_HASH, 1, 0u32);
+		let mut compressed = VoteGraph::new_compressed(GENESIS_HASH, 1, 0u32);
+		for &(hash, number) in &[("A", 2), ("E1", 6), ("F2", 7)] {
+			tracker.insert(hash, number, 100, &chain).unwrap();
+			compressed.insert(hash, number, 100, &chain).unwrap();
+		}
+
+		let covers = |node: &u32, descendent: &u32| node >= descendent;
+		assert!(tracker.is_well_formed(covers));
+		assert!(compressed.is_well_formed(covers));
+
+		// links which don't resolve.
+		let mut graph = tracker.clone();
+		graph.entries.remove("A");
+		assert!(!graph.is_well_formed(covers));
+
+		let mut graph = tracker.clone();
+		graph.entries.get_mut("A").unwrap().descendents.push("F1");
+		assert!(!graph.is_well_formed(covers));
+
+		// links which don't match each other.
+		let mut graph = tracker.clone();
+		graph.entries.get_mut("A").unwrap().descendents.retain(|hash| *hash != "E1");
+		assert!(!graph.is_well_formed(covers));
+
+		let mut graph = tracker.clone();
+		graph.entries.get_mut(GENESIS_HASH).unwrap().descendents.push("E1");
+		assert!(!graph.is_well_formed(covers));
+
+		let mut graph = tracker.clone();
+		graph.entries.get_mut("E1").unwrap().ancestry = Ancestry::Full(vec!["A"]);
+		assert!(!graph.is_well_formed(covers));
+
+		// heads which aren't the vote-nodes without descendents.
+		let mut graph = tracker.clone();
+		graph.heads.insert("A");
+		assert!(!graph.is_well_formed(covers));
+
+		let mut graph = tracker.clone();
+		graph.heads.remove("F2");
+		assert!(!graph.is_well_formed(covers));
+
+		// a cumulative vote missing the votes on a descendent.
+		let mut graph = tracker.clone();
+		graph.entries.get_mut("A").unwrap().cumulative_vote = 50;
+		assert!(!graph.is_well_formed(covers));
+
+		// a vote-node keeping another kind of ancestry than the graph.
+		let mut graph = tracker.clone();
+		graph.entries.get_mut("A").unwrap().ancestry =
+			Ancestry::Compressed(Some((GENESIS_HASH, 1)));
+		assert!(!graph.is_well_formed(covers));
+	}
+
+	#[test]
+	fn compressed_graph_adjust_base() {
+		let mut chain = DummyChain::new();
+		let mut tracker = VoteGraph::new_compressed("E", 6, 0u32);
+
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E", "F"]);
+		chain.push_blocks("E", &["EA", "EB", "EC", "ED"]);
+		chain.push_blocks("F", &["FA", "FB", "FC"]);
+
+		tracker.insert("FC", 10, 5, &chain).unwrap();
+		tracker.insert("ED", 10, 7, &chain).unwrap();
+
+		tracker.adjust_base(&["D", "C", "B", "A"]);
+		assert_eq!(tracker.base(), ("A", 2));
+
+		chain.push_blocks("A", &["3", "4", "5"]);
+		tracker.insert("5", 5, 3, &chain).unwrap();
+
+		assert_eq!(tracker.entries.get("A").unwrap().cumulative_vote, 15);
+		assert_eq!(
+			tracker.find_ancestor_with_chain("EB", 8, &chain, |&x| x > 7).unwrap(),
+			Some(("E", 6))
+		);
+	}
+
+	#[test]
+	fn prune_to_matches_graph_from_new_base() {
+		use quickcheck::{QuickCheck, TestResult};
+
+		// whether pruning a graph with the given votes to `new_base` gives the
+		// same results as a graph with the votes descending from it only.
+		fn matches<A: AncestryKind>(
+			mut pruned: VoteGraph<&'static str, u32, u32, A>,
+			chain: &DummyChain,
+			blocks: &[(&'static str, u32)],
+			votes: &[((&'static str, u32), u32)],
+			(base_hash, base_number): (&'static str, u32),
+		) -> bool {
+			let mut expected = VoteGraph::new(base_hash, base_number, 0u32);
+			let mut kept_votes = 0;
+			for &((hash, number), weight) in votes {
+				pruned.insert(hash, number, weight, chain).unwrap();
+				if chain.is_equal_or_descendent_of(base_hash, hash) {
+					expected.insert(hash, number, weight, chain).unwrap();
+					kept_votes += 1;
+				}
+			}
+
+			if pruned.prune_to(base_hash, base_number, chain).is_err() {
+				return kept_votes == 0
+			}
+
+			let total = expected.cumulative_vote(base_hash, base_number);
+			for &(hash, number) in blocks {
+				if pruned.cumulative_vote_with_chain(hash, number, chain).unwrap() !=
+					expected.cumulative_vote(hash, number)
+				{
+					return false
+				}
+			}
+			for threshold in &[total / 2 + 1, total * 2 / 3 + 1] {
+				let condition = |vote: &u32| vote >= threshold;
+				if pruned.find_ghost_with_chain(None, chain, condition).unwrap() !=
+					expected.find_ghost(None, condition)
+				{
+					return false
+				}
+			}
+
+			true
+		}
+
+		// `parents` shapes a tree of blocks, on which the `votes` are cast before
+		// pruning the graph to `new_base`.
+		fn prop(parents: Vec<u8>, votes: Vec<(u8, u8)>, new_base: u8) -> TestResult {
+			if parents.is_empty() || votes.is_empty() {
+				return TestResult::discard()
+			}
+
+			let (chain, blocks) = tree_of_blocks(&parents);
+			let votes = votes
+				.iter()
+				.map(|(block, weight)| {
+					(blocks[*block as usize % blocks.len()], *weight as u32 % 10 + 1)
+				})
+				.collect::<Vec<_>>();
+			let new_base = blocks[new_base as usize % blocks.len()];
+
+			let full = VoteGraph::new(GENESIS_HASH, 1, 0u32);
+			let compressed = VoteGraph::new_compressed(GENESIS_HASH, 1, 0u32);
+			TestResult::from_bool(
+				matches(full, &chain, &blocks, &votes, new_base) &&
+					matches(compressed, &chain, &blocks, &votes, new_base),
+			)
+		}
+
+		QuickCheck::new().quickcheck(prop as fn(Vec<u8>, Vec<(u8, u8)>, u8) -> TestResult);
+	}
 }
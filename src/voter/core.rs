@@ -0,0 +1,2091 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The voter as a synchronous state machine, without any I/O.
+
+#[cfg(feature = "std")]
+use log::{debug, trace};
+
+use super::{
+	pending_votes::PendingVotes, voting_round::VotingRound, BadCatchUp, BadCommit,
+	CatchUpProcessingOutcome, CatchUpRejection, CommitProcessingOutcome, CommunicationOut,
+	GoodCatchUp, GoodCommit, NeighborPacket, VoterPersistentState, VoterSetChange,
+	VoterSetChangeKind, VotingRule, CATCH_UP_THRESHOLD,
+};
+use crate::{
+	round::{Round, RoundParams, State as RoundState},
+	std::{
+		self,
+		collections::{BTreeMap, VecDeque},
+		vec::Vec,
+	},
+	validate_commit, validate_commit_with_verifier,
+	voter_set::VoterSet,
+	weights::VoteWeight,
+	BlockNumberOps, CatchUp, Chain, Commit, CommitValidationResult, Equivocation, Error,
+	HistoricalVotes, Message, Precommit, Prevote, PrimaryPropose, SignatureVerifier, SignedMessage,
+};
+
+/// The synchronous context a [`VoterCore`] needs to process its inputs: access
+/// to the chain and, optionally, a signature verifier and a voting rule.
+pub trait CoreEnvironment<H: Eq, N: BlockNumberOps, S, Id>: Chain<H, N> {
+	/// The verifier used to check the signatures on incoming votes, commits and
+	/// catch-up messages. By default there is none, in which case the caller is
+	/// responsible for checking signatures.
+	fn signature_verifier(&self) -> Option<&dyn SignatureVerifier<H, N, S, Id>> {
+		None
+	}
+
+	/// The rule restricting the target of our prevotes. By default there is
+	/// none, and we prevote for the answer to [`Output::RequestBestChain`].
+	fn voting_rule(&self) -> Option<&dyn VotingRule<H, N>> {
+		None
+	}
+}
+
+/// The kind of a timer armed by the voter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TimerKind {
+	/// The timer before prevotes can be cast in a round. This should be round
+	/// start + 2T where T is the gossip time estimate.
+	Prevote,
+	/// The timer before precommits can be cast in a round. This should be round
+	/// start + 4T.
+	Precommit,
+	/// The timer delaying the broadcast of a commit for a round. This delay
+	/// should not be static, to minimize the number of commits sent.
+	Commit,
+}
+
+/// Identifies a timer of the voter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TimerId {
+	/// The voter set id.
+	pub set_id: u64,
+	/// The round the timer belongs to.
+	pub round: u64,
+	/// The kind of timer.
+	pub kind: TimerKind,
+}
+
+/// Identifies a request for the best chain containing a block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum BestChainRequest {
+	/// The best chain to prevote on in the given round.
+	Prevote {
+		/// The voter set id.
+		set_id: u64,
+		/// The round number.
+		round: u64,
+	},
+	/// The best chain used to determine whether a forced voter set change is
+	/// due.
+	SetChange {
+		/// The id of the voter set being changed.
+		set_id: u64,
+	},
+}
+
+/// An input to a [`VoterCore`].
+#[derive(Clone, PartialEq)]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
+pub enum Input<H, N, S, Id: Eq + Ord> {
+	/// The round data requested by [`Output::StartRound`]: the id of the local
+	/// voter in the round, if any. The round doesn't progress before.
+	RoundData {
+		/// The voter set id.
+		set_id: u64,
+		/// The round number.
+		round: u64,
+		/// The local voter id.
+		voter_id: Option<Id>,
+	},
+	/// A vote received in the given round, including our own votes. Votes on
+	/// blocks which aren't known yet are buffered until reported with
+	/// [`Input::BlockImported`].
+	Vote {
+		/// The voter set id.
+		set_id: u64,
+		/// The round number.
+		round: u64,
+		/// The vote.
+		vote: SignedMessage<H, N, S, Id>,
+	},
+	/// A commit received for the given round. Answered with
+	/// [`Output::CommitProcessed`].
+	Commit {
+		/// The voter set id.
+		set_id: u64,
+		/// The round number.
+		round: u64,
+		/// The commit.
+		commit: Commit<H, N, S, Id>,
+	},
+	/// A catch-up received. Answered with [`Output::CatchUpProcessed`].
+	CatchUp(CatchUp<H, N, S, Id>),
+	/// A neighbor packet received from a peer, possibly answered with
+	/// [`Output::SendCatchUp`].
+	Neighbor(NeighborPacket<N>),
+	/// A timer armed with [`Output::ArmTimer`] has fired.
+	TimerFired(TimerId),
+	/// The answer to [`Output::RequestBestChain`]: the best block whose chain
+	/// contains the requested base, `None` if the base is unknown.
+	BestChain {
+		/// The request answered.
+		request: BestChainRequest,
+		/// The best block.
+		best: Option<(H, N)>,
+	},
+	/// Schedule a voter set change.
+	SetChange(VoterSetChange<H, N, Id>),
+	/// A block was imported, the buffered votes on it are handled.
+	BlockImported {
+		/// The block hash.
+		hash: H,
+		/// The block number.
+		number: N,
+	},
+}
+
+/// An output of a [`VoterCore`], to be acted on by the caller.
+#[derive(Clone, PartialEq)]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
+pub enum Output<H, N, S, Id> {
+	/// A round was started. Its data must be handed to the core with
+	/// [`Input::RoundData`], and the votes of the round with [`Input::Vote`].
+	StartRound {
+		/// The voter set id.
+		set_id: u64,
+		/// The round number.
+		round: u64,
+	},
+	/// A round was dropped, no more inputs are needed for it.
+	DropRound {
+		/// The voter set id.
+		set_id: u64,
+		/// The round number.
+		round: u64,
+	},
+	/// Arm a timer, to be reported with [`Input::TimerFired`].
+	ArmTimer(TimerId),
+	/// Look up the best block whose chain contains `base`, even if that block
+	/// is `base` itself. To be answered with [`Input::BestChain`].
+	RequestBestChain {
+		/// The request, to be included in the answer.
+		request: BestChainRequest,
+		/// The base block.
+		base: H,
+	},
+	/// Send a message in the given round. Our own votes should be signed and
+	/// handed back to the core with [`Input::Vote`].
+	SendRoundMessage {
+		/// The voter set id.
+		set_id: u64,
+		/// The round number.
+		round: u64,
+		/// The message.
+		message: Message<H, N>,
+	},
+	/// Send a message which is not round-localized.
+	SendGlobalMessage(CommunicationOut<H, N, S, Id>),
+	/// Send a catch-up to the peer whose neighbor packet was just handled.
+	SendCatchUp(CatchUp<H, N, S, Id>),
+	/// The outcome of handling an [`Input::Commit`].
+	CommitProcessed(CommitProcessingOutcome),
+	/// The outcome of handling an [`Input::CatchUp`].
+	CatchUpProcessed(CatchUpProcessingOutcome),
+	/// We've done a primary proposal in the given round.
+	Proposed {
+		/// The voter set id.
+		set_id: u64,
+		/// The round number.
+		round: u64,
+		/// The primary proposal.
+		propose: PrimaryPropose<H, N>,
+	},
+	/// We've prevoted in the given round.
+	Prevoted {
+		/// The voter set id.
+		set_id: u64,
+		/// The round number.
+		round: u64,
+		/// The prevote.
+		prevote: Prevote<H, N>,
+	},
+	/// We've precommitted in the given round.
+	Precommitted {
+		/// The voter set id.
+		set_id: u64,
+		/// The round number.
+		round: u64,
+		/// The precommit.
+		precommit: Precommit<H, N>,
+	},
+	/// Store the persistent state of the voter, see
+	/// [`Environment::persist_state`](super::Environment::persist_state).
+	/// Any outputs following this one must only be acted on once it's stored.
+	PersistState(VoterPersistentState<H, N, S, Id>),
+	/// A round was completed, see
+	/// [`Environment::completed`](super::Environment::completed).
+	Completed {
+		/// The voter set id.
+		set_id: u64,
+		/// The round number.
+		round: u64,
+		/// The state of the round.
+		state: RoundState<H, N>,
+		/// The base block of the round.
+		base: (H, N),
+		/// The votes of the round.
+		votes: HistoricalVotes<H, N, S, Id>,
+	},
+	/// A round was concluded, see
+	/// [`Environment::concluded`](super::Environment::concluded).
+	Concluded {
+		/// The voter set id.
+		set_id: u64,
+		/// The round number.
+		round: u64,
+		/// The state of the round.
+		state: RoundState<H, N>,
+		/// The base block of the round.
+		base: (H, N),
+		/// The votes of the round.
+		votes: HistoricalVotes<H, N, S, Id>,
+	},
+	/// Finalize a block.
+	Finalize {
+		/// The block hash.
+		hash: H,
+		/// The block number.
+		number: N,
+		/// The voter set id.
+		set_id: u64,
+		/// The round the block was finalized in.
+		round: u64,
+		/// The commit justifying the finality of the block.
+		commit: Commit<H, N, S, Id>,
+	},
+	/// An equivocation in prevotes has occurred.
+	PrevoteEquivocation {
+		/// The voter set id.
+		set_id: u64,
+		/// The round number.
+		round: u64,
+		/// The equivocation.
+		equivocation: Equivocation<Id, Prevote<H, N>, S>,
+	},
+	/// An equivocation in precommits has occurred.
+	PrecommitEquivocation {
+		/// The voter set id.
+		set_id: u64,
+		/// The round number.
+		round: u64,
+		/// The equivocation.
+		equivocation: Equivocation<Id, Precommit<H, N>, S>,
+	},
+}
+
+// the queue of outputs of the core.
+pub(super) type Outputs<H, N, S, Id> = VecDeque<Output<H, N, S, Id>>;
+
+// a mutable round along with the output queue of the core.
+type RoundWithOutputs<'a, H, N, S, Id> =
+	(&'a mut VotingRound<H, N, S, Id>, &'a mut Outputs<H, N, S, Id>);
+
+/// Parameters for starting a voter.
+///
+/// If there is no known last completed round, the genesis state (round number
+/// 0, no votes, genesis base) should be provided, see [`VoterParams::new`].
+/// When available, all messages required to complete the last round should be
+/// provided.
+pub struct VoterParams<H, N, S, Id: Ord + Eq> {
+	/// Actors and weights of the voter set.
+	pub voters: VoterSet<Id>,
+	/// The voter set id.
+	pub set_id: u64,
+	/// The number of the last completed round.
+	pub last_round_number: u64,
+	/// The votes of the last completed round.
+	pub last_round_votes: Vec<SignedMessage<H, N, S, Id>>,
+	/// The base block of the last completed round.
+	pub last_round_base: (H, N),
+	/// The last finalized block.
+	pub last_finalized: (H, N),
+}
+
+impl<H: Clone, N: Clone, S, Id: Ord + Eq> VoterParams<H, N, S, Id> {
+	/// Parameters for starting a voter on top of the last finalized block,
+	/// without any known last completed round.
+	pub fn new(voters: VoterSet<Id>, set_id: u64, last_finalized: (H, N)) -> Self {
+		VoterParams {
+			voters,
+			set_id,
+			last_round_number: 0,
+			last_round_votes: Vec::new(),
+			last_round_base: last_finalized.clone(),
+			last_finalized,
+		}
+	}
+}
+
+// the commit logic of a round running in the background.
+struct RoundCommitter<H, N, S, Id> {
+	timer_fired: bool,
+	last_commit: Option<Commit<H, N, S, Id>>,
+}
+
+// a round that is not the best round anymore, kept until its estimate is
+// finalized.
+struct BackgroundRound<H, N, S, Id>
+where
+	H: Clone + Eq + Ord + std::fmt::Debug,
+	N: Copy + BlockNumberOps + std::fmt::Debug,
+	S: Clone + Eq,
+	Id: Clone + Eq + Ord + std::fmt::Debug,
+{
+	inner: VotingRound<H, N, S, Id>,
+	finalized_number: N,
+	round_committer: Option<RoundCommitter<H, N, S, Id>>,
+}
+
+impl<H, N, S, Id> BackgroundRound<H, N, S, Id>
+where
+	H: Clone + Eq + Ord + std::fmt::Debug,
+	N: Copy + BlockNumberOps + std::fmt::Debug,
+	S: Clone + Eq,
+	Id: Clone + Eq + Ord + std::fmt::Debug,
+{
+	fn is_done(&self) -> bool {
+		// no need to listen on a round anymore once the estimate is finalized.
+		//
+		// we map `None` to true because
+		//   - rounds are not backgrounded when incomplete unless we've skipped forward
+		//   - if we skipped forward we may never complete this round and we don't need
+		//     to keep it forever.
+		self.round_committer.is_none() &&
+			self.inner.round_state().estimate.map_or(true, |x| x.1 <= self.finalized_number)
+	}
+
+	fn update_finalized<C: CoreEnvironment<H, N, S, Id>>(
+		&mut self,
+		env: &C,
+		new_finalized: &(H, N),
+	) {
+		self.finalized_number = std::cmp::max(self.finalized_number, new_finalized.1);
+
+		// the forks of the vote-graph which don't include the finalized block
+		// are of no use anymore. we prune down to the block finalized in this round at
+		// most, so that its state doesn't change.
+		let base = match self.inner.finalized() {
+			Some(finalized) if finalized.1 <= new_finalized.1 => finalized.clone(),
+			Some(_) => new_finalized.clone(),
+			None => return,
+		};
+
+		if base.1 > self.inner.dag_base().1 {
+			if let Err(e) = self.inner.prune_to(env, base.clone()) {
+				debug!(target: "afg", "Failed to prune round {} to {:?}: {:?}",
+					self.inner.round_number(),
+					base,
+					e,
+				);
+			}
+		}
+	}
+
+	// import a commit into the round while waiting for the commit timer. returns
+	// whether the commit was valid.
+	fn import_commit<C: CoreEnvironment<H, N, S, Id>>(
+		&mut self,
+		env: &C,
+		commit: Commit<H, N, S, Id>,
+		outputs: &mut Outputs<H, N, S, Id>,
+	) -> Result<bool, Error> {
+		// ignore commits for a block lower than we already finalized
+		if commit.target_number < self.inner.finalized().map_or_else(N::zero, |(_, n)| *n) {
+			return Ok(true)
+		}
+
+		if self.inner.check_and_import_from_commit(env, &commit, outputs)?.is_none() {
+			return Ok(false)
+		}
+
+		if let Some(ref mut committer) = self.round_committer {
+			committer.last_commit = Some(commit);
+		}
+
+		Ok(true)
+	}
+
+	// once the commit timer has fired, get the commit to broadcast, if any. we
+	// only broadcast a commit if we haven't seen one for what we've finalized.
+	fn commit(&mut self) -> Option<Commit<H, N, S, Id>> {
+		if !self.round_committer.as_ref()?.timer_fired {
+			return None
+		}
+
+		let last_commit = self.round_committer.take().and_then(|c| c.last_commit);
+		match (last_commit, self.inner.finalized()) {
+			(None, Some(_)) => self.inner.finalizing_commit().cloned(),
+			(Some(Commit { target_number, .. }), Some((_, finalized_number)))
+				if target_number < *finalized_number =>
+				self.inner.finalizing_commit().cloned(),
+			_ => None,
+		}
+	}
+}
+
+// the state of the best chain query used to determine whether a forced voter
+// set change is due.
+enum ForcedChangeQuery<H, N> {
+	Idle,
+	Pending,
+	Answered(Option<(H, N)>),
+	// answered without the change being due, we check again on the next input.
+	Stale,
+}
+
+/// The voter, as a synchronous state machine without any I/O.
+///
+/// A core is driven by handing it [`Input`]s: incoming votes, commits and
+/// catch-ups, fired timers and answers to best chain requests. In response it
+/// queues [`Output`]s, i.e. messages to send, blocks to finalize, timers to arm
+/// and best chains to look up, which are taken out with
+/// [`VoterCore::next_output`]. It's up to the caller to act on them, in order.
+///
+/// All the state of the voter lives in the core and none of its methods block
+/// or depend on a runtime, which allows embedding the protocol anywhere and
+/// testing it deterministically. The [`Voter`](super::Voter) future is an
+/// adapter driving a core with an [`Environment`](super::Environment).
+pub struct VoterCore<H, N, S, Id>
+where
+	H: Clone + Eq + Ord + std::fmt::Debug,
+	N: Copy + BlockNumberOps + std::fmt::Debug,
+	S: Clone + Eq,
+	Id: Clone + Eq + Ord + std::fmt::Debug,
+{
+	voters: VoterSet<Id>,
+	set_id: u64,
+	best_round: VotingRound<H, N, S, Id>,
+	past_rounds: BTreeMap<u64, BackgroundRound<H, N, S, Id>>,
+	last_finalized: (H, N),
+	// the round and commit that finalized `last_finalized` (if known), broadcast
+	// as the final commit of the voter set when it changes.
+	last_finalized_commit: Option<(u64, Commit<H, N, S, Id>)>,
+	// the commit protocol might finalize further than the current round (if we're
+	// behind), we keep track of last finalized in round so we don't violate any
+	// assumptions from round-to-round.
+	last_finalized_in_rounds: (H, N),
+	pending_set_changes: VecDeque<VoterSetChange<H, N, Id>>,
+	forced_change_query: ForcedChangeQuery<H, N>,
+	// the last neighbor packet we've sent out.
+	last_neighbor_packet: Option<NeighborPacket<N>>,
+	// the votes on blocks which haven't been imported yet.
+	pending_votes: PendingVotes<H, N, S, Id>,
+	outputs: Outputs<H, N, S, Id>,
+}
+
+impl<H, N, S, Id> VoterCore<H, N, S, Id>
+where
+	H: Clone + Eq + Ord + std::fmt::Debug,
+	N: Copy + BlockNumberOps + std::fmt::Debug,
+	S: Clone + Eq,
+	Id: Clone + Eq + Ord + std::fmt::Debug,
+{
+	/// Create a new voter core with the given parameters, see
+	/// [`Voter::new`](super::Voter::new).
+	pub fn new<C: CoreEnvironment<H, N, S, Id>>(env: &C, params: VoterParams<H, N, S, Id>) -> Self {
+		let VoterParams {
+			voters,
+			set_id,
+			last_round_number,
+			last_round_votes,
+			last_round_base,
+			last_finalized,
+		} = params;
+		let mut outputs = VecDeque::new();
+		let mut pending_votes = PendingVotes::new();
+
+		// the last completed round is part of the persistent state.
+		let completed_round =
+			(last_round_number, last_round_base.clone(), last_round_votes.clone());
+
+		// re-start the last round and queue all messages to be processed on first poll.
+		// keep it in the background so we can push the estimate backwards until finalized
+		// by actually waiting for more messages.
+		let mut last_round = None;
+		if last_round_number > 0 {
+			let params = RoundParams {
+				voters: voters.clone(),
+				base: last_round_base.clone(),
+				round_number: last_round_number,
+			};
+			last_round = instantiate_last_round(
+				env,
+				set_id,
+				params,
+				last_round_votes,
+				&mut outputs,
+				&mut pending_votes,
+			);
+
+			// when there is no information about the last completed round,
+			// the best we can do is assume that the estimate == the given base
+			// and that it is finalized. This is always the case for the genesis
+			// round of a set.
+		}
+
+		let (prior_round, last_round_state) = match last_round {
+			Some(ref round) => (Some(last_round_number), round.round_state()),
+			None => (None, RoundState::genesis(last_round_base)),
+		};
+
+		let mut best_round = VotingRound::new(
+			set_id,
+			last_round_number + 1,
+			voters.clone(),
+			last_finalized.clone(),
+			prior_round,
+			last_round_state,
+		);
+		best_round.track_persistent_state(
+			last_finalized.clone(),
+			completed_round.0,
+			completed_round.1,
+			completed_round.2,
+		);
+
+		let mut core = VoterCore {
+			voters,
+			set_id,
+			best_round,
+			past_rounds: BTreeMap::new(),
+			last_finalized: last_finalized.clone(),
+			last_finalized_commit: None,
+			last_finalized_in_rounds: last_finalized,
+			pending_set_changes: VecDeque::new(),
+			forced_change_query: ForcedChangeQuery::Idle,
+			last_neighbor_packet: None,
+			pending_votes,
+			outputs,
+		};
+
+		if let Some(last_round) = last_round {
+			core.start_round(last_round_number, false);
+			core.push_background_round(last_round);
+		}
+		core.start_round(last_round_number + 1, true);
+
+		core
+	}
+
+	/// Create a voter core from a persistent state, see
+	/// [`Voter::from_persistent_state`](super::Voter::from_persistent_state).
+	pub fn from_persistent_state<C: CoreEnvironment<H, N, S, Id>>(
+		env: &C,
+		voters: VoterSet<Id>,
+		state: VoterPersistentState<H, N, S, Id>,
+	) -> Self {
+		let VoterPersistentState {
+			set_id,
+			round_number,
+			round_base,
+			last_finalized,
+			primary_propose,
+			prevote,
+			precommit,
+			completed_round_number,
+			completed_round_base,
+			completed_round_votes,
+		} = state;
+
+		let params = VoterParams {
+			voters,
+			set_id,
+			last_round_number: completed_round_number,
+			last_round_votes: completed_round_votes,
+			last_round_base: completed_round_base,
+			last_finalized: round_base,
+		};
+		let mut core = VoterCore::new(env, params);
+
+		// blocks may have been finalized by commits above the base of the round.
+		if last_finalized.1 > core.last_finalized.1 {
+			core.best_round.note_finalized(&last_finalized);
+			core.last_finalized = last_finalized;
+		}
+
+		if round_number == completed_round_number + 1 {
+			core.best_round.restore_own_votes(primary_propose, prevote, precommit);
+		}
+
+		core
+	}
+
+	/// Get the id of the voter set we are currently voting in.
+	pub fn set_id(&self) -> u64 {
+		self.set_id
+	}
+
+	/// Get the voter set we are currently voting in.
+	pub fn voters(&self) -> &VoterSet<Id> {
+		&self.voters
+	}
+
+	/// Get the highest block finalized so far.
+	pub fn last_finalized(&self) -> &(H, N) {
+		&self.last_finalized
+	}
+
+	/// Get the votes of the round we are currently voting in.
+	pub fn best_round(&self) -> &Round<Id, H, N, S> {
+		self.best_round.votes()
+	}
+
+	/// Get the votes of the rounds running in the background, in ascending
+	/// order of round numbers.
+	pub fn background_rounds(&self) -> impl Iterator<Item = &Round<Id, H, N, S>> {
+		self.past_rounds.values().map(|background| background.inner.votes())
+	}
+
+	/// Get the votes of the given round of the current voter set, be it the
+	/// best round or one running in the background.
+	pub fn round(&self, round: u64) -> Option<&Round<Id, H, N, S>> {
+		if round == self.best_round.round_number() {
+			Some(self.best_round.votes())
+		} else {
+			self.past_rounds.get(&round).map(|background| background.inner.votes())
+		}
+	}
+
+	/// Get the current persistent state of the voter.
+	pub fn persistent_state(&self) -> VoterPersistentState<H, N, S, Id> {
+		self.best_round
+			.persistent_state()
+			.cloned()
+			.expect("persistent state is always tracked by the best round; qed")
+	}
+
+	/// Build a catch-up message for a peer which is currently voting in round
+	/// `peer_round`, see [`Voter::catch_up`](super::Voter::catch_up).
+	pub fn catch_up<C: CoreEnvironment<H, N, S, Id>>(
+		&self,
+		env: &C,
+		peer_round: u64,
+	) -> Option<CatchUp<H, N, S, Id>> {
+		let round = std::iter::once(&self.best_round)
+			.chain(self.past_rounds.values().map(|background| &background.inner))
+			.filter(|round| round.round_number() > peer_round && round.round_state().completable)
+			.max_by_key(|round| round.round_number())?;
+
+		make_catch_up(env, round)
+	}
+
+	/// Take the next output queued by the core, if any.
+	pub fn next_output(&mut self) -> Option<Output<H, N, S, Id>> {
+		self.outputs.pop_front()
+	}
+
+	/// Handle an input, queueing any resulting outputs.
+	///
+	/// Errors are only returned if the chain fails, in which case the voter
+	/// should be stopped.
+	pub fn handle<C: CoreEnvironment<H, N, S, Id>>(
+		&mut self,
+		env: &C,
+		input: Input<H, N, S, Id>,
+	) -> Result<(), Error> {
+		// any input is a reason to query the best chain again for a forced change.
+		if let ForcedChangeQuery::Stale = self.forced_change_query {
+			self.forced_change_query = ForcedChangeQuery::Idle;
+		}
+
+		let current_set_id = self.set_id;
+		match input {
+			Input::RoundData { set_id, round, voter_id } => {
+				if let Some((round, _)) = self.round_mut(set_id, round) {
+					round.set_voter_id(voter_id.as_ref());
+				}
+			},
+			Input::Vote { set_id, round, vote } => {
+				trace!(target: "afg", "Round {}: Got incoming message", round);
+
+				if let Some((voting_round, outputs)) = self.round_mut(set_id, round) {
+					if let Some(vote) = voting_round.handle_vote(env, vote, outputs)? {
+						if !self.pending_votes.insert(round, vote) {
+							trace!(target: "afg", "Round {}: Too many votes on unknown blocks, ignoring", round);
+						}
+					}
+				}
+			},
+			Input::Commit { set_id, round, commit } =>
+				self.handle_commit(env, set_id, round, commit)?,
+			Input::CatchUp(catch_up) => self.handle_catch_up(env, catch_up),
+			Input::Neighbor(packet) => self.handle_neighbor_packet(env, packet),
+			Input::TimerFired(TimerId { set_id, round, kind }) => match kind {
+				TimerKind::Prevote =>
+					if let Some((round, _)) = self.round_mut(set_id, round) {
+						round.prevote_timer_fired();
+					},
+				TimerKind::Precommit =>
+					if let Some((round, _)) = self.round_mut(set_id, round) {
+						round.precommit_timer_fired();
+					},
+				TimerKind::Commit =>
+					if let Some(committer) = self
+						.past_rounds
+						.get_mut(&round)
+						.filter(|_| set_id == current_set_id)
+						.and_then(|background| background.round_committer.as_mut())
+					{
+						committer.timer_fired = true;
+					},
+			},
+			Input::BestChain { request: BestChainRequest::Prevote { set_id, round }, best } =>
+				if let Some((round, outputs)) = self.round_mut(set_id, round) {
+					round.handle_best_chain(env, best, outputs);
+				},
+			Input::BestChain { request: BestChainRequest::SetChange { set_id }, best } => {
+				if set_id == self.set_id {
+					if let ForcedChangeQuery::Pending = self.forced_change_query {
+						self.forced_change_query = ForcedChangeQuery::Answered(best);
+					}
+				}
+			},
+			Input::SetChange(change) => {
+				trace!(target: "afg", "Scheduled {:?} voter set change at block {:?}",
+					change.kind,
+					change.effective_number,
+				);
+
+				self.pending_set_changes.push_back(change);
+			},
+			Input::BlockImported { hash, number } =>
+				for (round, vote) in self.pending_votes.take(&hash, number) {
+					if let Some((voting_round, outputs)) = self.round_mut(current_set_id, round) {
+						if voting_round.handle_vote(env, vote, outputs)?.is_some() {
+							trace!(target: "afg", "Round {}: Ignoring message on block {:?} not descending from round base",
+								round,
+								hash,
+							);
+						}
+					}
+				},
+		}
+
+		self.process(env)
+	}
+
+	// get a round of the current voter set, along with the output queue.
+	fn round_mut(
+		&mut self,
+		set_id: u64,
+		round: u64,
+	) -> Option<RoundWithOutputs<'_, H, N, S, Id>> {
+		let round = if set_id != self.set_id {
+			None
+		} else if round == self.best_round.round_number() {
+			Some(&mut self.best_round)
+		} else {
+			self.past_rounds.get_mut(&round).map(|background| &mut background.inner)
+		};
+
+		let outputs = &mut self.outputs;
+		round.map(move |round| (round, outputs))
+	}
+
+	// queue the outputs for a round that just started.
+	fn start_round(&mut self, round: u64, voting: bool) {
+		let set_id = self.set_id;
+		self.outputs.push_back(Output::StartRound { set_id, round });
+
+		if voting {
+			for kind in [TimerKind::Prevote, TimerKind::Precommit] {
+				self.outputs.push_back(Output::ArmTimer(TimerId { set_id, round, kind }));
+			}
+		}
+	}
+
+	// push an old voting round to be run in the background.
+	fn push_background_round(&mut self, mut round: VotingRound<H, N, S, Id>) {
+		let round_number = round.round_number();
+		round.untrack_persistent_state();
+
+		self.outputs.push_back(Output::ArmTimer(TimerId {
+			set_id: self.set_id,
+			round: round_number,
+			kind: TimerKind::Commit,
+		}));
+
+		self.past_rounds.insert(
+			round_number,
+			BackgroundRound {
+				inner: round,
+				// https://github.com/paritytech/finality-grandpa/issues/50
+				finalized_number: N::zero(),
+				round_committer: Some(RoundCommitter { timer_fired: false, last_commit: None }),
+			},
+		);
+	}
+
+	// the current state of the given round, if it's running in the background.
+	fn background_round_state(&self, round: Option<u64>) -> Option<RoundState<H, N>> {
+		round
+			.and_then(|round| self.past_rounds.get(&round))
+			.map(|background| background.inner.round_state())
+	}
+
+	// make progress on all rounds after an input, until nothing changes.
+	fn process<C: CoreEnvironment<H, N, S, Id>>(&mut self, env: &C) -> Result<(), Error> {
+		loop {
+			self.process_background_rounds(env);
+
+			// if the current `best_round` is completable and we've already precommitted,
+			// we start a new round at `best_round + 1`.
+			if let Some(state) = self.background_round_state(self.best_round.prior_round()) {
+				self.best_round.update_last_round_state(state);
+			}
+
+			let completed = self.best_round.process(env, &mut self.outputs);
+			if let Some(commit) = self.best_round.take_finalized_notification() {
+				let round = self.best_round.round_number();
+				self.note_finalized(env, round, commit);
+			}
+
+			self.conclude_background_rounds();
+
+			let enacted = self.process_set_changes(env)?;
+			self.process_neighbor_packet();
+
+			if enacted {
+				continue
+			}
+
+			if completed && self.best_round.precommitted() {
+				trace!(target: "afg", "Best round at {} has become completable. Starting new best round at {}",
+					self.best_round.round_number(),
+					self.best_round.round_number() + 1,
+				);
+
+				self.completed_best_round();
+				continue
+			}
+
+			return Ok(())
+		}
+	}
+
+	// do work on all background rounds, broadcasting any commits generated.
+	fn process_background_rounds<C: CoreEnvironment<H, N, S, Id>>(&mut self, env: &C) {
+		// in ascending order, so that rounds see the latest state of the round before.
+		let round_numbers = self.past_rounds.keys().cloned().collect::<Vec<_>>();
+
+		for round_number in round_numbers {
+			let prior_round = self.past_rounds[&round_number].inner.prior_round();
+			let last_round_state = self.background_round_state(prior_round);
+
+			let background = self
+				.past_rounds
+				.get_mut(&round_number)
+				.expect("round numbers were collected from the map above; qed");
+
+			if let Some(state) = last_round_state {
+				background.inner.update_last_round_state(state);
+			}
+
+			background.inner.process(env, &mut self.outputs);
+			let finalized = background.inner.take_finalized_notification();
+
+			if let Some(commit) = background.commit() {
+				debug!(
+					target: "afg", "Committing: round_number = {}, \
+					target_number = {:?}, target_hash = {:?}",
+					round_number,
+					commit.target_number,
+					commit.target_hash,
+				);
+
+				self.outputs.push_back(Output::SendGlobalMessage(CommunicationOut::Commit(
+					self.set_id,
+					round_number,
+					commit,
+				)));
+			}
+
+			if let Some(commit) = finalized {
+				self.note_finalized(env, round_number, commit);
+			}
+		}
+	}
+
+	// drop the background rounds whose estimate has been finalized.
+	fn conclude_background_rounds(&mut self) {
+		let concluded = self
+			.past_rounds
+			.iter()
+			.filter(|(_, background)| background.is_done())
+			.map(|(round_number, _)| *round_number)
+			.collect::<Vec<_>>();
+
+		for round_number in concluded {
+			let round = self
+				.past_rounds
+				.remove(&round_number)
+				.expect("round numbers were collected from the map above; qed")
+				.inner;
+
+			self.outputs.push_back(Output::Concluded {
+				set_id: self.set_id,
+				round: round_number,
+				state: round.round_state(),
+				base: round.dag_base(),
+				votes: round.historical_votes().clone(),
+			});
+			self.pending_votes.drop_round(round_number);
+			self.outputs
+				.push_back(Output::DropRound { set_id: self.set_id, round: round_number });
+		}
+	}
+
+	// note a block finalized in one of our rounds.
+	fn note_finalized<C: CoreEnvironment<H, N, S, Id>>(
+		&mut self,
+		env: &C,
+		round: u64,
+		commit: Commit<H, N, S, Id>,
+	) {
+		let (f_hash, f_num) = (commit.target_hash.clone(), commit.target_number);
+
+		for background in self.past_rounds.values_mut() {
+			background.update_finalized(env, &(f_hash.clone(), f_num));
+		}
+
+		if f_num > self.last_finalized.1 {
+			self.last_finalized = (f_hash.clone(), f_num);
+			self.last_finalized_commit = Some((round, commit.clone()));
+			self.best_round.note_finalized(&self.last_finalized);
+			self.outputs.push_back(Output::Finalize {
+				hash: f_hash.clone(),
+				number: f_num,
+				set_id: self.set_id,
+				round,
+				commit,
+			});
+		}
+
+		if f_num > self.last_finalized_in_rounds.1 {
+			self.last_finalized_in_rounds = (f_hash, f_num);
+		}
+	}
+
+	fn completed_best_round(&mut self) {
+		let old_round_number = self.best_round.round_number();
+
+		self.outputs.push_back(Output::Completed {
+			set_id: self.set_id,
+			round: old_round_number,
+			state: self.best_round.round_state(),
+			base: self.best_round.dag_base(),
+			votes: self.best_round.historical_votes().clone(),
+		});
+
+		let mut next_round = VotingRound::new(
+			self.set_id,
+			old_round_number + 1,
+			self.voters.clone(),
+			self.last_finalized_in_rounds.clone(),
+			Some(old_round_number),
+			self.best_round.round_state(),
+		);
+		next_round.track_persistent_state(
+			self.last_finalized.clone(),
+			old_round_number,
+			self.best_round.dag_base(),
+			self.best_round.historical_votes().seen().to_vec(),
+		);
+
+		let old_round = std::mem::replace(&mut self.best_round, next_round);
+		self.start_round(old_round_number + 1, true);
+		self.push_background_round(old_round);
+	}
+
+	/// Handle a commit. If it's for a round running in the background, we
+	/// import it into that round so that when we commit on that round, our
+	/// commit message will be informed by those that we've seen.
+	///
+	/// Otherwise, we will simply validate the commit and finalize its target.
+	fn handle_commit<C: CoreEnvironment<H, N, S, Id>>(
+		&mut self,
+		env: &C,
+		set_id: u64,
+		round_number: u64,
+		commit: Commit<H, N, S, Id>,
+	) -> Result<(), Error> {
+		trace!(target: "afg", "Got commit for round_number {:?}: target_number: {:?}, target_hash: {:?}",
+			round_number,
+			commit.target_number,
+			commit.target_hash,
+		);
+
+		if set_id != self.set_id {
+			trace!(target: "afg", "Ignoring commit for voter set {}, current set id is {}",
+				set_id,
+				self.set_id,
+			);
+
+			self.outputs.push_back(Output::CommitProcessed(CommitProcessingOutcome::Bad(
+				BadCommit::from(CommitValidationResult::<H, N>::default()),
+			)));
+			return Ok(())
+		}
+
+		// if the commit is for a background round dispatch to round committer.
+		if let Some(background) = self
+			.past_rounds
+			.get_mut(&round_number)
+			.filter(|background| background.round_committer.is_some())
+		{
+			if !background.import_commit(env, commit, &mut self.outputs)? {
+				trace!(target: "afg", "Ignoring invalid commit");
+			}
+
+			// Import to backgrounded round is good.
+			self.outputs.push_back(Output::CommitProcessed(CommitProcessingOutcome::Good(
+				GoodCommit::new(),
+			)));
+			return Ok(())
+		}
+
+		// otherwise validate the commit and signal the finalized block
+		// (if any) to the environment
+		let validation_result =
+			validate_commit_with_env(&commit, self.set_id, round_number, &self.voters, env)?;
+
+		if let Some((finalized_hash, finalized_number)) = validation_result.ghost.clone() {
+			// clean up any background rounds
+			for background in self.past_rounds.values_mut() {
+				background.update_finalized(env, &(finalized_hash.clone(), finalized_number));
+			}
+
+			if finalized_number > self.last_finalized.1 {
+				self.last_finalized = (finalized_hash.clone(), finalized_number);
+				self.last_finalized_commit = Some((round_number, commit.clone()));
+				self.best_round.note_finalized(&self.last_finalized);
+				self.outputs.push_back(Output::Finalize {
+					hash: finalized_hash,
+					number: finalized_number,
+					set_id: self.set_id,
+					round: round_number,
+					commit,
+				});
+			}
+
+			self.outputs.push_back(Output::CommitProcessed(CommitProcessingOutcome::Good(
+				GoodCommit::new(),
+			)));
+		} else {
+			// Failing validation of a commit is bad.
+			self.outputs.push_back(Output::CommitProcessed(CommitProcessingOutcome::Bad(
+				BadCommit::from(validation_result),
+			)));
+		}
+
+		Ok(())
+	}
+
+	fn handle_catch_up<C: CoreEnvironment<H, N, S, Id>>(
+		&mut self,
+		env: &C,
+		catch_up: CatchUp<H, N, S, Id>,
+	) {
+		trace!(target: "afg", "Got catch-up message for round {}", catch_up.round_number);
+
+		if catch_up.set_id != self.set_id {
+			trace!(target: "afg", "Ignoring catch-up for voter set {}, current set id is {}",
+				catch_up.set_id,
+				self.set_id,
+			);
+
+			self.outputs
+				.push_back(Output::CatchUpProcessed(CatchUpProcessingOutcome::Useless));
+			return
+		}
+
+		let round =
+			match validate_catch_up(catch_up, env, &self.voters, self.best_round.round_number()) {
+				Ok(round) => round,
+				Err(reason) => {
+					self.outputs.push_back(Output::CatchUpProcessed(
+						CatchUpProcessingOutcome::Bad(BadCatchUp::new(reason)),
+					));
+					return
+				},
+			};
+
+		let state = round.state();
+
+		// beyond this point, we set this round to the past and
+		// start voting in the next round.
+		let just_completed = VotingRound::completed(self.set_id, round);
+		let round_number = just_completed.round_number();
+
+		let mut new_best = VotingRound::new(
+			self.set_id,
+			round_number + 1,
+			self.voters.clone(),
+			self.last_finalized_in_rounds.clone(),
+			Some(round_number),
+			just_completed.round_state(),
+		);
+		new_best.track_persistent_state(
+			self.last_finalized.clone(),
+			round_number,
+			just_completed.dag_base(),
+			just_completed.historical_votes().seen().to_vec(),
+		);
+
+		self.start_round(round_number, false);
+		self.start_round(round_number + 1, true);
+
+		// update last-finalized in rounds _after_ starting new round.
+		// otherwise the base could be too eagerly set forward.
+		if let Some((f_hash, f_num)) = state.finalized {
+			if f_num > self.last_finalized_in_rounds.1 {
+				self.last_finalized_in_rounds = (f_hash, f_num);
+			}
+		}
+
+		self.outputs.push_back(Output::Completed {
+			set_id: self.set_id,
+			round: round_number,
+			state: just_completed.round_state(),
+			base: just_completed.dag_base(),
+			votes: just_completed.historical_votes().clone(),
+		});
+
+		self.push_background_round(just_completed);
+
+		let old_best = std::mem::replace(&mut self.best_round, new_best);
+		self.push_background_round(old_best);
+
+		self.outputs.push_back(Output::CatchUpProcessed(CatchUpProcessingOutcome::Good(
+			GoodCatchUp::new(),
+		)));
+	}
+
+	fn handle_neighbor_packet<C: CoreEnvironment<H, N, S, Id>>(
+		&mut self,
+		env: &C,
+		packet: NeighborPacket<N>,
+	) {
+		trace!(target: "afg", "Got neighbor packet: {:?}", packet);
+
+		if packet.set_id == self.set_id &&
+			packet.round + CATCH_UP_THRESHOLD <= self.best_round.round_number()
+		{
+			if let Some(catch_up) = self.catch_up(env, packet.round) {
+				self.outputs.push_back(Output::SendCatchUp(catch_up));
+			}
+		}
+	}
+
+	// Announce our view to peers if it has changed since we last did.
+	fn process_neighbor_packet(&mut self) {
+		let packet = NeighborPacket {
+			set_id: self.set_id,
+			round: self.best_round.round_number(),
+			last_finalized: self.last_finalized.1,
+		};
+
+		if self.last_neighbor_packet.as_ref() != Some(&packet) {
+			self.last_neighbor_packet = Some(packet.clone());
+			self.outputs
+				.push_back(Output::SendGlobalMessage(CommunicationOut::Neighbor(packet)));
+		}
+	}
+
+	// Enact any scheduled voter set changes which are due. Returns whether any
+	// change was enacted.
+	fn process_set_changes<C: CoreEnvironment<H, N, S, Id>>(
+		&mut self,
+		env: &C,
+	) -> Result<bool, Error> {
+		let mut enacted = false;
+
+		loop {
+			let (kind, effective_number, canon_hash) = match self.pending_set_changes.front() {
+				Some(change) => (change.kind, change.effective_number, change.canon_hash.clone()),
+				None => return Ok(enacted),
+			};
+
+			// a change announced on a fork which doesn't include the last
+			// finalized block can't be enacted anymore.
+			if self.last_finalized.1 >= effective_number &&
+				!env.is_equal_or_descendent_of(canon_hash.clone(), self.last_finalized.0.clone())
+			{
+				debug!(target: "afg", "Discarding voter set change announced in {:?}, not on the finalized chain",
+					canon_hash,
+				);
+
+				self.pending_set_changes.pop_front();
+				continue
+			}
+
+			let base = match kind {
+				VoterSetChangeKind::Standard if self.last_finalized.1 >= effective_number =>
+					self.last_finalized.clone(),
+				VoterSetChangeKind::Standard => return Ok(enacted),
+				VoterSetChangeKind::Forced =>
+					match self.forced_change_base(env, canon_hash, effective_number)? {
+						Some(base) => base,
+						None => return Ok(enacted),
+					},
+			};
+
+			let change = self
+				.pending_set_changes
+				.pop_front()
+				.expect("we checked self.pending_set_changes.front() just above; qed");
+
+			self.enact_set_change(change, base);
+			enacted = true;
+		}
+	}
+
+	// Query the best chain to find the block at which a forced change should be
+	// enacted. Returns `None` if the best chain doesn't contain the block
+	// announcing the change and a block at the given number yet, in which case
+	// we'll check again on the next input.
+	fn forced_change_base<C: CoreEnvironment<H, N, S, Id>>(
+		&mut self,
+		env: &C,
+		canon_hash: H,
+		effective_number: N,
+	) -> Result<Option<(H, N)>, Error> {
+		let last_finalized = self.last_finalized.clone();
+
+		// the change is effective at a block we've already finalized.
+		if effective_number <= last_finalized.1 {
+			self.forced_change_query = ForcedChangeQuery::Idle;
+			return Ok(Some(last_finalized))
+		}
+
+		let best = match std::mem::replace(&mut self.forced_change_query, ForcedChangeQuery::Idle) {
+			ForcedChangeQuery::Idle => {
+				self.outputs.push_back(Output::RequestBestChain {
+					request: BestChainRequest::SetChange { set_id: self.set_id },
+					base: last_finalized.0,
+				});
+				self.forced_change_query = ForcedChangeQuery::Pending;
+				return Ok(None)
+			},
+			query @ ForcedChangeQuery::Pending | query @ ForcedChangeQuery::Stale => {
+				self.forced_change_query = query;
+				return Ok(None)
+			},
+			ForcedChangeQuery::Answered(best) => best,
+		};
+
+		let base = forced_change_block(env, last_finalized.0, best, canon_hash, effective_number)?;
+		if base.is_none() {
+			self.forced_change_query = ForcedChangeQuery::Stale;
+		}
+
+		Ok(base)
+	}
+
+	fn enact_set_change(&mut self, change: VoterSetChange<H, N, Id>, base: (H, N)) {
+		trace!(target: "afg", "Enacting {:?} voter set change at block {:?}. Starting voter set {} on top of {:?}",
+			change.kind,
+			change.effective_number,
+			self.set_id + 1,
+			base,
+		);
+
+		// let peers lagging behind learn about the finality of the change block.
+		let last_finalized_commit = self.last_finalized_commit.take();
+		if let (VoterSetChangeKind::Standard, Some((round_number, commit))) =
+			(change.kind, last_finalized_commit)
+		{
+			self.outputs.push_back(Output::SendGlobalMessage(CommunicationOut::Commit(
+				self.set_id,
+				round_number,
+				commit,
+			)));
+		}
+
+		// all rounds of the old voter set are dropped.
+		let set_id = self.set_id;
+		let round_numbers = self
+			.past_rounds
+			.keys()
+			.cloned()
+			.chain(std::iter::once(self.best_round.round_number()))
+			.collect::<Vec<_>>();
+
+		for round in round_numbers {
+			self.outputs.push_back(Output::DropRound { set_id, round });
+		}
+		self.pending_votes.clear();
+
+		self.set_id += 1;
+		self.voters = change.next_voters;
+		self.last_finalized_in_rounds = base.clone();
+		self.forced_change_query = ForcedChangeQuery::Idle;
+
+		let mut best_round = VotingRound::new(
+			self.set_id,
+			1,
+			self.voters.clone(),
+			base.clone(),
+			None,
+			RoundState::genesis(base.clone()),
+		);
+		best_round.track_persistent_state(self.last_finalized.clone(), 0, base, Vec::new());
+
+		self.best_round = best_round;
+		self.past_rounds = BTreeMap::new();
+		self.start_round(1, true);
+	}
+}
+
+// The block of the best chain at which a forced change announced in `canon_hash`
+// is enacted. Returns `None` if the best chain doesn't contain the block
+// announcing the change and a block at the given number.
+pub(super) fn forced_change_block<H, N, C>(
+	env: &C,
+	last_finalized: H,
+	best: Option<(H, N)>,
+	canon_hash: H,
+	effective_number: N,
+) -> Result<Option<(H, N)>, Error>
+where
+	H: Clone + Eq,
+	N: Copy + BlockNumberOps,
+	C: Chain<H, N>,
+{
+	let block = match best {
+		Some((hash, number)) if number == effective_number => Some((hash, number)),
+		Some((hash, number)) if number > effective_number => {
+			// the ancestry is in reverse order starting from the parent of `hash`.
+			let ancestry = env.ancestry(last_finalized, hash)?;
+			let offset: usize = (number - effective_number).as_();
+
+			ancestry.get(offset - 1).map(|hash| (hash.clone(), effective_number))
+		},
+		_ => None,
+	};
+
+	Ok(block.filter(|block| env.is_equal_or_descendent_of(canon_hash, block.0.clone())))
+}
+
+// Instantiates the given last round, to be backgrounded until its estimate is finalized.
+//
+// This round must be completable based on the passed votes (and if not, `None` will be returned),
+// but it may be the case that there are some more votes to propagate in order to push
+// the estimate backwards and conclude the round (i.e. finalize its estimate).
+//
+// may only be called with non-zero last round.
+fn instantiate_last_round<H, N, S, Id, C>(
+	env: &C,
+	set_id: u64,
+	params: RoundParams<Id, H, N>,
+	last_round_votes: Vec<SignedMessage<H, N, S, Id>>,
+	outputs: &mut Outputs<H, N, S, Id>,
+	pending_votes: &mut PendingVotes<H, N, S, Id>,
+) -> Option<VotingRound<H, N, S, Id>>
+where
+	H: Clone + Eq + Ord + std::fmt::Debug,
+	N: Copy + BlockNumberOps + std::fmt::Debug,
+	S: Clone + Eq,
+	Id: Clone + Eq + Ord + std::fmt::Debug,
+	C: CoreEnvironment<H, N, S, Id>,
+{
+	let last_round_number = params.round_number;
+	let last_round_tracker = Round::new(params);
+
+	// start as completed so we don't cast votes.
+	let mut last_round = VotingRound::completed(set_id, last_round_tracker);
+
+	for vote in last_round_votes {
+		// bail if any votes are bad, votes on blocks we don't know yet are
+		// buffered like live votes.
+		if let Some(vote) = last_round.handle_vote(env, vote, outputs).ok()? {
+			if !pending_votes.insert(last_round_number, vote) {
+				trace!(target: "afg", "Round {}: Too many votes on unknown blocks, ignoring",
+					last_round_number,
+				);
+			}
+		}
+	}
+
+	if last_round.round_state().completable {
+		Some(last_round)
+	} else {
+		pending_votes.drop_round(last_round_number);
+		None
+	}
+}
+
+/// Validate the given commit, checking the signatures on its precommits if the
+/// environment provides a signature verifier.
+pub(super) fn validate_commit_with_env<H, N, S, Id, C>(
+	commit: &Commit<H, N, S, Id>,
+	set_id: u64,
+	round: u64,
+	voters: &VoterSet<Id>,
+	env: &C,
+) -> Result<CommitValidationResult<H, N>, Error>
+where
+	H: Clone + Eq + Ord + std::fmt::Debug,
+	N: Copy + BlockNumberOps + std::fmt::Debug,
+	S: Clone + Eq,
+	Id: Clone + Eq + Ord + std::fmt::Debug,
+	C: CoreEnvironment<H, N, S, Id>,
+{
+	match env.signature_verifier() {
+		Some(verifier) =>
+			validate_commit_with_verifier(commit, set_id, round, voters, env, verifier),
+		None => validate_commit(commit, voters, env),
+	}
+}
+
+/// Validate the given catch up and return a completed round with all prevotes
+/// and precommits from the catch up imported. If the catch up is invalid the
+/// reason why is returned instead.
+pub(super) fn validate_catch_up<H, N, S, Id, C>(
+	catch_up: CatchUp<H, N, S, Id>,
+	env: &C,
+	voters: &VoterSet<Id>,
+	best_round_number: u64,
+) -> Result<Round<Id, H, N, S>, CatchUpRejection>
+where
+	H: Clone + Eq + Ord + std::fmt::Debug,
+	N: Copy + BlockNumberOps + std::fmt::Debug,
+	S: Clone + Eq,
+	Id: Clone + Eq + Ord + std::fmt::Debug,
+	C: CoreEnvironment<H, N, S, Id>,
+{
+	if catch_up.round_number <= best_round_number {
+		trace!(target: "afg", "Ignoring because best round number is {}",
+			   best_round_number);
+
+		return Err(CatchUpRejection::Obsolete)
+	}
+
+	// check the signatures on all votes.
+	if let Some(verifier) = env.signature_verifier() {
+		let prevotes = catch_up.prevotes.iter().map(|signed| {
+			(Message::Prevote(signed.prevote.clone()), &signed.signature, &signed.id)
+		});
+		let precommits = catch_up.precommits.iter().map(|signed| {
+			(Message::Precommit(signed.precommit.clone()), &signed.signature, &signed.id)
+		});
+
+		for (message, signature, id) in prevotes.chain(precommits) {
+			if !verifier.verify(catch_up.set_id, catch_up.round_number, &message, signature, id) {
+				trace!(target: "afg",
+					   "Ignoring invalid catch up, invalid signature from voter: {:?}",
+					   id,
+				);
+
+				return Err(CatchUpRejection::InvalidSignature)
+			}
+		}
+	}
+
+	// check threshold support in prevotes and precommits.
+	{
+		let mut map = BTreeMap::new();
+
+		for prevote in &catch_up.prevotes {
+			if !voters.contains(&prevote.id) {
+				trace!(target: "afg",
+					   "Ignoring invalid catch up, invalid voter: {:?}",
+					   prevote.id,
+				);
+
+				return Err(CatchUpRejection::UnknownVoter)
+			}
+
+			map.entry(prevote.id.clone()).or_insert((false, false)).0 = true;
+		}
+
+		for precommit in &catch_up.precommits {
+			if !voters.contains(&precommit.id) {
+				trace!(target: "afg",
+					   "Ignoring invalid catch up, invalid voter: {:?}",
+					   precommit.id,
+				);
+
+				return Err(CatchUpRejection::UnknownVoter)
+			}
+
+			map.entry(precommit.id.clone()).or_insert((false, false)).1 = true;
+		}
+
+		let (pv, pc) = map.into_iter().fold(
+			(VoteWeight(0), VoteWeight(0)),
+			|(mut pv, mut pc), (id, (prevoted, precommitted))| {
+				if let Some(v) = voters.get(&id) {
+					if prevoted {
+						pv = pv + v.weight();
+					}
+
+					if precommitted {
+						pc = pc + v.weight();
+					}
+				}
+
+				(pv, pc)
+			},
+		);
+
+		let threshold = voters.threshold();
+		if pv < threshold || pc < threshold {
+			trace!(target: "afg",
+				   "Ignoring invalid catch up, missing voter threshold"
+			);
+
+			return Err(CatchUpRejection::MissingThreshold)
+		}
+	}
+
+	let mut round = Round::new(RoundParams {
+		round_number: catch_up.round_number,
+		voters: voters.clone(),
+		base: (catch_up.base_hash.clone(), catch_up.base_number),
+	});
+
+	// import prevotes first.
+	for crate::SignedPrevote { prevote, id, signature } in catch_up.prevotes {
+		match round.import_prevote(env, prevote, id, signature) {
+			Ok(_) => {},
+			Err(e) => {
+				trace!(target: "afg",
+					   "Ignoring invalid catch up, error importing prevote: {:?}",
+					   e,
+				);
+
+				return Err(CatchUpRejection::InvalidVote)
+			},
+		}
+	}
+
+	// then precommits.
+	for crate::SignedPrecommit { precommit, id, signature } in catch_up.precommits {
+		match round.import_precommit(env, precommit, id, signature) {
+			Ok(_) => {},
+			Err(e) => {
+				trace!(target: "afg",
+					   "Ignoring invalid catch up, error importing precommit: {:?}",
+					   e,
+				);
+
+				return Err(CatchUpRejection::InvalidVote)
+			},
+		}
+	}
+
+	let state = round.state();
+	if !state.completable {
+		return Err(CatchUpRejection::NotCompletable)
+	}
+
+	Ok(round)
+}
+
+/// Build a catch-up for the given round, including only the votes needed for it
+/// to pass `validate_catch_up`. Voters are picked in decreasing order of weight
+/// until the round is proven to be completable, all votes of a picked voter are
+/// included (i.e. including any equivocations). The votes are imported into a
+/// new round as they are picked, and the catch-up is validated only once. If
+/// the round isn't completable `None` is returned.
+fn make_catch_up<H, N, S, Id, C>(
+	env: &C,
+	round: &VotingRound<H, N, S, Id>,
+) -> Option<CatchUp<H, N, S, Id>>
+where
+	H: Clone + Eq + Ord + std::fmt::Debug,
+	N: Copy + BlockNumberOps + std::fmt::Debug,
+	S: Clone + Eq,
+	Id: Clone + Eq + Ord + std::fmt::Debug,
+	C: CoreEnvironment<H, N, S, Id>,
+{
+	let mut prevotes = BTreeMap::<_, Vec<_>>::new();
+	let mut precommits = BTreeMap::<_, Vec<_>>::new();
+
+	for SignedMessage { message, signature, id } in round.historical_votes().seen() {
+		match message {
+			Message::Prevote(prevote) =>
+				prevotes.entry(id.clone()).or_default().push(crate::SignedPrevote {
+					prevote: prevote.clone(),
+					signature: signature.clone(),
+					id: id.clone(),
+				}),
+			Message::Precommit(precommit) =>
+				precommits.entry(id.clone()).or_default().push(crate::SignedPrecommit {
+					precommit: precommit.clone(),
+					signature: signature.clone(),
+					id: id.clone(),
+				}),
+			Message::PrimaryPropose(_) => {},
+		}
+	}
+
+	// the sort is stable, so voters with the same weight keep their order in the set.
+	let mut candidates = round
+		.voters()
+		.iter()
+		.filter(|(id, _)| prevotes.contains_key(*id) || precommits.contains_key(*id))
+		.collect::<Vec<_>>();
+
+	candidates.sort_by_key(|(_, info)| std::cmp::Reverse(info.weight().get()));
+
+	// the graph base might have been adjusted backwards to fit votes on blocks
+	// lower than the round base, so we use it instead of the latter.
+	let (base_hash, base_number) = round.dag_base();
+	let mut catch_up = CatchUp {
+		set_id: round.set_id(),
+		round_number: round.round_number(),
+		prevotes: Vec::new(),
+		precommits: Vec::new(),
+		base_hash,
+		base_number,
+	};
+
+	let mut partial = Round::new(RoundParams {
+		round_number: catch_up.round_number,
+		voters: round.voters().clone(),
+		base: (catch_up.base_hash.clone(), catch_up.base_number),
+	});
+	let threshold = partial.threshold();
+
+	let mut completable = false;
+	for (id, _) in candidates {
+		for signed in prevotes.remove(id).into_iter().flatten() {
+			let crate::SignedPrevote { prevote, id, signature } = signed.clone();
+			partial.import_prevote(env, prevote, id, signature).ok()?;
+			catch_up.prevotes.push(signed);
+		}
+		for signed in precommits.remove(id).into_iter().flatten() {
+			let crate::SignedPrecommit { precommit, id, signature } = signed.clone();
+			partial.import_precommit(env, precommit, id, signature).ok()?;
+			catch_up.precommits.push(signed);
+		}
+
+		completable = partial.prevote_participation().0 >= threshold &&
+			partial.precommit_participation().0 >= threshold &&
+			partial.completable();
+		if completable {
+			break
+		}
+	}
+
+	if !completable || validate_catch_up(catch_up.clone(), env, round.voters(), 0).is_err() {
+		return None
+	}
+
+	Some(catch_up)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{
+		super::{MaxUnfinalizedGap, MAX_PENDING_VOTES_PER_ROUND},
+		*,
+	};
+	use crate::testing::chain::{DummyChain, GENESIS_HASH};
+
+	// the signature on a vote is the id of its voter.
+	type Id = u32;
+	type Signature = u32;
+
+	impl CoreEnvironment<&'static str, u32, Signature, Id> for DummyChain {}
+
+	type Core = VoterCore<&'static str, u32, Signature, Id>;
+
+	// act on the outputs of the core like a network of a single voter would,
+	// until `stop` returns true. timers fire in the order they were armed, once
+	// there is nothing else to do.
+	fn drive(
+		core: &mut Core,
+		chain: &DummyChain,
+		local_id: Id,
+		mut stop: impl FnMut(&Output<&'static str, u32, Signature, Id>) -> bool,
+	) {
+		let mut timers = VecDeque::new();
+
+		loop {
+			let output = match core.next_output() {
+				Some(output) => output,
+				None => match timers.pop_front() {
+					Some(timer) => {
+						core.handle(chain, Input::TimerFired(timer)).unwrap();
+						continue
+					},
+					None => return,
+				},
+			};
+
+			if stop(&output) {
+				return
+			}
+
+			let input = match output {
+				Output::StartRound { set_id, round } =>
+					Input::RoundData { set_id, round, voter_id: Some(local_id) },
+				Output::ArmTimer(timer) => {
+					timers.push_back(timer);
+					continue
+				},
+				Output::RequestBestChain { request, base } =>
+					Input::BestChain { request, best: chain.best_chain_containing(base) },
+				Output::SendRoundMessage { set_id, round, message } => Input::Vote {
+					set_id,
+					round,
+					vote: SignedMessage { message, signature: local_id, id: local_id },
+				},
+				_ => continue,
+			};
+
+			core.handle(chain, input).unwrap();
+		}
+	}
+
+	fn chain() -> DummyChain {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+		chain
+	}
+
+	#[test]
+	fn finalizes_and_commits_without_io() {
+		let local_id = 5;
+		let voters = VoterSet::new(std::iter::once((local_id, 100))).unwrap();
+		let chain = chain();
+
+		let last_finalized = chain.last_finalized();
+		let mut core = Core::new(&chain, VoterParams::new(voters, 0, last_finalized));
+
+		let mut finalized = None;
+		drive(&mut core, &chain, local_id, |output| match output {
+			Output::Finalize { hash, number, round, .. } => {
+				finalized = Some((*hash, *number, *round));
+				true
+			},
+			_ => false,
+		});
+
+		assert_eq!(finalized, Some(("E", 6, 1)));
+		assert_eq!(core.last_finalized(), &("E", 6));
+
+		// the first round is completed and its commit is broadcast once the
+		// commit timer fires.
+		let mut commit = None;
+		drive(&mut core, &chain, local_id, |output| match output {
+			Output::SendGlobalMessage(CommunicationOut::Commit(set_id, round, c)) => {
+				commit = Some((*set_id, *round, c.target_hash, c.target_number));
+				true
+			},
+			_ => false,
+		});
+
+		assert_eq!(commit, Some((0, 1, "E", 6)));
+	}
+
+	#[test]
+	fn ignores_inputs_of_other_voter_sets() {
+		let local_id = 5;
+		let voters = VoterSet::new(std::iter::once((local_id, 100))).unwrap();
+		let chain = chain();
+
+		let last_finalized = chain.last_finalized();
+		let mut core = Core::new(&chain, VoterParams::new(voters, 1, last_finalized));
+
+		// the round doesn't make progress with timers and votes of another set.
+		core.handle(&chain, Input::RoundData { set_id: 1, round: 1, voter_id: Some(local_id) })
+			.unwrap();
+		for kind in [TimerKind::Prevote, TimerKind::Precommit] {
+			core.handle(&chain, Input::TimerFired(TimerId { set_id: 0, round: 1, kind }))
+				.unwrap();
+		}
+
+		let outputs = std::iter::from_fn(|| core.next_output()).collect::<Vec<_>>();
+		assert!(!outputs.iter().any(|output| matches!(output, Output::RequestBestChain { .. })));
+
+		let precommit = Message::Precommit(Precommit::new("E", 6));
+		core.handle(
+			&chain,
+			Input::Vote {
+				set_id: 0,
+				round: 1,
+				vote: SignedMessage { message: precommit, signature: 5, id: local_id },
+			},
+		)
+		.unwrap();
+		assert_eq!(core.best_round().precommit_participation().1, 0);
+
+		// commits of other sets are rejected.
+		let commit = Commit { target_hash: "E", target_number: 6, precommits: Vec::new() };
+		core.handle(&chain, Input::Commit { set_id: 0, round: 1, commit }).unwrap();
+		assert!(matches!(
+			core.next_output(),
+			Some(Output::CommitProcessed(CommitProcessingOutcome::Bad(_)))
+		));
+		assert_eq!(core.last_finalized(), &last_finalized);
+	}
+
+	#[test]
+	fn persists_blocks_finalized_by_commits() {
+		let voters = VoterSet::new(std::iter::once((5, 100))).unwrap();
+		let chain = chain();
+
+		let last_finalized = chain.last_finalized();
+		let mut core = Core::new(&chain, VoterParams::new(voters.clone(), 0, last_finalized));
+		core.handle(&chain, Input::RoundData { set_id: 0, round: 1, voter_id: None })
+			.unwrap();
+
+		let commit = Commit {
+			target_hash: "E",
+			target_number: 6,
+			precommits: vec![crate::SignedPrecommit {
+				precommit: Precommit::new("E", 6),
+				signature: 5,
+				id: 5,
+			}],
+		};
+		core.handle(&chain, Input::Commit { set_id: 0, round: 1, commit }).unwrap();
+
+		let state = core.persistent_state();
+		assert_eq!(state.round_base, last_finalized);
+		assert_eq!(state.last_finalized, ("E", 6));
+		assert!(std::iter::from_fn(|| core.next_output())
+			.any(|output| matches!(output, Output::PersistState(ref s) if *s == state)));
+
+		// a restarted voter doesn't fall back to the base of the round.
+		let core = Core::from_persistent_state(&chain, voters, state.clone());
+		assert_eq!(core.last_finalized(), &("E", 6));
+		assert_eq!(core.persistent_state(), state);
+	}
+
+	#[test]
+	fn restricts_prevote_with_voting_rule() {
+		struct RuledChain(DummyChain, MaxUnfinalizedGap<u32>);
+
+		impl Chain<&'static str, u32> for RuledChain {
+			fn ancestry(
+				&self,
+				base: &'static str,
+				block: &'static str,
+			) -> Result<Vec<&'static str>, Error> {
+				self.0.ancestry(base, block)
+			}
+		}
+
+		impl CoreEnvironment<&'static str, u32, Signature, Id> for RuledChain {
+			fn voting_rule(&self) -> Option<&dyn VotingRule<&'static str, u32>> {
+				Some(&self.1)
+			}
+		}
+
+		let local_id = 5;
+		let voters = VoterSet::new(std::iter::once((local_id, 100))).unwrap();
+		let env = RuledChain(chain(), MaxUnfinalizedGap(2));
+
+		let last_finalized = env.0.last_finalized();
+		let mut core = Core::new(&env, VoterParams::new(voters, 0, last_finalized));
+
+		core.handle(&env, Input::RoundData { set_id: 0, round: 1, voter_id: Some(local_id) })
+			.unwrap();
+		let prevote_timer = TimerId { set_id: 0, round: 1, kind: TimerKind::Prevote };
+		core.handle(&env, Input::TimerFired(prevote_timer)).unwrap();
+
+		let request = BestChainRequest::Prevote { set_id: 0, round: 1 };
+		core.handle(&env, Input::BestChain { request, best: Some(("E", 6)) }).unwrap();
+
+		let prevoted = std::iter::from_fn(|| core.next_output()).find_map(|output| match output {
+			Output::Prevoted { prevote, .. } => Some(prevote),
+			_ => None,
+		});
+		assert_eq!(prevoted, Some(Prevote::new("B", 3)));
+	}
+
+	#[test]
+	fn buffers_votes_on_unknown_blocks() {
+		let voters = VoterSet::new([(5, 100), (6, 100)].iter().cloned()).unwrap();
+		let mut chain = chain();
+
+		let last_finalized = chain.last_finalized();
+		let mut core = Core::new(&chain, VoterParams::new(voters, 0, last_finalized));
+		core.handle(&chain, Input::RoundData { set_id: 0, round: 1, voter_id: None })
+			.unwrap();
+
+		let prevote = |target, number, id| Input::Vote {
+			set_id: 0,
+			round: 1,
+			vote: SignedMessage {
+				message: Message::Prevote(Prevote::new(target, number)),
+				signature: id,
+				id,
+			},
+		};
+
+		// votes on blocks which aren't known yet are buffered.
+		core.handle(&chain, prevote("F", 7, 5)).unwrap();
+		core.handle(&chain, prevote("G", 8, 6)).unwrap();
+		assert_eq!(core.best_round().prevote_participation().1, 0);
+
+		chain.push_blocks("E", &["F", "G"]);
+
+		// the votes are only replayed for the block reported.
+		core.handle(&chain, Input::BlockImported { hash: "F", number: 7 }).unwrap();
+		assert_eq!(core.best_round().prevote_participation().1, 1);
+
+		core.handle(&chain, Input::BlockImported { hash: "G", number: 8 }).unwrap();
+		assert_eq!(core.best_round().prevote_participation().1, 2);
+
+		// and only once.
+		core.handle(&chain, Input::BlockImported { hash: "G", number: 8 }).unwrap();
+		assert_eq!(core.best_round().prevote_participation().1, 2);
+	}
+
+	#[test]
+	fn ignores_votes_of_non_voters_on_unknown_blocks() {
+		let voters = VoterSet::new([(5, 100), (6, 100)].iter().cloned()).unwrap();
+		let mut chain = chain();
+
+		let last_finalized = chain.last_finalized();
+		let mut core = Core::new(&chain, VoterParams::new(voters, 0, last_finalized));
+		core.handle(&chain, Input::RoundData { set_id: 0, round: 1, voter_id: None })
+			.unwrap();
+
+		let prevote = |id| Input::Vote {
+			set_id: 0,
+			round: 1,
+			vote: SignedMessage {
+				message: Message::Prevote(Prevote::new("F", 7)),
+				signature: id,
+				id,
+			},
+		};
+
+		// without a signature verifier anyone can send votes under made-up ids,
+		// which must not take the place of the votes of the voter set.
+		for id in 100..100 + MAX_PENDING_VOTES_PER_ROUND as Id {
+			core.handle(&chain, prevote(id)).unwrap();
+		}
+		core.handle(&chain, prevote(5)).unwrap();
+
+		chain.push_blocks("E", &["F"]);
+		core.handle(&chain, Input::BlockImported { hash: "F", number: 7 }).unwrap();
+		assert_eq!(core.best_round().prevote_participation().1, 1);
+	}
+
+	#[test]
+	fn buffers_last_round_votes_on_unknown_blocks() {
+		let voters = VoterSet::new([(5, 100), (6, 1)].iter().cloned()).unwrap();
+		let chain = chain();
+
+		let vote = |message, id| SignedMessage { message, signature: id, id };
+		let last_round_votes = vec![
+			vote(Message::Prevote(Prevote::new("E", 6)), 5),
+			vote(Message::Precommit(Precommit::new("E", 6)), 5),
+			vote(Message::Prevote(Prevote::new("F", 7)), 6),
+		];
+
+		let last_finalized = chain.last_finalized();
+		let mut core = Core::new(
+			&chain,
+			VoterParams {
+				last_round_number: 1,
+				last_round_votes,
+				..VoterParams::new(voters, 0, last_finalized)
+			},
+		);
+
+		// the last round is still restored and the vote on "F" is kept until
+		// the block is imported.
+		assert!(core.past_rounds.contains_key(&1));
+		let pending = core.pending_votes.take(&"F", 7);
+		assert_eq!(pending.len(), 1);
+		assert_eq!(pending[0].0, 1);
+	}
+
+	#[test]
+	fn discards_voter_set_changes_announced_on_other_forks() {
+		let local_id = 5;
+		let voters = VoterSet::new(std::iter::once((local_id, 100))).unwrap();
+		let next_voters = VoterSet::new(std::iter::once((6, 100))).unwrap();
+		let mut chain = chain();
+		chain.push_blocks("B", &["CA"]);
+
+		let last_finalized = chain.last_finalized();
+		let mut core = Core::new(&chain, VoterParams::new(voters.clone(), 0, last_finalized));
+
+		// both changes are due once "C" is finalized, but only the second one
+		// was announced on the finalized chain.
+		for canon_hash in ["CA", "B"] {
+			let change = VoterSetChange {
+				canon_hash,
+				next_voters: next_voters.clone(),
+				effective_number: 4,
+				kind: VoterSetChangeKind::Standard,
+			};
+			core.handle(&chain, Input::SetChange(change)).unwrap();
+		}
+
+		drive(&mut core, &chain, local_id, |output| matches!(output, Output::Finalize { .. }));
+
+		assert_eq!(core.set_id(), 1);
+		assert_eq!(core.voters(), &next_voters);
+		assert!(core.pending_set_changes.is_empty());
+	}
+
+	#[test]
+	fn prunes_background_rounds_on_finality() {
+		let local_id = 5;
+		let voters = VoterSet::new([(local_id, 100), (6, 1)].iter().cloned()).unwrap();
+		let mut chain = chain();
+		chain.push_blocks("C", &["DA"]);
+
+		let last_finalized = chain.last_finalized();
+		let mut core = Core::new(&chain, VoterParams::new(voters, 0, last_finalized));
+		core.handle(&chain, Input::RoundData { set_id: 0, round: 1, voter_id: Some(local_id) })
+			.unwrap();
+
+		// a vote on a fork which won't be finalized.
+		let vote =
+			SignedMessage { message: Message::Prevote(Prevote::new("DA", 5)), signature: 6, id: 6 };
+		core.handle(&chain, Input::Vote { set_id: 0, round: 1, vote }).unwrap();
+
+		drive(&mut core, &chain, local_id, |output| {
+			matches!(output, Output::Finalize { round: 1, .. })
+		});
+		assert_eq!(core.round(1).map(|round| round.base()), Some((GENESIS_HASH, 1)));
+
+		// once the second round finalizes a block, the first one is pruned to
+		// its own finalized block.
+		chain.push_blocks("E", &["F"]);
+		drive(&mut core, &chain, local_id, |output| {
+			matches!(output, Output::Finalize { round: 2, .. })
+		});
+
+		let round = core.round(1).expect("first round is still in the background");
+		assert_eq!(round.base(), ("E", 6));
+		assert_eq!(round.prevote_participation().1, 2);
+		assert!(round.historical_votes().seen().iter().any(|signed| signed.id == 6));
+	}
+}
@@ -0,0 +1,306 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A guard against equivocations by the local voter.
+//!
+//! The outgoing sinks of the rounds handed out by
+//! [`Environment::round_data`](super::Environment::round_data) can be wrapped
+//! with a [`SelfEquivocationGuard`], which remembers the votes that went through
+//! them and drops any vote conflicting with an earlier one of the same round.
+//! This protects against double-votes in case a round is entered more than
+//! once, e.g. after restarting the voter.
+//!
+//! The guard only keeps its votes in memory. To be protected across restarts,
+//! restore it with [`SelfEquivocationGuard::from_persistent_state`] from the
+//! state last stored by [`Environment::persist_state`](super::Environment::persist_state),
+//! which is always stored before our votes are sent.
+
+use futures::prelude::*;
+use log::warn;
+use parking_lot::Mutex;
+
+use std::{
+	collections::BTreeMap,
+	pin::Pin,
+	sync::Arc,
+	task::{Context, Poll},
+};
+
+use super::VoterPersistentState;
+use crate::{Message, Precommit, Prevote, PrimaryPropose};
+
+// the votes the local voter has sent in a round.
+struct SentVotes<H, N> {
+	primary_propose: Option<PrimaryPropose<H, N>>,
+	prevote: Option<Prevote<H, N>>,
+	precommit: Option<Precommit<H, N>>,
+}
+
+impl<H, N> Default for SentVotes<H, N> {
+	fn default() -> Self {
+		SentVotes { primary_propose: None, prevote: None, precommit: None }
+	}
+}
+
+// the votes sent per voter set and round.
+type SharedSentVotes<H, N> = Arc<Mutex<BTreeMap<(u64, u64), SentVotes<H, N>>>>;
+
+impl<H: Clone + Eq, N: Clone + Eq> SentVotes<H, N> {
+	// whether sending `message` doesn't conflict with a vote sent earlier.
+	fn allows(&self, message: &Message<H, N>) -> bool {
+		fn allows<T: PartialEq>(slot: &Option<T>, vote: &T) -> bool {
+			slot.as_ref().map_or(true, |sent| sent == vote)
+		}
+
+		match message {
+			Message::PrimaryPropose(primary) => allows(&self.primary_propose, primary),
+			Message::Prevote(prevote) => allows(&self.prevote, prevote),
+			Message::Precommit(precommit) => allows(&self.precommit, precommit),
+		}
+	}
+
+	fn record(&mut self, message: Message<H, N>) {
+		match message {
+			Message::PrimaryPropose(primary) => self.primary_propose = Some(primary),
+			Message::Prevote(prevote) => self.prevote = Some(prevote),
+			Message::Precommit(precommit) => self.precommit = Some(precommit),
+		}
+	}
+}
+
+/// Keeps track of the votes sent by the local voter, per voter set and round,
+/// in order to prevent it from equivocating.
+///
+/// Sending the same vote more than once is allowed.
+pub struct SelfEquivocationGuard<H, N> {
+	sent: SharedSentVotes<H, N>,
+}
+
+impl<H, N> Clone for SelfEquivocationGuard<H, N> {
+	fn clone(&self) -> Self {
+		SelfEquivocationGuard { sent: self.sent.clone() }
+	}
+}
+
+impl<H, N> Default for SelfEquivocationGuard<H, N> {
+	fn default() -> Self {
+		SelfEquivocationGuard { sent: Arc::new(Mutex::new(BTreeMap::new())) }
+	}
+}
+
+impl<H, N> SelfEquivocationGuard<H, N> {
+	/// Create a new guard, not aware of any votes.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Create a new guard, aware of our votes in the current round of the given
+	/// persistent state, e.g. the one loaded after restarting the voter.
+	pub fn from_persistent_state<S, Id>(state: &VoterPersistentState<H, N, S, Id>) -> Self
+	where
+		H: Clone,
+		N: Clone,
+	{
+		let votes = SentVotes {
+			primary_propose: state.primary_propose.clone(),
+			prevote: state.prevote.clone(),
+			precommit: state.precommit.clone(),
+		};
+
+		let mut sent = BTreeMap::new();
+		sent.insert((state.set_id, state.round_number), votes);
+
+		SelfEquivocationGuard { sent: Arc::new(Mutex::new(sent)) }
+	}
+
+	/// Wrap the outgoing sink of round `round` of voter set `set_id`. All sinks
+	/// wrapped by clones of the same guard share the votes sent.
+	pub fn guard<Out>(&self, set_id: u64, round: u64, out: Out) -> GuardedOut<H, N, Out> {
+		GuardedOut { set_id, round, sent: self.sent.clone(), inner: out }
+	}
+
+	/// Forget about the votes sent in voter sets before `set_id` and in rounds
+	/// of the given set before `round`.
+	pub fn prune(&self, set_id: u64, round: u64) {
+		let mut sent = self.sent.lock();
+		*sent = sent.split_off(&(set_id, round));
+	}
+}
+
+/// An outgoing sink for round messages which drops any vote conflicting with one
+/// sent earlier in the same round, logging a warning instead of sending it.
+///
+/// Created by [`SelfEquivocationGuard::guard`].
+pub struct GuardedOut<H, N, Out> {
+	set_id: u64,
+	round: u64,
+	sent: SharedSentVotes<H, N>,
+	inner: Out,
+}
+
+impl<H, N, Out> Sink<Message<H, N>> for GuardedOut<H, N, Out>
+where
+	H: Clone + Eq + std::fmt::Debug,
+	N: Clone + Eq + std::fmt::Debug,
+	Out: Sink<Message<H, N>> + Unpin,
+{
+	type Error = Out::Error;
+
+	fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+		Sink::poll_ready(Pin::new(&mut self.inner), cx)
+	}
+
+	fn start_send(mut self: Pin<&mut Self>, message: Message<H, N>) -> Result<(), Self::Error> {
+		let key = (self.set_id, self.round);
+		let allowed = self.sent.lock().get(&key).map_or(true, |sent| sent.allows(&message));
+
+		if !allowed {
+			warn!(target: "afg", "Dropping {:?} in round {} of voter set {}, conflicting with an earlier vote",
+				message,
+				self.round,
+				self.set_id,
+			);
+
+			return Ok(())
+		}
+
+		// only votes accepted by the inner sink count as sent.
+		Sink::start_send(Pin::new(&mut self.inner), message.clone())?;
+		self.sent.lock().entry(key).or_default().record(message);
+
+		Ok(())
+	}
+
+	fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+		Sink::poll_flush(Pin::new(&mut self.inner), cx)
+	}
+
+	fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+		Sink::poll_close(Pin::new(&mut self.inner), cx)
+	}
+}
+
+impl<H, N, Out: Unpin> Unpin for GuardedOut<H, N, Out> {}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::{
+		channel::mpsc::{self, UnboundedReceiver},
+		executor::block_on,
+	};
+
+	type TestMessage = Message<&'static str, u32>;
+
+	fn out() -> (impl Sink<TestMessage, Error = ()> + Unpin, UnboundedReceiver<TestMessage>) {
+		let (tx, rx) = mpsc::unbounded();
+		(tx.sink_map_err(|_| ()), rx)
+	}
+
+	fn sent(rx: &mut UnboundedReceiver<TestMessage>) -> Vec<TestMessage> {
+		let mut sent = Vec::new();
+		while let Ok(message) = rx.try_recv() {
+			sent.push(message);
+		}
+		sent
+	}
+
+	#[test]
+	fn drops_conflicting_votes() {
+		let guard = SelfEquivocationGuard::new();
+		let (inner, mut rx) = out();
+		let mut out = guard.guard(0, 1, inner);
+
+		let prevote = Message::Prevote(Prevote::new("A", 2));
+		let precommit = Message::Precommit(Precommit::new("B", 3));
+		let primary = Message::PrimaryPropose(PrimaryPropose::new("A", 2));
+
+		// sending the same vote again is fine, a different one is dropped.
+		assert_eq!(block_on(out.send(prevote.clone())), Ok(()));
+		assert_eq!(block_on(out.send(prevote.clone())), Ok(()));
+		assert_eq!(block_on(out.send(Message::Prevote(Prevote::new("B", 3)))), Ok(()));
+
+		// precommits and primary proposals are tracked separately.
+		assert_eq!(block_on(out.send(precommit.clone())), Ok(()));
+		assert_eq!(block_on(out.send(Message::Precommit(Precommit::new("A", 2)))), Ok(()));
+		assert_eq!(block_on(out.send(primary.clone())), Ok(()));
+
+		assert_eq!(sent(&mut rx), vec![prevote.clone(), prevote, precommit, primary]);
+	}
+
+	#[test]
+	fn tracks_votes_across_sinks_of_the_same_round() {
+		let guard = SelfEquivocationGuard::new();
+		let prevote = |hash, number| Message::Prevote(Prevote::new(hash, number));
+		let send = |guard: &SelfEquivocationGuard<_, _>, set_id, round, message| {
+			let (out, mut rx) = out();
+			block_on(guard.guard(set_id, round, out).send(message)).unwrap();
+			sent(&mut rx)
+		};
+
+		assert_eq!(send(&guard, 0, 1, prevote("A", 2)), vec![prevote("A", 2)]);
+
+		// the round is re-entered.
+		assert_eq!(send(&guard.clone(), 0, 1, prevote("B", 3)), vec![]);
+
+		// other rounds and voter sets aren't affected.
+		assert_eq!(send(&guard, 0, 2, prevote("B", 3)), vec![prevote("B", 3)]);
+		assert_eq!(send(&guard, 1, 1, prevote("B", 3)), vec![prevote("B", 3)]);
+
+		// once pruned, the votes are forgotten.
+		guard.prune(0, 2);
+		assert_eq!(send(&guard, 0, 1, prevote("B", 3)), vec![prevote("B", 3)]);
+		assert_eq!(send(&guard, 0, 2, prevote("A", 2)), vec![]);
+	}
+
+	#[test]
+	fn votes_rejected_by_the_inner_sink_are_not_recorded() {
+		let guard = SelfEquivocationGuard::new();
+
+		let (tx, rx) = mpsc::unbounded();
+		drop(rx);
+		let mut closed = guard.guard(0, 1, tx.sink_map_err(|_| ()));
+		assert_eq!(block_on(closed.send(Message::Prevote(Prevote::new("A", 2)))), Err(()));
+
+		let (out, mut rx) = out();
+		let prevote = Message::Prevote(Prevote::new("B", 3));
+		block_on(guard.guard(0, 1, out).send(prevote.clone())).unwrap();
+		assert_eq!(sent(&mut rx), vec![prevote]);
+	}
+
+	#[test]
+	fn restores_votes_from_persistent_state() {
+		let state = VoterPersistentState::<_, _, (), ()> {
+			set_id: 0,
+			round_number: 1,
+			round_base: ("A", 2),
+			last_finalized: ("A", 2),
+			primary_propose: None,
+			prevote: Some(Prevote::new("B", 3)),
+			precommit: None,
+			completed_round_number: 0,
+			completed_round_base: ("A", 2),
+			completed_round_votes: Vec::new(),
+		};
+
+		let guard = SelfEquivocationGuard::from_persistent_state(&state);
+		let (inner, mut rx) = out();
+		let mut out = guard.guard(0, 1, inner);
+
+		let precommit = Message::Precommit(Precommit::new("B", 3));
+		block_on(out.send(Message::Prevote(Prevote::new("C", 4)))).unwrap();
+		block_on(out.send(precommit.clone())).unwrap();
+		assert_eq!(sent(&mut rx), vec![precommit]);
+	}
+}
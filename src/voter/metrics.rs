@@ -0,0 +1,108 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Metrics on the progress of the voter.
+//!
+//! An [`Environment`](super::Environment) can hand out a [`Metrics`]
+//! implementation, which the voter feeds with the timing of its rounds, the
+//! participation in them and the outcome of the commits and catch-ups it
+//! handles. The voter core doesn't have a clock, so durations are measured by
+//! the voter from the moment it starts a round.
+
+use std::time::{Duration, Instant};
+
+use super::CatchUpRejection;
+use crate::round::State as RoundState;
+
+/// Histograms and counters updated by the voter.
+///
+/// All methods default to doing nothing, so that implementations can pick the
+/// metrics they are interested in. Durations are only measured for rounds the
+/// voter has started itself, and only for milestones reached after the start
+/// of the round, e.g. a round imported through a catch-up is never timed.
+pub trait Metrics {
+	/// Time from the start of a round until its prevote-GHOST is known.
+	fn observe_prevote_ghost_time(&self, _elapsed: Duration) {}
+
+	/// Time from the start of a round until it is completable.
+	fn observe_completable_time(&self, _elapsed: Duration) {}
+
+	/// Time from the start of a round until a block is first finalized in it.
+	fn observe_finalization_time(&self, _elapsed: Duration) {}
+
+	/// Weight of the prevotes and precommits of a round when it is completed.
+	fn observe_completed_round_weight(&self, _prevote_weight: u64, _precommit_weight: u64) {}
+
+	/// Number of rounds per block finalized, since the previous finalization
+	/// in the same voter set.
+	fn observe_rounds_per_finalized_block(&self, _rounds: f64) {}
+
+	/// A commit received from the network was imported.
+	fn inc_commits_imported(&self) {}
+
+	/// A commit was broadcast to the network.
+	fn inc_commits_broadcast(&self) {}
+
+	/// A catch-up received from the network was accepted.
+	fn inc_catch_ups_accepted(&self) {}
+
+	/// A catch-up received from the network was rejected. Useless catch-ups,
+	/// e.g. for another voter set, aren't counted.
+	fn inc_catch_ups_rejected(&self, _reason: CatchUpRejection) {}
+}
+
+// the milestones of a started round which still have to be timed.
+pub(super) struct RoundTimings {
+	started: Instant,
+	prevote_ghost: bool,
+	completable: bool,
+	finalized: bool,
+}
+
+impl RoundTimings {
+	// start timing a round, skipping the milestones it has already reached.
+	pub(super) fn new<H, N>(state: Option<&RoundState<H, N>>) -> Self {
+		let (prevote_ghost, completable, finalized) = match state {
+			Some(state) =>
+				(state.prevote_ghost.is_some(), state.completable, state.finalized.is_some()),
+			None => (false, false, false),
+		};
+
+		RoundTimings { started: Instant::now(), prevote_ghost, completable, finalized }
+	}
+
+	// whether the prevote-GHOST or completability of the round are still to be timed.
+	pub(super) fn is_pending(&self) -> bool {
+		!self.prevote_ghost || !self.completable
+	}
+
+	pub(super) fn observe<H, N>(&mut self, state: &RoundState<H, N>, metrics: &dyn Metrics) {
+		if !self.prevote_ghost && state.prevote_ghost.is_some() {
+			self.prevote_ghost = true;
+			metrics.observe_prevote_ghost_time(self.started.elapsed());
+		}
+
+		if !self.completable && state.completable {
+			self.completable = true;
+			metrics.observe_completable_time(self.started.elapsed());
+		}
+	}
+
+	pub(super) fn finalized(&mut self, metrics: &dyn Metrics) {
+		if !self.finalized {
+			self.finalized = true;
+			metrics.observe_finalization_time(self.started.elapsed());
+		}
+	}
+}
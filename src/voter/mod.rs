@@ -24,36 +24,73 @@
 //!  round. If no local id is defined or if it's not part of the voter set then
 //!  votes will not be pushed to the sink. The protocol state machine still
 //!  transitions state as if the votes had been pushed out.
+//!
+//! Without the `std` feature only the [`VoterCore`] state machine is available,
+//! along with the messages it exchanges. It doesn't depend on a runtime: timers
+//! are armed with [`Output::ArmTimer`] and reported back with
+//! [`Input::TimerFired`], and it doesn't need to be locked since it's only
+//! driven through `&mut` access.
 
+#[cfg(feature = "std")]
 use futures::{
-	channel::mpsc::{self, UnboundedReceiver},
+	channel::mpsc::{self, UnboundedReceiver, UnboundedSender},
 	prelude::*,
 	ready,
 };
-#[cfg(feature = "std")]
-use log::trace;
+#[cfg(feature = "derive-codec")]
+use parity_scale_codec::{Decode, Encode};
+#[cfg(feature = "derive-codec")]
+use scale_info::TypeInfo;
 
+#[cfg(feature = "std")]
 use parking_lot::Mutex;
 
+#[cfg(feature = "std")]
 use std::{
-	collections::VecDeque,
+	collections::{BTreeMap, VecDeque},
 	hash::Hash,
 	pin::Pin,
 	sync::Arc,
 	task::{Context, Poll},
 };
 
+pub use self::{
+	core::{
+		BestChainRequest, CoreEnvironment, Input, Output, TimerId, TimerKind, VoterCore,
+		VoterParams,
+	},
+	voting_rule::{MaxUnfinalizedGap, TargetDepthOffset, VotingRule, VotingRules},
+};
+#[cfg(feature = "std")]
+use crate::{
+	round::{Round, State as RoundState},
+	Chain, CompactCommit, Equivocation, HistoricalVotes, Message, SignatureVerifier,
+};
 use crate::{
-	round::State as RoundState, validate_commit, voter_set::VoterSet, weights::VoteWeight,
-	BlockNumberOps, CatchUp, Chain, Commit, CommitValidationResult, CompactCommit, Equivocation,
-	HistoricalVotes, Message, Precommit, Prevote, PrimaryPropose, SignedMessage,
+	std::vec::Vec, voter_set::VoterSet, BlockNumberOps, CatchUp, Commit, CommitValidationResult,
+	Precommit, Prevote, PrimaryPropose, SignedMessage,
 };
-use past_rounds::PastRounds;
-use voting_round::{State as VotingRoundState, VotingRound};
+#[cfg(feature = "std")]
+pub use equivocation_guard::{GuardedOut, SelfEquivocationGuard};
+#[cfg(feature = "std")]
+pub use metrics::Metrics;
+#[cfg(feature = "std")]
+use metrics::RoundTimings;
+#[cfg(feature = "std")]
+pub use observer::{Observer, ObserverEnvironment};
 
-mod past_rounds;
+mod core;
+#[cfg(feature = "std")]
+mod equivocation_guard;
+#[cfg(feature = "std")]
+mod metrics;
+#[cfg(feature = "std")]
+mod observer;
+mod pending_votes;
 mod voting_round;
+mod voting_rule;
 
+#[cfg(feature = "std")]
 /// Necessary environment for a voter.
 ///
 /// This encapsulates the database and networking layers of the chain.
@@ -86,8 +123,8 @@ pub trait Environment<H: Eq, N: BlockNumberOps>: Chain<H, N> {
 	/// with the round number of the most recently completed round, in which case
 	/// it should yield a valid input stream.
 	///
-	/// The input stream should provide messages which correspond to known blocks
-	/// only.
+	/// Messages on blocks which aren't known yet are buffered by the voter until
+	/// reported with [`Voter::note_block_imported`].
 	///
 	/// The voting logic will push unsigned messages over-eagerly into the
 	/// output stream. It is the job of this stream to determine if those messages
@@ -101,7 +138,40 @@ pub trait Environment<H: Eq, N: BlockNumberOps>: Chain<H, N> {
 	///
 	/// Furthermore, this means that actual logic of creating and verifying
 	/// signatures is flexible and can be maintained outside this crate.
-	fn round_data(&self, round: u64) -> RoundData<Self::Id, Self::Timer, Self::In, Self::Out>;
+	///
+	/// Rounds are scoped to a voter set, identified by the given `set_id`. The
+	/// input stream must not yield messages from any other voter set.
+	///
+	/// This may be called more than once for the same round, e.g. when the voter
+	/// is restarted. The output stream can be wrapped with a
+	/// [`SelfEquivocationGuard`] to make sure no conflicting votes are sent.
+	fn round_data(
+		&self,
+		set_id: u64,
+		round: u64,
+	) -> RoundData<Self::Id, Self::Timer, Self::In, Self::Out>;
+
+	/// The verifier used to check the signatures on incoming votes, commits and
+	/// catch-up messages. By default there is none, in which case the input
+	/// streams are responsible for checking signatures.
+	fn signature_verifier(
+		&self,
+	) -> Option<&dyn SignatureVerifier<H, N, Self::Signature, Self::Id>> {
+		None
+	}
+
+	/// The rule restricting the target of our prevotes, see [`VotingRule`]. By
+	/// default there is none, and we prevote for the block returned by
+	/// [`Environment::best_chain_containing`].
+	fn voting_rule(&self) -> Option<&dyn VotingRule<H, N>> {
+		None
+	}
+
+	/// The metrics updated by the voter as it makes progress. By default there
+	/// are none.
+	fn metrics(&self) -> Option<&dyn Metrics> {
+		None
+	}
 
 	/// Return a timer that will be used to delay the broadcast of a commit
 	/// message. This delay should not be static to minimize the amount of
@@ -109,13 +179,36 @@ pub trait Environment<H: Eq, N: BlockNumberOps>: Chain<H, N> {
 	fn round_commit_timer(&self) -> Self::Timer;
 
 	/// Note that we've done a primary proposal in the given round.
-	fn proposed(&self, round: u64, propose: PrimaryPropose<H, N>) -> Result<(), Self::Error>;
+	fn proposed(
+		&self,
+		set_id: u64,
+		round: u64,
+		propose: PrimaryPropose<H, N>,
+	) -> Result<(), Self::Error>;
 
 	/// Note that we have prevoted in the given round.
-	fn prevoted(&self, round: u64, prevote: Prevote<H, N>) -> Result<(), Self::Error>;
+	fn prevoted(&self, set_id: u64, round: u64, prevote: Prevote<H, N>) -> Result<(), Self::Error>;
 
 	/// Note that we have precommitted in the given round.
-	fn precommitted(&self, round: u64, precommit: Precommit<H, N>) -> Result<(), Self::Error>;
+	fn precommitted(
+		&self,
+		set_id: u64,
+		round: u64,
+		precommit: Precommit<H, N>,
+	) -> Result<(), Self::Error>;
+
+	/// Note a change of the voter's persistent state, i.e. that it started
+	/// voting in a new round or that it's about to broadcast one of its own
+	/// votes. The state should be stored before returning, a voter restarted
+	/// from it with [`Voter::from_persistent_state`] won't equivocate.
+	///
+	/// By default the state isn't stored.
+	fn persist_state(
+		&self,
+		_state: &VoterPersistentState<H, N, Self::Signature, Self::Id>,
+	) -> Result<(), Self::Error> {
+		Ok(())
+	}
 
 	/// Note that a round is completed. This is called when a round has been
 	/// voted in and the next round can start. The round may continue to be run
@@ -123,6 +216,7 @@ pub trait Environment<H: Eq, N: BlockNumberOps>: Chain<H, N> {
 	/// Should return an error when something fatal occurs.
 	fn completed(
 		&self,
+		set_id: u64,
 		round: u64,
 		state: RoundState<H, N>,
 		base: (H, N),
@@ -137,6 +231,7 @@ pub trait Environment<H: Eq, N: BlockNumberOps>: Chain<H, N> {
 	/// that the votes passed to `completed` for this round are a prefix of the votes passed here.
 	fn concluded(
 		&self,
+		set_id: u64,
 		round: u64,
 		state: RoundState<H, N>,
 		base: (H, N),
@@ -149,6 +244,7 @@ pub trait Environment<H: Eq, N: BlockNumberOps>: Chain<H, N> {
 		&self,
 		hash: H,
 		number: N,
+		set_id: u64,
 		round: u64,
 		commit: Commit<H, N, Self::Signature, Self::Id>,
 	) -> Result<(), Self::Error>;
@@ -156,22 +252,78 @@ pub trait Environment<H: Eq, N: BlockNumberOps>: Chain<H, N> {
 	/// Note that an equivocation in prevotes has occurred.
 	fn prevote_equivocation(
 		&self,
+		set_id: u64,
 		round: u64,
 		equivocation: Equivocation<Self::Id, Prevote<H, N>, Self::Signature>,
 	);
 	/// Note that an equivocation in precommits has occurred.
 	fn precommit_equivocation(
 		&self,
+		set_id: u64,
 		round: u64,
 		equivocation: Equivocation<Self::Id, Precommit<H, N>, Self::Signature>,
 	);
 }
 
 /// Communication between nodes that is not round-localized.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
 pub enum CommunicationOut<H, N, S, Id> {
-	/// A commit message.
-	Commit(u64, Commit<H, N, S, Id>),
+	/// A commit message for the given voter set id and round number.
+	Commit(u64, u64, Commit<H, N, S, Id>),
+	/// A neighbor packet announcing our view to peers.
+	Neighbor(NeighborPacket<N>),
+}
+
+/// The number of rounds a peer must be behind us for it to be sent a catch-up.
+/// Peers which are just one round behind will likely catch up on their own.
+pub const CATCH_UP_THRESHOLD: u64 = 2;
+
+/// The maximum number of votes on blocks which haven't been imported yet that
+/// are buffered for a single voter, across all rounds.
+pub const MAX_PENDING_VOTES_PER_VOTER: usize = 16;
+
+/// The maximum number of votes on blocks which haven't been imported yet that
+/// are buffered for a single round.
+pub const MAX_PENDING_VOTES_PER_ROUND: usize = 1024;
+
+/// A neighbor packet, announcing the view of a node to its peers: the voter set
+/// and round it's currently voting in, and the number of the last block it has
+/// finalized.
+///
+/// Peers use this information to only gossip messages which are useful to the
+/// node, see [`NeighborPacket::is_round_message_useful`],
+/// [`NeighborPacket::is_commit_useful`] and [`NeighborPacket::is_catch_up_useful`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "derive-codec", derive(Encode, Decode, TypeInfo))]
+pub struct NeighborPacket<N> {
+	/// The voter set id.
+	pub set_id: u64,
+	/// The round number.
+	pub round: u64,
+	/// The number of the last finalized block.
+	pub last_finalized: N,
+}
+
+impl<N: BlockNumberOps> NeighborPacket<N> {
+	/// Whether a round message (i.e. a `SignedMessage`) of the given voter set and
+	/// round is useful to a node with this view. Nodes only keep track of messages
+	/// from their current round and the ones right before and after it.
+	pub fn is_round_message_useful(&self, set_id: u64, round: u64) -> bool {
+		set_id == self.set_id && round + 1 >= self.round && round <= self.round + 1
+	}
+
+	/// Whether a commit of the given voter set is useful to a node with this view,
+	/// i.e. whether it finalizes a block higher than what the node has finalized.
+	pub fn is_commit_useful<H, S, Id>(&self, set_id: u64, commit: &Commit<H, N, S, Id>) -> bool {
+		set_id == self.set_id && commit.target_number > self.last_finalized
+	}
+
+	/// Whether a catch-up is useful to a node with this view, i.e. whether it
+	/// allows the node to skip ahead to a later round.
+	pub fn is_catch_up_useful<H, S, Id>(&self, catch_up: &CatchUp<H, N, S, Id>) -> bool {
+		catch_up.set_id == self.set_id && catch_up.round_number > self.round
+	}
 }
 
 /// The outcome of processing a commit.
@@ -269,7 +421,7 @@ pub enum CatchUpProcessingOutcome {
 impl CatchUpProcessingOutcome {
 	/// Returns a `Bad` instance of catch up processing outcome's opaque type. Useful for testing.
 	pub fn bad() -> CatchUpProcessingOutcome {
-		CatchUpProcessingOutcome::Bad(BadCatchUp::new())
+		CatchUpProcessingOutcome::Bad(BadCatchUp::new(CatchUpRejection::InvalidSignature))
 	}
 
 	/// Returns a `Good` instance of catch up processing outcome's opaque type. Useful for testing.
@@ -293,15 +445,40 @@ impl GoodCatchUp {
 /// The result of processing for a bad catch up.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct BadCatchUp {
+	reason: CatchUpRejection,
 	_priv: (), // lets us add stuff without breaking API.
 }
 
 impl BadCatchUp {
-	pub(crate) fn new() -> Self {
-		BadCatchUp { _priv: () }
+	pub(crate) fn new(reason: CatchUpRejection) -> Self {
+		BadCatchUp { reason, _priv: () }
+	}
+
+	/// Get the reason why the catch up was rejected.
+	pub fn reason(&self) -> CatchUpRejection {
+		self.reason
 	}
 }
 
+/// The reason why a catch up was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CatchUpRejection {
+	/// The catch up isn't for a round higher than the one we're voting in.
+	Obsolete,
+	/// A vote in the catch up has an invalid signature.
+	InvalidSignature,
+	/// A vote in the catch up is from a voter outside of the voter set.
+	UnknownVoter,
+	/// The votes in the catch up don't reach the voter threshold.
+	MissingThreshold,
+	/// A vote in the catch up couldn't be imported, e.g. because its target
+	/// isn't a descendant of the round base.
+	InvalidVote,
+	/// The votes in the catch up don't make the round completable.
+	NotCompletable,
+}
+
+#[cfg(feature = "std")]
 /// Callback used to pass information about the outcome of importing a given
 /// message (e.g. vote, commit, catch up). Useful to propagate data to the
 /// network after making sure the import is successful.
@@ -312,13 +489,14 @@ pub enum Callback<O> {
 	Work(Box<dyn FnMut(O) + Send>),
 }
 
-#[cfg(any(test, feature = "test-helpers"))]
+#[cfg(all(feature = "std", any(test, feature = "test-helpers")))]
 impl<O> Clone for Callback<O> {
 	fn clone(&self) -> Self {
 		Callback::Blank
 	}
 }
 
+#[cfg(feature = "std")]
 impl<O> Callback<O> {
 	/// Do the work associated with the callback, if any.
 	pub fn run(&mut self, o: O) {
@@ -329,17 +507,24 @@ impl<O> Callback<O> {
 	}
 }
 
+#[cfg(feature = "std")]
 /// Communication between nodes that is not round-localized.
 #[cfg_attr(any(test, feature = "test-helpers"), derive(Clone))]
 pub enum CommunicationIn<H, N, S, Id> {
-	/// A commit message.
-	Commit(u64, CompactCommit<H, N, S, Id>, Callback<CommitProcessingOutcome>),
+	/// A commit message for the given voter set id and round number.
+	Commit(u64, u64, CompactCommit<H, N, S, Id>, Callback<CommitProcessingOutcome>),
 	/// A catch up message.
 	CatchUp(CatchUp<H, N, S, Id>, Callback<CatchUpProcessingOutcome>),
+	/// A neighbor packet from a peer. If the peer is lagging behind at least
+	/// [`CATCH_UP_THRESHOLD`] rounds, the callback is called with a catch-up
+	/// message to send back to it.
+	Neighbor(NeighborPacket<N>, Callback<CatchUp<H, N, S, Id>>),
 }
 
+#[cfg(feature = "std")]
 impl<H, N, S, Id> Unpin for CommunicationIn<H, N, S, Id> {}
 
+#[cfg(feature = "std")]
 /// Data necessary to participate in a round.
 pub struct RoundData<Id, Timer, Input, Output> {
 	/// Local voter id (if any.)
@@ -355,11 +540,13 @@ pub struct RoundData<Id, Timer, Input, Output> {
 	pub outgoing: Output,
 }
 
+#[cfg(feature = "std")]
 struct Buffered<S, I> {
 	inner: S,
 	buffer: VecDeque<I>,
 }
 
+#[cfg(feature = "std")]
 impl<S: Sink<I> + Unpin, I> Buffered<S, I> {
 	fn new(inner: S) -> Buffered<S, I> {
 		Buffered { buffer: VecDeque::new(), inner }
@@ -399,62 +586,286 @@ impl<S: Sink<I> + Unpin, I> Buffered<S, I> {
 	}
 }
 
-type FinalizedNotification<H, N, E> =
-	(H, N, u64, Commit<H, N, <E as Environment<H, N>>::Signature, <E as Environment<H, N>>::Id>);
+/// The kind of a voter set change, which determines when it is enacted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VoterSetChangeKind {
+	/// A standard change is enacted once a block at the effective number has
+	/// been finalized by the current voter set.
+	Standard,
+	/// A forced change is enacted once the best chain contains a block at the
+	/// effective number, without waiting for the current voter set to finalize
+	/// it. This allows recovering from a voter set which is unable to make
+	/// progress.
+	Forced,
+}
 
-// Instantiates the given last round, to be backgrounded until its estimate is finalized.
-//
-// This round must be completable based on the passed votes (and if not, `None` will be returned),
-// but it may be the case that there are some more votes to propagate in order to push
-// the estimate backwards and conclude the round (i.e. finalize its estimate).
-//
-// may only be called with non-zero last round.
-fn instantiate_last_round<H, N, E: Environment<H, N>>(
-	voters: VoterSet<E::Id>,
-	last_round_votes: Vec<SignedMessage<H, N, E::Signature, E::Id>>,
-	last_round_number: u64,
-	last_round_base: (H, N),
-	finalized_sender: mpsc::UnboundedSender<FinalizedNotification<H, N, E>>,
-	env: Arc<E>,
-) -> Option<VotingRound<H, N, E>>
-where
-	H: Clone + Eq + Ord + ::std::fmt::Debug,
-	N: Copy + BlockNumberOps + ::std::fmt::Debug,
-{
-	let last_round_tracker = crate::round::Round::new(crate::round::RoundParams {
-		voters,
-		base: last_round_base,
-		round_number: last_round_number,
-	});
+/// A change of the voter set, scheduled to be enacted at a given block number.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VoterSetChange<H, N, Id: Eq + Ord> {
+	/// The hash of the block announcing the change. The change is only enacted
+	/// on a chain containing this block.
+	pub canon_hash: H,
+	/// The voters that make up the next voter set.
+	pub next_voters: VoterSet<Id>,
+	/// The number of the block at which the change is enacted.
+	pub effective_number: N,
+	/// The kind of change.
+	pub kind: VoterSetChangeKind,
+}
 
-	// start as completed so we don't cast votes.
-	let mut last_round = VotingRound::completed(last_round_tracker, finalized_sender, None, env);
+#[cfg(feature = "std")]
+/// A handle to schedule voter set changes on a running [`Voter`].
+///
+/// Changes are enacted one at a time, in the order they were scheduled.
+#[derive(Clone)]
+pub struct VoterSetChangeHandle<H, N, Id: Eq + Ord>(UnboundedSender<VoterSetChange<H, N, Id>>);
 
-	for vote in last_round_votes {
-		// bail if any votes are bad.
-		last_round.handle_vote(vote).ok()?;
+#[cfg(feature = "std")]
+impl<H, N, Id: Eq + Ord> VoterSetChangeHandle<H, N, Id> {
+	/// Schedule a voter set change. The change is given back if the voter
+	/// has already been dropped.
+	pub fn schedule(
+		&self,
+		change: VoterSetChange<H, N, Id>,
+	) -> Result<(), VoterSetChange<H, N, Id>> {
+		self.0.unbounded_send(change).map_err(|e| e.into_inner())
 	}
+}
 
-	if last_round.round_state().completable {
-		Some(last_round)
-	} else {
-		None
+#[cfg(feature = "std")]
+/// A handle to notify a running [`Voter`] of imported blocks, see
+/// [`Voter::note_block_imported`].
+#[derive(Clone)]
+pub struct BlockImportHandle<H, N>(UnboundedSender<(H, N)>);
+
+#[cfg(feature = "std")]
+impl<H, N> BlockImportHandle<H, N> {
+	/// Note that a block was imported, handling the buffered votes on it. Does
+	/// nothing if the voter has already been dropped.
+	pub fn note_block_imported(&self, hash: H, number: N) {
+		let _ = self.0.unbounded_send((hash, number));
 	}
 }
 
-// The inner state of a voter aggregating the currently running round state
-// (i.e. best and background rounds). This state exists separately since it's
-// useful to wrap in a `Arc<Mutex<_>>` for sharing.
-struct InnerVoterState<H, N, E>
-where
-	H: Clone + Ord + std::fmt::Debug,
-	N: BlockNumberOps,
-	E: Environment<H, N>,
+/// The state a voter needs to be restarted after a crash without equivocating,
+/// see [`Environment::persist_state`] and [`Voter::from_persistent_state`].
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
+#[cfg_attr(feature = "derive-codec", derive(Encode, Decode, TypeInfo))]
+pub struct VoterPersistentState<H, N, S, Id> {
+	/// The voter set id.
+	pub set_id: u64,
+	/// The number of the round the voter is voting in.
+	pub round_number: u64,
+	/// The base block of the round the voter is voting in.
+	pub round_base: (H, N),
+	/// The highest block finalized by the voter, which may be above the base
+	/// of the round it's voting in.
+	pub last_finalized: (H, N),
+	/// Our primary proposal in the current round, if any.
+	pub primary_propose: Option<PrimaryPropose<H, N>>,
+	/// Our prevote in the current round, if any.
+	pub prevote: Option<Prevote<H, N>>,
+	/// Our precommit in the current round, if any.
+	pub precommit: Option<Precommit<H, N>>,
+	/// The number of the last completed round, 0 if there is none.
+	pub completed_round_number: u64,
+	/// The base block of the last completed round.
+	pub completed_round_base: (H, N),
+	/// The votes which completed the last completed round.
+	pub completed_round_votes: Vec<SignedMessage<H, N, S, Id>>,
+}
+
+/// An event in the lifecycle of a [`Voter`], see [`Voter::events`].
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq)]
+pub enum VoterEvent<H, N, S, Id> {
+	/// A round was started, either to vote in it or to follow it in the
+	/// background.
+	RoundStarted {
+		/// The voter set id.
+		set_id: u64,
+		/// The round number.
+		round: u64,
+	},
+	/// We've done a primary proposal in the given round.
+	Proposed {
+		/// The voter set id.
+		set_id: u64,
+		/// The round number.
+		round: u64,
+		/// The primary proposal.
+		propose: PrimaryPropose<H, N>,
+	},
+	/// We've prevoted in the given round.
+	Prevoted {
+		/// The voter set id.
+		set_id: u64,
+		/// The round number.
+		round: u64,
+		/// The prevote.
+		prevote: Prevote<H, N>,
+	},
+	/// We've precommitted in the given round.
+	Precommitted {
+		/// The voter set id.
+		set_id: u64,
+		/// The round number.
+		round: u64,
+		/// The precommit.
+		precommit: Precommit<H, N>,
+	},
+	/// A round was completed, see [`Environment::completed`].
+	Completed {
+		/// The voter set id.
+		set_id: u64,
+		/// The round number.
+		round: u64,
+		/// The state of the round.
+		state: RoundState<H, N>,
+		/// The base block of the round.
+		base: (H, N),
+		/// The votes of the round.
+		votes: HistoricalVotes<H, N, S, Id>,
+	},
+	/// A round was concluded, see [`Environment::concluded`].
+	Concluded {
+		/// The voter set id.
+		set_id: u64,
+		/// The round number.
+		round: u64,
+		/// The state of the round.
+		state: RoundState<H, N>,
+		/// The base block of the round.
+		base: (H, N),
+		/// The votes of the round.
+		votes: HistoricalVotes<H, N, S, Id>,
+	},
+	/// A block was finalized, see [`Environment::finalize_block`].
+	Finalized {
+		/// The block hash.
+		hash: H,
+		/// The block number.
+		number: N,
+		/// The voter set id.
+		set_id: u64,
+		/// The round the block was finalized in.
+		round: u64,
+		/// The commit justifying the finality of the block.
+		commit: Commit<H, N, S, Id>,
+	},
+	/// A valid commit received from the network was imported.
+	CommitImported {
+		/// The voter set id.
+		set_id: u64,
+		/// The round number.
+		round: u64,
+		/// The hash of the commit target.
+		target_hash: H,
+		/// The number of the commit target.
+		target_number: N,
+	},
+	/// We've broadcast a commit.
+	CommitBroadcast {
+		/// The voter set id.
+		set_id: u64,
+		/// The round number.
+		round: u64,
+		/// The commit.
+		commit: Commit<H, N, S, Id>,
+	},
+	/// A catch-up received from the network was applied, the voter skipped to
+	/// the round following it.
+	CatchUpApplied {
+		/// The voter set id.
+		set_id: u64,
+		/// The round number of the catch-up.
+		round: u64,
+	},
+	/// An equivocation in prevotes has occurred.
+	PrevoteEquivocation {
+		/// The voter set id.
+		set_id: u64,
+		/// The round number.
+		round: u64,
+		/// The equivocation.
+		equivocation: Equivocation<Id, Prevote<H, N>, S>,
+	},
+	/// An equivocation in precommits has occurred.
+	PrecommitEquivocation {
+		/// The voter set id.
+		set_id: u64,
+		/// The round number.
+		round: u64,
+		/// The equivocation.
+		equivocation: Equivocation<Id, Precommit<H, N>, S>,
+	},
+}
+
+#[cfg(feature = "std")]
+type VoterEventSender<H, N, E> = UnboundedSender<
+	VoterEvent<H, N, <E as Environment<H, N>>::Signature, <E as Environment<H, N>>::Id>,
+>;
+
+#[cfg(feature = "std")]
+type SharedVoterCore<H, N, E> = Arc<
+	Mutex<VoterCore<H, N, <E as Environment<H, N>>::Signature, <E as Environment<H, N>>::Id>>,
+>;
+
+#[cfg(feature = "std")]
+// The chain and signature verifier of an environment, as needed by the voter core.
+struct CoreEnv<'a, E>(&'a E);
+
+#[cfg(feature = "std")]
+impl<'a, H: Eq, N: BlockNumberOps, E: Environment<H, N>> Chain<H, N> for CoreEnv<'a, E> {
+	fn ancestry(&self, base: H, block: H) -> Result<Vec<H>, crate::Error> {
+		self.0.ancestry(base, block)
+	}
+
+	fn is_equal_or_descendent_of(&self, base: H, block: H) -> bool {
+		self.0.is_equal_or_descendent_of(base, block)
+	}
+
+	fn hash_at(&self, block: H, number: N) -> Option<H> {
+		self.0.hash_at(block, number)
+	}
+}
+
+#[cfg(feature = "std")]
+impl<'a, H: Eq, N: BlockNumberOps, E: Environment<H, N>> CoreEnvironment<H, N, E::Signature, E::Id>
+	for CoreEnv<'a, E>
 {
-	best_round: VotingRound<H, N, E>,
-	past_rounds: PastRounds<H, N, E>,
+	fn signature_verifier(&self) -> Option<&dyn SignatureVerifier<H, N, E::Signature, E::Id>> {
+		self.0.signature_verifier()
+	}
+
+	fn voting_rule(&self) -> Option<&dyn VotingRule<H, N>> {
+		self.0.voting_rule()
+	}
+}
+
+#[cfg(feature = "std")]
+// The streams and timers of a running round.
+struct RoundIo<H: Eq, N: BlockNumberOps, E: Environment<H, N>> {
+	incoming: E::In,
+	outgoing: Buffered<E::Out, Message<H, N>>,
+	// the timers handed out with the round data, until armed by the core.
+	prevote_timer: Option<E::Timer>,
+	precommit_timer: Option<E::Timer>,
+	timings: RoundTimings,
+}
+
+#[cfg(feature = "std")]
+// The callback of the global message being handled, run with the outcome of
+// handling it.
+enum GlobalCallback<H, N, S, Id> {
+	None,
+	Commit { set_id: u64, round: u64, target: (H, N), callback: Callback<CommitProcessingOutcome> },
+	CatchUp { set_id: u64, round: u64, callback: Callback<CatchUpProcessingOutcome> },
+	Neighbor(Callback<CatchUp<H, N, S, Id>>),
 }
 
+#[cfg(feature = "std")]
 /// A future that maintains and multiplexes between different rounds,
 /// and caches votes.
 ///
@@ -473,6 +884,16 @@ where
 /// Additionally, we also listen to commit messages from rounds that aren't
 /// currently running, we validate the commit and dispatch a finalization
 /// notification (if any) to the environment.
+///
+/// The voter set can be changed through a [`VoterSetChangeHandle`]. Once a
+/// scheduled change is enacted the voter discards all rounds of the current
+/// set, broadcasts the last commit it has seen for it (standard changes only),
+/// and starts voting in round 1 of the next set id, on top of the block that
+/// triggered the change. Commits and catch-ups for any other set id are
+/// rejected.
+///
+/// All of the protocol logic lives in a [`VoterCore`], the voter only drives
+/// it with the streams, timers and futures of the [`Environment`].
 pub struct Voter<H, N, E: Environment<H, N>, GlobalIn, GlobalOut>
 where
 	H: Clone + Eq + Ord + ::std::fmt::Debug,
@@ -481,18 +902,23 @@ where
 	GlobalOut: Sink<CommunicationOut<H, N, E::Signature, E::Id>, Error = E::Error> + Unpin,
 {
 	env: Arc<E>,
-	voters: VoterSet<E::Id>,
-	inner: Arc<Mutex<InnerVoterState<H, N, E>>>,
-	finalized_notifications: UnboundedReceiver<FinalizedNotification<H, N, E>>,
-	last_finalized_number: N,
+	core: SharedVoterCore<H, N, E>,
+	// the running rounds, by voter set id and round number.
+	rounds: BTreeMap<(u64, u64), RoundIo<H, N, E>>,
+	timers: BTreeMap<TimerId, E::Timer>,
+	best_chain_queries: BTreeMap<BestChainRequest, E::BestChain>,
 	global_in: GlobalIn,
 	global_out: Buffered<GlobalOut, CommunicationOut<H, N, E::Signature, E::Id>>,
-	// the commit protocol might finalize further than the current round (if we're
-	// behind), we keep track of last finalized in round so we don't violate any
-	// assumptions from round-to-round.
-	last_finalized_in_rounds: (H, N),
+	set_change_sender: UnboundedSender<VoterSetChange<H, N, E::Id>>,
+	set_change_receiver: UnboundedReceiver<VoterSetChange<H, N, E::Id>>,
+	block_import_sender: UnboundedSender<(H, N)>,
+	block_import_receiver: UnboundedReceiver<(H, N)>,
+	event_senders: Vec<VoterEventSender<H, N, E>>,
+	// the set id, round and number of the last block we finalized.
+	last_finalization: Option<(u64, u64, N)>,
 }
 
+#[cfg(feature = "std")]
 impl<'a, H: 'a, N, E: 'a, GlobalIn, GlobalOut> Voter<H, N, E, GlobalIn, GlobalOut>
 where
 	H: Clone + Ord + ::std::fmt::Debug + Sync + Send,
@@ -506,14 +932,12 @@ where
 	where
 		<E as Environment<H, N>>::Signature: Send,
 		<E as Environment<H, N>>::Id: Hash + Send,
-		<E as Environment<H, N>>::Timer: Send,
-		<E as Environment<H, N>>::Out: Send,
-		<E as Environment<H, N>>::In: Send,
 	{
-		Box::new(SharedVoterState(self.inner.clone()))
+		Box::new(SharedVoterState(self.core.clone()))
 	}
 }
 
+#[cfg(feature = "std")]
 impl<H, N, E: Environment<H, N>, GlobalIn, GlobalOut> Voter<H, N, E, GlobalIn, GlobalOut>
 where
 	H: Clone + Eq + Ord + ::std::fmt::Debug,
@@ -521,320 +945,490 @@ where
 	GlobalIn: Stream<Item = Result<CommunicationIn<H, N, E::Signature, E::Id>, E::Error>> + Unpin,
 	GlobalOut: Sink<CommunicationOut<H, N, E::Signature, E::Id>, Error = E::Error> + Unpin,
 {
-	/// Create new `Voter` tracker with given round number and base block.
+	/// Create new `Voter` tracker with given voter set id, round number and base block.
 	///
-	/// Provide data about the last completed round. If there is no
-	/// known last completed round, the genesis state (round number 0, no votes, genesis base),
-	/// should be provided. When available, all messages required to complete
-	/// the last round should be provided.
+	/// Provide data about the last completed round, see [`VoterParams`].
 	///
 	/// The input stream for commit messages should provide commits which
 	/// correspond to known blocks only (including all its precommits). It
 	/// is also responsible for validating the signature data in commit
-	/// messages.
+	/// messages, unless the environment provides a signature verifier.
 	pub fn new(
 		env: Arc<E>,
-		voters: VoterSet<E::Id>,
 		global_comms: (GlobalIn, GlobalOut),
-		last_round_number: u64,
-		last_round_votes: Vec<SignedMessage<H, N, E::Signature, E::Id>>,
-		last_round_base: (H, N),
-		last_finalized: (H, N),
+		params: VoterParams<H, N, E::Signature, E::Id>,
 	) -> Self {
-		let (finalized_sender, finalized_notifications) = mpsc::unbounded();
-		let last_finalized_number = last_finalized.1;
-
-		// re-start the last round and queue all messages to be processed on first poll.
-		// keep it in the background so we can push the estimate backwards until finalized
-		// by actually waiting for more messages.
-		let mut past_rounds = PastRounds::new();
-		let mut last_round_state =
-			crate::bridge_state::bridge_state(RoundState::genesis(last_round_base.clone())).1;
-
-		if last_round_number > 0 {
-			let maybe_completed_last_round = instantiate_last_round(
-				voters.clone(),
-				last_round_votes,
-				last_round_number,
-				last_round_base,
-				finalized_sender.clone(),
-				env.clone(),
-			);
+		let core = VoterCore::new(&CoreEnv(&*env), params);
 
-			if let Some(mut last_round) = maybe_completed_last_round {
-				last_round_state = last_round.bridge_state();
-				past_rounds.push(&*env, last_round);
-			}
+		Voter::with_core(env, global_comms, core)
+	}
 
-			// when there is no information about the last completed round,
-			// the best we can do is assume that the estimate == the given base
-			// and that it is finalized. This is always the case for the genesis
-			// round of a set.
-		}
+	/// Create a `Voter` from a persistent state handed to
+	/// [`Environment::persist_state`] earlier, e.g. before a crash.
+	///
+	/// The voter resumes voting in the round it was voting in, replaying any
+	/// votes it had cast there instead of casting new ones.
+	pub fn from_persistent_state(
+		env: Arc<E>,
+		voters: VoterSet<E::Id>,
+		global_comms: (GlobalIn, GlobalOut),
+		state: VoterPersistentState<H, N, E::Signature, E::Id>,
+	) -> Self {
+		let core = VoterCore::from_persistent_state(&CoreEnv(&*env), voters, state);
 
-		let best_round = VotingRound::new(
-			last_round_number + 1,
-			voters.clone(),
-			last_finalized.clone(),
-			Some(last_round_state),
-			finalized_sender,
-			env.clone(),
-		);
+		Voter::with_core(env, global_comms, core)
+	}
 
+	fn with_core(
+		env: Arc<E>,
+		global_comms: (GlobalIn, GlobalOut),
+		core: VoterCore<H, N, E::Signature, E::Id>,
+	) -> Self {
+		let (set_change_sender, set_change_receiver) = mpsc::unbounded();
+		let (block_import_sender, block_import_receiver) = mpsc::unbounded();
 		let (global_in, global_out) = global_comms;
 
-		let inner = Arc::new(Mutex::new(InnerVoterState { best_round, past_rounds }));
-
 		Voter {
 			env,
-			voters,
-			inner,
-			finalized_notifications,
-			last_finalized_number,
-			last_finalized_in_rounds: last_finalized,
+			core: Arc::new(Mutex::new(core)),
+			rounds: BTreeMap::new(),
+			timers: BTreeMap::new(),
+			best_chain_queries: BTreeMap::new(),
 			global_in,
 			global_out: Buffered::new(global_out),
+			set_change_sender,
+			set_change_receiver,
+			block_import_sender,
+			block_import_receiver,
+			event_senders: Vec::new(),
+			last_finalization: None,
 		}
 	}
 
-	fn prune_background_rounds(&mut self, cx: &mut Context) -> Result<(), E::Error> {
-		{
-			let mut inner = self.inner.lock();
+	/// Get the current persistent state of the voter. See also
+	/// [`Environment::persist_state`].
+	pub fn persistent_state(&self) -> VoterPersistentState<H, N, E::Signature, E::Id> {
+		self.core.lock().persistent_state()
+	}
 
-			// Do work on all background rounds, broadcasting any commits generated.
-			while let Poll::Ready(Some(item)) =
-				Stream::poll_next(Pin::new(&mut inner.past_rounds), cx)
-			{
-				let (number, commit) = item?;
-				self.global_out.push(CommunicationOut::Commit(number, commit));
-			}
-		}
+	/// Get the id of the voter set we are currently voting in.
+	pub fn set_id(&self) -> u64 {
+		self.core.lock().set_id()
+	}
 
-		while let Poll::Ready(res) =
-			Stream::poll_next(Pin::new(&mut self.finalized_notifications), cx)
-		{
-			let inner = self.inner.clone();
-			let mut inner = inner.lock();
+	/// Returns a handle which can be used to schedule voter set changes.
+	pub fn set_change_handle(&self) -> VoterSetChangeHandle<H, N, E::Id> {
+		VoterSetChangeHandle(self.set_change_sender.clone())
+	}
 
-			let (f_hash, f_num, round, commit) =
-				res.expect("one sender always kept alive in self.best_round; qed");
+	/// Note that a block was imported. Votes received before the block they
+	/// target was imported are buffered, within the limits of
+	/// [`MAX_PENDING_VOTES_PER_VOTER`] and [`MAX_PENDING_VOTES_PER_ROUND`],
+	/// and are handled in their round on the next poll of the voter.
+	pub fn note_block_imported(&self, hash: H, number: N) {
+		let _ = self.block_import_sender.unbounded_send((hash, number));
+	}
 
-			inner.past_rounds.update_finalized(f_num);
+	/// Returns a handle which can be used to note imported blocks once the
+	/// voter is running, see [`Voter::note_block_imported`].
+	pub fn block_import_handle(&self) -> BlockImportHandle<H, N> {
+		BlockImportHandle(self.block_import_sender.clone())
+	}
 
-			if self.set_last_finalized_number(f_num) {
-				self.env.finalize_block(f_hash.clone(), f_num, round, commit)?;
-			}
+	/// Subscribe to the events of the voter, covering its whole lifecycle.
+	///
+	/// The stream yields the events happening after the subscription, in order,
+	/// and ends once the voter is dropped.
+	pub fn events(&mut self) -> UnboundedReceiver<VoterEvent<H, N, E::Signature, E::Id>> {
+		let (sender, receiver) = mpsc::unbounded();
+		self.event_senders.push(sender);
+		receiver
+	}
 
-			if f_num > self.last_finalized_in_rounds.1 {
-				self.last_finalized_in_rounds = (f_hash, f_num);
-			}
-		}
+	/// Build a catch-up message for a peer which is currently voting in round `peer_round`.
+	///
+	/// The catch-up is built from the highest completable round we know of that
+	/// is ahead of the peer, be it the best round or one running in the
+	/// background. It only includes the votes of as many voters as necessary to
+	/// prove that the round is completable. Returns `None` if there is no such
+	/// round.
+	pub fn catch_up(&self, peer_round: u64) -> Option<CatchUp<H, N, E::Signature, E::Id>> {
+		self.core.lock().catch_up(&CoreEnv(&*self.env), peer_round)
+	}
 
-		Ok(())
+	// notify the subscribers of an event, dropping the ones which are gone.
+	fn emit(&mut self, event: VoterEvent<H, N, E::Signature, E::Id>) {
+		self.event_senders.retain(|sender| sender.unbounded_send(event.clone()).is_ok());
 	}
 
-	/// Process all incoming messages from other nodes.
-	///
-	/// Commit messages are handled with extra care. If a commit message references
-	/// a currently backgrounded round, we send it to that round so that when we commit
-	/// on that round, our commit message will be informed by those that we've seen.
-	///
-	/// Otherwise, we will simply handle the commit and issue a finalization command
-	/// to the environment.
-	fn process_incoming(&mut self, cx: &mut Context) -> Result<(), E::Error> {
-		while let Poll::Ready(Some(item)) = Stream::poll_next(Pin::new(&mut self.global_in), cx) {
-			match item? {
-				CommunicationIn::Commit(round_number, commit, mut process_commit_outcome) => {
-					trace!(target: "afg", "Got commit for round_number {:?}: target_number: {:?}, target_hash: {:?}",
-						round_number,
-						commit.target_number,
-						commit.target_hash,
-					);
+	// hand an input to the core and act on its outputs.
+	fn handle(
+		&mut self,
+		cx: &mut Context,
+		input: Input<H, N, E::Signature, E::Id>,
+		mut callback: GlobalCallback<H, N, E::Signature, E::Id>,
+	) -> Result<(), E::Error> {
+		self.core.lock().handle(&CoreEnv(&*self.env), input)?;
+		self.process_outputs(cx, &mut callback)
+	}
 
-					let commit: Commit<_, _, _, _> = commit.into();
-
-					let mut inner = self.inner.lock();
-
-					// if the commit is for a background round dispatch to round committer.
-					// that returns Some if there wasn't one.
-					if let Some(commit) = inner.past_rounds.import_commit(round_number, commit) {
-						// otherwise validate the commit and signal the finalized block
-						// (if any) to the environment
-						let validation_result = validate_commit(&commit, &self.voters, &*self.env)?;
-
-						if let Some((finalized_hash, finalized_number)) = validation_result.ghost {
-							// this can't be moved to a function because the compiler
-							// will complain about getting two mutable borrows to self
-							// (due to the call to `self.rounds.get_mut`).
-							let last_finalized_number = &mut self.last_finalized_number;
-
-							// clean up any background rounds
-							inner.past_rounds.update_finalized(finalized_number);
-
-							if finalized_number > *last_finalized_number {
-								*last_finalized_number = finalized_number;
-								self.env.finalize_block(
-									finalized_hash,
-									finalized_number,
-									round_number,
-									commit,
-								)?;
-							}
-
-							process_commit_outcome
-								.run(CommitProcessingOutcome::Good(GoodCommit::new()));
-						} else {
-							// Failing validation of a commit is bad.
-							process_commit_outcome.run(CommitProcessingOutcome::Bad(
-								BadCommit::from(validation_result),
-							));
-						}
-					} else {
-						// Import to backgrounded round is good.
-						process_commit_outcome
-							.run(CommitProcessingOutcome::Good(GoodCommit::new()));
-					}
+	// messages are sent as soon as they're output, so that a failure to send
+	// one stops the voter before it acts on any later output.
+	fn process_outputs(
+		&mut self,
+		cx: &mut Context,
+		callback: &mut GlobalCallback<H, N, E::Signature, E::Id>,
+	) -> Result<(), E::Error> {
+		loop {
+			let output = self.core.lock().next_output();
+			let output = match output {
+				Some(output) => output,
+				None => {
+					self.observe_rounds();
+					return Ok(())
 				},
-				CommunicationIn::CatchUp(catch_up, mut process_catch_up_outcome) => {
-					trace!(target: "afg", "Got catch-up message for round {}", catch_up.round_number);
-
-					let mut inner = self.inner.lock();
-
-					let round = if let Some(round) = validate_catch_up(
-						catch_up,
-						&*self.env,
-						&self.voters,
-						inner.best_round.round_number(),
-					) {
-						round
-					} else {
-						process_catch_up_outcome
-							.run(CatchUpProcessingOutcome::Bad(BadCatchUp::new()));
-						return Ok(())
-					};
+			};
 
-					let state = round.state();
+			match output {
+				Output::StartRound { set_id, round } => {
+					let data = self.env.round_data(set_id, round);
+					let timings = {
+						let core = self.core.lock();
+						let state = core
+							.round(round)
+							.filter(|_| core.set_id() == set_id)
+							.map(|votes| votes.state());
+
+						RoundTimings::new(state.as_ref())
+					};
 
-					// beyond this point, we set this round to the past and
-					// start voting in the next round.
-					let mut just_completed = VotingRound::completed(
-						round,
-						inner.best_round.finalized_sender(),
-						None,
-						self.env.clone(),
+					self.rounds.insert(
+						(set_id, round),
+						RoundIo {
+							incoming: data.incoming,
+							outgoing: Buffered::new(data.outgoing),
+							prevote_timer: Some(data.prevote_timer),
+							precommit_timer: Some(data.precommit_timer),
+							timings,
+						},
 					);
 
-					let new_best = VotingRound::new(
-						just_completed.round_number() + 1,
-						self.voters.clone(),
-						self.last_finalized_in_rounds.clone(),
-						Some(just_completed.bridge_state()),
-						inner.best_round.finalized_sender(),
-						self.env.clone(),
-					);
+					self.core.lock().handle(
+						&CoreEnv(&*self.env),
+						Input::RoundData { set_id, round, voter_id: data.voter_id },
+					)?;
+					self.emit(VoterEvent::RoundStarted { set_id, round });
+				},
+				Output::DropRound { set_id, round } => {
+					self.rounds.remove(&(set_id, round));
+					self.timers.retain(|id, _| (id.set_id, id.round) != (set_id, round));
+					self.best_chain_queries.remove(&BestChainRequest::Prevote { set_id, round });
+				},
+				Output::ArmTimer(id) => {
+					let io = self.rounds.get_mut(&(id.set_id, id.round));
+					let timer = match id.kind {
+						TimerKind::Prevote => io.and_then(|io| io.prevote_timer.take()),
+						TimerKind::Precommit => io.and_then(|io| io.precommit_timer.take()),
+						TimerKind::Commit => Some(self.env.round_commit_timer()),
+					};
 
-					// update last-finalized in rounds _after_ starting new round.
-					// otherwise the base could be too eagerly set forward.
-					if let Some((f_hash, f_num)) = state.finalized.clone() {
-						if f_num > self.last_finalized_in_rounds.1 {
-							self.last_finalized_in_rounds = (f_hash, f_num);
+					if let Some(timer) = timer {
+						self.timers.insert(id, timer);
+					}
+				},
+				Output::RequestBestChain { request, base } => {
+					self.best_chain_queries.insert(request, self.env.best_chain_containing(base));
+				},
+				Output::SendRoundMessage { set_id, round, message } =>
+					if let Some(io) = self.rounds.get_mut(&(set_id, round)) {
+						io.outgoing.push(message);
+						let _ = io.outgoing.poll(cx)?;
+					},
+				Output::SendGlobalMessage(message) => {
+					if let CommunicationOut::Commit(set_id, round, ref commit) = message {
+						if let Some(metrics) = self.env.metrics() {
+							metrics.inc_commits_broadcast();
 						}
+
+						let commit = commit.clone();
+						self.emit(VoterEvent::CommitBroadcast { set_id, round, commit });
 					}
 
-					self.env.completed(
-						just_completed.round_number(),
-						just_completed.round_state(),
-						just_completed.dag_base(),
-						just_completed.historical_votes(),
-					)?;
+					self.global_out.push(message);
+					let _ = self.global_out.poll(cx)?;
+				},
+				Output::SendCatchUp(catch_up) =>
+					if let GlobalCallback::Neighbor(callback) = callback {
+						callback.run(catch_up);
+					},
+				Output::CommitProcessed(outcome) => {
+					if let (Some(metrics), CommitProcessingOutcome::Good(_)) =
+						(self.env.metrics(), &outcome)
+					{
+						metrics.inc_commits_imported();
+					}
 
-					inner.past_rounds.push(&*self.env, just_completed);
+					if let GlobalCallback::Commit { set_id, round, target, callback } = callback {
+						if let CommitProcessingOutcome::Good(_) = outcome {
+							self.emit(VoterEvent::CommitImported {
+								set_id: *set_id,
+								round: *round,
+								target_hash: target.0.clone(),
+								target_number: target.1,
+							});
+						}
+
+						callback.run(outcome);
+					}
+				},
+				Output::CatchUpProcessed(outcome) => {
+					if let Some(metrics) = self.env.metrics() {
+						match outcome {
+							CatchUpProcessingOutcome::Good(_) => metrics.inc_catch_ups_accepted(),
+							CatchUpProcessingOutcome::Bad(ref bad) =>
+								metrics.inc_catch_ups_rejected(bad.reason()),
+							CatchUpProcessingOutcome::Useless => {},
+						}
+					}
 
-					let old_best = std::mem::replace(&mut inner.best_round, new_best);
-					inner.past_rounds.push(&*self.env, old_best);
+					if let GlobalCallback::CatchUp { set_id, round, callback } = callback {
+						if let CatchUpProcessingOutcome::Good(_) = outcome {
+							self.emit(VoterEvent::CatchUpApplied {
+								set_id: *set_id,
+								round: *round,
+							});
+						}
 
-					process_catch_up_outcome
-						.run(CatchUpProcessingOutcome::Good(GoodCatchUp::new()));
+						callback.run(outcome);
+					}
+				},
+				Output::Proposed { set_id, round, propose } => {
+					self.env.proposed(set_id, round, propose.clone())?;
+					self.emit(VoterEvent::Proposed { set_id, round, propose });
+				},
+				Output::Prevoted { set_id, round, prevote } => {
+					self.env.prevoted(set_id, round, prevote.clone())?;
+					self.emit(VoterEvent::Prevoted { set_id, round, prevote });
+				},
+				Output::Precommitted { set_id, round, precommit } => {
+					self.env.precommitted(set_id, round, precommit.clone())?;
+					self.emit(VoterEvent::Precommitted { set_id, round, precommit });
+				},
+				Output::PersistState(state) => self.env.persist_state(&state)?,
+				Output::Completed { set_id, round, state, base, votes } => {
+					self.env.completed(set_id, round, state.clone(), base.clone(), &votes)?;
+					self.observe_completed_round(set_id, round);
+					self.emit(VoterEvent::Completed { set_id, round, state, base, votes });
+				},
+				Output::Concluded { set_id, round, state, base, votes } => {
+					self.env.concluded(set_id, round, state.clone(), base.clone(), &votes)?;
+					self.emit(VoterEvent::Concluded { set_id, round, state, base, votes });
+				},
+				Output::Finalize { hash, number, set_id, round, commit } => {
+					self.env.finalize_block(hash.clone(), number, set_id, round, commit.clone())?;
+					self.observe_finalization(set_id, round, number);
+					self.emit(VoterEvent::Finalized { hash, number, set_id, round, commit });
+				},
+				Output::PrevoteEquivocation { set_id, round, equivocation } => {
+					self.env.prevote_equivocation(set_id, round, equivocation.clone());
+					self.emit(VoterEvent::PrevoteEquivocation { set_id, round, equivocation });
+				},
+				Output::PrecommitEquivocation { set_id, round, equivocation } => {
+					self.env.precommit_equivocation(set_id, round, equivocation.clone());
+					self.emit(VoterEvent::PrecommitEquivocation { set_id, round, equivocation });
 				},
 			}
 		}
-
-		Ok(())
 	}
 
-	// process the logic of the best round.
-	fn process_best_round(&mut self, cx: &mut Context) -> Poll<Result<(), E::Error>> {
-		// If the current `best_round` is completable and we've already precommitted,
-		// we start a new round at `best_round + 1`.
-		{
-			let mut inner = self.inner.lock();
-
-			let should_start_next = {
-				let completable = match inner.best_round.poll(cx)? {
-					Poll::Ready(()) => true,
-					Poll::Pending => false,
-				};
-
-				let precommitted = match inner.best_round.state() {
-					Some(&VotingRoundState::Precommitted) => true, // start when we've cast all votes.
-					_ => false,
-				};
+	// time the milestones reached by the running rounds.
+	fn observe_rounds(&mut self) {
+		let metrics = match self.env.metrics() {
+			Some(metrics) => metrics,
+			None => return,
+		};
 
-				completable && precommitted
-			};
+		let core = self.core.lock();
+		for (&(set_id, round), io) in self.rounds.iter_mut() {
+			if set_id != core.set_id() || !io.timings.is_pending() {
+				continue
+			}
 
-			if !should_start_next {
-				return Poll::Pending
+			if let Some(votes) = core.round(round) {
+				io.timings.observe(&votes.state(), metrics);
 			}
+		}
+	}
+
+	// record the participation in a round which was just completed.
+	fn observe_completed_round(&self, set_id: u64, round: u64) {
+		let metrics = match self.env.metrics() {
+			Some(metrics) => metrics,
+			None => return,
+		};
 
-			trace!(target: "afg", "Best round at {} has become completable. Starting new best round at {}",
-				inner.best_round.round_number(),
-				inner.best_round.round_number() + 1,
+		let core = self.core.lock();
+		if let Some(votes) = core.round(round).filter(|_| core.set_id() == set_id) {
+			metrics.observe_completed_round_weight(
+				votes.prevote_participation().0 .0,
+				votes.precommit_participation().0 .0,
 			);
 		}
+	}
 
-		self.completed_best_round()?;
+	// time the first finalization in a round, and count the rounds it took to
+	// finalize the blocks since the previous finalization.
+	fn observe_finalization(&mut self, set_id: u64, round: u64, number: N) {
+		let last_finalization = self.last_finalization.replace((set_id, round, number));
+		let metrics = match self.env.metrics() {
+			Some(metrics) => metrics,
+			None => return,
+		};
 
-		// round has been updated. so we need to re-poll.
-		self.poll_unpin(cx)
-	}
+		if let Some(io) = self.rounds.get_mut(&(set_id, round)) {
+			io.timings.finalized(metrics);
+		}
 
-	fn completed_best_round(&mut self) -> Result<(), E::Error> {
-		let mut inner = self.inner.lock();
+		match last_finalization {
+			Some((last_set_id, last_round, last_number))
+				if last_set_id == set_id && round >= last_round && number > last_number =>
+			{
+				let blocks: usize = (number - last_number).as_();
+				metrics.observe_rounds_per_finalized_block(
+					(round - last_round) as f64 / blocks as f64,
+				);
+			},
+			_ => {},
+		}
+	}
 
-		self.env.completed(
-			inner.best_round.round_number(),
-			inner.best_round.round_state(),
-			inner.best_round.dag_base(),
-			inner.best_round.historical_votes(),
-		)?;
+	// Process all incoming messages from other nodes. Returns whether any
+	// message was handled.
+	fn process_incoming(&mut self, cx: &mut Context) -> Result<bool, E::Error> {
+		let mut progress = false;
 
-		let old_round_number = inner.best_round.round_number();
+		while let Poll::Ready(Some(item)) = Stream::poll_next(Pin::new(&mut self.global_in), cx) {
+			progress = true;
 
-		let next_round = VotingRound::new(
-			old_round_number + 1,
-			self.voters.clone(),
-			self.last_finalized_in_rounds.clone(),
-			Some(inner.best_round.bridge_state()),
-			inner.best_round.finalized_sender(),
-			self.env.clone(),
-		);
+			match item? {
+				CommunicationIn::Commit(set_id, round, commit, callback) => {
+					let target = (commit.target_hash.clone(), commit.target_number);
+					self.handle(
+						cx,
+						Input::Commit { set_id, round, commit: commit.into() },
+						GlobalCallback::Commit { set_id, round, target, callback },
+					)?
+				},
+				CommunicationIn::CatchUp(catch_up, callback) => {
+					let (set_id, round) = (catch_up.set_id, catch_up.round_number);
+					self.handle(
+						cx,
+						Input::CatchUp(catch_up),
+						GlobalCallback::CatchUp { set_id, round, callback },
+					)?
+				},
+				CommunicationIn::Neighbor(packet, callback) =>
+					self.handle(cx, Input::Neighbor(packet), GlobalCallback::Neighbor(callback))?,
+			}
+		}
 
-		let old_round = ::std::mem::replace(&mut inner.best_round, next_round);
-		inner.past_rounds.push(&*self.env, old_round);
-		Ok(())
+		Ok(progress)
 	}
 
-	fn set_last_finalized_number(&mut self, finalized_number: N) -> bool {
-		let last_finalized_number = &mut self.last_finalized_number;
-		if finalized_number > *last_finalized_number {
-			*last_finalized_number = finalized_number;
-			return true
+	// Schedule any voter set changes received through the handle.
+	fn process_set_changes(&mut self, cx: &mut Context) -> Result<bool, E::Error> {
+		let mut progress = false;
+
+		while let Poll::Ready(Some(change)) =
+			Stream::poll_next(Pin::new(&mut self.set_change_receiver), cx)
+		{
+			progress = true;
+			self.handle(cx, Input::SetChange(change), GlobalCallback::None)?;
 		}
-		false
+
+		Ok(progress)
 	}
-}
 
+	// Process the blocks imported, replaying the votes buffered on them.
+	fn process_block_imports(&mut self, cx: &mut Context) -> Result<bool, E::Error> {
+		let mut progress = false;
+
+		while let Poll::Ready(Some((hash, number))) =
+			Stream::poll_next(Pin::new(&mut self.block_import_receiver), cx)
+		{
+			progress = true;
+			self.handle(cx, Input::BlockImported { hash, number }, GlobalCallback::None)?;
+		}
+
+		Ok(progress)
+	}
+
+	// Process the incoming votes of all rounds.
+	fn process_rounds(&mut self, cx: &mut Context) -> Result<bool, E::Error> {
+		let mut progress = false;
+
+		for (set_id, round) in self.rounds.keys().cloned().collect::<Vec<_>>() {
+			// the round might be dropped while handling its votes.
+			while let Some(io) = self.rounds.get_mut(&(set_id, round)) {
+				let vote = match Stream::poll_next(Pin::new(&mut io.incoming), cx) {
+					Poll::Ready(Some(vote)) => vote?,
+					_ => break,
+				};
+
+				progress = true;
+				self.handle(cx, Input::Vote { set_id, round, vote }, GlobalCallback::None)?;
+			}
+		}
+
+		Ok(progress)
+	}
+
+	// Notify the core of all fired timers.
+	fn process_timers(&mut self, cx: &mut Context) -> Result<bool, E::Error> {
+		let mut fired = Vec::new();
+
+		for (id, timer) in self.timers.iter_mut() {
+			if let Poll::Ready(res) = Future::poll(Pin::new(timer), cx) {
+				res?;
+				fired.push(*id);
+			}
+		}
+
+		for id in &fired {
+			self.timers.remove(id);
+		}
+
+		for id in &fired {
+			self.handle(cx, Input::TimerFired(*id), GlobalCallback::None)?;
+		}
+
+		Ok(!fired.is_empty())
+	}
+
+	// Hand the answers to all best chain queries to the core.
+	fn process_best_chains(&mut self, cx: &mut Context) -> Result<bool, E::Error> {
+		let mut answered = Vec::new();
+
+		for (request, query) in self.best_chain_queries.iter_mut() {
+			if let Poll::Ready(best) = Future::poll(Pin::new(query), cx) {
+				answered.push((*request, best?));
+			}
+		}
+
+		for (request, _) in &answered {
+			self.best_chain_queries.remove(request);
+		}
+
+		let progress = !answered.is_empty();
+		for (request, best) in answered {
+			self.handle(cx, Input::BestChain { request, best }, GlobalCallback::None)?;
+		}
+
+		Ok(progress)
+	}
+}
+
+#[cfg(feature = "std")]
 impl<H, N, E: Environment<H, N>, GlobalIn, GlobalOut> Future for Voter<H, N, E, GlobalIn, GlobalOut>
 where
 	H: Clone + Eq + Ord + ::std::fmt::Debug,
@@ -845,14 +1439,32 @@ where
 	type Output = Result<(), E::Error>;
 
 	fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), E::Error>> {
-		self.process_incoming(cx)?;
-		self.prune_background_rounds(cx)?;
-		let _ = self.global_out.poll(cx)?;
+		// act on the outputs queued when creating the core.
+		self.process_outputs(cx, &mut GlobalCallback::None)?;
+
+		// any input handled might have started new rounds, armed new timers or
+		// issued new queries, which need to be polled in turn.
+		loop {
+			let mut progress = self.process_incoming(cx)?;
+			progress |= self.process_set_changes(cx)?;
+			progress |= self.process_block_imports(cx)?;
+			progress |= self.process_rounds(cx)?;
+			progress |= self.process_timers(cx)?;
+			progress |= self.process_best_chains(cx)?;
+
+			for io in self.rounds.values_mut() {
+				let _ = io.outgoing.poll(cx)?;
+			}
+			let _ = self.global_out.poll(cx)?;
 
-		self.process_best_round(cx)
+			if !progress {
+				return Poll::Pending
+			}
+		}
 	}
 }
 
+#[cfg(feature = "std")]
 impl<H, N, E: Environment<H, N>, GlobalIn, GlobalOut> Unpin for Voter<H, N, E, GlobalIn, GlobalOut>
 where
 	H: Clone + Eq + Ord + ::std::fmt::Debug,
@@ -862,6 +1474,7 @@ where
 {
 }
 
+#[cfg(feature = "std")]
 /// Trait for querying the state of the voter. Used by `Voter` to return a queryable object
 /// without exposing too many data types.
 pub trait VoterState<Id: Eq + std::hash::Hash> {
@@ -870,6 +1483,7 @@ pub trait VoterState<Id: Eq + std::hash::Hash> {
 	fn get(&self) -> report::VoterState<Id>;
 }
 
+#[cfg(feature = "std")]
 /// Contains a number of data transfer objects for reporting data to the outside world.
 pub mod report {
 	use crate::weights::{VoteWeight, VoterWeight};
@@ -907,184 +1521,196 @@ pub mod report {
 	}
 }
 
-struct SharedVoterState<H, N, E>(Arc<Mutex<InnerVoterState<H, N, E>>>)
+#[cfg(feature = "std")]
+struct SharedVoterState<H, N, S, Id>(Arc<Mutex<VoterCore<H, N, S, Id>>>)
 where
-	H: Clone + Ord + std::fmt::Debug,
-	N: BlockNumberOps,
-	E: Environment<H, N>;
+	H: Clone + Eq + Ord + std::fmt::Debug,
+	N: Copy + BlockNumberOps + std::fmt::Debug,
+	S: Clone + Eq,
+	Id: Clone + Eq + Ord + std::fmt::Debug;
 
-impl<H, N, E> VoterState<E::Id> for SharedVoterState<H, N, E>
+#[cfg(feature = "std")]
+impl<H, N, S, Id> VoterState<Id> for SharedVoterState<H, N, S, Id>
 where
 	H: Clone + Eq + Ord + std::fmt::Debug,
-	N: BlockNumberOps,
-	E: Environment<H, N>,
-	<E as Environment<H, N>>::Id: Hash,
+	N: Copy + BlockNumberOps + std::fmt::Debug,
+	S: Clone + Eq,
+	Id: Clone + Eq + Ord + std::fmt::Debug + Hash,
 {
-	fn get(&self) -> report::VoterState<E::Id> {
-		let to_round_state = |voting_round: &VotingRound<H, N, E>| {
+	fn get(&self) -> report::VoterState<Id> {
+		let to_round_state = |round: &Round<Id, H, N, S>| {
 			(
-				voting_round.round_number(),
+				round.number(),
 				report::RoundState {
-					total_weight: voting_round.voters().total_weight(),
-					threshold_weight: voting_round.voters().threshold(),
-					prevote_current_weight: voting_round.prevote_weight(),
-					prevote_ids: voting_round.prevote_ids().collect(),
-					precommit_current_weight: voting_round.precommit_weight(),
-					precommit_ids: voting_round.precommit_ids().collect(),
+					total_weight: round.voters().total_weight(),
+					threshold_weight: round.voters().threshold(),
+					prevote_current_weight: round.prevote_participation().0,
+					prevote_ids: round.prevotes().into_iter().map(|pv| pv.0).collect(),
+					precommit_current_weight: round.precommit_participation().0,
+					precommit_ids: round.precommits().into_iter().map(|pc| pc.0).collect(),
 				},
 			)
 		};
 
-		let inner = self.0.lock();
-		let best_round = to_round_state(&inner.best_round);
-		let background_rounds = inner.past_rounds.voting_rounds().map(to_round_state).collect();
+		let core = self.0.lock();
+		let best_round = to_round_state(core.best_round());
+		let background_rounds = core.background_rounds().map(to_round_state).collect();
 
 		report::VoterState { best_round, background_rounds }
 	}
 }
 
-/// Validate the given catch up and return a completed round with all prevotes
-/// and precommits from the catch up imported. If the catch up is invalid `None`
-/// is returned instead.
-fn validate_catch_up<H, N, S, I, E>(
-	catch_up: CatchUp<H, N, S, I>,
-	env: &E,
-	voters: &VoterSet<I>,
-	best_round_number: u64,
-) -> Option<crate::round::Round<I, H, N, S>>
-where
-	H: Clone + Eq + Ord + std::fmt::Debug,
-	N: BlockNumberOps + std::fmt::Debug,
-	S: Clone + Eq,
-	I: Clone + Eq + std::fmt::Debug + Ord,
-	E: Environment<H, N>,
-{
-	if catch_up.round_number <= best_round_number {
-		trace!(target: "afg", "Ignoring because best round number is {}",
-			   best_round_number);
+#[cfg(all(test, feature = "std"))]
+mod tests {
+	use super::{core::validate_catch_up, *};
+	use crate::{
+		testing::{
+			self,
+			chain::GENESIS_HASH,
+			environment::{Environment, Id, Signature},
+		},
+		weights::{VoteWeight, VoterWeight},
+		SignedPrecommit, SignedPrevote,
+	};
+	use futures::{executor::LocalPool, task::SpawnExt};
+	use futures_timer::Delay;
+	use std::{collections::HashSet, iter, time::Duration};
 
-		return None
-	}
+	#[test]
+	fn talking_to_myself() {
+		let local_id = Id(5);
+		let voters = VoterSet::new(std::iter::once((local_id, 100))).unwrap();
 
-	// check threshold support in prevotes and precommits.
-	{
-		let mut map = std::collections::BTreeMap::new();
+		let (network, routing_task) = testing::environment::make_network();
 
-		for prevote in &catch_up.prevotes {
-			if !voters.contains(&prevote.id) {
-				trace!(target: "afg",
-					   "Ignoring invalid catch up, invalid voter: {:?}",
-					   prevote.id,
-				);
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network, local_id));
 
-				return None
-			}
+		// initialize chain
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+			chain.last_finalized()
+		});
 
-			map.entry(prevote.id.clone()).or_insert((false, false)).0 = true;
-		}
+		// run voter in background. scheduling it to shut down at the end.
+		let finalized = env.finalized_stream();
+		let voter = Voter::new(
+			env.clone(),
+			global_comms,
+			VoterParams::new(voters, 0, last_finalized),
+		);
 
-		for precommit in &catch_up.precommits {
-			if !voters.contains(&precommit.id) {
-				trace!(target: "afg",
-					   "Ignoring invalid catch up, invalid voter: {:?}",
-					   precommit.id,
-				);
+		let mut pool = LocalPool::new();
+		pool.spawner().spawn(voter.map(|v| v.expect("Error voting"))).unwrap();
+		pool.spawner().spawn(routing_task).unwrap();
 
-				return None
-			}
+		// wait for the best block to finalize.
+		pool.run_until(
+			finalized
+				.take_while(|&(_, n, _)| future::ready(n < 6))
+				.for_each(|_| future::ready(())),
+		)
+	}
 
-			map.entry(precommit.id.clone()).or_insert((false, false)).1 = true;
-		}
+	#[test]
+	fn records_metrics() {
+		let local_id = Id(5);
+		let voters = VoterSet::new(std::iter::once((local_id, 100))).unwrap();
 
-		let (pv, pc) = map.into_iter().fold(
-			(VoteWeight(0), VoteWeight(0)),
-			|(mut pv, mut pc), (id, (prevoted, precommitted))| {
-				if let Some(v) = voters.get(&id) {
-					if prevoted {
-						pv = pv + v.weight();
-					}
+		let (network, routing_task) = testing::environment::make_network();
 
-					if precommitted {
-						pc = pc + v.weight();
-					}
-				}
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network, local_id).recording_metrics());
 
-				(pv, pc)
-			},
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+			chain.last_finalized()
+		});
+
+		let finalized = env.finalized_stream();
+		let voter = Voter::new(
+			env.clone(),
+			global_comms,
+			VoterParams::new(voters, 0, last_finalized),
 		);
 
-		let threshold = voters.threshold();
-		if pv < threshold || pc < threshold {
-			trace!(target: "afg",
-				   "Ignoring invalid catch up, missing voter threshold"
-			);
+		let mut pool = LocalPool::new();
+		pool.spawner().spawn(voter.map(|v| v.expect("Error voting"))).unwrap();
+		pool.spawner().spawn(routing_task).unwrap();
 
-			return None
-		}
-	}
+		// finalize the best block, then extend the chain and finalize again.
+		let mut finalized = finalized.map(|(_, number, _)| number);
+		pool.run_until(
+			finalized
+				.by_ref()
+				.take_while(|&n| future::ready(n < 6))
+				.for_each(|_| future::ready(())),
+		);
 
-	let mut round = crate::round::Round::new(crate::round::RoundParams {
-		round_number: catch_up.round_number,
-		voters: voters.clone(),
-		base: (catch_up.base_hash.clone(), catch_up.base_number),
-	});
-
-	// import prevotes first.
-	for crate::SignedPrevote { prevote, id, signature } in catch_up.prevotes {
-		match round.import_prevote(env, prevote, id, signature) {
-			Ok(_) => {},
-			Err(e) => {
-				trace!(target: "afg",
-					   "Ignoring invalid catch up, error importing prevote: {:?}",
-					   e,
-				);
+		env.with_chain(|chain| chain.push_blocks("E", &["F", "G"]));
+		pool.run_until(
+			finalized.take_while(|&n| future::ready(n < 8)).for_each(|_| future::ready(())),
+		);
 
-				return None
-			},
-		}
+		let metrics = env.recorded_metrics();
+		assert!(metrics.prevote_ghost_times > 0);
+		assert!(metrics.completable_times > 0);
+		assert!(metrics.finalization_times > 0);
+		assert_eq!(metrics.completed_round_weights.first(), Some(&(100, 100)));
+		assert!(!metrics.rounds_per_finalized_block.is_empty());
 	}
 
-	// then precommits.
-	for crate::SignedPrecommit { precommit, id, signature } in catch_up.precommits {
-		match round.import_precommit(env, precommit, id, signature) {
-			Ok(_) => {},
-			Err(e) => {
-				trace!(target: "afg",
-					   "Ignoring invalid catch up, error importing precommit: {:?}",
-					   e,
-				);
+	#[test]
+	fn persists_own_votes() {
+		let local_id = Id(5);
+		let voters = VoterSet::new(std::iter::once((local_id, 100))).unwrap();
 
-				return None
-			},
-		}
-	}
+		let (network, routing_task) = testing::environment::make_network();
 
-	let state = round.state();
-	if !state.completable {
-		return None
-	}
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network, local_id));
 
-	Some(round)
-}
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+			chain.last_finalized()
+		});
 
-#[cfg(test)]
-mod tests {
-	use super::*;
-	use crate::{
-		testing::{
-			self,
-			chain::GENESIS_HASH,
-			environment::{Environment, Id, Signature},
-		},
-		weights::{VoteWeight, VoterWeight},
-		SignedPrecommit,
-	};
-	use futures::{executor::LocalPool, task::SpawnExt};
-	use futures_timer::Delay;
-	use std::{collections::HashSet, iter, time::Duration};
+		let finalized = env.finalized_stream();
+		let voter = Voter::new(
+			env.clone(),
+			global_comms,
+			VoterParams::new(voters, 0, last_finalized),
+		);
+
+		// nothing was cast yet.
+		let initial_state = voter.persistent_state();
+		assert_eq!(initial_state.round_number, 1);
+		assert_eq!(initial_state.round_base, last_finalized);
+		assert_eq!(initial_state.last_finalized, last_finalized);
+		assert_eq!(initial_state.prevote, None);
+
+		let mut pool = LocalPool::new();
+		pool.spawner().spawn(voter.map(|v| v.expect("Error voting"))).unwrap();
+		pool.spawner().spawn(routing_task).unwrap();
+
+		pool.run_until(
+			finalized
+				.take_while(|&(_, n, _)| future::ready(n < 6))
+				.for_each(|_| future::ready(())),
+		);
+
+		// the votes of the first round were persisted before being cast.
+		let persisted_states = env.persisted_states();
+		assert_eq!(persisted_states[0], initial_state);
+		assert!(persisted_states.iter().any(|state| {
+			state.round_number == 1 &&
+				state.prevote == Some(Prevote::new("E", 6)) &&
+				state.precommit == Some(Precommit::new("E", 6))
+		}));
+	}
 
 	#[test]
-	fn talking_to_myself() {
+	fn replays_own_votes_from_persistent_state() {
 		let local_id = Id(5);
 		let voters = VoterSet::new(std::iter::once((local_id, 100))).unwrap();
 
@@ -1093,34 +1719,103 @@ mod tests {
 		let global_comms = network.make_global_comms();
 		let env = Arc::new(Environment::new(network, local_id));
 
-		// initialize chain
 		let last_finalized = env.with_chain(|chain| {
 			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
 			chain.last_finalized()
 		});
 
-		// run voter in background. scheduling it to shut down at the end.
-		let finalized = env.finalized_stream();
-		let voter = Voter::new(
-			env.clone(),
-			voters,
-			global_comms,
-			0,
-			Vec::new(),
+		// we had prevoted for "B" in the first round before restarting, even
+		// though "E" is the best block now.
+		let state = VoterPersistentState {
+			set_id: 0,
+			round_number: 1,
+			round_base: last_finalized,
 			last_finalized,
-			last_finalized,
-		);
+			primary_propose: None,
+			prevote: Some(Prevote::new("B", 3)),
+			precommit: None,
+			completed_round_number: 0,
+			completed_round_base: last_finalized,
+			completed_round_votes: Vec::new(),
+		};
+
+		let mut finalized = env.finalized_stream();
+		let voter = Voter::from_persistent_state(env.clone(), voters, global_comms, state.clone());
+		assert_eq!(voter.persistent_state(), state);
 
 		let mut pool = LocalPool::new();
 		pool.spawner().spawn(voter.map(|v| v.expect("Error voting"))).unwrap();
 		pool.spawner().spawn(routing_task).unwrap();
 
-		// wait for the best block to finalize.
+		// our prevote is replayed, so we finalize "B" in the first round.
+		let (hash, number, _) = pool.run_until(finalized.next()).unwrap();
+		assert_eq!((hash, number), ("B", 3));
+		assert!(env
+			.persisted_states()
+			.iter()
+			.all(|state| state.round_number != 1 || state.prevote == Some(Prevote::new("B", 3))));
+	}
+
+	#[test]
+	fn drops_equivocating_votes_when_reentering_round() {
+		let local_id = Id(5);
+		let voters = VoterSet::new(std::iter::once((local_id, 100))).unwrap();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let env = Arc::new(Environment::new(network.clone(), local_id));
+
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+			chain.last_finalized()
+		});
+
+		let voter = |env, last_finalized| {
+			Voter::new(
+				env,
+				network.make_global_comms(),
+				VoterParams::new(voters.clone(), 0, last_finalized),
+			)
+		};
+
+		let mut pool = LocalPool::new();
+		pool.spawner().spawn(routing_task).unwrap();
+
+		// vote in the first round until "E" is finalized.
+		let finalized = env.finalized_stream();
+		let first_voter =
+			pool.spawner().spawn_with_handle(voter(env.clone(), last_finalized)).unwrap();
 		pool.run_until(
 			finalized
 				.take_while(|&(_, n, _)| future::ready(n < 6))
 				.for_each(|_| future::ready(())),
-		)
+		);
+		drop(first_voter);
+
+		// the voter is restarted without its persistent state and a new best
+		// block is imported in the meantime, so it would prevote differently
+		// in the rounds it has voted in already.
+		env.with_chain(|chain| chain.push_blocks("E", &["F"]));
+
+		// the conflicting votes are dropped and the voter keeps going.
+		let finalized = env.finalized_stream();
+		pool.spawner()
+			.spawn(voter(env.clone(), ("E", 6)).map(|v| v.expect("Error voting")))
+			.unwrap();
+		pool.run_until(
+			finalized
+				.take_while(|&(_, n, _)| future::ready(n < 7))
+				.for_each(|_| future::ready(())),
+		);
+
+		// the only prevote sent in the first round is the original one.
+		let (mut round_messages, _) = network.make_round_comms(0, 1, local_id);
+		let mut prevotes = Vec::new();
+		while let Some(Some(Ok(signed))) = round_messages.next().now_or_never() {
+			if let Message::Prevote(prevote) = signed.message {
+				prevotes.push(prevote);
+			}
+		}
+		assert_eq!(prevotes, vec![Prevote::new("E", 6)]);
 	}
 
 	#[test]
@@ -1146,12 +1841,8 @@ mod tests {
 				let finalized = env.finalized_stream();
 				let voter = Voter::new(
 					env.clone(),
-					voters.clone(),
 					network.make_global_comms(),
-					0,
-					Vec::new(),
-					last_finalized,
-					last_finalized,
+					VoterParams::new(voters.clone(), 0, last_finalized),
 				);
 
 				pool.spawner().spawn(voter.map(|v| v.expect("Error voting"))).unwrap();
@@ -1192,12 +1883,8 @@ mod tests {
 				let finalized = env.finalized_stream();
 				let voter = Voter::new(
 					env.clone(),
-					voters.clone(),
 					network.make_global_comms(),
-					0,
-					Vec::new(),
-					last_finalized,
-					last_finalized,
+					VoterParams::new(voters.clone(), 0, last_finalized),
 				);
 				let voter_state = voter.voter_state();
 
@@ -1259,12 +1946,8 @@ mod tests {
 		// run voter in background. scheduling it to shut down at the end.
 		let voter = Voter::new(
 			env.clone(),
-			voters.clone(),
 			global_comms,
-			0,
-			Vec::new(),
-			last_finalized,
-			last_finalized,
+			VoterParams::new(voters.clone(), 0, last_finalized),
 		);
 
 		let mut pool = LocalPool::new();
@@ -1284,7 +1967,7 @@ mod tests {
 
 		let (network, routing_task) = testing::environment::make_network();
 		let (commits_stream, commits_sink) = network.make_global_comms();
-		let (round_stream, round_sink) = network.make_round_comms(1, test_id);
+		let (round_stream, round_sink) = network.make_round_comms(0, 1, test_id);
 
 		let prevote = Message::Prevote(Prevote { target_hash: "E", target_number: 6 });
 
@@ -1315,12 +1998,8 @@ mod tests {
 		// run voter in background. scheduling it to shut down at the end.
 		let voter = Voter::new(
 			env.clone(),
-			voters.clone(),
 			global_comms,
-			0,
-			Vec::new(),
-			last_finalized,
-			last_finalized,
+			VoterParams::new(voters.clone(), 0, last_finalized),
 		);
 
 		let mut pool = LocalPool::new();
@@ -1359,8 +2038,10 @@ mod tests {
 					})
 					.then(|_| {
 						// send our commit
-						stream::iter(iter::once(Ok(CommunicationOut::Commit(commit.0, commit.1))))
-							.forward(commits_sink)
+						stream::iter(iter::once(Ok(CommunicationOut::Commit(
+							0, commit.0, commit.1,
+						))))
+						.forward(commits_sink)
 					})
 					.map(|_| ()),
 			)
@@ -1417,12 +2098,12 @@ mod tests {
 		// run voter in background.
 		let voter = Voter::new(
 			env.clone(),
-			voters.clone(),
 			global_comms,
-			1,
-			Vec::new(),
-			last_finalized,
-			last_finalized,
+			VoterParams {
+				last_round_number: 1,
+				last_round_votes: Vec::new(),
+				..VoterParams::new(voters.clone(), 0, last_finalized)
+			},
 		);
 
 		let mut pool = LocalPool::new();
@@ -1432,7 +2113,7 @@ mod tests {
 		// Send the commit message.
 		pool.spawner()
 			.spawn(
-				stream::iter(iter::once(Ok(CommunicationOut::Commit(0, commit.clone()))))
+				stream::iter(iter::once(Ok(CommunicationOut::Commit(0, 0, commit.clone()))))
 					.forward(commits_sink)
 					.map(|_| ()),
 			)
@@ -1470,12 +2151,8 @@ mod tests {
 
 			let voter = Voter::new(
 				env.clone(),
-				voters.clone(),
 				network.make_global_comms(),
-				0,
-				Vec::new(),
-				last_finalized,
-				last_finalized,
+				VoterParams::new(voters.clone(), 0, last_finalized),
 			);
 
 			(env, voter)
@@ -1496,6 +2173,7 @@ mod tests {
 		// send in a catch-up message for round 5.
 		network.send_message(CommunicationIn::CatchUp(
 			CatchUp {
+				set_id: 0,
 				base_number: 1,
 				base_hash: GENESIS_HASH,
 				round_number: 5,
@@ -1573,12 +2251,12 @@ mod tests {
 		// run voter in background. scheduling it to shut down at the end.
 		let voter = Voter::new(
 			env.clone(),
-			voters,
 			global_comms,
-			10,
-			Vec::new(),
-			last_finalized,
-			last_finalized,
+			VoterParams {
+				last_round_number: 10,
+				last_round_votes: Vec::new(),
+				..VoterParams::new(voters, 0, last_finalized)
+			},
 		);
 
 		let mut pool = LocalPool::new();
@@ -1639,7 +2317,7 @@ mod tests {
 			//
 			// this means we wouldn't be able to start round 3 until
 			// the estimate of round-1 moves backwards.
-			let (_, round_sink) = network.make_round_comms(2, Id(id));
+			let (_, round_sink) = network.make_round_comms(0, 2, Id(id));
 			let msgs = stream::iter(iter::once(Ok(prevote)).chain(iter::once(Ok(precommit))));
 			pool.spawner().spawn(msgs.forward(round_sink).map(|r| r.unwrap())).unwrap();
 		}
@@ -1647,7 +2325,7 @@ mod tests {
 		// round 1 fresh communication. we send one more precommit for "D" so the estimate
 		// moves backwards.
 		let sender = Id(67);
-		let (_, round_sink) = network.make_round_comms(1, sender);
+		let (_, round_sink) = network.make_round_comms(0, 1, sender);
 		let last_precommit = Message::Precommit(Precommit { target_hash: "D", target_number: 3 });
 		pool.spawner()
 			.spawn(
@@ -1660,12 +2338,12 @@ mod tests {
 		// run voter in background. scheduling it to shut down at the end.
 		let voter = Voter::new(
 			env.clone(),
-			voters,
 			global_comms,
-			1,
-			last_round_votes,
-			last_finalized,
-			last_finalized,
+			VoterParams {
+				last_round_number: 1,
+				last_round_votes,
+				..VoterParams::new(voters, 0, last_finalized)
+			},
 		);
 
 		pool.spawner()
@@ -1676,7 +2354,7 @@ mod tests {
 		// wait until we see a prevote on round 3 from our local ID,
 		// indicating that the round 3 has started.
 
-		let (round_stream, _) = network.make_round_comms(3, Id(1000));
+		let (round_stream, _) = network.make_round_comms(0, 3, Id(1000));
 		pool.run_until(
 			round_stream
 				.skip_while(move |v| {
@@ -1693,4 +2371,665 @@ mod tests {
 
 		assert_eq!(outer_env.last_completed_and_concluded(), (2, 1));
 	}
+
+	#[test]
+	fn enacts_standard_voter_set_change() {
+		let voters = VoterSet::new((0..3).map(|i| (Id(i), 1))).expect("nonempty");
+		let next_voters = VoterSet::new((3..6).map(|i| (Id(i), 1))).expect("nonempty");
+
+		let (network, routing_task) = testing::environment::make_network();
+		let mut pool = LocalPool::new();
+
+		let envs = (0..6)
+			.map(|i| {
+				let env = Arc::new(Environment::new(network.clone(), Id(i)));
+				let last_finalized = env.with_chain(|chain| {
+					chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+					chain.last_finalized()
+				});
+
+				let voter = Voter::new(
+					env.clone(),
+					network.make_global_comms(),
+					VoterParams::new(voters.clone(), 0, last_finalized),
+				);
+
+				// the change is enacted once block "C" is finalized.
+				voter
+					.set_change_handle()
+					.schedule(VoterSetChange {
+						canon_hash: "B",
+						next_voters: next_voters.clone(),
+						effective_number: 4,
+						kind: VoterSetChangeKind::Standard,
+					})
+					.unwrap();
+
+				pool.spawner().spawn(voter.map(|v| v.expect("Error voting"))).unwrap();
+
+				env
+			})
+			.collect::<Vec<_>>();
+
+		let mut finalized_streams =
+			envs.iter().map(|env| env.finalized_stream()).collect::<Vec<_>>();
+
+		pool.spawner().spawn(routing_task.map(|_| ())).unwrap();
+
+		pool.run_until(async move {
+			// the first voter set finalizes the best block, enacting the change.
+			for finalized in finalized_streams.iter_mut() {
+				while finalized.next().await.unwrap().1 < 6 {}
+			}
+
+			for env in &envs {
+				env.with_chain(|chain| chain.push_blocks("E", &["F", "G"]));
+			}
+
+			// the new blocks can only be finalized by the next voter set.
+			for finalized in finalized_streams.iter_mut() {
+				loop {
+					let (_, number, commit) = finalized.next().await.unwrap();
+					if number == 8 {
+						assert!(commit.precommits.iter().all(|p| next_voters.contains(&p.id)));
+						break
+					}
+				}
+			}
+		});
+	}
+
+	#[test]
+	fn enacts_forced_voter_set_change() {
+		// none of the voters of the current set are online.
+		let voters = VoterSet::new((0..3).map(|i| (Id(i), 1))).expect("nonempty");
+		let next_voters = VoterSet::new((3..6).map(|i| (Id(i), 1))).expect("nonempty");
+
+		let (network, routing_task) = testing::environment::make_network();
+		let mut pool = LocalPool::new();
+
+		let finalized_streams = (3..6)
+			.map(|i| {
+				let env = Arc::new(Environment::new(network.clone(), Id(i)));
+				let last_finalized = env.with_chain(|chain| {
+					chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+					chain.last_finalized()
+				});
+
+				let finalized = env.finalized_stream();
+				let voter = Voter::new(
+					env.clone(),
+					network.make_global_comms(),
+					VoterParams::new(voters.clone(), 0, last_finalized),
+				);
+
+				// the change is enacted on top of block "C" without it being finalized.
+				voter
+					.set_change_handle()
+					.schedule(VoterSetChange {
+						canon_hash: "B",
+						next_voters: next_voters.clone(),
+						effective_number: 4,
+						kind: VoterSetChangeKind::Forced,
+					})
+					.unwrap();
+
+				pool.spawner().spawn(voter.map(|v| v.expect("Error voting"))).unwrap();
+
+				let next_voters = next_voters.clone();
+				finalized.into_future().map(move |(finalized, _)| {
+					let (hash, number, commit) = finalized.unwrap();
+					assert_eq!((hash, number), ("E", 6));
+					assert!(commit.precommits.iter().all(|p| next_voters.contains(&p.id)));
+				})
+			})
+			.collect::<Vec<_>>();
+
+		pool.spawner().spawn(routing_task.map(|_| ())).unwrap();
+
+		pool.run_until(future::join_all(finalized_streams));
+	}
+
+	#[test]
+	fn rejects_messages_from_other_voter_sets() {
+		let voters = VoterSet::new((0..3).map(|i| (Id(i), 1))).expect("nonempty");
+
+		let (network, routing_task) = testing::environment::make_network();
+		let mut pool = LocalPool::new();
+
+		// a node which isn't part of the voter set.
+		let env = Arc::new(Environment::new(network.clone(), Id(100)).recording_metrics());
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+			chain.last_finalized()
+		});
+
+		// feed global messages directly to the voter, since the test network
+		// doesn't preserve callbacks.
+		let (global_in, global_in_rx) = mpsc::unbounded();
+		let global_out = sink::drain().sink_map_err(|e| match e {});
+
+		let voter = Voter::new(
+			env.clone(),
+			(global_in_rx.map(Ok), global_out),
+			VoterParams::new(voters, 0, last_finalized),
+		);
+
+		pool.spawner().spawn(voter.map(|v| v.expect("Error voting"))).unwrap();
+		pool.spawner().spawn(routing_task.map(|_| ())).unwrap();
+
+		let (commit_outcomes_tx, mut commit_outcomes) = mpsc::unbounded();
+		let commit_callback = || {
+			let tx = commit_outcomes_tx.clone();
+			Callback::Work(Box::new(move |outcome| tx.unbounded_send(outcome).unwrap()))
+		};
+
+		let commit = CompactCommit::from(Commit {
+			target_hash: "E",
+			target_number: 6,
+			precommits: (0..3)
+				.map(|i| SignedPrecommit {
+					precommit: Precommit { target_hash: "E", target_number: 6 },
+					signature: Signature(i),
+					id: Id(i),
+				})
+				.collect(),
+		});
+
+		// a valid commit, but for the next voter set.
+		global_in
+			.unbounded_send(CommunicationIn::Commit(1, 1, commit.clone(), commit_callback()))
+			.unwrap();
+		assert_eq!(
+			pool.run_until(commit_outcomes.next()),
+			Some(CommitProcessingOutcome::Bad(CommitValidationResult::<(), ()>::default().into())),
+		);
+
+		// an empty catch-up for the next voter set isn't even validated.
+		let (catch_up_outcome_tx, catch_up_outcome) = futures::channel::oneshot::channel();
+		let mut catch_up_outcome_tx = Some(catch_up_outcome_tx);
+		global_in
+			.unbounded_send(CommunicationIn::CatchUp(
+				CatchUp {
+					set_id: 1,
+					round_number: 5,
+					prevotes: Vec::new(),
+					precommits: Vec::new(),
+					base_hash: GENESIS_HASH,
+					base_number: 1,
+				},
+				Callback::Work(Box::new(move |outcome| {
+					let _ = catch_up_outcome_tx.take().unwrap().send(outcome);
+				})),
+			))
+			.unwrap();
+		assert_eq!(pool.run_until(catch_up_outcome), Ok(CatchUpProcessingOutcome::Useless));
+
+		// the same commit for the current voter set finalizes the block.
+		global_in
+			.unbounded_send(CommunicationIn::Commit(0, 1, commit, commit_callback()))
+			.unwrap();
+		assert!(matches!(
+			pool.run_until(commit_outcomes.next()),
+			Some(CommitProcessingOutcome::Good(_)),
+		));
+		assert_eq!(env.with_chain(|chain| chain.last_finalized()), ("E", 6));
+
+		let metrics = env.recorded_metrics();
+		assert_eq!(metrics.commits_imported, 1);
+		assert!(metrics.catch_ups_rejected.is_empty());
+	}
+
+	#[test]
+	fn checks_signatures_with_environment_verifier() {
+		let voters = VoterSet::new((0..3).map(|i| (Id(i), 1))).expect("nonempty");
+
+		let (network, routing_task) = testing::environment::make_network();
+		let mut pool = LocalPool::new();
+
+		let env = Arc::new(Environment::new(network.clone(), Id(100)).verifying_signatures());
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+			chain.last_finalized()
+		});
+
+		let (global_in, global_in_rx) = mpsc::unbounded();
+		let global_out = sink::drain().sink_map_err(|e| match e {});
+
+		let voter = Voter::new(
+			env.clone(),
+			(global_in_rx.map(Ok), global_out),
+			VoterParams::new(voters.clone(), 0, last_finalized),
+		);
+
+		pool.spawner().spawn(voter.map(|v| v.expect("Error voting"))).unwrap();
+		pool.spawner().spawn(routing_task.map(|_| ())).unwrap();
+
+		let (commit_outcomes_tx, mut commit_outcomes) = mpsc::unbounded();
+		let commit_callback = || {
+			let tx = commit_outcomes_tx.clone();
+			Callback::Work(Box::new(move |outcome| tx.unbounded_send(outcome).unwrap()))
+		};
+
+		// the signature of the last voter is forged.
+		let signed_precommit = |i, signature| SignedPrecommit {
+			precommit: Precommit { target_hash: "E", target_number: 6 },
+			signature: Signature(signature),
+			id: Id(i),
+		};
+		let commit = |signatures: [u32; 3]| {
+			CompactCommit::from(Commit {
+				target_hash: "E",
+				target_number: 6,
+				precommits: (0..3).map(|i| signed_precommit(i, signatures[i as usize])).collect(),
+			})
+		};
+
+		global_in
+			.unbounded_send(CommunicationIn::Commit(0, 1, commit([0, 1, 99]), commit_callback()))
+			.unwrap();
+		assert!(matches!(
+			pool.run_until(commit_outcomes.next()),
+			Some(CommitProcessingOutcome::Bad(_)),
+		));
+		assert_eq!(env.with_chain(|chain| chain.last_finalized()), last_finalized);
+
+		global_in
+			.unbounded_send(CommunicationIn::Commit(0, 1, commit([0, 1, 2]), commit_callback()))
+			.unwrap();
+		assert!(matches!(
+			pool.run_until(commit_outcomes.next()),
+			Some(CommitProcessingOutcome::Good(_)),
+		));
+		assert_eq!(env.with_chain(|chain| chain.last_finalized()), ("E", 6));
+
+		// catch-ups are checked the same way.
+		let catch_up = |signature| CatchUp {
+			set_id: 0,
+			round_number: 5,
+			prevotes: (0..3)
+				.map(|i| SignedPrevote {
+					prevote: Prevote { target_hash: "E", target_number: 6 },
+					signature: Signature(i),
+					id: Id(i),
+				})
+				.collect(),
+			precommits: vec![
+				signed_precommit(0, 0),
+				signed_precommit(1, 1),
+				signed_precommit(2, signature),
+			],
+			base_hash: GENESIS_HASH,
+			base_number: 1,
+		};
+
+		assert_eq!(
+			validate_catch_up(catch_up(99), &CoreEnv(&*env), &voters, 0).err(),
+			Some(CatchUpRejection::InvalidSignature),
+		);
+		assert!(validate_catch_up(catch_up(2), &CoreEnv(&*env), &voters, 0).is_ok());
+	}
+
+	#[test]
+	fn builds_minimal_catch_up() {
+		let weights = [1, 1, 5, 1, 1];
+		let voters = VoterSet::new(weights.iter().enumerate().map(|(i, w)| (Id(i as u32), *w)))
+			.expect("nonempty");
+
+		let (network, _) = testing::environment::make_network();
+		let env = Arc::new(Environment::new(network.clone(), Id(100)));
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+			chain.last_finalized()
+		});
+
+		// every voter voted for the best block in the last round.
+		let last_round_votes = (0..5)
+			.flat_map(|i| {
+				vec![
+					SignedMessage {
+						message: Message::Prevote(Prevote::new("E", 6)),
+						signature: Signature(i),
+						id: Id(i),
+					},
+					SignedMessage {
+						message: Message::Precommit(Precommit::new("E", 6)),
+						signature: Signature(i),
+						id: Id(i),
+					},
+				]
+			})
+			.collect();
+
+		let voter = Voter::new(
+			env.clone(),
+			network.make_global_comms(),
+			VoterParams {
+				last_round_number: 1,
+				last_round_votes,
+				..VoterParams::new(voters.clone(), 0, last_finalized)
+			},
+		);
+
+		// nothing to catch up on, the best round isn't completable yet.
+		assert_eq!(voter.catch_up(1), None);
+
+		let catch_up = voter.catch_up(0).unwrap();
+		assert_eq!(catch_up.round_number, 1);
+		assert_eq!((catch_up.base_hash, catch_up.base_number), last_finalized);
+
+		// the heaviest voters are enough to reach the threshold of 7.
+		let expected = vec![Id(2), Id(0), Id(1)];
+		assert_eq!(catch_up.prevotes.iter().map(|p| p.id).collect::<Vec<_>>(), expected);
+		assert_eq!(catch_up.precommits.iter().map(|p| p.id).collect::<Vec<_>>(), expected);
+
+		assert!(validate_catch_up(catch_up, &CoreEnv(&*env), &voters, 0).is_ok());
+	}
+
+	#[test]
+	fn neighbor_packet_usefulness() {
+		let packet = NeighborPacket { set_id: 1, round: 5, last_finalized: 10u32 };
+
+		assert!(!packet.is_round_message_useful(0, 5));
+		assert!(!packet.is_round_message_useful(1, 3));
+		assert!(packet.is_round_message_useful(1, 4));
+		assert!(packet.is_round_message_useful(1, 5));
+		assert!(packet.is_round_message_useful(1, 6));
+		assert!(!packet.is_round_message_useful(1, 7));
+
+		let commit = |target_number| Commit::<&str, u32, Signature, Id> {
+			target_hash: "X",
+			target_number,
+			precommits: Vec::new(),
+		};
+
+		assert!(!packet.is_commit_useful(1, &commit(10)));
+		assert!(!packet.is_commit_useful(2, &commit(11)));
+		assert!(packet.is_commit_useful(1, &commit(11)));
+
+		let catch_up = |set_id, round_number| CatchUp::<&str, u32, Signature, Id> {
+			set_id,
+			round_number,
+			prevotes: Vec::new(),
+			precommits: Vec::new(),
+			base_hash: "X",
+			base_number: 10,
+		};
+
+		assert!(!packet.is_catch_up_useful(&catch_up(1, 5)));
+		assert!(!packet.is_catch_up_useful(&catch_up(0, 6)));
+		assert!(packet.is_catch_up_useful(&catch_up(1, 6)));
+	}
+
+	#[test]
+	fn announces_neighbor_packets() {
+		let local_id = Id(5);
+		let voters = VoterSet::new(std::iter::once((local_id, 100))).expect("nonempty");
+
+		let (network, routing_task) = testing::environment::make_network();
+		let env = Arc::new(Environment::new(network, local_id));
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+			chain.last_finalized()
+		});
+
+		let (_global_in, global_in_rx) = mpsc::unbounded();
+		let (global_out, global_out_rx) = mpsc::unbounded();
+
+		let voter = Voter::new(
+			env,
+			(global_in_rx.map(Ok), global_out.sink_map_err(|_| panic!("receiver alive"))),
+			VoterParams::new(voters, 0, last_finalized),
+		);
+
+		let mut pool = LocalPool::new();
+		pool.spawner().spawn(voter.map(|v| v.expect("Error voting"))).unwrap();
+		pool.spawner().spawn(routing_task.map(|_| ())).unwrap();
+
+		let mut neighbor_packets = global_out_rx.filter_map(|message| {
+			future::ready(match message {
+				CommunicationOut::Neighbor(packet) => Some(packet),
+				_ => None,
+			})
+		});
+
+		// the initial view is announced straight away.
+		assert_eq!(
+			pool.run_until(neighbor_packets.next()),
+			Some(NeighborPacket { set_id: 0, round: 1, last_finalized: 1 }),
+		);
+
+		// and then again when finalizing the best block and moving to the next round.
+		let mut packets = Vec::new();
+		while packets.last() != Some(&NeighborPacket { set_id: 0, round: 2, last_finalized: 6 }) {
+			packets.push(pool.run_until(neighbor_packets.next()).unwrap());
+		}
+	}
+
+	#[test]
+	fn answers_lagging_neighbor_with_catch_up() {
+		let voters = VoterSet::new((0..3).map(|i| (Id(i), 1))).expect("nonempty");
+
+		let (network, _) = testing::environment::make_network();
+		let env = Arc::new(Environment::new(network, Id(100)));
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+			chain.last_finalized()
+		});
+
+		let last_round_votes = (0..3)
+			.flat_map(|i| {
+				vec![
+					SignedMessage {
+						message: Message::Prevote(Prevote::new("E", 6)),
+						signature: Signature(i),
+						id: Id(i),
+					},
+					SignedMessage {
+						message: Message::Precommit(Precommit::new("E", 6)),
+						signature: Signature(i),
+						id: Id(i),
+					},
+				]
+			})
+			.collect();
+
+		let (global_in, global_in_rx) = mpsc::unbounded();
+		let global_out = sink::drain().sink_map_err(|e| match e {});
+
+		// we are voting in round 2, having completed round 1.
+		let voter = Voter::new(
+			env,
+			(global_in_rx.map(Ok), global_out),
+			VoterParams {
+				last_round_number: 1,
+				last_round_votes,
+				..VoterParams::new(voters, 0, last_finalized)
+			},
+		);
+
+		let mut pool = LocalPool::new();
+		pool.spawner().spawn(voter.map(|v| v.expect("Error voting"))).unwrap();
+
+		let (catch_ups_tx, mut catch_ups) = mpsc::unbounded();
+		let neighbor = |round| {
+			let catch_ups_tx = catch_ups_tx.clone();
+			CommunicationIn::Neighbor(
+				NeighborPacket { set_id: 0, round, last_finalized: 1 },
+				Callback::Work(Box::new(move |catch_up| {
+					catch_ups_tx.unbounded_send((round, catch_up)).unwrap()
+				})),
+			)
+		};
+
+		// a peer one round behind isn't sent a catch-up, whereas one two rounds
+		// behind is.
+		global_in.unbounded_send(neighbor(1)).unwrap();
+		global_in.unbounded_send(neighbor(0)).unwrap();
+
+		let (round, catch_up) = pool.run_until(catch_ups.next()).unwrap();
+		assert_eq!(round, 0);
+		assert_eq!(catch_up.round_number, 1);
+
+		// all callbacks have been processed.
+		drop(catch_ups_tx);
+		assert!(pool.run_until(catch_ups.next()).is_none());
+	}
+
+	#[test]
+	fn emits_voter_events() {
+		let local_id = Id(5);
+		let voters = VoterSet::new(std::iter::once((local_id, 100))).unwrap();
+
+		let (network, routing_task) = testing::environment::make_network();
+
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network, local_id));
+
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+			chain.last_finalized()
+		});
+
+		let finalized = env.finalized_stream();
+		let mut voter = Voter::new(
+			env.clone(),
+			global_comms,
+			VoterParams::new(voters, 0, last_finalized),
+		);
+		let mut events = voter.events();
+
+		let mut pool = LocalPool::new();
+		pool.spawner().spawn(voter.map(|v| v.expect("Error voting"))).unwrap();
+		pool.spawner().spawn(routing_task).unwrap();
+
+		pool.run_until(
+			finalized
+				.take_while(|&(_, n, _)| future::ready(n < 6))
+				.for_each(|_| future::ready(())),
+		);
+
+		let events = iter::from_fn(|| events.try_recv().ok()).collect::<Vec<_>>();
+		assert_eq!(
+			events[..3],
+			[
+				VoterEvent::RoundStarted { set_id: 0, round: 1 },
+				VoterEvent::Prevoted { set_id: 0, round: 1, prevote: Prevote::new("E", 6) },
+				VoterEvent::Precommitted { set_id: 0, round: 1, precommit: Precommit::new("E", 6) },
+			],
+		);
+
+		// the round is completed once the block is finalized, and the next one started.
+		let finalized = events.iter().position(|event| {
+			matches!(event, VoterEvent::Finalized { hash: "E", number: 6, set_id: 0, round: 1, .. })
+		});
+		let completed = events
+			.iter()
+			.position(|event| matches!(event, VoterEvent::Completed { set_id: 0, round: 1, .. }));
+		let started = events
+			.iter()
+			.position(|event| matches!(event, VoterEvent::RoundStarted { set_id: 0, round: 2 }));
+
+		assert!(finalized.is_some() && finalized < completed && completed < started);
+	}
+
+	#[test]
+	fn emits_commit_imported_event() {
+		let local_id = Id(5);
+		let test_id = Id(42);
+		let voters =
+			VoterSet::new([(local_id, 100), (test_id, 201)].iter().cloned()).expect("nonempty");
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (_, commits_sink) = network.make_global_comms();
+
+		let commit = Commit {
+			target_hash: "E",
+			target_number: 6,
+			precommits: vec![SignedPrecommit {
+				precommit: Precommit { target_hash: "E", target_number: 6 },
+				signature: Signature(test_id.0),
+				id: test_id,
+			}],
+		};
+
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network, local_id));
+
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+			chain.last_finalized()
+		});
+
+		let mut voter =
+			Voter::new(env.clone(), global_comms, VoterParams::new(voters, 0, last_finalized));
+		let events = voter.events();
+
+		let mut pool = LocalPool::new();
+		pool.spawner().spawn(voter.map(|v| v.expect("Error voting"))).unwrap();
+		pool.spawner().spawn(routing_task.map(|_| ())).unwrap();
+
+		pool.spawner()
+			.spawn(
+				stream::iter(iter::once(Ok(CommunicationOut::Commit(0, 0, commit))))
+					.forward(commits_sink)
+					.map(|_| ()),
+			)
+			.unwrap();
+
+		let imported =
+			VoterEvent::CommitImported { set_id: 0, round: 0, target_hash: "E", target_number: 6 };
+		assert!(pool.run_until(events.any(|event| future::ready(event == imported))));
+	}
+
+	#[test]
+	fn emits_catch_up_applied_event() {
+		let voters = VoterSet::new((0..3).map(|i| (Id(i), 1u64))).expect("nonempty");
+
+		let (network, routing_task) = testing::environment::make_network();
+		let mut pool = LocalPool::new();
+		pool.spawner().spawn(routing_task.map(|_| ())).unwrap();
+
+		let env = Arc::new(Environment::new(network.clone(), Id(4)));
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+			chain.last_finalized()
+		});
+
+		let mut voter = Voter::new(
+			env.clone(),
+			network.make_global_comms(),
+			VoterParams::new(voters, 0, last_finalized),
+		);
+		let events = voter.events();
+
+		let pv = |id| crate::SignedPrevote {
+			prevote: crate::Prevote { target_hash: "C", target_number: 4 },
+			id: Id(id),
+			signature: Signature(99),
+		};
+
+		let pc = |id| crate::SignedPrecommit {
+			precommit: crate::Precommit { target_hash: "C", target_number: 4 },
+			id: Id(id),
+			signature: Signature(99),
+		};
+
+		network.send_message(CommunicationIn::CatchUp(
+			CatchUp {
+				set_id: 0,
+				base_number: 1,
+				base_hash: GENESIS_HASH,
+				round_number: 5,
+				prevotes: vec![pv(0), pv(1), pv(2)],
+				precommits: vec![pc(0), pc(1), pc(2)],
+			},
+			Callback::Blank,
+		));
+
+		pool.spawner().spawn(voter.map(|_| ())).unwrap();
+
+		let caught_up = VoterEvent::CatchUpApplied { set_id: 0, round: 5 };
+		assert!(pool.run_until(events.any(|event| future::ready(event == caught_up))));
+	}
 }
@@ -0,0 +1,518 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A passive observer in GRANDPA. This follows commit messages in order to
+//! learn about finality, without ever casting votes.
+//!
+//! Observers rely on a much smaller external context than voters:
+//!   - an incoming stream of global messages (commits and catch-ups).
+//!   - providing the voter set.
+//!   - finalizing blocks.
+//!
+//! Unlike a voter, an observer doesn't run any rounds: it only validates the
+//! commits it receives and keeps track of the voter set.
+
+use futures::{
+	channel::mpsc::{self, UnboundedReceiver, UnboundedSender},
+	prelude::*,
+};
+use log::{debug, trace};
+
+use std::{
+	collections::VecDeque,
+	pin::Pin,
+	sync::Arc,
+	task::{Context, Poll},
+};
+
+use super::{
+	core::{forced_change_block, validate_commit_with_env, CoreEnvironment},
+	CatchUpProcessingOutcome, CommitProcessingOutcome, CommunicationIn, GoodCommit, VoterSetChange,
+	VoterSetChangeHandle, VoterSetChangeKind,
+};
+use crate::{voter_set::VoterSet, BlockNumberOps, Chain, Commit, SignatureVerifier};
+
+/// Necessary environment for an observer.
+///
+/// This encapsulates the database layer of the chain. Unlike the voter's
+/// [`Environment`](super::Environment) there is no need to provide round
+/// data, timers or outgoing message sinks, since an observer never votes.
+pub trait ObserverEnvironment<H: Eq, N: BlockNumberOps>: Chain<H, N> {
+	/// The associated Id for the Environment.
+	type Id: Clone + Eq + Ord + std::fmt::Debug;
+	/// The associated Signature type for the Environment.
+	type Signature: Eq + Clone;
+	/// The associated Error type.
+	type Error: From<crate::Error> + ::std::error::Error;
+
+	/// Return the best block whose chain contains the given block hash, `None`
+	/// if the block is unknown. This is only used to determine when a forced
+	/// voter set change is due.
+	fn best_chain_containing(&self, base: H) -> Option<(H, N)>;
+
+	/// Called when a block should be finalized.
+	fn finalize_block(
+		&self,
+		hash: H,
+		number: N,
+		set_id: u64,
+		round: u64,
+		commit: Commit<H, N, Self::Signature, Self::Id>,
+	) -> Result<(), Self::Error>;
+
+	/// The verifier used to check the signatures on incoming commits. By default
+	/// there is none, in which case the input stream is responsible for checking
+	/// signatures.
+	fn signature_verifier(
+		&self,
+	) -> Option<&dyn SignatureVerifier<H, N, Self::Signature, Self::Id>> {
+		None
+	}
+}
+
+// The chain and signature verifier of an observer environment, as needed to
+// validate commits like a voter does.
+struct CoreEnv<'a, E>(&'a E);
+
+impl<'a, H: Eq, N: BlockNumberOps, E: ObserverEnvironment<H, N>> Chain<H, N> for CoreEnv<'a, E> {
+	fn ancestry(&self, base: H, block: H) -> Result<Vec<H>, crate::Error> {
+		self.0.ancestry(base, block)
+	}
+
+	fn is_equal_or_descendent_of(&self, base: H, block: H) -> bool {
+		self.0.is_equal_or_descendent_of(base, block)
+	}
+
+	fn hash_at(&self, block: H, number: N) -> Option<H> {
+		self.0.hash_at(block, number)
+	}
+}
+
+impl<'a, H: Eq, N: BlockNumberOps, E: ObserverEnvironment<H, N>>
+	CoreEnvironment<H, N, E::Signature, E::Id> for CoreEnv<'a, E>
+{
+	fn signature_verifier(&self) -> Option<&dyn SignatureVerifier<H, N, E::Signature, E::Id>> {
+		self.0.signature_verifier()
+	}
+}
+
+/// A future that follows the commit messages of a voter set and finalizes
+/// blocks accordingly.
+///
+/// Each incoming commit of the current voter set id is validated against the
+/// voter set, exactly as a voter would, and if it finalizes a block higher
+/// than what we've finalized so far, a finalization notification is
+/// dispatched to the environment. Commits for any other voter set id are
+/// rejected.
+///
+/// The voter set can be changed through a [`VoterSetChangeHandle`], the changes
+/// being enacted under the same conditions as for a voter. Catch-up messages
+/// and neighbor packets are of no use to an observer and are ignored.
+///
+/// The future resolves once the incoming stream is exhausted.
+pub struct Observer<H, N, E: ObserverEnvironment<H, N>, GlobalIn>
+where
+	H: Clone + Eq + Ord + ::std::fmt::Debug,
+	N: Copy + BlockNumberOps + ::std::fmt::Debug,
+	GlobalIn: Stream<Item = Result<CommunicationIn<H, N, E::Signature, E::Id>, E::Error>> + Unpin,
+{
+	env: Arc<E>,
+	voters: VoterSet<E::Id>,
+	set_id: u64,
+	last_finalized: (H, N),
+	global_in: GlobalIn,
+	pending_set_changes: VecDeque<VoterSetChange<H, N, E::Id>>,
+	set_change_sender: UnboundedSender<VoterSetChange<H, N, E::Id>>,
+	set_change_receiver: UnboundedReceiver<VoterSetChange<H, N, E::Id>>,
+}
+
+impl<H, N, E: ObserverEnvironment<H, N>, GlobalIn> Observer<H, N, E, GlobalIn>
+where
+	H: Clone + Eq + Ord + ::std::fmt::Debug,
+	N: Copy + BlockNumberOps + ::std::fmt::Debug,
+	GlobalIn: Stream<Item = Result<CommunicationIn<H, N, E::Signature, E::Id>, E::Error>> + Unpin,
+{
+	/// Create a new `Observer` following the commits of the given voter set.
+	///
+	/// The input stream for commit messages should provide commits which
+	/// correspond to known blocks only (including all its precommits). It
+	/// is also responsible for validating the signature data in commit
+	/// messages, unless the environment provides a signature verifier.
+	pub fn new(
+		env: Arc<E>,
+		voters: VoterSet<E::Id>,
+		set_id: u64,
+		global_in: GlobalIn,
+		last_finalized: (H, N),
+	) -> Self {
+		let (set_change_sender, set_change_receiver) = mpsc::unbounded();
+
+		Observer {
+			env,
+			voters,
+			set_id,
+			last_finalized,
+			global_in,
+			pending_set_changes: VecDeque::new(),
+			set_change_sender,
+			set_change_receiver,
+		}
+	}
+
+	/// Get the highest block finalized by this observer so far.
+	pub fn last_finalized(&self) -> &(H, N) {
+		&self.last_finalized
+	}
+
+	/// Get the id of the voter set we are currently observing.
+	pub fn set_id(&self) -> u64 {
+		self.set_id
+	}
+
+	/// Returns a handle which can be used to schedule voter set changes.
+	pub fn set_change_handle(&self) -> VoterSetChangeHandle<H, N, E::Id> {
+		VoterSetChangeHandle(self.set_change_sender.clone())
+	}
+
+	// validate a commit and finalize its target if it's higher than what we've
+	// finalized so far.
+	fn import_commit(
+		&mut self,
+		set_id: u64,
+		round: u64,
+		commit: Commit<H, N, E::Signature, E::Id>,
+	) -> Result<CommitProcessingOutcome, E::Error> {
+		if set_id != self.set_id {
+			trace!(target: "afg", "Ignoring commit for voter set {}, observing voter set {}",
+				set_id,
+				self.set_id,
+			);
+
+			return Ok(CommitProcessingOutcome::bad())
+		}
+
+		let env = CoreEnv(&*self.env);
+		let validation_result =
+			validate_commit_with_env(&commit, set_id, round, &self.voters, &env)?;
+
+		let (hash, number) = match validation_result.ghost.clone() {
+			Some(ghost) => ghost,
+			None => return Ok(CommitProcessingOutcome::Bad(validation_result.into())),
+		};
+
+		if number > self.last_finalized.1 {
+			self.last_finalized = (hash.clone(), number);
+			self.env.finalize_block(hash, number, set_id, round, commit)?;
+		}
+
+		Ok(CommitProcessingOutcome::Good(GoodCommit::new()))
+	}
+
+	// enact the scheduled voter set changes which are due.
+	fn enact_set_changes(&mut self) -> Result<(), E::Error> {
+		while let Some(change) = self.pending_set_changes.front() {
+			let (kind, effective_number) = (change.kind, change.effective_number);
+			let canon_hash = change.canon_hash.clone();
+
+			// a change announced on a fork which doesn't include the last
+			// finalized block can't be enacted anymore.
+			let last_finalized = self.last_finalized.0.clone();
+			if self.last_finalized.1 >= effective_number &&
+				!self.env.is_equal_or_descendent_of(canon_hash.clone(), last_finalized)
+			{
+				debug!(target: "afg", "Discarding voter set change announced in {:?}, not on the finalized chain",
+					canon_hash,
+				);
+
+				self.pending_set_changes.pop_front();
+				continue
+			}
+
+			let due = match kind {
+				_ if self.last_finalized.1 >= effective_number => true,
+				VoterSetChangeKind::Standard => false,
+				VoterSetChangeKind::Forced => {
+					let best = self.env.best_chain_containing(self.last_finalized.0.clone());
+					forced_change_block(
+						&*self.env,
+						self.last_finalized.0.clone(),
+						best,
+						canon_hash,
+						effective_number,
+					)?
+					.is_some()
+				},
+			};
+
+			if !due {
+				return Ok(())
+			}
+
+			let change = self
+				.pending_set_changes
+				.pop_front()
+				.expect("we checked self.pending_set_changes.front() just above; qed");
+
+			trace!(target: "afg", "Enacting {:?} voter set change at block {:?}, observing voter set {}",
+				change.kind,
+				change.effective_number,
+				self.set_id + 1,
+			);
+
+			self.voters = change.next_voters;
+			self.set_id += 1;
+		}
+
+		Ok(())
+	}
+
+	// Schedule any voter set changes received through the handle.
+	fn process_set_changes(&mut self, cx: &mut Context) -> Result<(), E::Error> {
+		while let Poll::Ready(Some(change)) =
+			Stream::poll_next(Pin::new(&mut self.set_change_receiver), cx)
+		{
+			trace!(target: "afg", "Scheduled {:?} voter set change at block {:?}",
+				change.kind,
+				change.effective_number,
+			);
+
+			self.pending_set_changes.push_back(change);
+		}
+
+		self.enact_set_changes()
+	}
+
+	/// Process all incoming messages from other nodes.
+	///
+	/// Returns `Poll::Ready` once the incoming stream has been exhausted.
+	fn process_incoming(&mut self, cx: &mut Context) -> Poll<Result<(), E::Error>> {
+		loop {
+			match Stream::poll_next(Pin::new(&mut self.global_in), cx) {
+				Poll::Ready(Some(item)) => match item? {
+					CommunicationIn::Commit(set_id, round, commit, mut callback) => {
+						trace!(target: "afg", "Observed commit for round_number {:?}: target_number: {:?}, target_hash: {:?}",
+							round,
+							commit.target_number,
+							commit.target_hash,
+						);
+
+						let outcome = self.import_commit(set_id, round, commit.into())?;
+						callback.run(outcome);
+					},
+					CommunicationIn::CatchUp(catch_up, mut process_catch_up_outcome) => {
+						trace!(target: "afg", "Ignoring catch-up message for round {} while observing",
+							catch_up.round_number,
+						);
+
+						process_catch_up_outcome.run(CatchUpProcessingOutcome::Useless);
+					},
+					CommunicationIn::Neighbor(..) => {},
+				},
+				Poll::Ready(None) => return Poll::Ready(Ok(())),
+				Poll::Pending => return Poll::Pending,
+			}
+
+			// changes scheduled while handling a commit apply to the next one.
+			self.process_set_changes(cx)?;
+		}
+	}
+}
+
+impl<H, N, E: ObserverEnvironment<H, N>, GlobalIn> Future for Observer<H, N, E, GlobalIn>
+where
+	H: Clone + Eq + Ord + ::std::fmt::Debug,
+	N: Copy + BlockNumberOps + ::std::fmt::Debug,
+	GlobalIn: Stream<Item = Result<CommunicationIn<H, N, E::Signature, E::Id>, E::Error>> + Unpin,
+{
+	type Output = Result<(), E::Error>;
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), E::Error>> {
+		self.process_set_changes(cx)?;
+		self.process_incoming(cx)
+	}
+}
+
+impl<H, N, E: ObserverEnvironment<H, N>, GlobalIn> Unpin for Observer<H, N, E, GlobalIn>
+where
+	H: Clone + Eq + Ord + ::std::fmt::Debug,
+	N: Copy + BlockNumberOps + ::std::fmt::Debug,
+	GlobalIn: Stream<Item = Result<CommunicationIn<H, N, E::Signature, E::Id>, E::Error>> + Unpin,
+{
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		testing::{
+			self,
+			chain::GENESIS_HASH,
+			environment::{Environment, Id, Signature},
+		},
+		voter::{Callback, Voter, VoterParams},
+		CompactCommit, Precommit, SignedPrecommit,
+	};
+	use futures::{channel::mpsc, executor::LocalPool, task::SpawnExt};
+
+	#[test]
+	fn observer_follows_voters() {
+		let voters = VoterSet::new((0..3).map(|i| (Id(i), 1))).expect("nonempty");
+
+		let (network, routing_task) = testing::environment::make_network();
+		let mut pool = LocalPool::new();
+
+		for i in 0..3 {
+			let env = Arc::new(Environment::new(network.clone(), Id(i)));
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let voter = Voter::new(
+				env,
+				network.make_global_comms(),
+				VoterParams::new(voters.clone(), 0, last_finalized),
+			);
+
+			pool.spawner().spawn(voter.map(|v| v.expect("Error voting"))).unwrap();
+		}
+
+		// the observer has an id which is not part of the voter set.
+		let env = Arc::new(Environment::new(network.clone(), Id(100)));
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+			chain.last_finalized()
+		});
+
+		let (global_in, _) = network.make_global_comms();
+		let observer = Observer::new(env.clone(), voters, 0, global_in, last_finalized);
+
+		pool.spawner().spawn(observer.map(|o| o.expect("Error observing"))).unwrap();
+		pool.spawner().spawn(routing_task).unwrap();
+
+		// wait for the observer to learn about the finality of the best block.
+		pool.run_until(
+			env.finalized_stream()
+				.take_while(|&(_, n, _)| future::ready(n < 6))
+				.for_each(|_| future::ready(())),
+		)
+	}
+
+	#[test]
+	fn observer_ignores_invalid_commits() {
+		let voters = VoterSet::new((0..3).map(|i| (Id(i), 1))).expect("nonempty");
+
+		let (network, _routing_task) = testing::environment::make_network();
+		let env = Arc::new(Environment::new(network, Id(100)));
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+			chain.last_finalized()
+		});
+
+		let precommit = |id| SignedPrecommit {
+			precommit: Precommit::new("E", 6),
+			signature: Signature(id),
+			id: Id(id),
+		};
+
+		let commit = |precommits| {
+			CompactCommit::from(Commit { target_hash: "E", target_number: 6, precommits })
+		};
+
+		let (outcomes_tx, outcomes_rx) = mpsc::unbounded();
+		let callback = || {
+			let outcomes_tx = outcomes_tx.clone();
+			Callback::Work(Box::new(move |outcome| {
+				outcomes_tx.unbounded_send(outcome).unwrap();
+			}))
+		};
+
+		// a commit without enough precommits, a commit from another voter set and
+		// a valid one.
+		let messages = vec![
+			Ok(CommunicationIn::Commit(0, 1, commit(vec![precommit(0)]), callback())),
+			Ok(CommunicationIn::Commit(
+				1,
+				1,
+				commit(vec![precommit(0), precommit(1), precommit(2)]),
+				callback(),
+			)),
+			Ok(CommunicationIn::Commit(
+				0,
+				1,
+				commit(vec![precommit(0), precommit(1), precommit(2)]),
+				callback(),
+			)),
+		];
+
+		let mut observer = Observer::new(env, voters, 0, stream::iter(messages), last_finalized);
+		futures::executor::block_on(&mut observer).unwrap();
+
+		drop(outcomes_tx);
+		let outcomes = futures::executor::block_on(outcomes_rx.collect::<Vec<_>>());
+
+		assert!(matches!(outcomes[0], CommitProcessingOutcome::Bad(_)));
+		assert!(matches!(outcomes[1], CommitProcessingOutcome::Bad(_)));
+		assert!(matches!(outcomes[2], CommitProcessingOutcome::Good(_)));
+		assert_eq!(observer.last_finalized(), &("E", 6));
+	}
+
+	#[test]
+	fn observer_follows_voter_set_changes() {
+		let voters = VoterSet::new((0..3).map(|i| (Id(i), 1))).expect("nonempty");
+		let next_voters = VoterSet::new((10..13).map(|i| (Id(i), 1))).expect("nonempty");
+
+		let (network, _routing_task) = testing::environment::make_network();
+		let env = Arc::new(Environment::new(network, Id(100)));
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E", "F"]);
+			chain.last_finalized()
+		});
+
+		let commit = |target_hash, target_number, ids: std::ops::Range<u32>| {
+			let precommits = ids
+				.map(|id| SignedPrecommit {
+					precommit: Precommit::new(target_hash, target_number),
+					signature: Signature(id),
+					id: Id(id),
+				})
+				.collect();
+
+			CompactCommit::from(Commit { target_hash, target_number, precommits })
+		};
+
+		// the first commit finalizes the block enacting the change, the second
+		// one is from the next voter set.
+		let messages = vec![
+			Ok(CommunicationIn::Commit(0, 1, commit("E", 6, 0..3), Callback::Blank)),
+			Ok(CommunicationIn::Commit(1, 1, commit("F", 7, 10..13), Callback::Blank)),
+		];
+
+		let mut observer = Observer::new(env, voters, 0, stream::iter(messages), last_finalized);
+		observer
+			.set_change_handle()
+			.schedule(VoterSetChange {
+				canon_hash: "D",
+				next_voters,
+				effective_number: 6,
+				kind: VoterSetChangeKind::Standard,
+			})
+			.unwrap();
+
+		futures::executor::block_on(&mut observer).unwrap();
+
+		assert_eq!(observer.set_id(), 1);
+		assert_eq!(observer.last_finalized(), &("F", 7));
+	}
+}
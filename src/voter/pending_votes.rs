@@ -0,0 +1,212 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A buffer for the votes on blocks which haven't been imported yet.
+//!
+//! Votes are gossiped independently of blocks, so a vote can reach the voter
+//! before the block it targets. Such votes are kept, keyed by the hash of their
+//! target, until the block is imported or their round is dropped. The buffer is
+//! bounded both per voter and per round, so that a misbehaving voter can't fill
+//! it with votes on blocks that will never exist.
+
+use crate::{
+	std::{collections::btree_map::BTreeMap, vec::Vec},
+	SignedMessage,
+};
+
+use super::{MAX_PENDING_VOTES_PER_ROUND, MAX_PENDING_VOTES_PER_VOTER};
+
+// a vote along with the number of its round.
+type RoundVote<H, N, S, Id> = (u64, SignedMessage<H, N, S, Id>);
+
+pub(super) struct PendingVotes<H, N, S, Id> {
+	// the votes by the hash of their target.
+	by_target: BTreeMap<H, Vec<RoundVote<H, N, S, Id>>>,
+	per_voter: BTreeMap<Id, usize>,
+	per_round: BTreeMap<u64, usize>,
+}
+
+impl<H, N, S, Id> PendingVotes<H, N, S, Id>
+where
+	H: Clone + Ord,
+	N: Copy + Eq,
+	S: Eq,
+	Id: Clone + Ord,
+{
+	pub(super) fn new() -> Self {
+		PendingVotes {
+			by_target: BTreeMap::new(),
+			per_voter: BTreeMap::new(),
+			per_round: BTreeMap::new(),
+		}
+	}
+
+	// buffer a vote of the given round, unless its voter or round already have
+	// too many votes buffered. returns whether the vote is buffered.
+	pub(super) fn insert(&mut self, round: u64, vote: SignedMessage<H, N, S, Id>) -> bool {
+		let target = vote.message.target().0.clone();
+		if let Some(votes) = self.by_target.get(&target) {
+			if votes.iter().any(|(r, v)| *r == round && *v == vote) {
+				return true
+			}
+		}
+
+		let voter_count = self.per_voter.get(&vote.id).cloned().unwrap_or(0);
+		let round_count = self.per_round.get(&round).cloned().unwrap_or(0);
+		if voter_count >= MAX_PENDING_VOTES_PER_VOTER || round_count >= MAX_PENDING_VOTES_PER_ROUND
+		{
+			return false
+		}
+
+		self.per_voter.insert(vote.id.clone(), voter_count + 1);
+		self.per_round.insert(round, round_count + 1);
+		self.by_target.entry(target).or_default().push((round, vote));
+
+		true
+	}
+
+	// take the votes on the given block, along with the number of their round.
+	// votes with the right hash but another number are invalid and dropped.
+	pub(super) fn take(&mut self, hash: &H, number: N) -> Vec<RoundVote<H, N, S, Id>> {
+		let votes = self.by_target.remove(hash).unwrap_or_default();
+		for (round, vote) in &votes {
+			self.forget(*round, &vote.id);
+		}
+
+		votes
+			.into_iter()
+			.filter(|(_, vote)| vote.message.target().1 == number)
+			.collect()
+	}
+
+	// drop the votes of the given round.
+	pub(super) fn drop_round(&mut self, round: u64) {
+		if self.per_round.remove(&round).is_none() {
+			return
+		}
+
+		let mut dropped = Vec::new();
+		self.by_target.retain(|_, votes| {
+			votes.retain(|(r, vote)| {
+				if *r == round {
+					dropped.push(vote.id.clone());
+				}
+				*r != round
+			});
+			!votes.is_empty()
+		});
+
+		for id in dropped {
+			self.forget_voter(&id);
+		}
+	}
+
+	// drop all votes, e.g. when the voter set changes.
+	pub(super) fn clear(&mut self) {
+		self.by_target.clear();
+		self.per_voter.clear();
+		self.per_round.clear();
+	}
+
+	// the number of votes buffered.
+	#[cfg(test)]
+	pub(super) fn len(&self) -> usize {
+		self.per_round.values().sum()
+	}
+
+	fn forget(&mut self, round: u64, id: &Id) {
+		if let Some(count) = self.per_round.get_mut(&round) {
+			*count -= 1;
+			if *count == 0 {
+				self.per_round.remove(&round);
+			}
+		}
+
+		self.forget_voter(id);
+	}
+
+	fn forget_voter(&mut self, id: &Id) {
+		if let Some(count) = self.per_voter.get_mut(id) {
+			*count -= 1;
+			if *count == 0 {
+				self.per_voter.remove(id);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{Message, Prevote};
+
+	fn vote(
+		id: u64,
+		target: &'static str,
+		number: u32,
+	) -> SignedMessage<&'static str, u32, u64, u64> {
+		SignedMessage { message: Message::Prevote(Prevote::new(target, number)), signature: id, id }
+	}
+
+	#[test]
+	fn takes_votes_on_imported_block() {
+		let mut pending = PendingVotes::new();
+
+		assert!(pending.insert(1, vote(1, "A", 2)));
+		assert!(pending.insert(2, vote(2, "A", 2)));
+		assert!(pending.insert(1, vote(3, "B", 3)));
+		// duplicates aren't buffered twice.
+		assert!(pending.insert(1, vote(1, "A", 2)));
+		assert_eq!(pending.len(), 3);
+
+		// votes with the wrong number are dropped.
+		assert!(pending.insert(1, vote(4, "B", 4)));
+		assert_eq!(pending.take(&"B", 3), vec![(1, vote(3, "B", 3))]);
+		assert_eq!(pending.take(&"A", 2), vec![(1, vote(1, "A", 2)), (2, vote(2, "A", 2))]);
+		assert!(pending.take(&"A", 2).is_empty());
+		assert_eq!(pending.len(), 0);
+		assert!(pending.per_voter.is_empty());
+	}
+
+	#[test]
+	fn drops_votes_of_dropped_rounds() {
+		let mut pending = PendingVotes::new();
+
+		assert!(pending.insert(1, vote(1, "A", 2)));
+		assert!(pending.insert(2, vote(1, "B", 3)));
+		pending.drop_round(1);
+
+		assert!(pending.take(&"A", 2).is_empty());
+		assert_eq!(pending.per_voter.get(&1), Some(&1));
+		assert_eq!(pending.take(&"B", 3), vec![(2, vote(1, "B", 3))]);
+	}
+
+	#[test]
+	fn limits_votes_per_voter_and_round() {
+		let mut pending = PendingVotes::new();
+
+		for i in 0..MAX_PENDING_VOTES_PER_VOTER {
+			assert!(pending.insert(i as u64, vote(1, "A", 2)));
+		}
+		assert!(!pending.insert(0, vote(1, "B", 3)));
+		assert!(pending.insert(0, vote(2, "B", 3)));
+
+		pending.clear();
+		for i in 0..MAX_PENDING_VOTES_PER_ROUND {
+			assert!(pending.insert(1, vote(i as u64, "A", 2)));
+		}
+		assert!(!pending.insert(1, vote(u64::MAX, "A", 2)));
+		assert!(pending.insert(2, vote(u64::MAX, "A", 2)));
+	}
+}
@@ -14,69 +14,46 @@
 
 //! Logic for voting and handling messages within a single round.
 
-#[cfg(feature = "std")]
-use futures::ready;
-use futures::{channel::mpsc::UnboundedSender, prelude::*};
 #[cfg(feature = "std")]
 use log::{debug, trace, warn};
 
-use std::{
-	pin::Pin,
-	sync::Arc,
-	task::{Context, Poll},
+use super::{
+	core::{validate_commit_with_env, BestChainRequest, CoreEnvironment, Output, Outputs},
+	VoterPersistentState, VotingRule,
 };
-
-use super::{Buffered, Environment, FinalizedNotification};
 use crate::{
 	round::{Round, State as RoundState},
-	validate_commit,
+	std::{self, vec::Vec},
 	voter_set::VoterSet,
-	weights::VoteWeight,
-	BlockNumberOps, Commit, HistoricalVotes, ImportResult, Message, Precommit, Prevote,
+	BlockNumberOps, Commit, Error, HistoricalVotes, ImportResult, Message, Precommit, Prevote,
 	PrimaryPropose, SignedMessage, SignedPrecommit,
 };
 
 /// The state of a voting round.
-pub(super) enum State<T, W> {
-	Start(T, T),
-	Proposed(T, T),
-	Prevoting(T, W),
-	Prevoted(T),
+pub(super) enum State<H, N> {
+	Start,
+	Proposed,
+	// waiting for the best chain containing the given block, to prevote on.
+	Prevoting((H, N)),
+	Prevoted,
 	Precommitted,
 }
 
-impl<T, W> std::fmt::Debug for State<T, W> {
+#[cfg(feature = "std")]
+impl<H, N> std::fmt::Debug for State<H, N> {
 	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
 		match self {
-			State::Start(..) => write!(f, "Start"),
-			State::Proposed(..) => write!(f, "Proposed"),
-			State::Prevoting(..) => write!(f, "Prevoting"),
-			State::Prevoted(_) => write!(f, "Prevoted"),
+			State::Start => write!(f, "Start"),
+			State::Proposed => write!(f, "Proposed"),
+			State::Prevoting(_) => write!(f, "Prevoting"),
+			State::Prevoted => write!(f, "Prevoted"),
 			State::Precommitted => write!(f, "Precommitted"),
 		}
 	}
 }
 
-/// Logic for a voter on a specific round.
-pub(super) struct VotingRound<H, N, E: Environment<H, N>>
-where
-	H: Clone + Eq + Ord + ::std::fmt::Debug,
-	N: Copy + BlockNumberOps + ::std::fmt::Debug,
-{
-	env: Arc<E>,
-	voting: Voting,
-	votes: Round<E::Id, H, N, E::Signature>,
-	incoming: E::In,
-	outgoing: Buffered<E::Out, Message<H, N>>,
-	state: Option<State<E::Timer, (H, E::BestChain)>>, // state machine driving votes.
-	bridged_round_state: Option<crate::bridge_state::PriorView<H, N>>, // updates to later round
-	last_round_state: Option<crate::bridge_state::LatterView<H, N>>, // updates from prior round
-	primary_block: Option<(H, N)>,                     // a block posted by primary as a hint.
-	finalized_sender: UnboundedSender<FinalizedNotification<H, N, E>>,
-	best_finalized: Option<Commit<H, N, E::Signature, E::Id>>,
-}
-
 /// Whether we should vote in the current round (i.e. push votes to the sink.)
+#[derive(Clone, Copy)]
 enum Voting {
 	/// Voting is disabled for the current round.
 	No,
@@ -90,116 +67,175 @@ enum Voting {
 impl Voting {
 	/// Whether the voter should cast round votes (prevotes and precommits.)
 	fn is_active(&self) -> bool {
-		match self {
-			Voting::Yes | Voting::Primary => true,
-			_ => false,
-		}
+		matches!(self, Voting::Yes | Voting::Primary)
 	}
 
 	/// Whether the voter is the primary proposer.
 	fn is_primary(&self) -> bool {
-		match self {
-			Voting::Primary => true,
-			_ => false,
-		}
+		matches!(self, Voting::Primary)
 	}
 }
 
-impl<H, N, E: Environment<H, N>> VotingRound<H, N, E>
+// the votes we've cast in a round before a restart.
+struct OwnVotes<H, N> {
+	primary_propose: Option<PrimaryPropose<H, N>>,
+	prevote: Option<Prevote<H, N>>,
+	precommit: Option<Precommit<H, N>>,
+}
+
+/// Logic for a voter on a specific round.
+///
+/// The round doesn't do any I/O, the messages it sends and the notifications
+/// for the environment are pushed to a queue of outputs instead.
+pub(super) struct VotingRound<H, N, S, Id>
+where
+	H: Clone + Eq + Ord + std::fmt::Debug,
+	N: Copy + BlockNumberOps + std::fmt::Debug,
+	S: Clone + Eq,
+	Id: Clone + Eq + Ord + std::fmt::Debug,
+{
+	set_id: u64,
+	voting: Option<Voting>, // unknown until the round data has been provided.
+	votes: Round<Id, H, N, S>,
+	state: Option<State<H, N>>, // state machine driving votes.
+	prevote_timer_fired: bool,
+	precommit_timer_fired: bool,
+	prior_round: Option<u64>, // the round `last_round_state` is bridged from, if any.
+	last_round_state: Option<RoundState<H, N>>, // updates from prior round
+	primary_block: Option<(H, N)>, // a block posted by primary as a hint.
+	best_finalized: Option<Commit<H, N, S, Id>>,
+	// the finalized block as of the last time the round was processed.
+	last_finalized: Option<(H, N)>,
+	// a commit for a newly finalized block, to be picked up by the voter.
+	finalized_notification: Option<Commit<H, N, S, Id>>,
+	// the persistent state of the voter, only tracked while this is the best round.
+	persistent_state: Option<VoterPersistentState<H, N, S, Id>>,
+	state_persisted: bool,
+	replayed: OwnVotes<H, N>,
+}
+
+impl<H, N, S, Id> VotingRound<H, N, S, Id>
 where
-	H: Clone + Eq + Ord + ::std::fmt::Debug,
-	N: Copy + BlockNumberOps + ::std::fmt::Debug,
+	H: Clone + Eq + Ord + std::fmt::Debug,
+	N: Copy + BlockNumberOps + std::fmt::Debug,
+	S: Clone + Eq,
+	Id: Clone + Eq + Ord + std::fmt::Debug,
 {
-	/// Create a new voting round.
+	/// Create a new voting round, building on the state of the last round. The
+	/// state is bridged from `prior_round` if given, see
+	/// [`Self::update_last_round_state`].
 	pub(super) fn new(
+		set_id: u64,
 		round_number: u64,
-		voters: VoterSet<E::Id>,
+		voters: VoterSet<Id>,
 		base: (H, N),
-		last_round_state: Option<crate::bridge_state::LatterView<H, N>>,
-		finalized_sender: UnboundedSender<FinalizedNotification<H, N, E>>,
-		env: Arc<E>,
-	) -> VotingRound<H, N, E> {
-		let round_data = env.round_data(round_number);
+		prior_round: Option<u64>,
+		last_round_state: RoundState<H, N>,
+	) -> Self {
 		let round_params = crate::round::RoundParams { voters, base, round_number };
 
-		let votes = Round::new(round_params);
-
-		let voting = if round_data.voter_id.as_ref() == Some(&votes.primary_voter().0) {
-			Voting::Primary
-		} else if round_data.voter_id.as_ref().map_or(false, |id| votes.voters().contains(id)) {
-			Voting::Yes
-		} else {
-			Voting::No
-		};
-
 		VotingRound {
-			votes,
-			voting,
-			incoming: round_data.incoming,
-			outgoing: Buffered::new(round_data.outgoing),
-			state: Some(State::Start(round_data.prevote_timer, round_data.precommit_timer)),
-			bridged_round_state: None,
+			set_id,
+			voting: None,
+			votes: Round::new(round_params),
+			state: Some(State::Start),
+			prevote_timer_fired: false,
+			precommit_timer_fired: false,
+			prior_round,
+			last_round_state: Some(last_round_state),
 			primary_block: None,
 			best_finalized: None,
-			env,
-			last_round_state,
-			finalized_sender,
+			last_finalized: None,
+			finalized_notification: None,
+			persistent_state: None,
+			state_persisted: false,
+			replayed: OwnVotes { primary_propose: None, prevote: None, precommit: None },
 		}
 	}
 
 	/// Create a voting round from a completed `Round`. We will not vote further
 	/// in this round.
-	pub(super) fn completed(
-		votes: Round<E::Id, H, N, E::Signature>,
-		finalized_sender: UnboundedSender<FinalizedNotification<H, N, E>>,
-		last_round_state: Option<crate::bridge_state::LatterView<H, N>>,
-		env: Arc<E>,
-	) -> VotingRound<H, N, E> {
-		let round_data = env.round_data(votes.number());
-
+	pub(super) fn completed(set_id: u64, votes: Round<Id, H, N, S>) -> Self {
 		VotingRound {
+			set_id,
+			voting: Some(Voting::No),
 			votes,
-			voting: Voting::No,
-			incoming: round_data.incoming,
-			outgoing: Buffered::new(round_data.outgoing),
 			state: None,
-			bridged_round_state: None,
+			prevote_timer_fired: false,
+			precommit_timer_fired: false,
+			prior_round: None,
+			last_round_state: None,
 			primary_block: None,
-			env,
-			last_round_state,
-			finalized_sender,
 			best_finalized: None,
+			last_finalized: None,
+			finalized_notification: None,
+			persistent_state: None,
+			state_persisted: false,
+			replayed: OwnVotes { primary_propose: None, prevote: None, precommit: None },
 		}
 	}
 
-	/// Poll the round. When the round is completable and messages have been flushed, it will return `Poll::Ready` but
-	/// can continue to be polled.
-	pub(super) fn poll(&mut self, cx: &mut Context) -> Poll<Result<(), E::Error>> {
-		trace!(target: "afg", "Polling round {}, state = {:?}, step = {:?}", self.votes.number(), self.votes.state(), self.state);
+	/// Note the id of the local voter in this round, if any. The round doesn't
+	/// make any progress before.
+	pub(super) fn set_voter_id(&mut self, voter_id: Option<&Id>) {
+		if self.voting.is_some() {
+			return
+		}
+
+		self.voting = Some(if voter_id == Some(self.votes.primary_voter().0) {
+			Voting::Primary
+		} else if voter_id.map_or(false, |id| self.votes.voters().contains(id)) {
+			Voting::Yes
+		} else {
+			Voting::No
+		});
+	}
+
+	/// Note that the prevote timer of this round has fired.
+	pub(super) fn prevote_timer_fired(&mut self) {
+		self.prevote_timer_fired = true;
+	}
+
+	/// Note that the precommit timer of this round has fired.
+	pub(super) fn precommit_timer_fired(&mut self) {
+		self.precommit_timer_fired = true;
+	}
+
+	/// Process the round after its inputs changed: cast any votes which are due
+	/// and note newly finalized blocks. Returns whether the round is completed,
+	/// i.e. it's completable and the estimate of the previous round has been
+	/// finalized. The round can continue to be processed afterwards.
+	pub(super) fn process<C: CoreEnvironment<H, N, S, Id>>(
+		&mut self,
+		env: &C,
+		outputs: &mut Outputs<H, N, S, Id>,
+	) -> bool {
+		trace!(target: "afg", "Processing round {}, state = {:?}, step = {:?}", self.votes.number(), self.votes.state(), self.state);
+
+		// we don't know whether to vote before having the round data.
+		if self.voting.is_none() {
+			return false
+		}
 
-		let pre_state = self.votes.state();
-		self.process_incoming(cx)?;
+		if !self.state_persisted {
+			self.persist_state(outputs);
+		}
 
 		// we only cast votes when we have access to the previous round state.
 		// we might have started this round as a prospect "future" round to
 		// check whether the voter is lagging behind the current round.
-		let last_round_state = self.last_round_state.as_ref().map(|s| s.get(cx).clone());
+		let last_round_state = self.last_round_state.clone();
 		if let Some(ref last_round_state) = last_round_state {
-			self.primary_propose(last_round_state)?;
-			self.prevote(cx, last_round_state)?;
-			self.precommit(cx, last_round_state)?;
+			self.primary_propose(last_round_state, outputs);
+			self.prevote(env, last_round_state, outputs);
+			self.precommit(env, last_round_state, outputs);
 		}
 
-		ready!(self.outgoing.poll(cx))?;
-		self.process_incoming(cx)?; // in case we got a new message signed locally.
-
-		// broadcast finality notifications after attempting to cast votes
-		let post_state = self.votes.state();
-		self.notify(pre_state, post_state);
+		self.notify(env);
 
 		// early exit if the current round is not completable
 		if !self.votes.completable() {
-			return Poll::Pending
+			return false
 		}
 
 		// make sure that the previous round estimate has been finalized
@@ -232,27 +268,104 @@ where
 		// the previous round estimate must be finalized
 		if !last_round_estimate_finalized {
 			trace!(target: "afg", "Round {} completable but estimate not finalized.", self.round_number());
+			#[cfg(feature = "std")]
 			self.log_participation(log::Level::Trace);
-			return Poll::Pending
+			return false
 		}
 
 		debug!(target: "afg", "Completed round {}, state = {:?}, step = {:?}",
 			self.votes.number(), self.votes.state(), self.state);
 
+		#[cfg(feature = "std")]
 		self.log_participation(log::Level::Debug);
 
 		// both exit conditions verified, we can complete this round
-		Poll::Ready(Ok(()))
+		true
 	}
 
-	/// Inspect the state of this round.
-	pub(super) fn state(&self) -> Option<&State<E::Timer, (H, E::BestChain)>> {
-		self.state.as_ref()
+	/// Whether we have precommitted in this round, or decided not to.
+	pub(super) fn precommitted(&self) -> bool {
+		matches!(self.state, Some(State::Precommitted))
 	}
 
-	/// Get access to the underlying environment.
-	pub(super) fn env(&self) -> &E {
-		&*self.env
+	/// Get the voter set id of the round.
+	pub(super) fn set_id(&self) -> u64 {
+		self.set_id
+	}
+
+	/// Start tracking the persistent state of the voter, with this as the
+	/// round it's voting in and the given round as the last completed one.
+	pub(super) fn track_persistent_state(
+		&mut self,
+		last_finalized: (H, N),
+		completed_round_number: u64,
+		completed_round_base: (H, N),
+		completed_round_votes: Vec<SignedMessage<H, N, S, Id>>,
+	) {
+		self.persistent_state = Some(VoterPersistentState {
+			set_id: self.set_id,
+			round_number: self.round_number(),
+			round_base: self.dag_base(),
+			last_finalized,
+			primary_propose: None,
+			prevote: None,
+			precommit: None,
+			completed_round_number,
+			completed_round_base,
+			completed_round_votes,
+		});
+		self.state_persisted = false;
+	}
+
+	/// Note a newly finalized block in the persistent state, if tracked, to be
+	/// persisted on the next processing of the round.
+	pub(super) fn note_finalized(&mut self, finalized: &(H, N)) {
+		if let Some(ref mut state) = self.persistent_state {
+			if finalized.1 > state.last_finalized.1 {
+				state.last_finalized = finalized.clone();
+				self.state_persisted = false;
+			}
+		}
+	}
+
+	/// Stop tracking the persistent state of the voter, once this round is no
+	/// longer the one it's voting in.
+	pub(super) fn untrack_persistent_state(&mut self) {
+		self.persistent_state = None;
+	}
+
+	/// Restore the votes we've cast in this round before a restart, they will
+	/// be replayed instead of casting new ones.
+	pub(super) fn restore_own_votes(
+		&mut self,
+		primary_propose: Option<PrimaryPropose<H, N>>,
+		prevote: Option<Prevote<H, N>>,
+		precommit: Option<Precommit<H, N>>,
+	) {
+		if let Some(ref mut state) = self.persistent_state {
+			state.primary_propose = primary_propose.clone();
+			state.prevote = prevote.clone();
+			state.precommit = precommit.clone();
+		}
+
+		self.replayed = OwnVotes { primary_propose, prevote, precommit };
+	}
+
+	/// Get the persistent state of the voter, if tracked by this round.
+	pub(super) fn persistent_state(&self) -> Option<&VoterPersistentState<H, N, S, Id>> {
+		self.persistent_state.as_ref()
+	}
+
+	/// Get the round the state of the last round is bridged from, if any.
+	pub(super) fn prior_round(&self) -> Option<u64> {
+		self.prior_round
+	}
+
+	/// Update the state of the last round.
+	pub(super) fn update_last_round_state(&mut self, state: RoundState<H, N>) {
+		if self.last_round_state.is_some() {
+			self.last_round_state = Some(state);
+		}
 	}
 
 	/// Get the round number.
@@ -265,13 +378,28 @@ where
 		self.votes.state()
 	}
 
+	/// Get the underlying vote accumulator.
+	pub(super) fn votes(&self) -> &Round<Id, H, N, S> {
+		&self.votes
+	}
+
 	/// Get the base block in the dag.
 	pub(super) fn dag_base(&self) -> (H, N) {
 		self.votes.base()
 	}
 
+	/// Move the base block in the dag forward, dropping the forks which don't
+	/// include it. See [`Round::prune_to`].
+	pub(super) fn prune_to<C: CoreEnvironment<H, N, S, Id>>(
+		&mut self,
+		env: &C,
+		base: (H, N),
+	) -> Result<(), Error> {
+		self.votes.prune_to(env, base.0, base.1)
+	}
+
 	/// Get the voters in this round.
-	pub(super) fn voters(&self) -> &VoterSet<E::Id> {
+	pub(super) fn voters(&self) -> &VoterSet<Id> {
 		self.votes.voters()
 	}
 
@@ -280,68 +408,42 @@ where
 		self.votes.finalized()
 	}
 
-	/// Get the current total weight of prevotes.
-	pub(super) fn prevote_weight(&self) -> VoteWeight {
-		self.votes.prevote_participation().0
-	}
-
-	/// Get the current total weight of precommits.
-	pub(super) fn precommit_weight(&self) -> VoteWeight {
-		self.votes.precommit_participation().0
-	}
-
-	/// Get the Ids of the prevoters.
-	pub(super) fn prevote_ids(&self) -> impl Iterator<Item = E::Id> {
-		self.votes.prevotes().into_iter().map(|pv| pv.0)
-	}
-
-	/// Get the Ids of the precommitters.
-	pub(super) fn precommit_ids(&self) -> impl Iterator<Item = E::Id> {
-		self.votes.precommits().into_iter().map(|pv| pv.0)
-	}
-
 	/// Check a commit. If it's valid, import all the votes into the round as well.
 	/// Returns the finalized base if it checks out.
-	pub(super) fn check_and_import_from_commit(
+	pub(super) fn check_and_import_from_commit<C: CoreEnvironment<H, N, S, Id>>(
 		&mut self,
-		commit: &Commit<H, N, E::Signature, E::Id>,
-	) -> Result<Option<(H, N)>, E::Error> {
-		let base = validate_commit(commit, self.voters(), &*self.env)?.ghost;
+		env: &C,
+		commit: &Commit<H, N, S, Id>,
+		outputs: &mut Outputs<H, N, S, Id>,
+	) -> Result<Option<(H, N)>, Error> {
+		let base =
+			validate_commit_with_env(commit, self.set_id, self.round_number(), self.voters(), env)?
+				.ghost;
 		if base.is_none() {
 			return Ok(None)
 		}
 
 		for SignedPrecommit { precommit, signature, id } in commit.precommits.iter().cloned() {
-			let import_result =
-				self.votes.import_precommit(&*self.env, precommit, id, signature)?;
-			if let ImportResult { equivocation: Some(e), .. } = import_result {
-				self.env.precommit_equivocation(self.round_number(), e);
+			let import_result = self.votes.import_precommit(env, precommit, id, signature)?;
+			if let ImportResult { equivocation: Some(equivocation), .. } = import_result {
+				outputs.push_back(Output::PrecommitEquivocation {
+					set_id: self.set_id,
+					round: self.round_number(),
+					equivocation,
+				});
 			}
 		}
 
 		Ok(base)
 	}
 
-	/// Get a clone of the finalized sender.
-	pub(super) fn finalized_sender(&self) -> UnboundedSender<FinalizedNotification<H, N, E>> {
-		self.finalized_sender.clone()
-	}
-
-	// call this when we build on top of a given round in order to get a handle
-	// to updates to the latest round-state.
-	pub(super) fn bridge_state(&mut self) -> crate::bridge_state::LatterView<H, N> {
-		let (prior_view, latter_view) = crate::bridge_state::bridge_state(self.votes.state());
-		if self.bridged_round_state.is_some() {
-			warn!(target: "afg", "Bridged state from round {} more than once.",
-				self.votes.number());
-		}
-
-		self.bridged_round_state = Some(prior_view);
-		latter_view
+	/// Take the commit for a block newly finalized in this round, if any.
+	pub(super) fn take_finalized_notification(&mut self) -> Option<Commit<H, N, S, Id>> {
+		self.finalized_notification.take()
 	}
 
 	/// Get a commit justifying the best finalized block.
-	pub(super) fn finalizing_commit(&self) -> Option<&Commit<H, N, E::Signature, E::Id>> {
+	pub(super) fn finalizing_commit(&self) -> Option<&Commit<H, N, S, Id>> {
 		self.best_finalized.as_ref()
 	}
 
@@ -349,40 +451,74 @@ where
 	/// imported order and indicating the indices where we voted. At most two
 	/// prevotes and two precommits per voter are present, further equivocations
 	/// are not stored (as they are redundant).
-	pub(super) fn historical_votes(&self) -> &HistoricalVotes<H, N, E::Signature, E::Id> {
+	pub(super) fn historical_votes(&self) -> &HistoricalVotes<H, N, S, Id> {
 		self.votes.historical_votes()
 	}
 
-	/// Handle a vote manually.
-	pub(super) fn handle_vote(
+	/// Handle an incoming vote.
+	///
+	/// A vote whose target is above the round base but not known to descend
+	/// from it is given back, as its target might not have been imported yet.
+	pub(super) fn handle_vote<C: CoreEnvironment<H, N, S, Id>>(
 		&mut self,
-		vote: SignedMessage<H, N, E::Signature, E::Id>,
-	) -> Result<(), E::Error> {
-		let SignedMessage { message, signature, id } = vote;
-		if !self
-			.env
-			.is_equal_or_descendent_of(self.votes.base().0, message.target().0.clone())
-		{
+		env: &C,
+		vote: SignedMessage<H, N, S, Id>,
+		outputs: &mut Outputs<H, N, S, Id>,
+	) -> Result<Option<SignedMessage<H, N, S, Id>>, Error> {
+		if let Some(verifier) = env.signature_verifier() {
+			if !verifier.verify(
+				self.set_id,
+				self.round_number(),
+				&vote.message,
+				&vote.signature,
+				&vote.id,
+			) {
+				trace!(target: "afg", "Ignoring message from {:?} with invalid signature", vote.id);
+				return Ok(None)
+			}
+		}
+
+		// votes of non-voters would otherwise be buffered below, taking the
+		// place of honest votes on blocks which are still being imported.
+		if !self.voters().contains(&vote.id) {
+			trace!(target: "afg", "Ignoring message from {:?}, not in the voter set", vote.id);
+			return Ok(None)
+		}
+
+		if !env.is_equal_or_descendent_of(self.votes.base().0, vote.message.target().0.clone()) {
+			if vote.message.target().1 > self.votes.base().1 {
+				trace!(target: "afg", "Message targeting {:?} not known to descend from round base {:?}",
+					vote.message.target(),
+					self.votes.base(),
+				);
+				return Ok(Some(vote))
+			}
+
 			trace!(target: "afg", "Ignoring message targeting {:?} lower than round base {:?}",
-				message.target(),
+				vote.message.target(),
 				self.votes.base(),
 			);
-			return Ok(())
+			return Ok(None)
 		}
 
+		let SignedMessage { message, signature, id } = vote;
+
+		let (set_id, round) = (self.set_id, self.votes.number());
 		match message {
 			Message::Prevote(prevote) => {
-				let import_result =
-					self.votes.import_prevote(&*self.env, prevote, id, signature)?;
-				if let ImportResult { equivocation: Some(e), .. } = import_result {
-					self.env.prevote_equivocation(self.votes.number(), e);
+				let import_result = self.votes.import_prevote(env, prevote, id, signature)?;
+				if let ImportResult { equivocation: Some(equivocation), .. } = import_result {
+					outputs.push_back(Output::PrevoteEquivocation { set_id, round, equivocation });
 				}
 			},
 			Message::Precommit(precommit) => {
-				let import_result =
-					self.votes.import_precommit(&*self.env, precommit, id, signature)?;
-				if let ImportResult { equivocation: Some(e), .. } = import_result {
-					self.env.precommit_equivocation(self.votes.number(), e);
+				let import_result = self.votes.import_precommit(env, precommit, id, signature)?;
+				if let ImportResult { equivocation: Some(equivocation), .. } = import_result {
+					outputs.push_back(Output::PrecommitEquivocation {
+						set_id,
+						round,
+						equivocation,
+					});
 				}
 			},
 			Message::PrimaryPropose(primary) => {
@@ -395,9 +531,69 @@ where
 			},
 		}
 
-		Ok(())
+		Ok(None)
+	}
+
+	/// Handle the answer to the best chain request of this round, casting our
+	/// prevote.
+	pub(super) fn handle_best_chain<C: CoreEnvironment<H, N, S, Id>>(
+		&mut self,
+		env: &C,
+		best_chain: Option<(H, N)>,
+		outputs: &mut Outputs<H, N, S, Id>,
+	) {
+		let base = match self.state.take() {
+			Some(State::Prevoting(base)) => base,
+			x => {
+				self.state = x;
+				return
+			},
+		};
+
+		if let Some(best_chain) = best_chain {
+			let target = match env.voting_rule() {
+				Some(rule) => self.restrict_prevote(env, rule, &base, best_chain),
+				None => best_chain,
+			};
+			let prevote = Prevote { target_hash: target.0, target_number: target.1 };
+
+			debug!(target: "afg", "Casting prevote for round {}", self.votes.number());
+			self.record_own_vote(outputs, |state| state.prevote = Some(prevote.clone()));
+			outputs.push_back(Output::Prevoted {
+				set_id: self.set_id,
+				round: self.round_number(),
+				prevote: prevote.clone(),
+			});
+			self.votes.set_prevoted_index();
+			self.send(outputs, Message::Prevote(prevote));
+			self.state = Some(State::Prevoted);
+		} else {
+			// if this block is considered unknown, something has gone wrong.
+			// log and handle, but skip casting a vote.
+			warn!(target: "afg",
+				"Could not cast prevote: previously known block {:?} has disappeared",
+				base,
+			);
+
+			// when we can't construct a prevote, we shouldn't precommit.
+			self.state = None;
+			self.voting = Some(Voting::No);
+		}
+	}
+
+	fn voting(&self) -> Voting {
+		self.voting.unwrap_or(Voting::No)
+	}
+
+	fn send(&self, outputs: &mut Outputs<H, N, S, Id>, message: Message<H, N>) {
+		outputs.push_back(Output::SendRoundMessage {
+			set_id: self.set_id,
+			round: self.round_number(),
+			message,
+		});
 	}
 
+	#[cfg(feature = "std")]
 	fn log_participation(&self, log_level: log::Level) {
 		let total_weight = self.voters().total_weight();
 		let threshold = self.voters().threshold();
@@ -414,218 +610,187 @@ where
 			number, precommit_weight, threshold, total_weight, n_precommits, n_voters);
 	}
 
-	fn process_incoming(&mut self, cx: &mut Context) -> Result<(), E::Error> {
-		while let Poll::Ready(Some(incoming)) = Stream::poll_next(Pin::new(&mut self.incoming), cx)
-		{
-			trace!(target: "afg", "Round {}: Got incoming message", self.round_number());
-			self.handle_vote(incoming?)?;
+	// hand the persistent state (if tracked) over to the environment.
+	fn persist_state(&mut self, outputs: &mut Outputs<H, N, S, Id>) {
+		if let Some(ref state) = self.persistent_state {
+			outputs.push_back(Output::PersistState(state.clone()));
 		}
 
-		Ok(())
+		self.state_persisted = true;
 	}
 
-	fn primary_propose(&mut self, last_round_state: &RoundState<H, N>) -> Result<(), E::Error> {
-		match self.state.take() {
-			Some(State::Start(prevote_timer, precommit_timer)) => {
-				let maybe_estimate = last_round_state.estimate.clone();
-
-				match (maybe_estimate, self.voting.is_primary()) {
-					(Some(last_round_estimate), true) => {
-						let maybe_finalized = last_round_state.finalized.clone();
-
-						// Last round estimate has not been finalized.
-						let should_send_primary =
-							maybe_finalized.map_or(true, |f| last_round_estimate.1 > f.1);
-						if should_send_primary {
-							debug!(target: "afg", "Sending primary block hint for round {}", self.votes.number());
-							let primary = PrimaryPropose {
-								target_hash: last_round_estimate.0,
-								target_number: last_round_estimate.1,
-							};
-							self.env.proposed(self.round_number(), primary.clone())?;
-							self.outgoing.push(Message::PrimaryPropose(primary));
-							self.state = Some(State::Proposed(prevote_timer, precommit_timer));
-
-							return Ok(())
-						} else {
-							debug!(target: "afg", "Last round estimate has been finalized, \
-								not sending primary block hint for round {}", self.votes.number());
-						}
-					},
-					(None, true) => {
-						debug!(target: "afg", "Last round estimate does not exist, \
-							not sending primary block hint for round {}", self.votes.number());
-					},
-					_ => {},
-				}
-
-				self.state = Some(State::Start(prevote_timer, precommit_timer));
-			},
-			x => {
-				self.state = x;
-			},
+	// record one of our own votes in the persistent state and persist it,
+	// before the vote is broadcast.
+	fn record_own_vote(
+		&mut self,
+		outputs: &mut Outputs<H, N, S, Id>,
+		record: impl FnOnce(&mut VoterPersistentState<H, N, S, Id>),
+	) {
+		if let Some(ref mut state) = self.persistent_state {
+			record(state);
 		}
 
-		Ok(())
+		self.persist_state(outputs)
 	}
 
-	fn prevote(
+	fn primary_propose(
 		&mut self,
-		cx: &mut Context,
 		last_round_state: &RoundState<H, N>,
-	) -> Result<(), E::Error> {
-		let state = self.state.take();
-
-		let start_prevoting = |this: &mut Self,
-		                       mut prevote_timer: E::Timer,
-		                       precommit_timer: E::Timer,
-		                       proposed: bool,
-		                       cx: &mut Context| {
-			let should_prevote = match prevote_timer.poll_unpin(cx) {
-				Poll::Ready(Err(e)) => return Err(e),
-				Poll::Ready(Ok(())) => true,
-				Poll::Pending => this.votes.completable(),
-			};
+		outputs: &mut Outputs<H, N, S, Id>,
+	) {
+		if !matches!(self.state, Some(State::Start)) {
+			return
+		}
 
-			if should_prevote {
-				if this.voting.is_active() {
-					debug!(target: "afg", "Constructing prevote for round {}", this.votes.number());
+		if let Some(primary) = self.replayed.primary_propose.clone() {
+			debug!(target: "afg", "Replaying primary block hint for round {}", self.votes.number());
+			self.send(outputs, Message::PrimaryPropose(primary));
+			self.state = Some(State::Proposed);
 
-					let (base, best_chain) = this.construct_prevote(last_round_state);
+			return
+		}
+
+		let maybe_estimate = last_round_state.estimate.clone();
 
-					// since we haven't polled the future above yet we need to
-					// manually schedule the current task to be awoken so the
-					// `best_chain` future is then polled below after we switch the
-					// state to `Prevoting`.
-					cx.waker().wake_by_ref();
+		match (maybe_estimate, self.voting().is_primary()) {
+			(Some(last_round_estimate), true) => {
+				let maybe_finalized = last_round_state.finalized.clone();
 
-					this.state = Some(State::Prevoting(precommit_timer, (base, best_chain)));
+				// Last round estimate has not been finalized.
+				let should_send_primary =
+					maybe_finalized.map_or(true, |f| last_round_estimate.1 > f.1);
+				if should_send_primary {
+					debug!(target: "afg", "Sending primary block hint for round {}", self.votes.number());
+					let primary = PrimaryPropose {
+						target_hash: last_round_estimate.0,
+						target_number: last_round_estimate.1,
+					};
+					self.record_own_vote(outputs, |state| {
+						state.primary_propose = Some(primary.clone())
+					});
+					outputs.push_back(Output::Proposed {
+						set_id: self.set_id,
+						round: self.round_number(),
+						propose: primary.clone(),
+					});
+					self.send(outputs, Message::PrimaryPropose(primary));
+					self.state = Some(State::Proposed);
 				} else {
-					this.state = Some(State::Prevoted(precommit_timer));
+					debug!(target: "afg", "Last round estimate has been finalized, \
+						not sending primary block hint for round {}", self.votes.number());
 				}
-			} else if proposed {
-				this.state = Some(State::Proposed(prevote_timer, precommit_timer));
-			} else {
-				this.state = Some(State::Start(prevote_timer, precommit_timer));
-			}
-
-			Ok(())
-		};
-
-		let finish_prevoting = |this: &mut Self,
-		                        precommit_timer: E::Timer,
-		                        base: H,
-		                        mut best_chain: E::BestChain,
-		                        cx: &mut Context| {
-			let best_chain = match best_chain.poll_unpin(cx) {
-				Poll::Ready(Err(e)) => return Err(e),
-				Poll::Ready(Ok(best_chain)) => best_chain,
-				Poll::Pending => {
-					this.state = Some(State::Prevoting(precommit_timer, (base, best_chain)));
-					return Ok(())
-				},
-			};
+			},
+			(None, true) => {
+				debug!(target: "afg", "Last round estimate does not exist, \
+					not sending primary block hint for round {}", self.votes.number());
+			},
+			_ => {},
+		}
+	}
 
-			if let Some(target) = best_chain {
-				let prevote = Prevote { target_hash: target.0, target_number: target.1 };
-
-				debug!(target: "afg", "Casting prevote for round {}", this.votes.number());
-				this.env.prevoted(this.round_number(), prevote.clone())?;
-				this.votes.set_prevoted_index();
-				this.outgoing.push(Message::Prevote(prevote));
-				this.state = Some(State::Prevoted(precommit_timer));
-			} else {
-				// if this block is considered unknown, something has gone wrong.
-				// log and handle, but skip casting a vote.
-				warn!(target: "afg",
-					"Could not cast prevote: previously known block {:?} has disappeared",
-					base,
-				);
+	fn prevote<C: CoreEnvironment<H, N, S, Id>>(
+		&mut self,
+		env: &C,
+		last_round_state: &RoundState<H, N>,
+		outputs: &mut Outputs<H, N, S, Id>,
+	) {
+		if !matches!(self.state, Some(State::Start) | Some(State::Proposed)) {
+			return
+		}
 
-				// when we can't construct a prevote, we shouldn't precommit.
-				this.state = None;
-				this.voting = Voting::No;
-			}
+		let should_prevote = self.prevote_timer_fired || self.votes.completable();
+		if !should_prevote {
+			return
+		}
 
-			Ok(())
-		};
+		let voting = self.voting();
+		if let (true, Some(prevote)) = (voting.is_active(), self.replayed.prevote.clone()) {
+			debug!(target: "afg", "Replaying prevote for round {}", self.votes.number());
+			self.votes.set_prevoted_index();
+			self.send(outputs, Message::Prevote(prevote));
+			self.state = Some(State::Prevoted);
+		} else if voting.is_active() {
+			debug!(target: "afg", "Constructing prevote for round {}", self.votes.number());
+
+			let base = self.construct_prevote(env, last_round_state);
+			outputs.push_back(Output::RequestBestChain {
+				request: BestChainRequest::Prevote {
+					set_id: self.set_id,
+					round: self.round_number(),
+				},
+				base: base.0.clone(),
+			});
 
-		match state {
-			Some(State::Start(prevote_timer, precommit_timer)) => {
-				start_prevoting(self, prevote_timer, precommit_timer, false, cx)?;
-			},
-			Some(State::Proposed(prevote_timer, precommit_timer)) => {
-				start_prevoting(self, prevote_timer, precommit_timer, true, cx)?;
-			},
-			Some(State::Prevoting(precommit_timer, (base, best_chain))) => {
-				finish_prevoting(self, precommit_timer, base, best_chain, cx)?;
-			},
-			x => {
-				self.state = x;
-			},
+			self.state = Some(State::Prevoting(base));
+		} else {
+			self.state = Some(State::Prevoted);
 		}
-
-		Ok(())
 	}
 
-	fn precommit(
+	fn precommit<C: CoreEnvironment<H, N, S, Id>>(
 		&mut self,
-		cx: &mut Context,
+		env: &C,
 		last_round_state: &RoundState<H, N>,
-	) -> Result<(), E::Error> {
-		match self.state.take() {
-			Some(State::Prevoted(mut precommit_timer)) => {
-				let last_round_estimate = last_round_state
-					.estimate
-					.clone()
-					.expect("Rounds only started when prior round completable; qed");
+		outputs: &mut Outputs<H, N, S, Id>,
+	) {
+		if !matches!(self.state, Some(State::Prevoted)) {
+			return
+		}
 
-				let should_precommit = {
-					// we wait for the last round's estimate to be equal to or
-					// the ancestor of the current round's p-Ghost before precommitting.
-					self.votes.state().prevote_ghost.as_ref().map_or(false, |p_g| {
-						p_g == &last_round_estimate ||
-							self.env
-								.is_equal_or_descendent_of(last_round_estimate.0, p_g.0.clone())
-					})
-				} && match precommit_timer.poll_unpin(cx) {
-					Poll::Ready(Err(e)) => return Err(e),
-					Poll::Ready(Ok(())) => true,
-					Poll::Pending => self.votes.completable(),
-				};
-
-				if should_precommit {
-					if self.voting.is_active() {
-						debug!(target: "afg", "Casting precommit for round {}", self.votes.number());
-						let precommit = self.construct_precommit();
-						self.env.precommitted(self.round_number(), precommit.clone())?;
-						self.votes.set_precommitted_index();
-						self.outgoing.push(Message::Precommit(precommit));
-					}
-					self.state = Some(State::Precommitted);
-				} else {
-					self.state = Some(State::Prevoted(precommit_timer));
-				}
-			},
-			x => {
-				self.state = x;
-			},
+		let last_round_estimate = last_round_state
+			.estimate
+			.clone()
+			.expect("Rounds only started when prior round completable; qed");
+
+		let should_precommit = {
+			// we wait for the last round's estimate to be equal to or
+			// the ancestor of the current round's p-Ghost before precommitting.
+			self.votes.state().prevote_ghost.as_ref().map_or(false, |p_g| {
+				p_g == &last_round_estimate ||
+					env.is_equal_or_descendent_of(last_round_estimate.0, p_g.0.clone())
+			})
+		} && (self.precommit_timer_fired || self.votes.completable());
+
+		if !should_precommit {
+			return
+		}
+
+		let voting = self.voting();
+		if let (true, Some(precommit)) = (voting.is_active(), self.replayed.precommit.clone()) {
+			debug!(target: "afg", "Replaying precommit for round {}", self.votes.number());
+			self.votes.set_precommitted_index();
+			self.send(outputs, Message::Precommit(precommit));
+		} else if voting.is_active() {
+			debug!(target: "afg", "Casting precommit for round {}", self.votes.number());
+			let precommit = self.construct_precommit();
+			self.record_own_vote(outputs, |state| state.precommit = Some(precommit.clone()));
+			outputs.push_back(Output::Precommitted {
+				set_id: self.set_id,
+				round: self.round_number(),
+				precommit: precommit.clone(),
+			});
+			self.votes.set_precommitted_index();
+			self.send(outputs, Message::Precommit(precommit));
 		}
 
-		Ok(())
+		self.state = Some(State::Precommitted);
 	}
 
-	// construct a prevote message based on local state.
-	fn construct_prevote(&self, last_round_state: &RoundState<H, N>) -> (H, E::BestChain) {
+	// find the block to prevote on the best chain containing of, based on local state.
+	fn construct_prevote<C: CoreEnvironment<H, N, S, Id>>(
+		&self,
+		env: &C,
+		last_round_state: &RoundState<H, N>,
+	) -> (H, N) {
 		let last_round_estimate = last_round_state
 			.estimate
 			.clone()
 			.expect("Rounds only started when prior round completable; qed");
 
-		let find_descendent_of = match self.primary_block {
+		match self.primary_block {
 			None => {
 				// vote for best chain containing prior round-estimate.
-				last_round_estimate.0
+				last_round_estimate
 			},
 			Some(ref primary_block) => {
 				// we will vote for the best chain containing `p_hash` iff
@@ -639,17 +804,16 @@ where
 
 				// if the blocks are equal, we don't check ancestry.
 				if primary_block == &last_prevote_g {
-					primary_block.0.clone()
+					primary_block.clone()
 				} else if primary_block.1 >= last_prevote_g.1 {
-					last_round_estimate.0
+					last_round_estimate
 				} else {
 					// from this point onwards, the number of the primary-broadcasted
 					// block is less than the last prevote-GHOST's number.
 					// if the primary block is in the ancestry of p-G we vote for the
 					// best chain containing it.
 					let &(ref p_hash, p_num) = primary_block;
-					match self.env.ancestry(last_round_estimate.0.clone(), last_prevote_g.0.clone())
-					{
+					match env.ancestry(last_round_estimate.0.clone(), last_prevote_g.0.clone()) {
 						Ok(ancestry) => {
 							let to_sub = p_num + N::one();
 
@@ -660,12 +824,12 @@ where
 							};
 
 							if ancestry.get(offset).map_or(false, |b| b == p_hash) {
-								p_hash.clone()
+								(p_hash.clone(), p_num)
 							} else {
-								last_round_estimate.0
+								last_round_estimate
 							}
 						},
-						Err(crate::Error::NotDescendent) => {
+						Err(_) => {
 							// This is only possible in case of massive equivocation
 							warn!(target: "afg",
 								"Possible case of massive equivocation: \
@@ -674,14 +838,44 @@ where
 								last_round_estimate,
 							);
 
-							last_round_estimate.0
+							last_round_estimate
 						},
 					}
 				}
 			},
+		}
+	}
+
+	// apply the voting rule to the best block containing our prevote base. the
+	// target is never restricted below the prevote base, or off the best chain.
+	fn restrict_prevote<C: CoreEnvironment<H, N, S, Id>>(
+		&self,
+		env: &C,
+		rule: &dyn VotingRule<H, N>,
+		prevote_base: &(H, N),
+		best_chain: (H, N),
+	) -> (H, N) {
+		let target = match rule.restrict_vote(env, &self.votes.base(), &best_chain, &best_chain) {
+			Some(target) => target,
+			None => return best_chain,
 		};
 
-		(find_descendent_of.clone(), self.env.best_chain_containing(find_descendent_of))
+		if target.1 <= prevote_base.1 {
+			return prevote_base.clone()
+		}
+
+		if !env.is_equal_or_descendent_of(target.0.clone(), best_chain.0.clone()) {
+			warn!(target: "afg",
+				"Ignoring voting rule restriction to {:?}, not an ancestor of the best block {:?}",
+				target,
+				best_chain,
+			);
+
+			return best_chain
+		}
+
+		debug!(target: "afg", "Restricted prevote target from {:?} to {:?}", best_chain, target);
+		target
 	}
 
 	// construct a precommit message based on local state.
@@ -694,23 +888,20 @@ where
 		Precommit { target_hash: t.0, target_number: t.1 }
 	}
 
-	// notify when new blocks are finalized or when the round-estimate is updated
-	fn notify(&mut self, last_state: RoundState<H, N>, new_state: RoundState<H, N>) {
-		if last_state != new_state {
-			if let Some(ref b) = self.bridged_round_state {
-				b.update(new_state.clone());
-			}
-		}
+	// note when new blocks are finalized.
+	fn notify<C: CoreEnvironment<H, N, S, Id>>(&mut self, env: &C) {
+		let new_state = self.votes.state();
 
 		// send notification only when the round is completable and we've cast votes.
 		// this is a workaround that ensures when we re-instantiate the voter after
 		// a shutdown, we never re-create the same round with a base that was finalized
 		// in this round or after.
-		// we try to notify if either the round state changed or if we haven't
+		// we try to notify if either the finalized block changed or if we haven't
 		// sent any notification yet (this is to guard against seeing enough
 		// votes to finalize before having precommited)
-		let state_changed = last_state.finalized != new_state.finalized;
+		let state_changed = self.last_finalized != new_state.finalized;
 		let sent_finality_notifications = self.best_finalized.is_some();
+		self.last_finalized = new_state.finalized.clone();
 
 		if new_state.completable && (state_changed || !sent_finality_notifications) {
 			let precommitted = matches!(self.state, Some(State::Precommitted));
@@ -721,14 +912,13 @@ where
 			if precommitted || cant_vote {
 				if let Some((f_hash, f_number)) = new_state.finalized {
 					let commit = Commit {
-						target_hash: f_hash.clone(),
+						target_hash: f_hash,
 						target_number: f_number,
-						precommits: self.votes.finalizing_precommits(&*self.env)
+						precommits: self.votes.finalizing_precommits(env)
 							.expect("always returns none if something was finalized; this is checked above; qed")
 							.collect(),
 					};
-					let finalized = (f_hash.clone(), f_number, self.votes.number(), commit.clone());
-					let _ = self.finalized_sender.unbounded_send(finalized);
+					self.finalized_notification = Some(commit.clone());
 					self.best_finalized = Some(commit);
 				}
 			}
@@ -0,0 +1,224 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rules restricting the target of the prevotes cast by the voter.
+//!
+//! The voter prevotes for the best block containing its prevote base, as
+//! returned by the environment. A [`VotingRule`] can pull that target back to
+//! one of its ancestors, e.g. to keep the voter from voting too far ahead of
+//! the last finalized block. The voter never applies a restriction which
+//! would take the target below its prevote base, or off the best chain.
+
+use crate::{
+	std::{boxed::Box, vec::Vec},
+	BlockNumberOps, Chain,
+};
+
+/// A rule restricting the target of a prevote.
+pub trait VotingRule<H: Eq, N: Copy + BlockNumberOps> {
+	/// Restrict the prevote target `current_target`, a block on the chain of
+	/// `best_target`, given the base of the round the vote is cast in.
+	///
+	/// Returns the new target, which should be an ancestor of `current_target`,
+	/// or `None` to leave it as is.
+	fn restrict_vote(
+		&self,
+		chain: &dyn Chain<H, N>,
+		base: &(H, N),
+		best_target: &(H, N),
+		current_target: &(H, N),
+	) -> Option<(H, N)>;
+}
+
+/// Never vote more than the given number of blocks past the round base, i.e.
+/// the last block finalized when the round started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaxUnfinalizedGap<N>(pub N);
+
+impl<H: Clone + Eq, N: Copy + BlockNumberOps> VotingRule<H, N> for MaxUnfinalizedGap<N> {
+	fn restrict_vote(
+		&self,
+		chain: &dyn Chain<H, N>,
+		base: &(H, N),
+		_best_target: &(H, N),
+		current_target: &(H, N),
+	) -> Option<(H, N)> {
+		// compare the gap rather than adding it to the base, which may overflow.
+		if current_target.1 <= base.1 || current_target.1 - base.1 <= self.0 {
+			return None
+		}
+
+		ancestor_at(chain, base, current_target, base.1 + self.0)
+	}
+}
+
+/// Vote for the block the given number of blocks behind the best block, e.g.
+/// to leave some time for it to propagate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TargetDepthOffset<N>(pub N);
+
+impl<H: Clone + Eq, N: Copy + BlockNumberOps> VotingRule<H, N> for TargetDepthOffset<N> {
+	fn restrict_vote(
+		&self,
+		chain: &dyn Chain<H, N>,
+		base: &(H, N),
+		best_target: &(H, N),
+		current_target: &(H, N),
+	) -> Option<(H, N)> {
+		let target_number = if best_target.1 < base.1 || best_target.1 - base.1 < self.0 {
+			base.1
+		} else {
+			best_target.1 - self.0
+		};
+		if current_target.1 <= target_number {
+			return None
+		}
+
+		ancestor_at(chain, base, current_target, target_number)
+	}
+}
+
+/// A set of voting rules, applied in the order they were added. Each rule
+/// restricts the target left by the previous ones.
+pub struct VotingRules<H, N> {
+	rules: Vec<Box<dyn VotingRule<H, N> + Send + Sync>>,
+}
+
+impl<H, N> Default for VotingRules<H, N> {
+	fn default() -> Self {
+		VotingRules { rules: Vec::new() }
+	}
+}
+
+impl<H: Eq, N: Copy + BlockNumberOps> VotingRules<H, N> {
+	/// Create an empty set of voting rules, which doesn't restrict any vote.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Add a rule, to be applied after the ones added so far.
+	pub fn with<R>(mut self, rule: R) -> Self
+	where
+		R: VotingRule<H, N> + Send + Sync + 'static,
+	{
+		self.rules.push(Box::new(rule));
+		self
+	}
+}
+
+impl<H: Clone + Eq, N: Copy + BlockNumberOps> VotingRule<H, N> for VotingRules<H, N> {
+	fn restrict_vote(
+		&self,
+		chain: &dyn Chain<H, N>,
+		base: &(H, N),
+		best_target: &(H, N),
+		current_target: &(H, N),
+	) -> Option<(H, N)> {
+		let mut restricted: Option<(H, N)> = None;
+
+		for rule in &self.rules {
+			let target = restricted.as_ref().unwrap_or(current_target);
+			match rule.restrict_vote(chain, base, best_target, target) {
+				// rules may only move the target backwards.
+				Some(new_target) if new_target.1 < target.1 => restricted = Some(new_target),
+				_ => {},
+			}
+		}
+
+		restricted
+	}
+}
+
+// the ancestor of `block` with the given number, which is between the base and
+// the block.
+fn ancestor_at<H: Clone + Eq, N: Copy + BlockNumberOps>(
+	chain: &dyn Chain<H, N>,
+	base: &(H, N),
+	block: &(H, N),
+	number: N,
+) -> Option<(H, N)> {
+	if number <= base.1 {
+		return Some(base.clone())
+	}
+
+	if number >= block.1 {
+		return Some(block.clone())
+	}
+
+	// the ancestry is in reverse order from the parent of `block`.
+	let offset: usize = (block.1 - number - N::one()).as_();
+	let ancestry = chain.ancestry(base.0.clone(), block.0.clone()).ok()?;
+
+	ancestry.get(offset).map(|hash| (hash.clone(), number))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::chain::{DummyChain, GENESIS_HASH};
+
+	fn chain() -> DummyChain {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+		chain
+	}
+
+	#[test]
+	fn max_unfinalized_gap() {
+		let chain = chain();
+		let (base, best) = ((GENESIS_HASH, 1), ("E", 6));
+
+		let rule = MaxUnfinalizedGap(2);
+		assert_eq!(rule.restrict_vote(&chain, &base, &best, &best), Some(("B", 3)));
+		assert_eq!(rule.restrict_vote(&chain, &base, &best, &("B", 3)), None);
+		assert_eq!(rule.restrict_vote(&chain, &("C", 4), &best, &best), None);
+
+		// a large gap doesn't overflow the block number.
+		let rule = MaxUnfinalizedGap(u32::MAX);
+		assert_eq!(rule.restrict_vote(&chain, &base, &best, &best), None);
+	}
+
+	#[test]
+	fn target_depth_offset() {
+		let chain = chain();
+		let (base, best) = ((GENESIS_HASH, 1), ("E", 6));
+
+		let rule = TargetDepthOffset(1);
+		assert_eq!(rule.restrict_vote(&chain, &base, &best, &best), Some(("D", 5)));
+		assert_eq!(rule.restrict_vote(&chain, &base, &best, &("C", 4)), None);
+
+		// the target isn't moved below the base.
+		let rule = TargetDepthOffset(10);
+		assert_eq!(rule.restrict_vote(&chain, &base, &best, &best), Some(base));
+
+		let rule = TargetDepthOffset(u32::MAX);
+		assert_eq!(rule.restrict_vote(&chain, &base, &best, &best), Some(base));
+	}
+
+	#[test]
+	fn voting_rules_apply_the_most_restrictive_rule() {
+		let chain = chain();
+		let (base, best) = ((GENESIS_HASH, 1), ("E", 6));
+
+		let rules = VotingRules::new().with(TargetDepthOffset(1)).with(MaxUnfinalizedGap(3));
+		assert_eq!(rules.restrict_vote(&chain, &base, &best, &best), Some(("C", 4)));
+
+		let rules = VotingRules::new().with(MaxUnfinalizedGap(3)).with(TargetDepthOffset(1));
+		assert_eq!(rules.restrict_vote(&chain, &base, &best, &best), Some(("C", 4)));
+
+		let rules = VotingRules::new().with(MaxUnfinalizedGap(10));
+		assert_eq!(rules.restrict_vote(&chain, &base, &best, &best), None);
+		assert_eq!(VotingRules::new().restrict_vote(&chain, &base, &best, &best), None);
+	}
+}